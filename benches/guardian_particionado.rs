@@ -0,0 +1,92 @@
+//! Mide cuantos pedidos de ecommerce por segundo resuelve el guardian del local
+//! segun la cantidad de particiones en las que se reparte su stock. Cada pedido
+//! bloquea y luego cancela un producto, persistiendo ambas operaciones en el
+//! registro de su particion, como ocurre al ejecutar un local.
+
+use actix::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use pidgeonhole::aliases::TablaStock;
+use pidgeonhole::local::guardian::{Bloquear, Cancelar, Guardian};
+use pidgeonhole::local::particiones::{self, GuardianParticionado};
+use pidgeonhole::local::registro::Registro;
+use pidgeonhole::pedido::{Carrito, Pedido};
+
+const PRODUCTOS: u16 = 64;
+const PEDIDOS_POR_TANDA: u16 = 256;
+
+fn directorio_de_prueba(cantidad_particiones: usize) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "bench-guardian-{}-{}",
+        cantidad_particiones,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn crear_guardian(cantidad_particiones: usize) -> Addr<GuardianParticionado> {
+    let stock: TablaStock = (0..PRODUCTOS).map(|id| (id, u16::MAX)).collect();
+    let dir = directorio_de_prueba(cantidad_particiones);
+    let guardianes = particiones::dividir_stock(stock, cantidad_particiones)
+        .into_iter()
+        .enumerate()
+        .map(|(particion, stock)| {
+            let registro =
+                Registro::abrir_particion(&dir, 0, particion, cantidad_particiones).unwrap();
+            Guardian::new(stock).con_registro(registro).unwrap()
+        })
+        .collect();
+    GuardianParticionado::new(guardianes).start()
+}
+
+/// Resuelve una tanda de pedidos concurrentes, bloqueando y cancelando cada uno
+async fn resolver_tanda(guardian: &Addr<GuardianParticionado>) {
+    join_all((0..PEDIDOS_POR_TANDA).map(|id_pedido| async move {
+        let carrito = Carrito::from(Pedido::new(id_pedido % PRODUCTOS, 1));
+        guardian
+            .send(Bloquear::new(carrito, id_pedido, 0))
+            .await
+            .unwrap()
+            .unwrap();
+        guardian
+            .send(Cancelar::new(id_pedido, 0))
+            .await
+            .unwrap()
+            .unwrap();
+    }))
+    .await;
+}
+
+fn pedidos_por_segundo(c: &mut Criterion) {
+    let mut grupo = c.benchmark_group("guardian_particionado");
+    grupo.throughput(Throughput::Elements(PEDIDOS_POR_TANDA as u64));
+    grupo.sample_size(20);
+    grupo.measurement_time(Duration::from_secs(5));
+
+    for cantidad_particiones in [1, 2, 4, 8] {
+        grupo.bench_with_input(
+            BenchmarkId::from_parameter(cantidad_particiones),
+            &cantidad_particiones,
+            |b, &cantidad_particiones| {
+                b.iter_custom(|tandas| {
+                    System::new().block_on(async move {
+                        let guardian = crear_guardian(cantidad_particiones);
+                        let inicio = Instant::now();
+                        for _ in 0..tandas {
+                            resolver_tanda(&guardian).await;
+                        }
+                        inicio.elapsed()
+                    })
+                })
+            },
+        );
+    }
+    grupo.finish();
+}
+
+criterion_group!(benches, pedidos_por_segundo);
+criterion_main!(benches);
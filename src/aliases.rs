@@ -2,11 +2,7 @@
 //! para hacer sencillo el pase de uno al otro y dar mayor
 //! claridad al codigo
 
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-    thread::JoinHandle,
-};
+use std::{collections::HashMap, sync::Arc, thread::JoinHandle};
 use tokio::sync::{Mutex, Notify};
 
 use crate::{ecommerce::handler::Handler, errores::ErrorEcommerce};
@@ -18,6 +14,7 @@ pub type CantidadPedido = u8;
 pub type IdLocal = u16;
 pub type Puerto = u16;
 pub type IdEcommerce = u16;
-pub type MonitorAsync = (Mutex<HashSet<(Puerto, IdPedido)>>, Notify);
+pub type IdTransferencia = u16;
+pub type MonitorAsync = (Mutex<HashMap<(IdLocal, Puerto, IdPedido), bool>>, Notify);
 pub type TablaStock = HashMap<u16, u16>;
 pub type Ecommerce = (Arc<Handler>, JoinHandle<Result<(), ErrorEcommerce>>);
@@ -1,16 +1,20 @@
 //! Este modulo contiene una estructura util que permite enviar señales a
 //! una direccion derivada de un identificador para que corte su señal, o
-//! que la retorne
+//! que la retorne. Tambien permite preguntarle a un local quien es el lider, o
+//! pedirle que se drene y termine, como tarea de mantenimiento
 
-use crate::mensajes::TipoMensaje;
-use crate::{id_a_dir_local, id_a_dir_medico};
+use crate::mensajes::{MensajeEleccion, TipoMensaje};
+use crate::{id_a_dir_local, id_a_dir_medico, MAX_MENSAJE};
 use clap::Parser;
 
+use std::io;
 use std::net::UdpSocket;
+use std::time::Duration;
 
 /// Estructura que envia mensajes de aviso a un local.
 /// Contiene el identificador del local al que se desea avisar, y
-/// un flag de si lo debe matar o revivir
+/// un flag de si lo debe matar o revivir, de si se le consulta el lider, o de
+/// si se le pide que se drene
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Dios {
@@ -19,6 +23,12 @@ pub struct Dios {
 
     #[arg(short, long, default_value_t = false)]
     kill: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    lider: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    drenar: bool,
 }
 
 impl Dios {
@@ -32,7 +42,15 @@ impl Dios {
             }
         };
 
-        if self.kill {
+        if self.lider {
+            self.consultar_lider(&puerta_al_cielo);
+        } else if self.drenar {
+            let direccion_objetivo = id_a_dir_local(self.id);
+            match puerta_al_cielo.send_to(&[TipoMensaje::Drenar as u8], direccion_objetivo) {
+                Ok(_) => println!("El local {} se drenara y terminara", self.id),
+                Err(_) => println!("No se le pudo pedir al local {} que se drene", self.id),
+            }
+        } else if self.kill {
             let direccion_objetivo = id_a_dir_local(self.id);
             match puerta_al_cielo.send_to(&[TipoMensaje::Matar as u8], direccion_objetivo) {
                 Ok(_) => println!("Objetivo cumplido, la presa esta en el cielo"),
@@ -48,4 +66,27 @@ impl Dios {
             }
         }
     }
+
+    /// Le pregunta al local quien es el lider, y espera su respuesta
+    fn consultar_lider(&self, socket: &UdpSocket) {
+        let consulta = MensajeEleccion::ConsultaLider.as_bytes();
+        if socket.send_to(&consulta, id_a_dir_local(self.id)).is_err()
+            || socket
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .is_err()
+        {
+            println!("No se pudo consultar al local {}", self.id);
+            return;
+        }
+        let mut buf: [u8; MAX_MENSAJE as usize] = [0; MAX_MENSAJE as usize];
+        let respuesta = socket.recv(&mut buf).and_then(|_| {
+            let mut cursor = io::Cursor::new(buf);
+            let tipo = TipoMensaje::from_bytes(&mut cursor)?;
+            MensajeEleccion::from_bytes(tipo, &mut cursor)
+        });
+        match respuesta {
+            Ok(MensajeEleccion::Coordinador(lider)) => println!("El lider es el local {}", lider),
+            _ => println!("El local {} no sabe quien es el lider", self.id),
+        }
+    }
 }
@@ -9,43 +9,113 @@ use std::io::{self, Read};
 use std::net::UdpSocket;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::aliases::{Ecommerce, IdLocal, IdPedido};
-use crate::mensajes::{AckEcommerce, MensajeEcommerce, MensajesServidor, TipoMensaje};
-use crate::pedido::Pedido;
+use crate::membresia::{Membresia, INTERVALO_RUMORES, TIEMPO_SIN_NOVEDADES};
+use crate::mensajes::{
+    AckEcommerce, ConsultaEstado, EstadoConsultado, MensajeEcommerce, MensajeMembresia,
+    MensajeModificacion, MensajesServidor, RespuestaEstado, RespuestaModificacion,
+    ResultadoModificacion, TipoMensaje,
+};
+use crate::pedido::{Carrito, ModificacionProgramada, PedidoEcommerce};
+use crate::topologia::Topologia;
 
-use crate::{id_a_dir_local, CANTIDAD_LOCALES, MAX_MENSAJE};
-use rand::Rng;
+use crate::{id_a_dir_local, MAX_MENSAJE};
+use rand::seq::SliceRandom;
+
+/// Tiempo que se espera la respuesta de un local a la consulta por el estado de un pedido
+const PLAZO_CONSULTA_ESTADO: Duration = Duration::from_millis(500);
+
+/// Tiempo que se espera la respuesta a la modificacion de un pedido, que puede
+/// reenviarse entre los locales hasta llegar al que lo tiene reservado
+const PLAZO_MODIFICACION: Duration = Duration::from_secs(1);
+
+/// Cantidad de veces seguidas que se acepta que los locales respondan que un
+/// pedido sigue en curso. Como las reservas expiran a los pocos segundos, si
+/// el resultado no llega tras tantas consultas se da por perdido y se reenvia
+const MAX_CONSULTAS_EN_CURSO: u32 = 10;
+
+/// Cada cuanto se le consulta al local si sigue teniendo un pedido en espera de stock
+const PLAZO_CONSULTA_EN_ESPERA: Duration = Duration::from_secs(10);
 
 /// Estructura que maneja el envio de pedidos a los locales, junto con la lectura de
-/// acusos de recibo y de finalizacion.
+/// acusos de recibo y de finalizacion. Lleva cuenta de los pedidos que un local
+/// dejo en espera de stock, para no reenviarlos a otro local. Cuando un local no
+/// responde, reenvia el pedido al siguiente segun la topologia de delegacion. Si
+/// el resultado se demora, antes de reenviarlo le consulta al local en que estado
+/// esta el pedido, por si aun lo tiene en curso o ya lo resolvio. Recuerda a
+/// que local le envio cada pedido, para pedirle las modificaciones del cliente.
+/// Los locales a los que envia son los activos segun la membresia del cluster,
+/// que le consulta periodicamente a los locales que conoce o a las semillas.
 pub struct Handler {
     socket: UdpSocket,
-    pedidos_pendientes: (Mutex<HashMap<IdPedido, Pedido>>, Condvar),
+    topologia: Topologia,
+    membresia: Membresia,
+    semillas: Vec<IdLocal>,
+    pedidos_pendientes: (Mutex<HashMap<IdPedido, Carrito>>, Condvar),
+    pedidos_en_espera: Mutex<HashSet<IdPedido>>,
     acks: (Mutex<HashSet<IdPedido>>, Condvar),
+    estados: (Mutex<HashMap<IdPedido, EstadoConsultado>>, Condvar),
+    locales_pedidos: Mutex<HashMap<IdPedido, IdLocal>>,
+    modificaciones: (Mutex<HashMap<IdPedido, ResultadoModificacion>>, Condvar),
 }
 
 impl Handler {
     /// Inicializa un handler, al que se le pasa la cantidad de pedidos de los que se debera
     /// hacer cargo. Inicializa un hilo que escucha por un socket por las respuestas de los
-    /// locales.
-    pub fn new(cant_pedidos: usize) -> std::io::Result<Ecommerce> {
+    /// locales, y otro que consulta la membresia del cluster a partir de las semillas dadas.
+    /// Los reenvios siguen la topologia dada.
+    pub fn new(
+        cant_pedidos: usize,
+        topologia: Topologia,
+        semillas: Vec<IdLocal>,
+    ) -> std::io::Result<Ecommerce> {
         let addr = "127.0.0.1:0";
         let socket = UdpSocket::bind(addr)?;
         let handler = Arc::new(Self {
             socket,
+            topologia,
+            membresia: Membresia::new(),
+            semillas,
             pedidos_pendientes: (Mutex::new(HashMap::new()), Condvar::new()),
+            pedidos_en_espera: Mutex::new(HashSet::new()),
             acks: (Mutex::new(HashSet::new()), Condvar::new()),
+            estados: (Mutex::new(HashMap::new()), Condvar::new()),
+            locales_pedidos: Mutex::new(HashMap::new()),
+            modificaciones: (Mutex::new(HashMap::new()), Condvar::new()),
         });
 
         let handler_clone = handler.clone();
 
         let handle = thread::spawn(move || handler_clone.read_loop(cant_pedidos));
 
+        let handler_clone = handler.clone();
+        thread::spawn(move || handler_clone.consultar_membresia());
+
         Ok((handler, handle))
     }
 
+    /// Le consulta periodicamente la membresia a alguno de los locales activos, o
+    /// a las semillas si no conoce a ninguno. Las respuestas las recibe el hilo
+    /// lector. Esta funcion no termina nunca
+    fn consultar_membresia(&self) {
+        loop {
+            let vivos = self.membresia.vivos();
+            let destinos = match vivos.choose(&mut rand::thread_rng()) {
+                Some(id) => vec![*id],
+                None => self.semillas.clone(),
+            };
+            for id in destinos {
+                let consulta = [TipoMensaje::ConsultaMembresia as u8];
+                if self.socket.send_to(&consulta, id_a_dir_local(id)).is_err() {
+                    eprintln!("No pudo consultar la membresia al local {}", id);
+                }
+            }
+            thread::sleep(INTERVALO_RUMORES);
+        }
+    }
+
     /// Lee del socket asociado y espera a los acuses de recibo y las confirmaciones de
     /// los pedidos
     /// Esta funcion es bloqueante, y finalice una vez se lea la confirmacion de todos los pedidos
@@ -71,6 +141,12 @@ impl Handler {
                 TipoMensaje::AckEcommerce => {
                     self.procesar_ack_ecommerce(&mut cursor)?;
                 }
+                TipoMensaje::Membresia => match MensajeMembresia::from_bytes(&mut cursor) {
+                    Ok(rumor) => {
+                        self.membresia.fusionar(&rumor);
+                    }
+                    Err(error) => eprintln!("No pudo leer la membresia: {:?}", error),
+                },
                 TipoMensaje::MensajeServidor => {
                     let mensaje = match MensajesServidor::from_bytes(&mut cursor) {
                         Ok(mensaje) => mensaje,
@@ -80,10 +156,39 @@ impl Handler {
                         }
                     };
 
-                    let id = self.procesar_mensaje_servidor(mensaje);
+                    let id = match self.procesar_mensaje_servidor(mensaje)? {
+                        Some(id) => id,
+                        None => continue,
+                    };
 
-                    if self.pedidos_pendientes.0.lock()?.remove(&id).is_some() {
-                        self.pedidos_pendientes.1.notify_all();
+                    if self.finalizar_pedido(id)? {
+                        cant_pedidos -= 1;
+                        if cant_pedidos == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+                TipoMensaje::RespuestaModificacion => {
+                    match RespuestaModificacion::from_bytes(&mut cursor) {
+                        Ok(respuesta) => {
+                            self.modificaciones
+                                .0
+                                .lock()?
+                                .insert(respuesta.id_pedido, respuesta.resultado);
+                            self.modificaciones.1.notify_all();
+                        }
+                        Err(error) => eprintln!("No pudo leer una modificacion: {:?}", error),
+                    }
+                }
+                TipoMensaje::RespuestaEstado => {
+                    let respuesta = match RespuestaEstado::from_bytes(&mut cursor) {
+                        Ok(respuesta) => respuesta,
+                        Err(error) => {
+                            eprintln!("No pudo leer el estado de un pedido: {:?}", error);
+                            continue;
+                        }
+                    };
+                    if self.procesar_respuesta_estado(respuesta)? {
                         cant_pedidos -= 1;
                         if cant_pedidos == 0 {
                             return Ok(());
@@ -95,24 +200,104 @@ impl Handler {
         }
     }
 
-    /// Procesa un mensaje proveniente del servidor, y devuelve el id del pedido asociado.
-    /// Imprime por pantalla el resultado del pedido
-    fn procesar_mensaje_servidor(&self, mensaje: MensajesServidor) -> IdPedido {
+    /// Da por terminado un pedido pendiente, despertando al hilo que espera su
+    /// resultado. Devuelve si el pedido seguia pendiente
+    fn finalizar_pedido(&self, id: IdPedido) -> Result<bool, ErrorEcommerce> {
+        if self.pedidos_pendientes.0.lock()?.remove(&id).is_none() {
+            return Ok(false);
+        }
+        self.pedidos_pendientes.1.notify_all();
+        self.pedidos_en_espera.lock()?.remove(&id);
+        Ok(true)
+    }
+
+    /// Procesa la respuesta de un local a la consulta por el estado de un pedido.
+    /// Si el pedido ya se resolvio, lo da por terminado como si hubiera llegado
+    /// su resultado, que se habra perdido en el camino. Devuelve si el pedido
+    /// seguia pendiente y termino
+    fn procesar_respuesta_estado(
+        &self,
+        respuesta: RespuestaEstado,
+    ) -> Result<bool, ErrorEcommerce> {
+        let id = respuesta.id_pedido;
+        let resultado = match respuesta.estado {
+            EstadoConsultado::Confirmado => Some(MensajesServidor::PedidoExitoso(id)),
+            EstadoConsultado::Cancelado => Some(MensajesServidor::PedidoCancelado(id)),
+            _ => None,
+        };
+        let termino = match resultado {
+            Some(resultado) if self.pedidos_pendientes.0.lock()?.contains_key(&id) => {
+                self.procesar_mensaje_servidor(resultado)?;
+                self.finalizar_pedido(id)?
+            }
+            _ => false,
+        };
+
+        self.estados.0.lock()?.insert(id, respuesta.estado);
+        self.estados.1.notify_all();
+        Ok(termino)
+    }
+
+    /// Le consulta al local dado en que estado esta el pedido, y devuelve la
+    /// respuesta, o nada si el local no responde a tiempo
+    fn consultar_estado(
+        &self,
+        id_pedido: IdPedido,
+        id_local: IdLocal,
+    ) -> Result<Option<EstadoConsultado>, ErrorEcommerce> {
+        self.estados.0.lock()?.remove(&id_pedido);
+        let puerto = self.socket.local_addr()?.port();
+        let consulta = ConsultaEstado::new(id_pedido, puerto);
+        if self
+            .socket
+            .send_to(&consulta.as_bytes(), id_a_dir_local(id_local))
+            .is_err()
+        {
+            eprintln!(
+                "No pudo consultar el estado del pedido al local {}",
+                id_local
+            );
+        }
+
+        let (mut estados, _) = self
+            .estados
+            .1
+            .wait_timeout_while(self.estados.0.lock()?, PLAZO_CONSULTA_ESTADO, |estados| {
+                !estados.contains_key(&id_pedido)
+            })
+            .map_err(Into::<ErrorEcommerce>::into)?;
+        Ok(estados.remove(&id_pedido))
+    }
+
+    /// Procesa un mensaje proveniente del servidor, y devuelve el id del pedido asociado
+    /// si el mensaje es un resultado final. Imprime por pantalla el resultado del pedido
+    fn procesar_mensaje_servidor(
+        &self,
+        mensaje: MensajesServidor,
+    ) -> Result<Option<IdPedido>, ErrorEcommerce> {
         match mensaje {
             MensajesServidor::PedidoExitoso(id) => {
                 println!("El pedido con id {} fue exitoso", id.to_string().blue());
-                id
+                Ok(Some(id))
             }
             MensajesServidor::PedidoCancelado(id) => {
                 println!("El pedido con id {} fue cancelado", id.to_string().blue());
-                id
+                Ok(Some(id))
             }
             MensajesServidor::NoHayStock(id) => {
                 println!(
                     "No hay stock en ninguna tienda para el pedido con id {}",
                     id.to_string().blue()
                 );
-                id
+                Ok(Some(id))
+            }
+            MensajesServidor::EnEspera(id) => {
+                println!(
+                    "El pedido con id {} quedo en espera hasta que haya stock",
+                    id.to_string().blue()
+                );
+                self.pedidos_en_espera.lock()?.insert(id);
+                Ok(None)
             }
         }
     }
@@ -134,22 +319,45 @@ impl Handler {
         Ok(())
     }
 
-    /// Devuelve el id de la tienda mas cercana al ecommerce (modelado con un random)
-    pub fn encontrar_tienda_cercana() -> IdLocal {
-        rand::thread_rng().gen_range(0..CANTIDAD_LOCALES) as IdLocal
+    /// Devuelve el id de la tienda mas cercana al ecommerce (modelado con un random
+    /// entre los locales activos). Si aun no conoce ninguno, espera a conocerlo
+    /// # Errors:
+    /// Si pasado un tiempo sigue sin conocer ningun local activo
+    pub fn encontrar_tienda_cercana(&self) -> Result<IdLocal, ErrorEcommerce> {
+        let inicio = Instant::now();
+        loop {
+            if let Some(id) = self.membresia.vivos().choose(&mut rand::thread_rng()) {
+                return Ok(*id);
+            }
+            if inicio.elapsed() > TIEMPO_SIN_NOVEDADES {
+                return Err(ErrorEcommerce::SinLocalesActivos);
+            }
+            thread::sleep(INTERVALO_RUMORES);
+        }
     }
 
     /// Procesa todos los pedidos pasados por parametro, de forma concurrente. Crea una
     /// tarea en un threadpool por cada pedido
     pub fn procesar_pedidos(
         handler: Arc<Self>,
-        pedidos: Vec<Pedido>,
+        pedidos: Vec<PedidoEcommerce>,
     ) -> Result<(), ErrorEcommerce> {
         let pool = ThreadPoolBuilder::new()
             .num_threads(num_cpus::get())
             .build()?;
 
         for (id_pedido, pedido) in pedidos.into_iter().enumerate() {
+            if let Some(modificacion) = pedido.modificacion {
+                // Cada modificacion espera en su propio hilo, para no ocupar el
+                // threadpool mientras los pedidos esperan su resultado
+                let arc_clone = handler.clone();
+                thread::spawn(move || {
+                    let id_pedido = id_pedido as IdPedido;
+                    if let Err(e) = arc_clone.modificar_pedido(id_pedido, modificacion) {
+                        eprintln!("Error modificando el pedido {}: {:?}", id_pedido, e);
+                    }
+                });
+            }
             let arc_clone = handler.clone();
             pool.spawn(move || {
                 if let Err(e) = arc_clone.procesar_pedido(id_pedido, pedido) {
@@ -162,16 +370,79 @@ impl Handler {
     }
 
     /// Envia el pedido a la tienda mas cercana
-    fn procesar_pedido(&self, id_pedido: usize, pedido: Pedido) -> Result<(), ErrorEcommerce> {
-        if pedido.get_amount() == 0 {
+    fn procesar_pedido(
+        &self,
+        id_pedido: usize,
+        pedido: PedidoEcommerce,
+    ) -> Result<(), ErrorEcommerce> {
+        if pedido.carrito.esta_vacio() {
             return Err(ErrorEcommerce::CantidadCero);
         }
         let id_pedido = id_pedido as IdPedido;
-        let msg = MensajeEcommerce::new(id_pedido, pedido);
+        let msg = MensajeEcommerce::new(id_pedido, pedido.carrito).con_espera(pedido.esperar);
 
-        let id_local = Self::encontrar_tienda_cercana();
+        let id_local = self.encontrar_tienda_cercana()?;
 
-        self.enviar_pedido(msg, id_local)
+        self.enviar_pedido(msg, id_local, id_local)
+    }
+
+    /// Pasado el tiempo indicado, le pide al local al que se envio el pedido que
+    /// lo cancele o reduzca, e informa la respuesta
+    fn modificar_pedido(
+        &self,
+        id_pedido: IdPedido,
+        programada: ModificacionProgramada,
+    ) -> Result<(), ErrorEcommerce> {
+        thread::sleep(Duration::from_millis(programada.tras_ms));
+        let Some(id_local) = self.locales_pedidos.lock()?.get(&id_pedido).copied() else {
+            println!(
+                "El pedido {} no llego a ningun local, no se puede modificar",
+                id_pedido.to_string().blue()
+            );
+            return Ok(());
+        };
+
+        let puerto = self.socket.local_addr()?.port();
+        let mensaje = MensajeModificacion::new(id_pedido, puerto, programada.modificacion);
+        println!(
+            "Pidiendo {:?} del pedido {} a tienda {}",
+            programada.modificacion,
+            id_pedido.to_string().blue(),
+            id_local.to_string().red()
+        );
+        if self
+            .socket
+            .send_to(&mensaje.as_bytes(), id_a_dir_local(id_local))
+            .is_err()
+        {
+            eprintln!("No pudo enviar la modificacion a traves del socket");
+        }
+
+        let (mut resultados, _) = self
+            .modificaciones
+            .1
+            .wait_timeout_while(
+                self.modificaciones.0.lock()?,
+                PLAZO_MODIFICACION,
+                |resultados| !resultados.contains_key(&id_pedido),
+            )
+            .map_err(Into::<ErrorEcommerce>::into)?;
+        match resultados.remove(&id_pedido) {
+            Some(ResultadoModificacion::Aceptada) => println!(
+                "La modificacion del pedido {} fue aceptada",
+                id_pedido.to_string().blue()
+            ),
+            Some(resultado) => println!(
+                "La modificacion del pedido {} fue rechazada: {:?}",
+                id_pedido.to_string().blue(),
+                resultado
+            ),
+            None => println!(
+                "No hubo respuesta a la modificacion del pedido {}",
+                id_pedido.to_string().blue()
+            ),
+        }
+        Ok(())
     }
 
     /// Espera el ack del pedido, y devuelve el resultado de la espera.
@@ -179,10 +450,11 @@ impl Handler {
     fn esperar_ack(
         &self,
         mensaje: MensajeEcommerce,
+        origen: IdLocal,
         id_local: IdLocal,
     ) -> Result<(), ErrorEcommerce> {
         let id_pedido = mensaje.id_pedido;
-        let (_, wait_result) = self
+        let (_guard, wait_result) = self
             .acks
             .1
             .wait_timeout_while(self.acks.0.lock()?, Duration::from_millis(500), |acks| {
@@ -194,20 +466,29 @@ impl Handler {
             println!(
                 "No recibi ack de pedido {}, reenviando a {}",
                 id_pedido.to_string().blue(),
-                (id_local + 1) % CANTIDAD_LOCALES as IdLocal
+                self.topologia
+                    .siguiente(origen, id_local, &self.membresia.vivos())
             );
             return Err(ErrorEcommerce::AckTimeout);
         } else {
             println!("Recibi ack de pedido {}", id_pedido.to_string().blue());
+            self.locales_pedidos.lock()?.insert(id_pedido, id_local);
         }
         Ok(())
     }
 
     /// Espera a la finalicacion del pedido, y devuelve el resultado de la espera.
-    /// Devuelve error si se cumple un tiempo limite sin recibir el ack
+    /// Cada vez que se cumple un tiempo limite sin recibir el resultado, mas largo
+    /// si el pedido quedo en espera de stock, le consulta al local en que estado
+    /// esta el pedido: si lo tiene
+    /// en curso sigue esperando, si lo delego le consulta al local que lo recibio,
+    /// y si no lo conoce o no responde, reenvia el pedido a otro local. Tambien
+    /// lo reenvia si sigue en curso tras `MAX_CONSULTAS_EN_CURSO` consultas, o si
+    /// la cadena de delegaciones vuelve a un local ya consultado
     fn esperar_finalizacion(
         &self,
         mensaje: MensajeEcommerce,
+        origen: IdLocal,
         id_local: IdLocal,
     ) -> Result<(), ErrorEcommerce> {
         self.pedidos_pendientes
@@ -215,30 +496,95 @@ impl Handler {
             .lock()?
             .insert(mensaje.id_pedido, mensaje.pedido.clone());
 
-        let (_, wait_result) = self
-            .pedidos_pendientes
-            .1
-            .wait_timeout_while(
-                self.pedidos_pendientes.0.lock()?,
-                Duration::from_secs(3),
-                |pedidos| pedidos.contains_key(&mensaje.id_pedido),
-            )
-            .map_err(Into::<ErrorEcommerce>::into)?;
-
-        if wait_result.timed_out() {
-            let siguiente_local = (id_local + 1) % CANTIDAD_LOCALES as IdLocal;
-            self.enviar_pedido(mensaje, siguiente_local)?;
+        let mut local_consultado = id_local;
+        let mut consultados = HashSet::from([id_local]);
+        let mut consultas_en_curso = 0;
+        loop {
+            let plazo = if self.pedidos_en_espera.lock()?.contains(&mensaje.id_pedido) {
+                PLAZO_CONSULTA_EN_ESPERA
+            } else {
+                Duration::from_secs(3)
+            };
+            let (guard, wait_result) = self
+                .pedidos_pendientes
+                .1
+                .wait_timeout_while(self.pedidos_pendientes.0.lock()?, plazo, |pedidos| {
+                    pedidos.contains_key(&mensaje.id_pedido)
+                })
+                .map_err(Into::<ErrorEcommerce>::into)?;
+
+            if !wait_result.timed_out() {
+                return Ok(());
+            }
+            drop(guard);
+
+            // Un pedido en espera de stock puede seguir reservado sin limite, mientras
+            // el local responda que lo tiene
+            let en_espera = self.pedidos_en_espera.lock()?.contains(&mensaje.id_pedido);
+            match self.consultar_estado(mensaje.id_pedido, local_consultado)? {
+                Some(EstadoConsultado::Reservado) if en_espera => {
+                    println!(
+                        "El pedido {} sigue en espera de stock en la tienda {}",
+                        mensaje.id_pedido.to_string().blue(),
+                        local_consultado
+                    );
+                }
+                Some(EstadoConsultado::Reservado) => {
+                    consultas_en_curso += 1;
+                    if consultas_en_curso >= MAX_CONSULTAS_EN_CURSO {
+                        println!(
+                            "El pedido {} sigue en curso en la tienda {} tras {} consultas, lo doy por perdido",
+                            mensaje.id_pedido.to_string().blue(),
+                            local_consultado,
+                            consultas_en_curso
+                        );
+                        break;
+                    }
+                    println!(
+                        "El pedido {} sigue en curso en la tienda {}",
+                        mensaje.id_pedido.to_string().blue(),
+                        local_consultado
+                    );
+                }
+                Some(EstadoConsultado::Delegado(otro)) => {
+                    if !consultados.insert(otro) {
+                        println!(
+                            "El pedido {} volvio a delegarse a la tienda {}, ya consultada",
+                            mensaje.id_pedido.to_string().blue(),
+                            otro
+                        );
+                        break;
+                    }
+                    println!(
+                        "El pedido {} fue delegado a la tienda {}",
+                        mensaje.id_pedido.to_string().blue(),
+                        otro
+                    );
+                    local_consultado = otro;
+                    consultas_en_curso = 0;
+                }
+                Some(EstadoConsultado::Confirmado) | Some(EstadoConsultado::Cancelado) => {
+                    return Ok(());
+                }
+                Some(EstadoConsultado::Desconocido) | None => break,
+            }
         }
-        Ok(())
+
+        let siguiente_local = self
+            .topologia
+            .siguiente(origen, id_local, &self.membresia.vivos());
+        self.enviar_pedido(mensaje, origen, siguiente_local)
     }
 
     // Envia un pedido a una tienda y espera en el monitor a esperar que le devuelvan el ack
     // correspondiente. Si recibe el ack, espera por el veredicto final sobre el destino del
     // pedido, si no lo recibe envia a la tienda siguiente. Si el veredicto final no llega,
-    // entonces vuelve a enviar el pedido a otra tienda.
+    // entonces vuelve a enviar el pedido a otra tienda. Las tiendas se recorren en el orden
+    // de preferencia de la primera a la que se le envio el pedido.
     fn enviar_pedido(
         &self,
         mensaje: MensajeEcommerce,
+        origen: IdLocal,
         id_local: IdLocal,
     ) -> Result<(), ErrorEcommerce> {
         let dir_tienda_cercana = id_a_dir_local(id_local);
@@ -255,10 +601,13 @@ impl Handler {
             eprintln!("No pudo enviar mensaje a traves del socket");
         }
 
-        if self.esperar_ack(mensaje.clone(), id_local).is_err() {
-            self.enviar_pedido(mensaje, (id_local + 1) % CANTIDAD_LOCALES as IdLocal)?;
+        if self.esperar_ack(mensaje.clone(), origen, id_local).is_err() {
+            let siguiente_local =
+                self.topologia
+                    .siguiente(origen, id_local, &self.membresia.vivos());
+            self.enviar_pedido(mensaje, origen, siguiente_local)?;
             return Ok(());
         }
-        self.esperar_finalizacion(mensaje, id_local)
+        self.esperar_finalizacion(mensaje, origen, id_local)
     }
 }
@@ -3,7 +3,9 @@ use std::path::PathBuf;
 
 use pidgeonhole::ecommerce::handler;
 use pidgeonhole::errores::{self, ErrorDuranteParseo, ErrorEcommerce};
+use pidgeonhole::membresia;
 use pidgeonhole::pedido;
+use pidgeonhole::topologia;
 use rand::seq::IteratorRandom;
 
 fn obtener_archivo_pedidos_rand() -> Result<PathBuf, ErrorDuranteParseo> {
@@ -20,11 +22,14 @@ fn main() -> Result<(), errores::Error> {
 
     let mut pedidos_json = File::open(archivo_json).map_err(Into::<ErrorDuranteParseo>::into)?;
 
-    let pedidos =
-        pedido::from_reader(&mut pedidos_json).map_err(Into::<ErrorDuranteParseo>::into)?;
+    let pedidos = pedido::pedidos_ecommerce_from_reader(&mut pedidos_json)
+        .map_err(Into::<ErrorDuranteParseo>::into)?;
 
-    let (handler, handle) =
-        handler::Handler::new(pedidos.len()).map_err(Into::<ErrorEcommerce>::into)?;
+    let topologia = topologia::obtener_topologia()?;
+    let semillas = membresia::obtener_semillas()?;
+
+    let (handler, handle) = handler::Handler::new(pedidos.len(), topologia, semillas)
+        .map_err(Into::<ErrorEcommerce>::into)?;
 
     handler::Handler::procesar_pedidos(handler, pedidos)?;
 
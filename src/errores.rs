@@ -3,6 +3,7 @@
 
 use actix::MailboxError;
 use rayon::ThreadPoolBuildError;
+use std::fmt;
 use std::io;
 use std::net::AddrParseError;
 use std::sync::{MutexGuard, PoisonError, WaitTimeoutResult};
@@ -56,6 +57,18 @@ pub enum ErrorGuardian {
     NoHayStock,
     NoHaySuficienteStock,
     PedidoInexistente,
+    ParticionNoDisponible,
+    PedidoYaBloqueado,
+    ReduccionInvalida,
+    RegistroNoDisponible,
+    DevolucionInvalida,
+    CarritoInvalido,
+}
+
+impl From<ErrorCarrito> for ErrorGuardian {
+    fn from(_err: ErrorCarrito) -> Self {
+        ErrorGuardian::CarritoInvalido
+    }
 }
 
 /// Enumerativo que define todos los errores que pueden darse
@@ -68,6 +81,7 @@ pub enum ErrorEcommerce {
     PedidoTimeout,
     AckTimeout,
     CantidadCero,
+    SinLocalesActivos,
 }
 
 impl<T> From<PoisonError<MutexGuard<'_, T>>> for ErrorEcommerce {
@@ -101,6 +115,14 @@ pub enum ErrorDuranteParseo {
     FormatoArchivoInvalido,
     NoSePudoObtenerId,
     NoSeHalloArchivoPedidos,
+    RegistroInconsistente,
+    CantidadParticionesInvalida,
+    CantidadCajasInvalida,
+    CapacidadRepartoInvalida,
+    VentanaRetiroInvalida,
+    ParticionesGuardadasDistintas,
+    TopologiaInvalida,
+    MembresiaInvalida,
 }
 
 impl From<io::Error> for ErrorDuranteParseo {
@@ -155,6 +177,15 @@ impl From<ErrorGuardian> for ErrorServidor {
     }
 }
 
+/// Enumerativo que define todos los errores que pueden darse
+/// al interpretar un comando de la consola del local
+#[derive(Debug, PartialEq)]
+pub enum ErrorConsola {
+    ComandoVacio,
+    ComandoDesconocido,
+    ArgumentosInvalidos,
+}
+
 /// Enumerativo que define todos los errores que pueden darse
 /// desde el mensajero
 #[derive(Debug)]
@@ -168,3 +199,22 @@ impl From<io::Error> for ErrorMensajero {
         ErrorMensajero::DestinoInaccesible
     }
 }
+
+/// Enumerativo que define todos los errores que pueden darse
+/// al armar un carrito
+#[derive(Debug, PartialEq)]
+pub enum ErrorCarrito {
+    DemasiadasLineas,
+}
+
+impl fmt::Display for ErrorCarrito {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCarrito::DemasiadasLineas => write!(
+                f,
+                "un carrito no puede tener mas de {} lineas",
+                crate::pedido::MAX_LINEAS_CARRITO
+            ),
+        }
+    }
+}
@@ -1,7 +1,9 @@
-//! Crea 10 archivos de stock y 10 archivos de pedidos aleatorios, en la carpeta `configs`.
+//! Crea 10 archivos de stock, 20 archivos de pedidos y 10 cronogramas de reposicion
+//! aleatorios, en la carpeta `configs`.
 
 use pidgeonhole::generators::{
-    pedidos_gen::generar_arch_pedidos_aleatorio, stock_gen::generar_arch_stock_aleatorio,
+    pedidos_gen::generar_arch_pedidos_aleatorio, reposicion_gen::generar_arch_reposicion_aleatorio,
+    stock_gen::generar_arch_stock_aleatorio,
 };
 fn main() {
     for i in 0..10 {
@@ -15,4 +17,10 @@ fn main() {
         generar_arch_pedidos_aleatorio(nombre.as_str(), (1, 20), (0, 200), 100)
             .unwrap_or_else(|_| println!("Error en creacion"));
     }
+
+    for i in 0..10 {
+        let nombre = format!("configs/reposicion{}.json", i);
+        generar_arch_reposicion_aleatorio(nombre.as_str(), (0, 60000), (1, 50), (0, 200), 20)
+            .unwrap_or_else(|_| println!("Error en creacion"));
+    }
 }
@@ -1,4 +1,5 @@
 //! Este modulo define los generadores aleatorios de archivos de pedidos y de stock
 
 pub mod pedidos_gen;
+pub mod reposicion_gen;
 pub mod stock_gen;
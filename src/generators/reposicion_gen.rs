@@ -0,0 +1,77 @@
+//! Este modulo permite generar cronogramas de reposicion aleatorios, donde
+//! una gran parte de las cosas son parametrizables. Los archivos generados
+//! son en formato json, generando una lista de reposiciones con el instante
+//! en que llegan, el id del producto y la cantidad que se repone.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+};
+
+use rand::Rng;
+
+use crate::local::reposicion::Reposicion;
+
+/// Genera un cronograma aleatorio de reposiciones, con los parametros dados,
+/// ordenado por el instante en que ocurre cada una
+fn generar_reposiciones_aleatorias(
+    rango_instante_ms: (u64, u64),
+    rango_cant: (u16, u16),
+    rango_ids: (u16, u16),
+    max_tam: u16,
+) -> Vec<Reposicion> {
+    let mut rng = rand::thread_rng();
+    let mut reposiciones: Vec<Reposicion> = (0..max_tam)
+        .map(|_| Reposicion {
+            instante_ms: rng.gen_range(rango_instante_ms.0..=rango_instante_ms.1),
+            id_producto: rng.gen_range(rango_ids.0..=rango_ids.1),
+            cantidad: rng.gen_range(rango_cant.0..=rango_cant.1),
+            desde_local: None,
+            vencimiento: None,
+        })
+        .collect();
+    reposiciones.sort_by_key(|r| r.instante_ms);
+    reposiciones
+}
+
+/// A partir de un nombre de archivo, un rango de instantes en milisegundos, un
+/// rango de la cantidad repuesta, un rango de los ids y una cantidad de
+/// reposiciones genera un archivo de cronograma de reposicion
+pub fn generar_arch_reposicion_aleatorio(
+    nombre_arch: &str,
+    rango_instante_ms: (u64, u64),
+    rango_cant: (u16, u16),
+    rango_ids: (u16, u16),
+    max_tam: u16,
+) -> io::Result<()> {
+    let mut file = File::create(nombre_arch)?;
+    let reposiciones =
+        generar_reposiciones_aleatorias(rango_instante_ms, rango_cant, rango_ids, max_tam);
+    let json_data = serde_json::to_string_pretty(&reposiciones)?;
+    file.write_all(json_data.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::local::reposicion;
+
+    use super::*;
+    #[test]
+    fn generar_archivos_randon() {
+        let res = generar_arch_reposicion_aleatorio(
+            "configs/reposicion1.json",
+            (0, 60000),
+            (1, 50),
+            (0, 1000),
+            20,
+        );
+        let mut reposicion_json = File::open("configs/reposicion1.json").unwrap();
+        let reposiciones = reposicion::from_reader(&mut reposicion_json);
+
+        assert!(res.is_ok());
+        assert!(reposiciones.is_ok());
+        assert_eq!(reposiciones.unwrap().len(), 20);
+    }
+}
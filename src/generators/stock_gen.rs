@@ -63,6 +63,6 @@ mod test {
 
         assert!(res.is_ok());
         assert!(stocks.is_ok());
-        assert!(stocks.unwrap().len() <= 100);
+        assert!(stocks.unwrap().productos().count() <= 100);
     }
 }
@@ -6,12 +6,27 @@ pub mod ecommerce;
 pub mod errores;
 pub mod generators;
 pub mod local;
+pub mod membresia;
 pub mod mensajes;
 pub mod pedido;
+pub mod topologia;
 
 /// Convierte un identificador de un local, a su direccion IP
 pub fn id_a_dir_local(id: IdLocal) -> String {
-    format!("127.0.0.1:{}", 9000 + id)
+    format!("127.0.0.1:{}", id_a_puerto_local(id))
+}
+
+/// Convierte un identificador de un local, al puerto en el que escucha
+pub fn id_a_puerto_local(id: IdLocal) -> Puerto {
+    9000 + id
+}
+
+/// Convierte un identificador de un local, al puerto con el que los demas locales
+/// identifican las reservas de sus transferencias. Nadie escucha en este puerto,
+/// pero asi esas reservas no se confunden con las de las busquedas del local,
+/// que usan su puerto
+pub fn id_a_puerto_transferencias(id: IdLocal) -> Puerto {
+    11000 + id
 }
 
 /// Convierte un identificador de un local, a la direccion IP de su medico
@@ -29,13 +44,5 @@ pub fn puerto_a_ip(puerto: Puerto) -> String {
     format!("127.0.0.1:{puerto}")
 }
 
-/// Obtiene el siguiente local en la lista, dado un local
-pub fn siguiente_id_local(id: IdLocal) -> IdLocal {
-    (id + 1) % u16::from(CANTIDAD_LOCALES)
-}
-
-/// Cantidad de locales a ejecutar
-pub const CANTIDAD_LOCALES: u8 = 4;
-
 /// Longitud maxima de los mensajes enviados entre los procesos
 pub const MAX_MENSAJE: u8 = 100;
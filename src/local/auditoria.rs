@@ -0,0 +1,272 @@
+//! Este modulo define el libro de movimientos del guardian, que permite
+//! reconstruir por que cambio el stock de un producto. Cada operacion
+//! exitosa del guardian deja un movimiento por producto afectado, con el
+//! canal que la origino y el pedido al que corresponde. El libro recuerda
+//! solo los movimientos mas recientes, hasta un maximo.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::aliases::{CantidadProducto, IdPedido, IdProducto, Puerto};
+
+/// Tipo de operacion que realizo el guardian sobre el stock
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TipoOperacion {
+    Descontar,
+    Bloquear,
+    Confirmar,
+    Cancelar,
+    Reducir,
+    Expirar,
+    Reponer,
+    Vencer,
+    Devolver,
+}
+
+/// Canal por el que llego la operacion al guardian. `Venta` es una venta
+/// presencial que no corresponde al pedido de ningun empleado
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Canal {
+    Empleado,
+    Ecommerce,
+    Venta,
+    Reposicion,
+}
+
+/// Identificador del pedido que origino un movimiento. Los pedidos de los
+/// empleados se identifican por su numero, y los del ecommerce por su id
+/// junto con el puerto del ecommerce que lo realizo
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ClavePedido {
+    Empleado(usize),
+    Ecommerce { id_pedido: IdPedido, puerto: Puerto },
+}
+
+impl ClavePedido {
+    /// Devuelve el canal por el que llego el pedido
+    pub fn canal(&self) -> Canal {
+        match self {
+            ClavePedido::Empleado(_) => Canal::Empleado,
+            ClavePedido::Ecommerce { .. } => Canal::Ecommerce,
+        }
+    }
+}
+
+/// Registro de un cambio en el stock de un producto. El instante se mide en
+/// microsegundos desde la creacion del libro, con un reloj monotonico, y la
+/// secuencia ordena los movimientos ocurridos en un mismo instante
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Movimiento {
+    pub secuencia: u64,
+    pub instante_us: u64,
+    pub operacion: TipoOperacion,
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+    pub canal: Canal,
+    pub pedido: Option<ClavePedido>,
+}
+
+/// Criterio para consultar movimientos. Los campos en `None` no filtran
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FiltroMovimientos {
+    pub id_producto: Option<IdProducto>,
+    pub pedido: Option<ClavePedido>,
+}
+
+impl FiltroMovimientos {
+    /// Devuelve si el movimiento cumple con el filtro
+    pub fn acepta(&self, movimiento: &Movimiento) -> bool {
+        self.id_producto
+            .is_none_or(|id| movimiento.id_producto == id)
+            && self
+                .pedido
+                .is_none_or(|pedido| movimiento.pedido == Some(pedido))
+    }
+}
+
+/// Cantidad de movimientos que recuerda el libro por defecto. Al superarla se
+/// olvidan los mas antiguos; solo quedan exportados si se volco el libro antes
+pub const MAX_MOVIMIENTOS: usize = 100_000;
+
+/// Libro de movimientos del guardian
+pub struct Auditoria {
+    inicio: Instant,
+    movimientos: VecDeque<Movimiento>,
+    siguiente_secuencia: u64,
+    max_movimientos: usize,
+}
+
+impl Default for Auditoria {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Auditoria {
+    /// Crea un libro vacio, cuyo reloj empieza a correr en este momento
+    pub fn new() -> Self {
+        Self::desde(Instant::now())
+    }
+
+    /// Crea un libro vacio, cuyo reloj mide el tiempo desde el inicio dado
+    pub fn desde(inicio: Instant) -> Self {
+        Self {
+            inicio,
+            movimientos: VecDeque::new(),
+            siguiente_secuencia: 0,
+            max_movimientos: MAX_MOVIMIENTOS,
+        }
+    }
+
+    /// Indica cuantos movimientos recuerda el libro. Si no se indica, es
+    /// `MAX_MOVIMIENTOS`
+    pub fn con_max_movimientos(mut self, max_movimientos: usize) -> Self {
+        self.max_movimientos = max_movimientos;
+        self
+    }
+
+    /// Agrega un movimiento por cada producto afectado por una operacion,
+    /// olvidando los mas antiguos si se supera el maximo. Las operaciones sin
+    /// pedido asociado se atribuyen a la venta presencial si descuentan stock,
+    /// y a la reposicion en otro caso.
+    pub fn registrar(
+        &mut self,
+        operacion: TipoOperacion,
+        pedido: Option<ClavePedido>,
+        lineas: impl IntoIterator<Item = (IdProducto, CantidadProducto)>,
+    ) {
+        let instante_us = self.inicio.elapsed().as_micros() as u64;
+        let canal = match (pedido, operacion) {
+            (Some(pedido), _) => pedido.canal(),
+            (None, TipoOperacion::Descontar) => Canal::Venta,
+            (None, _) => Canal::Reposicion,
+        };
+        let mut lineas: Vec<_> = lineas.into_iter().collect();
+        lineas.sort();
+        for (id_producto, cantidad) in lineas {
+            self.movimientos.push_back(Movimiento {
+                secuencia: self.siguiente_secuencia,
+                instante_us,
+                operacion,
+                id_producto,
+                cantidad,
+                canal,
+                pedido,
+            });
+            self.siguiente_secuencia += 1;
+        }
+        while self.movimientos.len() > self.max_movimientos {
+            self.movimientos.pop_front();
+        }
+    }
+
+    /// Devuelve, en orden, los movimientos que cumplen con el filtro
+    pub fn consultar(&self, filtro: &FiltroMovimientos) -> Vec<Movimiento> {
+        self.movimientos
+            .iter()
+            .filter(|movimiento| filtro.acepta(movimiento))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Escribe los movimientos en formato JSONL, uno por linea
+pub fn exportar(movimientos: &[Movimiento], writer: &mut dyn Write) -> io::Result<()> {
+    for movimiento in movimientos {
+        serde_json::to_writer(&mut *writer, movimiento)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEDIDO_ECOMMERCE: ClavePedido = ClavePedido::Ecommerce {
+        id_pedido: 1,
+        puerto: 3000,
+    };
+
+    fn auditoria_de_prueba() -> Auditoria {
+        let mut auditoria = Auditoria::new();
+        auditoria.registrar(
+            TipoOperacion::Bloquear,
+            Some(PEDIDO_ECOMMERCE),
+            [(2, 1), (1, 3)],
+        );
+        auditoria.registrar(
+            TipoOperacion::Descontar,
+            Some(ClavePedido::Empleado(4)),
+            [(1, 1)],
+        );
+        auditoria.registrar(TipoOperacion::Reponer, None, [(2, 10)]);
+        auditoria.registrar(TipoOperacion::Descontar, None, [(2, 1)]);
+        auditoria
+    }
+
+    #[test]
+    fn registrar_agrega_un_movimiento_por_producto_con_su_canal() {
+        let movimientos = auditoria_de_prueba().consultar(&FiltroMovimientos::default());
+
+        assert_eq!(movimientos.len(), 5);
+        assert_eq!(movimientos[0].id_producto, 1);
+        assert_eq!(movimientos[0].canal, Canal::Ecommerce);
+        assert_eq!(movimientos[2].canal, Canal::Empleado);
+        assert_eq!(movimientos[3].canal, Canal::Reposicion);
+        assert_eq!(movimientos[4].canal, Canal::Venta);
+        assert!(movimientos
+            .windows(2)
+            .all(|par| par[0].secuencia < par[1].secuencia
+                && par[0].instante_us <= par[1].instante_us));
+    }
+
+    #[test]
+    fn consultar_filtra_por_producto_y_por_pedido() {
+        let auditoria = auditoria_de_prueba();
+
+        let del_producto = auditoria.consultar(&FiltroMovimientos {
+            id_producto: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(del_producto.len(), 2);
+
+        let del_pedido = auditoria.consultar(&FiltroMovimientos {
+            pedido: Some(PEDIDO_ECOMMERCE),
+            ..Default::default()
+        });
+        assert_eq!(del_pedido.len(), 2);
+        assert!(del_pedido
+            .iter()
+            .all(|m| m.operacion == TipoOperacion::Bloquear));
+    }
+
+    #[test]
+    fn el_libro_olvida_los_movimientos_mas_antiguos_al_superar_el_maximo() {
+        let mut auditoria = Auditoria::new().con_max_movimientos(3);
+        auditoria.registrar(TipoOperacion::Reponer, None, [(1, 5), (2, 5)]);
+        auditoria.registrar(TipoOperacion::Descontar, None, [(1, 1), (2, 1)]);
+
+        let movimientos = auditoria.consultar(&FiltroMovimientos::default());
+        let secuencias: Vec<_> = movimientos.iter().map(|m| m.secuencia).collect();
+        assert_eq!(secuencias, vec![1, 2, 3]);
+        assert_eq!(movimientos[0].operacion, TipoOperacion::Reponer);
+        assert_eq!(movimientos[0].id_producto, 2);
+    }
+
+    #[test]
+    fn exportar_escribe_un_movimiento_por_linea() {
+        let movimientos = auditoria_de_prueba().consultar(&FiltroMovimientos::default());
+        let mut salida = Vec::new();
+        exportar(&movimientos, &mut salida).unwrap();
+
+        let leidos: Vec<Movimiento> = String::from_utf8(salida)
+            .unwrap()
+            .lines()
+            .map(|linea| serde_json::from_str(linea).unwrap())
+            .collect();
+        assert_eq!(leidos, movimientos);
+    }
+}
@@ -0,0 +1,176 @@
+//! Este modulo permite que un cliente del local consiga en otro local un
+//! producto que aqui se agoto. El pedido recorre el anillo de locales igual
+//! que un pedido de ecommerce delegado, pero el destinatario del resultado es
+//! el propio local, que luego le informa al cliente donde puede retirarlo.
+
+use actix::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use super::mensajero::Mensajero;
+use super::servidor::{delegar_pedido, Delegacion};
+use crate::aliases::{IdLocal, IdPedido};
+use crate::id_a_puerto_local;
+use crate::mensajes::{MensajeDelegado, MensajeEcommerce, MensajesServidor};
+use crate::pedido::{Carrito, Pedido};
+
+/// Tiempo maximo que se espera el resultado de una busqueda. Alcanza para
+/// recorrer todo el anillo aunque varios locales no respondan.
+const TIEMPO_MAXIMO_BUSQUEDA: Duration = Duration::from_secs(15);
+
+/// Resultado de buscar un producto en los demas locales
+#[derive(MessageResponse, Debug, Clone, Copy, PartialEq)]
+pub enum ResultadoBusqueda {
+    /// El producto quedo reservado y puede retirarse en el local dado
+    Retirar(IdLocal),
+    /// El local dado tenia el producto, pero no pudo reservarlo
+    Cancelado(IdLocal),
+    /// Ningun local tiene el producto
+    NoHayStock,
+    /// No llego ningun resultado a tiempo
+    SinRespuesta,
+}
+
+impl ResultadoBusqueda {
+    /// Interpreta el resultado que le envio un local al terminar de resolver el pedido
+    fn desde(resultado: &MensajesServidor, origen: IdLocal) -> Self {
+        match resultado {
+            MensajesServidor::PedidoExitoso(_) => ResultadoBusqueda::Retirar(origen),
+            MensajesServidor::PedidoCancelado(_) => ResultadoBusqueda::Cancelado(origen),
+            MensajesServidor::NoHayStock(_) | MensajesServidor::EnEspera(_) => {
+                ResultadoBusqueda::NoHayStock
+            }
+        }
+    }
+}
+
+/// Estructura que maneja las busquedas de productos en otros locales. Recuerda
+/// las busquedas en curso para pasarle a cada una su resultado cuando llega
+pub struct Busquedas {
+    id_local: IdLocal,
+    mensajero: Addr<Mensajero>,
+    delegacion: Delegacion,
+    en_curso: HashMap<IdPedido, oneshot::Sender<ResultadoBusqueda>>,
+}
+
+impl Busquedas {
+    /// Crea el manejador de busquedas del local dado. Delega los pedidos igual
+    /// que el servidor, con quien comparte la delegacion ya que es este quien
+    /// recibe los acks y las respuestas de las consultas de stock
+    pub fn new(id_local: IdLocal, mensajero: Addr<Mensajero>, delegacion: Delegacion) -> Self {
+        Self {
+            id_local,
+            mensajero,
+            delegacion,
+            en_curso: HashMap::new(),
+        }
+    }
+}
+
+impl Actor for Busquedas {
+    type Context = Context<Self>;
+}
+
+/// Mensaje que pide buscar un producto en los demas locales, para un cliente
+/// del local que no pudo comprarlo aqui
+#[derive(Message, Debug, PartialEq)]
+#[rtype(result = "ResultadoBusqueda")]
+pub struct BuscarEnOtrosLocales {
+    pedido: Pedido,
+    id: IdPedido,
+}
+
+impl BuscarEnOtrosLocales {
+    /// Crea una nueva busqueda para el pedido dado
+    pub fn new(pedido: Pedido, id: IdPedido) -> Self {
+        Self { pedido, id }
+    }
+}
+
+impl Handler<BuscarEnOtrosLocales> for Busquedas {
+    type Result = ResponseActFuture<Self, ResultadoBusqueda>;
+
+    fn handle(&mut self, msg: BuscarEnOtrosLocales, _ctx: &mut Context<Self>) -> Self::Result {
+        let (tx, rx) = oneshot::channel();
+        self.en_curso.insert(msg.id, tx);
+
+        let puerto_local = id_a_puerto_local(self.id_local);
+        let mensaje = MensajeDelegado::new(
+            MensajeEcommerce::new(msg.id, Carrito::from(msg.pedido)),
+            puerto_local,
+            HashSet::new(),
+        );
+        let mensajero = self.mensajero.clone();
+        let delegacion = self.delegacion.clone();
+        let id_local = self.id_local;
+        let id = msg.id;
+        Box::pin(
+            async move {
+                if delegar_pedido(&delegacion, &mensajero, mensaje, id_local)
+                    .await
+                    .is_some()
+                {
+                    // No hay otro local al cual delegar
+                    return Some(ResultadoBusqueda::NoHayStock);
+                }
+                timeout(TIEMPO_MAXIMO_BUSQUEDA, rx).await.ok()?.ok()
+            }
+            .into_actor(self)
+            .map(move |resultado, busquedas, _ctx| {
+                busquedas.en_curso.remove(&id);
+                resultado.unwrap_or(ResultadoBusqueda::SinRespuesta)
+            }),
+        )
+    }
+}
+
+/// Mensaje con el resultado de un pedido que el local delego para uno de sus
+/// clientes, tal como lo envio el local que lo resolvio
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResultadoRecibido {
+    pub resultado: MensajesServidor,
+    pub origen: IdLocal,
+}
+
+impl Handler<ResultadoRecibido> for Busquedas {
+    type Result = ();
+
+    fn handle(&mut self, msg: ResultadoRecibido, _ctx: &mut Context<Self>) -> Self::Result {
+        let id = match msg.resultado {
+            MensajesServidor::PedidoExitoso(id)
+            | MensajesServidor::PedidoCancelado(id)
+            | MensajesServidor::NoHayStock(id)
+            | MensajesServidor::EnEspera(id) => id,
+        };
+        match self.en_curso.remove(&id) {
+            Some(aviso) => {
+                let _ = aviso.send(ResultadoBusqueda::desde(&msg.resultado, msg.origen));
+            }
+            None => eprintln!("Llego el resultado de una busqueda desconocida: {}", id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn el_resultado_indica_donde_retirar_el_producto() {
+        assert_eq!(
+            ResultadoBusqueda::desde(&MensajesServidor::PedidoExitoso(1), 2),
+            ResultadoBusqueda::Retirar(2)
+        );
+        assert_eq!(
+            ResultadoBusqueda::desde(&MensajesServidor::PedidoCancelado(1), 3),
+            ResultadoBusqueda::Cancelado(3)
+        );
+        assert_eq!(
+            ResultadoBusqueda::desde(&MensajesServidor::NoHayStock(1), 0),
+            ResultadoBusqueda::NoHayStock
+        );
+    }
+}
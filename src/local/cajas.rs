@@ -0,0 +1,229 @@
+//! Este modulo define las cajas del local: un conjunto de empleados que
+//! atienden a los clientes en paralelo. Cada pedido que llega se le asigna a
+//! una caja, ya sea por turnos o a la que tenga menos clientes pendientes.
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+use super::busquedas::BuscarEnOtrosLocales;
+use super::empleado::{Empleado, TomarPedido};
+use super::llegadas::ProcesoLlegadas;
+use super::mensajes_actores::{Descontar, PedidoAtendido};
+use super::reporte::VentaLocal;
+use crate::aliases::IdLocal;
+
+/// Criterio con el que se elige la caja que atiende a cada cliente
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Asignacion {
+    /// Las cajas atienden por turnos, una despues de la otra
+    #[default]
+    PorTurnos,
+    /// Atiende la caja con menos clientes pendientes
+    MenosOcupada,
+}
+
+/// Configuracion de la atencion en el local, leida de "configs/cajas{ID}.json".
+/// Sin archivo, una unica caja recibe un cliente cada 500 ms. La semilla de
+/// las llegadas es por defecto el id del local. Salvo que se indique lo
+/// contrario, los clientes piden buscar en otros locales lo que aqui se agoto.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfiguracionCajas {
+    #[serde(default = "una_caja")]
+    pub cajas: usize,
+    #[serde(default)]
+    pub asignacion: Asignacion,
+    #[serde(default)]
+    pub llegadas: ProcesoLlegadas,
+    #[serde(default)]
+    pub semilla: Option<u64>,
+    #[serde(default = "si")]
+    pub buscar_en_otros_locales: bool,
+}
+
+fn una_caja() -> usize {
+    1
+}
+
+fn si() -> bool {
+    true
+}
+
+impl Default for ConfiguracionCajas {
+    fn default() -> Self {
+        Self {
+            cajas: una_caja(),
+            asignacion: Asignacion::default(),
+            llegadas: ProcesoLlegadas::default(),
+            semilla: None,
+            buscar_en_otros_locales: si(),
+        }
+    }
+}
+
+impl ConfiguracionCajas {
+    /// Devuelve la semilla de las llegadas para el local dado
+    pub fn semilla_de(&self, id_local: IdLocal) -> u64 {
+        self.semilla.unwrap_or(id_local as u64)
+    }
+}
+
+/// A partir de un reader en formato json, crea la configuracion de las cajas
+pub fn from_reader(reader: &mut dyn Read) -> Result<ConfiguracionCajas, serde_json::Error> {
+    serde_json::from_reader(reader)
+}
+
+/// Elige la caja que atiende al proximo cliente, dados los clientes pendientes
+/// de cada caja y el turno actual. Ante un empate gana la de menor numero.
+pub fn elegir_caja(asignacion: Asignacion, pendientes: &[usize], turno: usize) -> usize {
+    match asignacion {
+        Asignacion::PorTurnos => turno % pendientes.len(),
+        Asignacion::MenosOcupada => pendientes
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, pendientes)| **pendientes)
+            .map_or(0, |(caja, _)| caja),
+    }
+}
+
+/// Estructura de las cajas del local. Cuenta con los empleados de cada caja,
+/// y con cuantos clientes tiene pendientes cada uno
+pub struct Cajas {
+    guardian: Recipient<Descontar>,
+    busquedas: Option<Recipient<BuscarEnOtrosLocales>>,
+    ventas: Option<Recipient<VentaLocal>>,
+    asignacion: Asignacion,
+    empleados: Vec<Addr<Empleado>>,
+    pendientes: Vec<usize>,
+    turno: usize,
+}
+
+impl Cajas {
+    /// Crea las cajas del local, con la cantidad de empleados dada, todos
+    /// descontando del mismo guardian
+    /// # Panics
+    /// Si la cantidad de cajas es cero
+    pub fn new(guardian: Recipient<Descontar>, cantidad: usize, asignacion: Asignacion) -> Self {
+        assert!(cantidad > 0, "El local necesita al menos una caja");
+        Self {
+            guardian,
+            busquedas: None,
+            ventas: None,
+            asignacion,
+            empleados: Vec::with_capacity(cantidad),
+            pendientes: vec![0; cantidad],
+            turno: 0,
+        }
+    }
+
+    /// Indica a quien le piden los empleados que busque en otros locales los
+    /// productos agotados
+    pub fn con_busquedas(mut self, busquedas: Recipient<BuscarEnOtrosLocales>) -> Self {
+        self.busquedas = Some(busquedas);
+        self
+    }
+
+    /// Indica a quien le avisan los empleados el resultado de cada venta
+    pub fn con_ventas(mut self, ventas: Recipient<VentaLocal>) -> Self {
+        self.ventas = Some(ventas);
+        self
+    }
+}
+
+impl Actor for Cajas {
+    type Context = Context<Self>;
+
+    /// Inicia el empleado de cada caja, que avisa cuando termina de atender
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let aviso: Recipient<PedidoAtendido> = ctx.address().recipient();
+        for caja in 0..self.pendientes.len() {
+            let mut empleado =
+                Empleado::new(self.guardian.clone()).con_aviso_atencion(caja, aviso.clone());
+            if let Some(busquedas) = &self.busquedas {
+                empleado = empleado.con_busquedas(busquedas.clone());
+            }
+            if let Some(ventas) = &self.ventas {
+                empleado = empleado.con_ventas(ventas.clone());
+            }
+            self.empleados.push(empleado.start());
+        }
+    }
+}
+
+impl Handler<TomarPedido> for Cajas {
+    type Result = ();
+
+    fn handle(&mut self, msg: TomarPedido, _ctx: &mut Context<Self>) -> Self::Result {
+        let caja = elegir_caja(self.asignacion, &self.pendientes, self.turno);
+        self.turno += 1;
+        self.pendientes[caja] += 1;
+        println!(
+            "El cliente {} es atendido en la caja {}",
+            msg.id_pedido, caja
+        );
+        self.empleados[caja].do_send(msg);
+    }
+}
+
+impl Handler<PedidoAtendido> for Cajas {
+    type Result = ();
+
+    fn handle(&mut self, msg: PedidoAtendido, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(pendientes) = self.pendientes.get_mut(msg.caja) {
+            *pendientes = pendientes.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pedido::Pedido;
+    use actix::actors::mocker::Mocker;
+
+    #[test]
+    fn elegir_caja_por_turnos_o_la_menos_ocupada() {
+        let pendientes = [2, 0, 1];
+        let turnos: Vec<usize> = (0..4)
+            .map(|turno| elegir_caja(Asignacion::PorTurnos, &pendientes, turno))
+            .collect();
+        assert_eq!(turnos, vec![0, 1, 2, 0]);
+        assert_eq!(elegir_caja(Asignacion::MenosOcupada, &pendientes, 0), 1);
+        assert_eq!(elegir_caja(Asignacion::MenosOcupada, &[1, 1], 1), 0);
+    }
+
+    #[actix_rt::test]
+    async fn las_cajas_reparten_los_pedidos_entre_sus_empleados() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let guardian = Mocker::<Descontar>::mock(Box::new(move |msg, _ctx| {
+            let descontar = msg.downcast_ref::<Descontar>().unwrap();
+            tx.send((descontar.id, descontar.sender.clone())).unwrap();
+            Box::new(Some(()))
+        }))
+        .start()
+        .recipient();
+
+        let cajas = Cajas::new(guardian, 2, Asignacion::PorTurnos).start();
+        for id in 0..4 {
+            cajas.do_send(TomarPedido::new(Pedido::new(1, 1), id));
+        }
+
+        let mut recibidos = Vec::new();
+        for _ in 0..4 {
+            recibidos.push(rx.recv().await.unwrap());
+        }
+        recibidos.sort_by_key(|(id, _)| *id);
+        assert_eq!(recibidos[0].1, recibidos[2].1);
+        assert_eq!(recibidos[1].1, recibidos[3].1);
+        assert_ne!(recibidos[0].1, recibidos[1].1);
+    }
+
+    #[test]
+    fn la_configuracion_por_defecto_es_una_caja() {
+        let config: ConfiguracionCajas = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.cajas, 1);
+        assert_eq!(config.asignacion, Asignacion::PorTurnos);
+        assert_eq!(config.semilla_de(3), 3);
+    }
+}
@@ -0,0 +1,314 @@
+//! Este modulo define la consola de punto de venta del local, con la que un
+//! operador carga a mano ventas, devoluciones y consultas de stock mientras el
+//! servidor sigue atendiendo a los ecommerce. Las ventas las atiende un
+//! empleado, como las de las cajas, y las consultas van directo al guardian.
+//! Las devoluciones tambien, indicando la venta de la consola a la que
+//! corresponden, y el guardian solo las acepta si esa venta se concreto y le
+//! quedan unidades por devolver.
+
+use actix::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::thread;
+use tokio::sync::mpsc;
+
+use super::empleado::TomarPedido;
+use super::guardian::{Devolver, ObtenerInventario, ObtenerStock};
+use super::particiones::GuardianParticionado;
+use crate::aliases::{CantidadProducto, IdProducto};
+use crate::errores::{ErrorConsola, ErrorGuardian};
+use crate::pedido::Pedido;
+
+const AYUDA: &str = "Comandos disponibles:
+  vender <producto> <cantidad>    vende unidades de un producto
+  devolver <venta> <cantidad>     devuelve unidades de una venta de la consola
+  stock [producto]                muestra el stock de un producto, o de todos
+  ayuda                           muestra este mensaje
+  salir                           cierra la consola";
+
+/// Comando ingresado por el operador
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comando {
+    Vender {
+        id_producto: IdProducto,
+        cantidad: u8,
+    },
+    Devolver {
+        venta: usize,
+        cantidad: CantidadProducto,
+    },
+    Stock(Option<IdProducto>),
+    Ayuda,
+    Salir,
+}
+
+impl Comando {
+    /// Interpreta una linea ingresada por el operador
+    /// # Errors:
+    /// Si el comando no existe, o sus argumentos no son validos
+    pub fn parsear(linea: &str) -> Result<Self, ErrorConsola> {
+        let mut palabras = linea.split_whitespace();
+        let Some(nombre) = palabras.next() else {
+            return Err(ErrorConsola::ComandoVacio);
+        };
+        let argumentos: Vec<&str> = palabras.collect();
+        let comando = match (nombre, argumentos.as_slice()) {
+            ("vender", [id, cantidad]) => Comando::Vender {
+                id_producto: parsear_numero(id)?,
+                cantidad: parsear_numero(cantidad)?,
+            },
+            ("devolver", [venta, cantidad]) => Comando::Devolver {
+                venta: parsear_numero(venta)?,
+                cantidad: parsear_numero(cantidad)?,
+            },
+            ("stock", []) => Comando::Stock(None),
+            ("stock", [id]) => Comando::Stock(Some(parsear_numero(id)?)),
+            ("ayuda", []) => Comando::Ayuda,
+            ("salir", []) => Comando::Salir,
+            ("vender" | "devolver" | "stock" | "ayuda" | "salir", _) => {
+                return Err(ErrorConsola::ArgumentosInvalidos)
+            }
+            _ => return Err(ErrorConsola::ComandoDesconocido),
+        };
+        Ok(comando)
+    }
+}
+
+/// Lee la entrada estandar linea por linea desde un hilo aparte, ya que la
+/// lectura bloquea y no se puede cancelar, y devuelve el canal por el que
+/// llegan las lineas leidas
+pub fn leer_entrada_estandar() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        for linea in io::stdin().lock().lines() {
+            let Ok(linea) = linea else {
+                break;
+            };
+            if tx.send(linea).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn parsear_numero<T: std::str::FromStr>(palabra: &str) -> Result<T, ErrorConsola> {
+    palabra
+        .parse()
+        .map_err(|_| ErrorConsola::ArgumentosInvalidos)
+}
+
+/// Consola de punto de venta. Las ventas se numeran a partir del id dado,
+/// para no confundirlas con las de los demas clientes del local, y se recuerda
+/// que producto se vendio en cada una para poder devolverlo
+pub struct Consola {
+    cajas: Recipient<TomarPedido>,
+    guardian: Addr<GuardianParticionado>,
+    proxima_venta: usize,
+    ventas: HashMap<usize, IdProducto>,
+}
+
+impl Consola {
+    /// Crea una consola que le pasa las ventas a las cajas, y las devoluciones
+    /// y consultas de stock al guardian
+    pub fn new(
+        cajas: Recipient<TomarPedido>,
+        guardian: Addr<GuardianParticionado>,
+        primera_venta: usize,
+    ) -> Self {
+        Self {
+            cajas,
+            guardian,
+            proxima_venta: primera_venta,
+            ventas: HashMap::new(),
+        }
+    }
+
+    /// Recibe lineas hasta que el operador sale o se termina la entrada, y
+    /// ejecuta el comando de cada una
+    pub async fn atender(mut self, mut lineas: mpsc::UnboundedReceiver<String>) {
+        println!("{}", AYUDA);
+        while let Some(linea) = lineas.recv().await {
+            match Comando::parsear(&linea) {
+                Ok(Comando::Salir) => break,
+                Ok(comando) => self.ejecutar(comando).await,
+                Err(ErrorConsola::ComandoVacio) => {}
+                Err(e) => println!("Comando invalido ({:?}), ingrese 'ayuda'", e),
+            }
+        }
+        println!("Cerrando la consola del local");
+    }
+
+    /// Ejecuta un comando, mostrando su resultado
+    pub async fn ejecutar(&mut self, comando: Comando) {
+        match comando {
+            Comando::Vender {
+                id_producto,
+                cantidad,
+            } => {
+                let id = self.proxima_venta;
+                self.proxima_venta += 1;
+                self.ventas.insert(id, id_producto);
+                println!(
+                    "Venta {}: {} unidades del producto {}",
+                    id, cantidad, id_producto
+                );
+                if self
+                    .cajas
+                    .send(TomarPedido::new(Pedido::new(id_producto, cantidad), id))
+                    .await
+                    .is_err()
+                {
+                    println!("No se pudo registrar la venta, las cajas no responden");
+                }
+            }
+            Comando::Devolver { venta, cantidad } => {
+                let Some(&id_producto) = self.ventas.get(&venta) else {
+                    println!("La venta {} no se hizo desde esta consola", venta);
+                    return;
+                };
+                match self
+                    .guardian
+                    .send(Devolver::new(venta, id_producto, cantidad))
+                    .await
+                {
+                    Ok(Ok(())) => println!(
+                        "Se devolvieron {} unidades del producto {} de la venta {}",
+                        cantidad, id_producto, venta
+                    ),
+                    Ok(Err(ErrorGuardian::DevolucionInvalida)) => println!(
+                        "La venta {} no tiene {} unidades por devolver",
+                        venta, cantidad
+                    ),
+                    _ => println!("No se pudo registrar la devolucion"),
+                }
+            }
+            Comando::Stock(Some(id)) => match self.guardian.send(ObtenerStock { id }).await {
+                Ok(disponibles) => {
+                    println!("Hay {} unidades del producto {}", disponibles, id)
+                }
+                Err(_) => println!("No se pudo consultar el stock"),
+            },
+            Comando::Stock(None) => match self.guardian.send(ObtenerInventario).await {
+                Ok(inventario) => {
+                    println!(
+                        "{:<10}{:>12}{:>12}",
+                        "Producto", "Disponibles", "Reservadas"
+                    );
+                    for (id, producto) in inventario.productos {
+                        println!(
+                            "{:<10}{:>12}{:>12}",
+                            id, producto.disponibles, producto.reservadas
+                        );
+                    }
+                }
+                Err(_) => println!("No se pudo consultar el stock"),
+            },
+            Comando::Ayuda => println!("{}", AYUDA),
+            Comando::Salir => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::auditoria::TipoOperacion;
+    use crate::local::empleado::Empleado;
+    use crate::local::guardian::{ConsultarMovimientos, Guardian};
+    use actix::actors::mocker::Mocker;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parsear_comandos_del_operador() {
+        assert_eq!(
+            Comando::parsear("vender 3 2"),
+            Ok(Comando::Vender {
+                id_producto: 3,
+                cantidad: 2
+            })
+        );
+        assert_eq!(
+            Comando::parsear("  devolver 100 10 "),
+            Ok(Comando::Devolver {
+                venta: 100,
+                cantidad: 10
+            })
+        );
+        assert_eq!(Comando::parsear("stock"), Ok(Comando::Stock(None)));
+        assert_eq!(Comando::parsear("stock 4"), Ok(Comando::Stock(Some(4))));
+        assert_eq!(
+            Comando::parsear("vender 3"),
+            Err(ErrorConsola::ArgumentosInvalidos)
+        );
+        assert_eq!(
+            Comando::parsear("vender 3 -1"),
+            Err(ErrorConsola::ArgumentosInvalidos)
+        );
+        assert_eq!(
+            Comando::parsear("robar 1 1"),
+            Err(ErrorConsola::ComandoDesconocido)
+        );
+        assert_eq!(Comando::parsear(""), Err(ErrorConsola::ComandoVacio));
+    }
+
+    #[actix_rt::test]
+    async fn la_consola_vende_por_las_cajas_hasta_que_el_operador_sale() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let cajas = Mocker::<TomarPedido>::mock(Box::new(move |msg, _ctx| {
+            let tomar = msg.downcast_ref::<TomarPedido>().unwrap();
+            tx.send((tomar.pedido.clone(), tomar.id_pedido)).unwrap();
+            Box::new(Some(()))
+        }))
+        .start()
+        .recipient();
+        let guardian =
+            GuardianParticionado::new(vec![Guardian::new(HashMap::from([(1, 5)]))]).start();
+
+        let (entrada, lineas) = mpsc::unbounded_channel();
+        for linea in ["vender 1 2", "stock 1", "vender 1 1", "salir", "vender 1 1"] {
+            entrada.send(linea.to_string()).unwrap();
+        }
+        Consola::new(cajas, guardian.clone(), 100)
+            .atender(lineas)
+            .await;
+
+        assert_eq!(rx.recv().await.unwrap(), (Pedido::new(1, 2), 100));
+        assert_eq!(rx.recv().await.unwrap(), (Pedido::new(1, 1), 101));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn la_consola_solo_devuelve_unidades_de_sus_ventas_concretadas() {
+        let guardian =
+            GuardianParticionado::new(vec![Guardian::new(HashMap::from([(1, 5), (2, 5)]))]).start();
+        let cajas = Empleado::new(guardian.clone().recipient())
+            .start()
+            .recipient();
+        let mut consola = Consola::new(cajas, guardian.clone(), 100);
+
+        for linea in [
+            "vender 1 2",
+            "vender 2 9",
+            "devolver 100 3",
+            "devolver 100 1",
+            "devolver 100 2",
+            "devolver 101 1",
+            "devolver 7 1",
+        ] {
+            consola.ejecutar(Comando::parsear(linea).unwrap()).await;
+        }
+
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 4);
+        assert_eq!(guardian.send(ObtenerStock { id: 2 }).await.unwrap(), 5);
+        let devoluciones = guardian
+            .send(ConsultarMovimientos::del_producto(1))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|movimiento| movimiento.operacion == TipoOperacion::Devolver)
+            .count();
+        assert_eq!(devoluciones, 1);
+    }
+}
@@ -0,0 +1,179 @@
+//! Este modulo contiene la consulta de stock en paralelo, con la que un local
+//! elige a quien delegarle un pedido que no puede cubrir. En lugar de recorrer
+//! a sus vecinos de a uno, les pregunta a todos a la vez si pueden cubrirlo,
+//! espera las respuestas hasta un plazo, y delega directamente al mejor. Los
+//! vecinos que no responden a tiempo se tratan igual que los que no envian el
+//! ack de una delegacion: se saltean.
+
+use actix::Addr;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{timeout_at, Instant};
+
+use super::guardian::ObtenerStock;
+use super::mensajero::{Enviar, Mensajero};
+use super::particiones::GuardianParticionado;
+use crate::aliases::{IdLocal, IdPedido, Puerto};
+use crate::id_a_dir_local;
+use crate::mensajes::{ConsultaStock, MensajeDelegado, RespuestaStock};
+use crate::topologia::Criterio;
+
+type Respuesta = (IdLocal, RespuestaStock);
+
+/// Consultas de stock que el local tiene en curso. El servidor le pasa cada
+/// respuesta que recibe, y esta la hace llegar a la consulta correspondiente
+#[derive(Default)]
+pub struct ConsultasStock {
+    pendientes: Mutex<HashMap<(Puerto, IdPedido), mpsc::UnboundedSender<Respuesta>>>,
+}
+
+impl ConsultasStock {
+    /// Crea un registro de consultas vacio
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pasa una respuesta recibida a la consulta que la espera. Si la consulta
+    /// ya termino, la respuesta llego tarde y se descarta
+    pub async fn recibir(&self, origen: IdLocal, respuesta: RespuestaStock) {
+        let clave = (respuesta.puerto, respuesta.id_pedido);
+        match self.pendientes.lock().await.get(&clave) {
+            Some(consulta) => {
+                let _ = consulta.send((origen, respuesta));
+            }
+            None => println!(
+                "Llego tarde la respuesta del local {} por el pedido {}",
+                origen, clave.1
+            ),
+        }
+    }
+
+    /// Le consulta el stock del pedido a los locales dados, y devuelve las
+    /// respuestas que llegaron antes de que se cumpla el plazo
+    pub async fn consultar(
+        &self,
+        mensajero: &Addr<Mensajero>,
+        mensaje: &MensajeDelegado,
+        locales: &[IdLocal],
+        plazo: Duration,
+    ) -> Vec<Respuesta> {
+        let clave = (mensaje.puerto_ecommerce, mensaje.get_id());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.pendientes.lock().await.insert(clave, tx);
+
+        let consulta = ConsultaStock::new(clave.1, clave.0, mensaje.get_pedido()).as_bytes();
+        let mut consultados = Vec::with_capacity(locales.len());
+        for id in locales {
+            let Ok(destino) = id_a_dir_local(*id).parse::<SocketAddr>() else {
+                continue;
+            };
+            if let Ok(Ok(_)) = mensajero.send(Enviar::new(consulta.clone(), destino)).await {
+                consultados.push(*id);
+            }
+        }
+
+        let limite = Instant::now() + plazo;
+        let mut respuestas: Vec<Respuesta> = Vec::with_capacity(consultados.len());
+        while respuestas.len() < consultados.len() {
+            match timeout_at(limite, rx.recv()).await {
+                Ok(Some((origen, respuesta))) => {
+                    let repetida = respuestas.iter().any(|(otro, _)| *otro == origen);
+                    if consultados.contains(&origen) && !repetida {
+                        respuestas.push((origen, respuesta));
+                    }
+                }
+                _ => break,
+            }
+        }
+        self.pendientes.lock().await.remove(&clave);
+        respuestas
+    }
+}
+
+/// Ordena, del mejor al peor, a los locales que respondieron que pueden cubrir
+/// el pedido. La preferencia es el orden de los vecinos segun la topologia, y
+/// desempata cuando se elige por stock
+pub fn ordenar_candidatos(
+    criterio: Criterio,
+    preferencia: &[IdLocal],
+    respuestas: Vec<Respuesta>,
+) -> Vec<IdLocal> {
+    let posicion = |id: &IdLocal| preferencia.iter().position(|otro| otro == id);
+    let mut candidatos: Vec<Respuesta> = respuestas
+        .into_iter()
+        .filter(|(_, respuesta)| respuesta.puede_cubrir)
+        .collect();
+    match criterio {
+        Criterio::MasCercano => candidatos.sort_by_key(|(id, _)| posicion(id)),
+        Criterio::MasStock => candidatos
+            .sort_by_key(|(id, respuesta)| (std::cmp::Reverse(respuesta.unidades), posicion(id))),
+    }
+    candidatos.into_iter().map(|(id, _)| id).collect()
+}
+
+/// Responde la consulta de stock de otro local, preguntandole al guardian
+/// cuantas unidades hay de cada producto del pedido. Las lineas de un mismo
+/// producto se suman, ya que el pedido solo puede cubrirse si alcanza para todas
+pub async fn responder_consulta(
+    guardian: Addr<GuardianParticionado>,
+    mensajero: Addr<Mensajero>,
+    consulta: ConsultaStock,
+    origen: SocketAddr,
+) {
+    let mut puede_cubrir = true;
+    let mut unidades = u16::MAX;
+    for (id_producto, cantidad) in consulta.pedido.cantidades_por_producto() {
+        let Ok(disponibles) = guardian.send(ObtenerStock { id: id_producto }).await else {
+            eprintln!("No se pudo consultar el stock al guardian");
+            return;
+        };
+        puede_cubrir &= disponibles >= cantidad;
+        unidades = unidades.min(disponibles);
+    }
+    let respuesta =
+        RespuestaStock::new(consulta.id_pedido, consulta.puerto, puede_cubrir, unidades);
+    if mensajero
+        .send(Enviar::new(respuesta.as_bytes(), origen))
+        .await
+        .is_err()
+    {
+        eprintln!("No se pudo responder la consulta de stock");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn respuesta(puede_cubrir: bool, unidades: u16) -> RespuestaStock {
+        RespuestaStock::new(1, 3402, puede_cubrir, unidades)
+    }
+
+    #[test]
+    fn los_candidatos_se_ordenan_segun_el_criterio() {
+        let preferencia = [3, 1, 2, 0];
+        let respuestas = vec![
+            (0, respuesta(true, 50)),
+            (1, respuesta(true, 8)),
+            (2, respuesta(false, 1)),
+            (3, respuesta(true, 8)),
+        ];
+        assert_eq!(
+            ordenar_candidatos(Criterio::MasCercano, &preferencia, respuestas.clone()),
+            vec![3, 1, 0]
+        );
+        assert_eq!(
+            ordenar_candidatos(Criterio::MasStock, &preferencia, respuestas),
+            vec![0, 3, 1]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn las_respuestas_tardias_se_descartan() {
+        let consultas = ConsultasStock::new();
+        consultas.recibir(2, respuesta(true, 5)).await;
+        assert!(consultas.pendientes.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! Este modulo detecta la caida de los demas locales a partir de latidos. Cada
+//! local le envia periodicamente un latido a los miembros del cluster, y
+//! sospecha que esta caido un local del que no recibe latidos hace un tiempo,
+//! o al que no pudo delegarle un pedido. La sospecha dura hasta el siguiente
+//! latido de ese local, por lo que un local que revive vuelve a considerarse
+//! apenas se reciben sus latidos, sin esperar a que se entere la membresia.
+
+use actix::Addr;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::mensajero::{Enviar, Mensajero};
+use crate::aliases::IdLocal;
+use crate::id_a_dir_local;
+use crate::membresia::Membresia;
+use crate::mensajes::MensajeLatido;
+
+/// Cada cuanto un local le envia un latido a los demas
+pub const INTERVALO_LATIDOS: Duration = Duration::from_millis(200);
+
+/// Tiempo sin recibir latidos de un local tras el cual se sospecha que esta caido
+pub const PLAZO_SOSPECHA: Duration = Duration::from_millis(600);
+
+/// Estado de un local segun el detector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EstadoLocal {
+    /// Nunca se recibio un latido suyo, por lo que no se sabe si esta vivo
+    Desconocido,
+    /// Sus latidos llegan a tiempo
+    Vivo,
+    /// Se sospecha que esta caido
+    Sospechado,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Seguimiento {
+    ultimo_latido: Instant,
+    sospechado: bool,
+}
+
+/// Detector de fallas de los demas locales
+#[derive(Debug)]
+pub struct DetectorFallas {
+    plazo: Duration,
+    locales: Mutex<HashMap<IdLocal, Seguimiento>>,
+}
+
+impl DetectorFallas {
+    /// Crea un detector que todavia no recibio latidos de ningun local
+    pub fn new() -> Self {
+        Self {
+            plazo: PLAZO_SOSPECHA,
+            locales: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Indica el tiempo sin latidos tras el cual se sospecha de un local. Si no
+    /// se indica, es `PLAZO_SOSPECHA`
+    pub fn con_plazo(mut self, plazo: Duration) -> Self {
+        self.plazo = plazo;
+        self
+    }
+
+    fn locales(&self) -> MutexGuard<'_, HashMap<IdLocal, Seguimiento>> {
+        // El seguimiento siempre queda consistente, aunque otro hilo haya fallado
+        self.locales.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Anota un latido del local dado, que deja de ser sospechado
+    pub fn latido(&self, id: IdLocal) {
+        if self.estado(id) == EstadoLocal::Sospechado {
+            println!("El local {} volvio a dar latidos", id);
+        }
+        self.locales().insert(
+            id,
+            Seguimiento {
+                ultimo_latido: Instant::now(),
+                sospechado: false,
+            },
+        );
+    }
+
+    /// Sospecha del local dado hasta su proximo latido, por ejemplo porque no
+    /// acuso recibir un pedido delegado
+    pub fn sospechar(&self, id: IdLocal) {
+        println!("Sospecho que el local {} esta caido", id);
+        self.locales()
+            .entry(id)
+            .and_modify(|seguimiento| seguimiento.sospechado = true)
+            .or_insert(Seguimiento {
+                ultimo_latido: Instant::now(),
+                sospechado: true,
+            });
+    }
+
+    /// Devuelve el estado del local dado
+    pub fn estado(&self, id: IdLocal) -> EstadoLocal {
+        match self.locales().get(&id) {
+            None => EstadoLocal::Desconocido,
+            Some(seguimiento)
+                if seguimiento.sospechado || seguimiento.ultimo_latido.elapsed() >= self.plazo =>
+            {
+                EstadoLocal::Sospechado
+            }
+            Some(_) => EstadoLocal::Vivo,
+        }
+    }
+
+    /// Olvida los latidos recibidos, por ejemplo al revivir el local, ya que
+    /// mientras estuvo caido no pudo recibir los de los demas
+    pub fn reiniciar(&self) {
+        self.locales().clear();
+    }
+}
+
+impl Default for DetectorFallas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Envia latidos periodicamente a los demas miembros del cluster, hasta que
+/// termine el programa. Se le envian tambien a los locales sospechados, para
+/// que se enteren apenas revivan
+pub async fn latir(id_local: IdLocal, membresia: Arc<Membresia>, mensajero: Addr<Mensajero>) {
+    let latido = MensajeLatido::new(id_local).as_bytes();
+    loop {
+        for destino in membresia.miembros() {
+            if destino == id_local {
+                continue;
+            }
+            // Si el local esta desconectado el envio falla, y los demas
+            // sospecharan de el al no recibir sus latidos
+            if let Ok(direccion) = id_a_dir_local(destino).parse() {
+                mensajero.do_send(Enviar::new(latido.clone(), direccion));
+            }
+        }
+        tokio::time::sleep(INTERVALO_LATIDOS).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn se_sospecha_de_un_local_sin_latidos_recientes() {
+        let detector = DetectorFallas::new().con_plazo(Duration::from_millis(50));
+        assert_eq!(detector.estado(1), EstadoLocal::Desconocido);
+
+        detector.latido(1);
+        assert_eq!(detector.estado(1), EstadoLocal::Vivo);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(detector.estado(1), EstadoLocal::Sospechado);
+
+        detector.latido(1);
+        assert_eq!(detector.estado(1), EstadoLocal::Vivo);
+        detector.reiniciar();
+        assert_eq!(detector.estado(1), EstadoLocal::Desconocido);
+    }
+
+    #[test]
+    fn la_sospecha_dura_hasta_el_siguiente_latido() {
+        let detector = DetectorFallas::new();
+        detector.sospechar(2);
+        assert_eq!(detector.estado(2), EstadoLocal::Sospechado);
+
+        detector.latido(3);
+        detector.sospechar(3);
+        assert_eq!(detector.estado(3), EstadoLocal::Sospechado);
+        detector.latido(3);
+        assert_eq!(detector.estado(3), EstadoLocal::Vivo);
+    }
+}
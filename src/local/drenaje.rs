@@ -0,0 +1,121 @@
+//! Este modulo define el drenaje del local, con el que se apaga sin abandonar
+//! los pedidos de ecommerce que tiene en curso. Mientras se drena, el servidor
+//! deja de reservar pedidos nuevos y los desvia a otros locales, y espera a que
+//! terminen los que ya estaba resolviendo. El drenaje se inicia al recibir
+//! Ctrl+C, o a pedido de un operador como comando de mantenimiento.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+/// Estado del drenaje del local, compartido entre el servidor, las tareas que
+/// resuelven cada pedido y quien inicia el drenaje
+#[derive(Default)]
+pub struct Drenaje {
+    drenando: AtomicBool,
+    iniciado: Notify,
+    terminado: Notify,
+    en_curso: AtomicUsize,
+    sin_pedidos: Notify,
+}
+
+impl Drenaje {
+    /// Crea el estado de un local que no se esta drenando
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inicia el drenaje, y devuelve si no se habia iniciado antes
+    pub fn iniciar(&self) -> bool {
+        let ya_drenando = self.drenando.swap(true, Ordering::SeqCst);
+        if !ya_drenando {
+            self.iniciado.notify_one();
+        }
+        !ya_drenando
+    }
+
+    /// Devuelve si el local se esta drenando
+    pub fn drenando(&self) -> bool {
+        self.drenando.load(Ordering::SeqCst)
+    }
+
+    /// Espera a que se inicie el drenaje
+    pub async fn esperar_inicio(&self) {
+        while !self.drenando() {
+            self.iniciado.notified().await;
+        }
+    }
+
+    /// Anota un pedido en curso, que deja de estarlo al soltar lo devuelto
+    pub fn comenzar_pedido(self: &Arc<Self>) -> PedidoEnCurso {
+        self.en_curso.fetch_add(1, Ordering::SeqCst);
+        PedidoEnCurso(self.clone())
+    }
+
+    /// Devuelve cuantos pedidos estan en curso
+    pub fn en_curso(&self) -> usize {
+        self.en_curso.load(Ordering::SeqCst)
+    }
+
+    /// Espera a que no quede ningun pedido en curso, como mucho el plazo dado.
+    /// Devuelve si terminaron todos
+    pub async fn esperar_pedidos(&self, plazo: Duration) -> bool {
+        timeout(plazo, async {
+            while self.en_curso() > 0 {
+                self.sin_pedidos.notified().await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    /// Avisa que el drenaje termino y el servidor puede dejar de escuchar
+    pub fn terminar(&self) {
+        self.terminado.notify_one();
+    }
+
+    /// Espera a que termine el drenaje
+    pub async fn esperar_fin(&self) {
+        self.terminado.notified().await;
+    }
+}
+
+/// Pedido que el servidor esta resolviendo. Al soltarlo, termine como termine
+/// la tarea que lo resuelve, deja de contar como en curso
+pub struct PedidoEnCurso(Arc<Drenaje>);
+
+impl Drop for PedidoEnCurso {
+    fn drop(&mut self) {
+        if self.0.en_curso.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.sin_pedidos.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn el_drenaje_espera_a_los_pedidos_en_curso() {
+        let drenaje = Arc::new(Drenaje::new());
+        let pedido = drenaje.comenzar_pedido();
+        let otro = drenaje.comenzar_pedido();
+        assert_eq!(drenaje.en_curso(), 2);
+
+        assert!(drenaje.iniciar());
+        assert!(!drenaje.iniciar());
+        drenaje.esperar_inicio().await;
+        assert!(!drenaje.esperar_pedidos(Duration::from_millis(50)).await);
+
+        drop(pedido);
+        actix_rt::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(otro);
+        });
+        assert!(drenaje.esperar_pedidos(Duration::from_secs(1)).await);
+        assert_eq!(drenaje.en_curso(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+//! Este modulo elige un lider entre los locales activos, para las tareas que
+//! requieren un unico coordinador en el cluster. Usa el algoritmo bully: el
+//! lider es el local activo de mayor id. Un local que nota que no hay lider, o
+//! que el lider dejo de estar activo segun la membresia, inicia una eleccion
+//! avisandole a los locales de mayor id. Si alguno responde, este continua la
+//! eleccion; si no, se proclama coordinador. El lider se anuncia
+//! periodicamente, de forma que un local que revive tras ser matado, o que se
+//! habia proclamado lider sin conocer a los demas, se entera de quien es el
+//! verdadero lider, o lo desafia si tiene un id mayor.
+
+use actix::prelude::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::mensajero::Enviar;
+use crate::aliases::IdLocal;
+use crate::id_a_dir_local;
+use crate::membresia::Membresia;
+use crate::mensajes::MensajeEleccion;
+
+/// Tiempo que se espera la respuesta de los locales de mayor id
+const PLAZO_RESPUESTA: Duration = Duration::from_millis(500);
+
+/// Tiempo que se espera el anuncio del coordinador despues de recibir una respuesta
+const PLAZO_COORDINADOR: Duration = Duration::from_millis(1500);
+
+/// Cada cuanto se verifica que el lider siga activo, y el lider se anuncia
+const INTERVALO_LIDER: Duration = Duration::from_secs(1);
+
+/// Actor que lleva adelante la eleccion del lider desde un local
+pub struct Eleccion {
+    id_local: IdLocal,
+    mensajero: Recipient<Enviar>,
+    membresia: Arc<Membresia>,
+    lider: Option<IdLocal>,
+    /// Numero de la eleccion en curso, si la hay, para ignorar los plazos de
+    /// elecciones anteriores
+    en_curso: Option<u32>,
+    ultima_eleccion: u32,
+    respondida: bool,
+}
+
+impl Eleccion {
+    /// Crea el actor de eleccion del local dado, que elige entre los locales
+    /// activos segun la membresia y envia los mensajes a traves del mensajero
+    pub fn new(id_local: IdLocal, mensajero: Recipient<Enviar>, membresia: Arc<Membresia>) -> Self {
+        Self {
+            id_local,
+            mensajero,
+            membresia,
+            lider: None,
+            en_curso: None,
+            ultima_eleccion: 0,
+            respondida: false,
+        }
+    }
+
+    fn enviar(&self, mensaje: MensajeEleccion, destino: SocketAddr) {
+        self.mensajero
+            .do_send(Enviar::new(mensaje.as_bytes(), destino));
+    }
+
+    fn enviar_a_locales(&self, mensaje: MensajeEleccion, locales: &[IdLocal]) {
+        for id in locales {
+            if let Ok(destino) = id_a_dir_local(*id).parse() {
+                self.enviar(mensaje, destino);
+            }
+        }
+    }
+
+    fn otros_vivos(&self) -> Vec<IdLocal> {
+        self.membresia
+            .vivos()
+            .into_iter()
+            .filter(|id| *id != self.id_local)
+            .collect()
+    }
+
+    /// Inicia una eleccion, salvo que ya haya una en curso
+    fn iniciar(&mut self, ctx: &mut Context<Self>) {
+        if self.en_curso.is_some() {
+            return;
+        }
+        self.ultima_eleccion += 1;
+        let ronda = self.ultima_eleccion;
+        self.en_curso = Some(ronda);
+        self.respondida = false;
+
+        let mayores: Vec<IdLocal> = self
+            .otros_vivos()
+            .into_iter()
+            .filter(|id| *id > self.id_local)
+            .collect();
+        if mayores.is_empty() {
+            self.proclamarse();
+            return;
+        }
+        println!("El local {} inicia una eleccion de lider", self.id_local);
+        self.enviar_a_locales(MensajeEleccion::Eleccion(self.id_local), &mayores);
+
+        ctx.run_later(PLAZO_RESPUESTA, move |act, ctx| {
+            if act.en_curso != Some(ronda) {
+                return;
+            }
+            if !act.respondida {
+                act.proclamarse();
+                return;
+            }
+            // Un local mayor tomo la eleccion, pero si no se proclama se vuelve a empezar
+            ctx.run_later(PLAZO_COORDINADOR, move |act, ctx| {
+                if act.en_curso == Some(ronda) {
+                    act.en_curso = None;
+                    act.iniciar(ctx);
+                }
+            });
+        });
+    }
+
+    /// Se proclama lider y se lo anuncia a los demas locales activos
+    fn proclamarse(&mut self) {
+        if self.lider != Some(self.id_local) {
+            println!("El local {} es el nuevo lider", self.id_local);
+        }
+        self.lider = Some(self.id_local);
+        self.en_curso = None;
+        self.enviar_a_locales(
+            MensajeEleccion::Coordinador(self.id_local),
+            &self.otros_vivos(),
+        );
+    }
+
+    /// Verifica que el lider siga activo, o lo anuncia si es el propio local
+    fn verificar_lider(&mut self, ctx: &mut Context<Self>) {
+        if self.en_curso.is_some() {
+            return;
+        }
+        match self.lider {
+            Some(lider) if lider == self.id_local => self.enviar_a_locales(
+                MensajeEleccion::Coordinador(self.id_local),
+                &self.otros_vivos(),
+            ),
+            Some(lider) if self.membresia.vivos().contains(&lider) => {}
+            _ => self.iniciar(ctx),
+        }
+    }
+}
+
+impl Actor for Eleccion {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(INTERVALO_LIDER, |act, ctx| act.verificar_lider(ctx));
+    }
+}
+
+/// Mensaje que pide iniciar una eleccion, por ejemplo al revivir el local
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct IniciarEleccion;
+
+impl Handler<IniciarEleccion> for Eleccion {
+    type Result = ();
+
+    fn handle(&mut self, _msg: IniciarEleccion, ctx: &mut Context<Self>) -> Self::Result {
+        self.lider = None;
+        self.iniciar(ctx);
+    }
+}
+
+/// Mensaje que pide el lider actual, si se conoce
+#[derive(Message)]
+#[rtype(result = "Option<IdLocal>")]
+pub struct ObtenerLider;
+
+impl Handler<ObtenerLider> for Eleccion {
+    type Result = Option<IdLocal>;
+
+    fn handle(&mut self, _msg: ObtenerLider, _ctx: &mut Context<Self>) -> Self::Result {
+        self.lider
+    }
+}
+
+/// Mensaje que envia el servidor al recibir un mensaje de la eleccion
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EleccionRecibida {
+    pub mensaje: MensajeEleccion,
+    pub origen: SocketAddr,
+}
+
+impl Handler<EleccionRecibida> for Eleccion {
+    type Result = ();
+
+    fn handle(&mut self, msg: EleccionRecibida, ctx: &mut Context<Self>) -> Self::Result {
+        match msg.mensaje {
+            MensajeEleccion::Eleccion(candidato) if candidato < self.id_local => {
+                self.enviar(MensajeEleccion::Respuesta(self.id_local), msg.origen);
+                self.iniciar(ctx);
+            }
+            MensajeEleccion::Eleccion(_) => {}
+            MensajeEleccion::Respuesta(_) => self.respondida = true,
+            MensajeEleccion::Coordinador(lider) if lider < self.id_local => {
+                // Un local activo de mayor id le disputa el liderazgo
+                self.lider = Some(lider);
+                self.iniciar(ctx);
+            }
+            MensajeEleccion::Coordinador(lider) => {
+                if self.lider != Some(lider) {
+                    println!(
+                        "El local {} reconoce como lider al {}",
+                        self.id_local, lider
+                    );
+                }
+                self.lider = Some(lider);
+                self.en_curso = None;
+            }
+            MensajeEleccion::ConsultaLider => {
+                if let Some(lider) = self.lider {
+                    self.enviar(MensajeEleccion::Coordinador(lider), msg.origen);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mensajes::{EntradaMembresia, MensajeMembresia, TipoMensaje};
+    use actix::actors::mocker::Mocker;
+    use std::io;
+    use tokio::sync::mpsc;
+
+    /// Crea un mensajero falso que entrega por un canal cada mensaje de la
+    /// eleccion junto a su destino
+    fn crear_mensajero() -> (
+        Recipient<Enviar>,
+        mpsc::UnboundedReceiver<(MensajeEleccion, SocketAddr)>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mensajero = Mocker::<Enviar>::mock(Box::new(move |msg, _ctx| {
+            let enviar = msg.downcast_ref::<Enviar>().unwrap();
+            let mut cursor = io::Cursor::new(enviar.mensaje.clone());
+            let tipo = TipoMensaje::from_bytes(&mut cursor).unwrap();
+            let mensaje = MensajeEleccion::from_bytes(tipo, &mut cursor).unwrap();
+            tx.send((mensaje, enviar.target)).unwrap();
+            Box::new(Some(Ok::<(), crate::errores::ErrorMensajero>(())))
+        }))
+        .start()
+        .recipient();
+        (mensajero, rx)
+    }
+
+    fn membresia_con(id: IdLocal, otros: &[IdLocal]) -> Arc<Membresia> {
+        let membresia = Membresia::de_local(id);
+        membresia.fusionar(&MensajeMembresia::new(
+            otros
+                .iter()
+                .map(|otro| EntradaMembresia::new(*otro, 1, true))
+                .collect(),
+        ));
+        Arc::new(membresia)
+    }
+
+    fn dir(id: IdLocal) -> SocketAddr {
+        id_a_dir_local(id).parse().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn si_los_mayores_no_responden_el_local_se_proclama_lider() {
+        let (mensajero, mut enviados) = crear_mensajero();
+        let eleccion = Eleccion::new(2, mensajero, membresia_con(2, &[0, 5])).start();
+
+        eleccion.send(IniciarEleccion).await.unwrap();
+        assert_eq!(
+            enviados.recv().await.unwrap(),
+            (MensajeEleccion::Eleccion(2), dir(5))
+        );
+        assert_eq!(eleccion.send(ObtenerLider).await.unwrap(), None);
+
+        let mut anunciados = vec![
+            enviados.recv().await.unwrap(),
+            enviados.recv().await.unwrap(),
+        ];
+        anunciados.sort_by_key(|(_, destino)| destino.port());
+        assert_eq!(
+            anunciados,
+            vec![
+                (MensajeEleccion::Coordinador(2), dir(0)),
+                (MensajeEleccion::Coordinador(2), dir(5))
+            ]
+        );
+        assert_eq!(eleccion.send(ObtenerLider).await.unwrap(), Some(2));
+    }
+
+    #[actix_rt::test]
+    async fn un_local_mayor_responde_y_disputa_el_liderazgo() {
+        let (mensajero, mut enviados) = crear_mensajero();
+        let eleccion = Eleccion::new(3, mensajero, membresia_con(3, &[1])).start();
+
+        eleccion
+            .send(EleccionRecibida {
+                mensaje: MensajeEleccion::Eleccion(1),
+                origen: dir(1),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            enviados.recv().await.unwrap(),
+            (MensajeEleccion::Respuesta(3), dir(1))
+        );
+        assert_eq!(
+            enviados.recv().await.unwrap(),
+            (MensajeEleccion::Coordinador(3), dir(1))
+        );
+
+        let consultante: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        eleccion
+            .send(EleccionRecibida {
+                mensaje: MensajeEleccion::ConsultaLider,
+                origen: consultante,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            enviados.recv().await.unwrap(),
+            (MensajeEleccion::Coordinador(3), consultante)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn el_anuncio_de_un_lider_mayor_se_acepta() {
+        let (mensajero, _enviados) = crear_mensajero();
+        let eleccion = Eleccion::new(1, mensajero, membresia_con(1, &[4])).start();
+
+        eleccion
+            .send(EleccionRecibida {
+                mensaje: MensajeEleccion::Coordinador(4),
+                origen: dir(4),
+            })
+            .await
+            .unwrap();
+        assert_eq!(eleccion.send(ObtenerLider).await.unwrap(), Some(4));
+    }
+}
@@ -1,22 +1,103 @@
 //! Este modulo contiene lo necesario para poder llevar a cabo pedidos, dado un local
 //! inicializado. Requiere de la existencia del guardian, ya que hara a este los pedidos
 
+use super::busquedas::{BuscarEnOtrosLocales, ResultadoBusqueda};
 use super::mensajes_actores::{
-    Descontar, PedidoConcretado, ProductoNoDisponible, Respuestas, StockInsuficiente,
+    Descontar, PedidoAtendido, PedidoConcretado, ProductoNoDisponible, Respuestas,
+    StockInsuficiente,
 };
+use super::reporte::VentaLocal;
+use crate::aliases::IdPedido;
 use crate::pedido::Pedido;
 use actix::prelude::*;
 use colored::Colorize;
+use std::collections::HashMap;
 
 /// Estructura de empleado. Cuenta con una direccion del gua
+/// Si atiende una de las cajas del local, avisa cada vez que termina con un pedido.
+/// Si el local no tiene el producto, puede buscarlo en los demas locales.
+/// Si el local lleva un reporte de ventas, le avisa el resultado de cada venta.
 pub struct Empleado {
     guardian: Recipient<Descontar>,
+    caja: usize,
+    aviso_atencion: Option<Recipient<PedidoAtendido>>,
+    busquedas: Option<Recipient<BuscarEnOtrosLocales>>,
+    ventas: Option<Recipient<VentaLocal>>,
+    pedidos_en_curso: HashMap<usize, Pedido>,
 }
 
 impl Empleado {
     /// Crea un nuevo empleado dado un guardian
     pub fn new(guardian: Recipient<Descontar>) -> Self {
-        Self { guardian }
+        Self {
+            guardian,
+            caja: 0,
+            aviso_atencion: None,
+            busquedas: None,
+            ventas: None,
+            pedidos_en_curso: HashMap::new(),
+        }
+    }
+
+    /// Indica a quien pedirle que busque en los demas locales los productos que
+    /// el cliente no pudo comprar aqui
+    pub fn con_busquedas(mut self, busquedas: Recipient<BuscarEnOtrosLocales>) -> Self {
+        self.busquedas = Some(busquedas);
+        self
+    }
+
+    /// Indica a quien avisarle el resultado de cada venta, para el reporte del local
+    pub fn con_ventas(mut self, ventas: Recipient<VentaLocal>) -> Self {
+        self.ventas = Some(ventas);
+        self
+    }
+
+    /// Busca en los demas locales el producto de un pedido que no pudo cumplirse,
+    /// e informa al cliente donde puede retirarlo
+    fn buscar_en_otros_locales(&mut self, id: usize, ctx: &mut Context<Self>) {
+        let (Some(busquedas), Some(pedido)) =
+            (self.busquedas.clone(), self.pedidos_en_curso.remove(&id))
+        else {
+            return;
+        };
+        println!(
+            "Buscando {} del pedido local {} en otros locales",
+            pedido,
+            id.to_string().green()
+        );
+        let busqueda = BuscarEnOtrosLocales::new(pedido, id as IdPedido);
+        ctx.spawn(
+            async move { busquedas.send(busqueda).await }
+                .into_actor(self)
+                .map(move |resultado, _empleado, _ctx| match resultado {
+                    Ok(ResultadoBusqueda::Retirar(local)) => println!(
+                        "El pedido local {} puede retirarse en el local {}",
+                        id.to_string().green(),
+                        local.to_string().blue()
+                    ),
+                    Ok(ResultadoBusqueda::Cancelado(local)) => println!(
+                        "El local {} tenia el producto del pedido local {}, pero no pudo reservarlo",
+                        local.to_string().blue(),
+                        id.to_string().green()
+                    ),
+                    Ok(ResultadoBusqueda::NoHayStock) => println!(
+                        "Ningun local tiene el producto del pedido local {}",
+                        id.to_string().green()
+                    ),
+                    _ => println!(
+                        "No se pudo averiguar si otro local tiene el producto del pedido local {}",
+                        id.to_string().green()
+                    ),
+                }),
+        );
+    }
+
+    /// Indica la caja que atiende el empleado, y a quien avisarle cuando
+    /// termina de atender un pedido
+    pub fn con_aviso_atencion(mut self, caja: usize, aviso: Recipient<PedidoAtendido>) -> Self {
+        self.caja = caja;
+        self.aviso_atencion = Some(aviso);
+        self
     }
 }
 
@@ -34,8 +115,8 @@ impl Actor for Empleado {
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct TomarPedido {
-    pedido: Pedido,
-    id_pedido: usize,
+    pub(super) pedido: Pedido,
+    pub(super) id_pedido: usize,
 }
 
 impl TomarPedido {
@@ -52,6 +133,10 @@ impl Handler<TomarPedido> for Empleado {
             "Se realizo localmente {} con id {}",
             msg.pedido, msg.id_pedido
         );
+        if self.busquedas.is_some() || self.ventas.is_some() {
+            self.pedidos_en_curso
+                .insert(msg.id_pedido, msg.pedido.clone());
+        }
         self.guardian.do_send(Descontar::new(
             msg.pedido,
             msg.id_pedido,
@@ -65,6 +150,18 @@ impl Handler<Respuestas> for Empleado {
     type Result = ();
 
     fn handle(&mut self, msg: Respuestas, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(aviso) = &self.aviso_atencion {
+            aviso.do_send(PedidoAtendido { caja: self.caja });
+        }
+        if let Some(ventas) = &self.ventas {
+            let id = match msg {
+                Respuestas::PedidoConcretado(id)
+                | Respuestas::ProductoNoDisponible(id)
+                | Respuestas::StockInsuficiente(id) => id,
+            };
+            let pedido = self.pedidos_en_curso.get(&id).cloned();
+            ventas.do_send(VentaLocal::new(msg, pedido));
+        }
         match msg {
             Respuestas::PedidoConcretado(id) => {
                 ctx.address().do_send(PedidoConcretado::new(id));
@@ -83,6 +180,7 @@ impl Handler<PedidoConcretado> for Empleado {
     type Result = ();
 
     fn handle(&mut self, msg: PedidoConcretado, _ctx: &mut Context<Self>) -> Self::Result {
+        self.pedidos_en_curso.remove(&msg.id);
         println!(
             "El pedido local con id {} fue exitoso!",
             msg.id.to_string().green()
@@ -93,33 +191,39 @@ impl Handler<PedidoConcretado> for Empleado {
 impl Handler<StockInsuficiente> for Empleado {
     type Result = ();
 
-    fn handle(&mut self, msg: StockInsuficiente, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: StockInsuficiente, ctx: &mut Context<Self>) -> Self::Result {
         println!(
             "No hay suficiente stock para manejar el pedido con id {}",
             msg.id.to_string().green()
         );
+        self.buscar_en_otros_locales(msg.id, ctx);
     }
 }
 
 impl Handler<ProductoNoDisponible> for Empleado {
     type Result = ();
 
-    fn handle(&mut self, msg: ProductoNoDisponible, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: ProductoNoDisponible, ctx: &mut Context<Self>) -> Self::Result {
         println!(
             "El producto de {} no esta disponible",
             msg.id.to_string().green()
         );
+        self.buscar_en_otros_locales(msg.id, ctx);
     }
 }
 #[cfg(test)]
 mod tests {
     use actix::{actors::mocker::Mocker, Actor, Recipient};
 
-    use crate::{local::mensajes_actores::Descontar, pedido::Pedido};
+    use crate::{
+        local::mensajes_actores::{Descontar, Respuestas},
+        pedido::Pedido,
+    };
 
-    use super::{Empleado, TomarPedido};
+    use super::{BuscarEnOtrosLocales, Empleado, ResultadoBusqueda, TomarPedido};
 
     #[actix_rt::test]
+    #[allow(clippy::useless_vec)]
     async fn test_escenario_se_realizan_multiples_pedidos_y_se_procesan_ordenados() {
         let (tx, mut rx) = tokio::sync::mpsc::channel(2);
         //setup
@@ -158,4 +262,32 @@ mod tests {
         assert_eq!(msg2.id, 1);
         assert_eq!(msg2.sender, empleado_addr.recipient());
     }
+
+    #[actix_rt::test]
+    async fn si_el_producto_no_esta_disponible_lo_busca_en_otros_locales() {
+        let guardian: Recipient<Descontar> = Mocker::<Descontar>::mock(Box::new(|msg, _ctx| {
+            let descontar = msg.downcast_ref::<Descontar>().unwrap();
+            descontar
+                .sender
+                .do_send(Respuestas::ProductoNoDisponible(descontar.id));
+            Box::new(Some(()))
+        }))
+        .start()
+        .recipient();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let busquedas: Recipient<BuscarEnOtrosLocales> =
+            Mocker::<BuscarEnOtrosLocales>::mock(Box::new(move |msg, _ctx| {
+                let busqueda = msg.downcast::<BuscarEnOtrosLocales>().unwrap();
+                tx.send(*busqueda).unwrap();
+                Box::new(Some(ResultadoBusqueda::Retirar(2)))
+            }))
+            .start()
+            .recipient();
+
+        let empleado = Empleado::new(guardian).con_busquedas(busquedas).start();
+        empleado.do_send(TomarPedido::new(Pedido::new(7, 3), 5));
+
+        let busqueda = rx.recv().await.unwrap();
+        assert_eq!(busqueda, BuscarEnOtrosLocales::new(Pedido::new(7, 3), 5));
+    }
 }
@@ -5,64 +5,630 @@
 use actix::prelude::*;
 use actix::{Actor, Context};
 
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
+use super::auditoria::{Auditoria, ClavePedido, FiltroMovimientos, Movimiento, TipoOperacion};
+use super::lotes::{Lote, StockLotes};
 use super::mensajes_actores::{Descontar, Respuestas};
-use crate::aliases::TablaStock;
-use crate::aliases::{CantidadPedido, CantidadProducto, IdPedido, IdProducto, Puerto};
-use crate::errores::ErrorGuardian;
-use crate::pedido::Pedido;
-
-/// Estructura de guardian. Cuenta con el stock del local y
-/// con un mapa en el que guarda los pedidos que fueron bloqueados
-/// pero no aun confirmados, identificandolos por la tupla id
-/// de pedido y puerto de Ecommerce que lo realizo
+use super::registro::{LotesBloqueados, Operacion, Registro};
+use crate::aliases::{CantidadProducto, IdPedido, IdProducto, Puerto};
+use crate::errores::{ErrorDuranteParseo, ErrorGuardian};
+use crate::pedido::Carrito;
+
+/// Tiempo que un bloqueo permanece vigente si no se indica otro. Tambien se
+/// usa para los bloqueos recuperados del registro, cuyo dueño ya no existe.
+pub const TTL_BLOQUEO_POR_DEFECTO: Duration = Duration::from_secs(5);
+
+/// Cada cuanto el guardian revisa si hay lotes vencidos para descartar
+const INTERVALO_VENCIMIENTOS: Duration = Duration::from_secs(60);
+
+/// Cuanto se espera para volver a cancelar un bloqueo vencido cuya cancelacion
+/// no pudo persistirse
+pub(super) const REINTENTO_VENCIMIENTO: Duration = Duration::from_secs(1);
+
+/// Estructura de guardian. Cuenta con el stock del local, dividido en lotes
+/// que se consumen del que vence primero al ultimo, y con un mapa en el que
+/// guarda los carritos que fueron bloqueados pero no aun confirmados,
+/// identificandolos por la tupla id de pedido y puerto de Ecommerce que lo
+/// realizo, junto con los lotes de los que salieron. Cada bloqueo tiene
+/// un temporizador que lo cancela si no se resuelve a tiempo. Ademas lleva
+/// una cola de pedidos en espera, que se bloquean en orden de llegada a medida
+/// que vuelve a haber stock. Cada cambio del stock queda anotado en un libro
+/// de movimientos consultable, y opcionalmente se persiste en un registro.
+/// Periodicamente descarta los lotes vencidos. Tambien recuerda cuantas
+/// unidades de cada venta presencial pueden devolverse todavia.
 pub struct Guardian {
-    stock: TablaStock,
-    pedidos_bloqueados: HashMap<(IdPedido, Puerto), Pedido>,
+    stock: StockLotes,
+    pedidos_bloqueados: HashMap<(IdPedido, Puerto), Carrito>,
+    lotes_bloqueados: HashMap<(IdPedido, Puerto), Vec<(IdProducto, Lote)>>,
+    vencimientos: HashMap<(IdPedido, Puerto), SpawnHandle>,
+    pedidos_en_espera: VecDeque<Encolar>,
+    por_devolver: HashMap<(usize, IdProducto), CantidadProducto>,
+    aviso_expiracion: Option<Recipient<PedidoExpirado>>,
+    registro: Option<Registro>,
+    auditoria: Auditoria,
 }
 
 impl Guardian {
-    /// Crea un nuevo guardian dada un stock a resguardar
-    pub fn new(stock: TablaStock) -> Self {
+    /// Crea un nuevo guardian dada un stock a resguardar, ya sea por lotes o
+    /// como una cantidad por producto sin vencimiento
+    pub fn new(stock: impl Into<StockLotes>) -> Self {
         Self {
-            stock,
+            stock: stock.into(),
             pedidos_bloqueados: HashMap::new(),
+            lotes_bloqueados: HashMap::new(),
+            vencimientos: HashMap::new(),
+            pedidos_en_espera: VecDeque::new(),
+            por_devolver: HashMap::new(),
+            aviso_expiracion: None,
+            registro: None,
+            auditoria: Auditoria::new(),
         }
     }
 
-    /// Descuenta una cantidad determinada de un producto, devolviendo
-    /// el resultado de la operacion
+    /// Hace que los instantes del libro de movimientos se midan desde el
+    /// inicio dado, para que sean comparables con los de otros guardianes
+    pub fn con_reloj(mut self, inicio: Instant) -> Self {
+        self.auditoria = Auditoria::desde(inicio);
+        self
+    }
+
+    /// Indica a quien avisarle cuando un bloqueo expira, para que pueda
+    /// notificarle al ecommerce que su pedido fue cancelado
+    pub fn con_aviso_expiracion(mut self, aviso: Recipient<PedidoExpirado>) -> Self {
+        self.aviso_expiracion = Some(aviso);
+        self
+    }
+
+    /// Asocia un registro al guardian, guardando en el su estado actual como
+    /// punto de partida. A partir de ahora cada cambio del stock se persiste.
+    pub fn con_registro(mut self, mut registro: Registro) -> std::io::Result<Self> {
+        registro.compactar(
+            &self.stock,
+            self.bloqueados_como_lista(),
+            self.lotes_bloqueados_como_lista(),
+        )?;
+        self.registro = Some(registro);
+        Ok(self)
+    }
+
+    /// Reconstruye un guardian a partir de lo guardado en el registro: parte del
+    /// ultimo snapshot y vuelve a aplicar las operaciones posteriores a este.
     /// # Errors
-    /// * `ErrorGuardian::NoHaySuficienteStock` si existe producto, pero el stock no es suficiente
-    /// * `ErrorGuardian::NoHayStock` si no hay ninguna unidad del producto
-    fn descontar_stock(
+    /// * `ErrorDuranteParseo::FormatoArchivoInvalido` si los archivos del registro estan corruptos
+    /// * `ErrorDuranteParseo::RegistroInconsistente` si alguna operacion no puede volver a aplicarse
+    pub fn recuperar(mut registro: Registro) -> Result<Self, ErrorDuranteParseo> {
+        let (snapshot, operaciones) = registro.leer()?;
+        let mut guardian = Self::new(snapshot.stock);
+        guardian.pedidos_bloqueados = snapshot
+            .pedidos_bloqueados
+            .into_iter()
+            .map(|(id_pedido, puerto, carrito)| ((id_pedido, puerto), carrito))
+            .collect();
+        guardian.lotes_bloqueados = snapshot
+            .lotes_bloqueados
+            .into_iter()
+            .map(|(id_pedido, puerto, lotes)| ((id_pedido, puerto), lotes))
+            .collect();
+
+        for operacion in operaciones {
+            guardian
+                .aplicar(operacion)
+                .map_err(|_| ErrorDuranteParseo::RegistroInconsistente)?;
+        }
+        Ok(guardian.con_registro(registro)?)
+    }
+
+    /// Devuelve los ids de los pedidos bloqueados
+    pub(super) fn ids_bloqueados(&self) -> impl Iterator<Item = (IdPedido, Puerto)> + '_ {
+        self.pedidos_bloqueados.keys().copied()
+    }
+
+    /// Devuelve los pedidos bloqueados en el formato en que se guardan en el registro
+    fn bloqueados_como_lista(&self) -> Vec<(IdPedido, Puerto, Carrito)> {
+        self.pedidos_bloqueados
+            .iter()
+            .map(|((id_pedido, puerto), carrito)| (*id_pedido, *puerto, carrito.clone()))
+            .collect()
+    }
+
+    /// Devuelve los lotes de los pedidos bloqueados en el formato en que se
+    /// guardan en el registro
+    fn lotes_bloqueados_como_lista(&self) -> LotesBloqueados {
+        self.lotes_bloqueados
+            .iter()
+            .map(|((id_pedido, puerto), lotes)| (*id_pedido, *puerto, lotes.clone()))
+            .collect()
+    }
+
+    /// Aplica una operacion sobre el estado del guardian, sin registrarla
+    fn aplicar(&mut self, operacion: Operacion) -> Result<(), ErrorGuardian> {
+        match operacion {
+            Operacion::Descontar {
+                id_producto,
+                cantidad,
+                fecha,
+            } => self
+                .stock
+                .tomar(id_producto, cantidad, fecha.unwrap_or(NaiveDate::MIN))
+                .map(|_| ()),
+            Operacion::Bloquear {
+                id_pedido,
+                puerto,
+                pedido,
+                fecha,
+            } => {
+                if self.pedidos_bloqueados.contains_key(&(id_pedido, puerto)) {
+                    return Err(ErrorGuardian::PedidoYaBloqueado);
+                }
+                let lotes = self.descontar_carrito(&pedido, fecha.unwrap_or(NaiveDate::MIN))?;
+                self.pedidos_bloqueados.insert((id_pedido, puerto), pedido);
+                self.lotes_bloqueados.insert((id_pedido, puerto), lotes);
+                Ok(())
+            }
+            Operacion::Confirmar { id_pedido, puerto } => {
+                self.lotes_bloqueados.remove(&(id_pedido, puerto));
+                match self.pedidos_bloqueados.remove(&(id_pedido, puerto)) {
+                    None => Err(ErrorGuardian::PedidoInexistente),
+                    Some(_) => Ok(()),
+                }
+            }
+            Operacion::Cancelar { id_pedido, puerto } => {
+                match self.pedidos_bloqueados.remove(&(id_pedido, puerto)) {
+                    Some(carrito) => {
+                        // Los bloqueos recuperados de un registro anterior a los
+                        // lotes no saben de que lote salieron, y vuelven sin vencimiento
+                        let lotes = self
+                            .lotes_bloqueados
+                            .remove(&(id_pedido, puerto))
+                            .unwrap_or_else(|| {
+                                carrito
+                                    .cantidades_por_producto()
+                                    .into_iter()
+                                    .map(|(id, cantidad)| (id, Lote::sin_vencimiento(cantidad)))
+                                    .collect()
+                            });
+                        for (id, lote) in lotes {
+                            self.stock.agregar(id, lote);
+                        }
+                        Ok(())
+                    }
+                    None => Err(ErrorGuardian::PedidoInexistente),
+                }
+            }
+            Operacion::Reducir {
+                id_pedido,
+                puerto,
+                id_producto,
+                cantidad,
+            } => {
+                let clave = (id_pedido, puerto);
+                let carrito = self
+                    .pedidos_bloqueados
+                    .get(&clave)
+                    .ok_or(ErrorGuardian::PedidoInexistente)?;
+                let reducido = carrito
+                    .reducir(id_producto, cantidad)
+                    .ok_or(ErrorGuardian::ReduccionInvalida)?;
+                self.pedidos_bloqueados.insert(clave, reducido);
+                let devueltos = match self.lotes_bloqueados.get_mut(&clave) {
+                    Some(lotes) => devolver_de_lotes(lotes, id_producto, cantidad),
+                    None => vec![Lote::sin_vencimiento(cantidad)],
+                };
+                for lote in devueltos {
+                    self.stock.agregar(id_producto, lote);
+                }
+                Ok(())
+            }
+            Operacion::Reponer {
+                id_producto,
+                cantidad,
+                vencimiento,
+            } => {
+                self.stock.agregar(
+                    id_producto,
+                    Lote {
+                        vencimiento,
+                        cantidad,
+                    },
+                );
+                Ok(())
+            }
+            Operacion::DescartarVencidos { fecha } => {
+                self.stock.descartar_vencidos(fecha);
+                Ok(())
+            }
+            Operacion::Devolver {
+                id_producto,
+                cantidad,
+            } => {
+                self.stock
+                    .agregar(id_producto, Lote::sin_vencimiento(cantidad));
+                Ok(())
+            }
+        }
+    }
+
+    /// Programa la expiracion de un bloqueo, reemplazando la que pudiera haber
+    fn programar_vencimiento(
         &mut self,
-        id: IdProducto,
-        cantidad: CantidadPedido,
-    ) -> Result<(), ErrorGuardian> {
-        if let Some(cantidad_disp) = self.stock.get_mut(&id) {
-            match cantidad_disp.cmp(&&mut (cantidad as u16)) {
-                Ordering::Greater => {
-                    *cantidad_disp -= cantidad as u16;
-                    return Ok(());
+        id: (IdPedido, Puerto),
+        ttl: Duration,
+        ctx: &mut Context<Self>,
+    ) {
+        let handle = ctx.run_later(ttl, move |guardian, ctx| guardian.expirar(id, ctx));
+        if let Some(anterior) = self.vencimientos.insert(id, handle) {
+            ctx.cancel_future(anterior);
+        }
+    }
+
+    /// Cancela el temporizador de un bloqueo que ya fue resuelto
+    fn cancelar_vencimiento(&mut self, id: &(IdPedido, Puerto), ctx: &mut Context<Self>) {
+        if let Some(handle) = self.vencimientos.remove(id) {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    /// Intenta bloquear un pedido en espera. Si lo logra, le programa la
+    /// expiracion y le avisa a quien lo encolo; si no, lo devuelve
+    fn intentar_desencolar(
+        &mut self,
+        encolado: Encolar,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Encolar> {
+        let operacion = Operacion::Bloquear {
+            id_pedido: encolado.id.0,
+            puerto: encolado.id.1,
+            pedido: encolado.pedido.clone(),
+            fecha: Some(hoy()),
+        };
+        if self.ejecutar(operacion).is_err() {
+            return Err(encolado);
+        }
+
+        self.programar_vencimiento(encolado.id, TTL_BLOQUEO_POR_DEFECTO, ctx);
+        if encolado.aviso.send(()).is_err() {
+            // Nadie espera ya por este pedido, por lo que se libera el stock.
+            // Si no puede cancelarse, el bloqueo expira por su cuenta
+            let cancelado = self.ejecutar(Operacion::Cancelar {
+                id_pedido: encolado.id.0,
+                puerto: encolado.id.1,
+            });
+            if cancelado.is_ok() {
+                self.cancelar_vencimiento(&encolado.id, ctx);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recorre la cola de pedidos en espera en orden de llegada, bloqueando los
+    /// que ahora pueden cubrirse. Un pedido no puede adelantarse a otro anterior
+    /// que espera alguno de sus mismos productos.
+    fn atender_pedidos_en_espera(&mut self, ctx: &mut Context<Self>) {
+        let mut productos_esperando = HashSet::new();
+        let mut siguen_en_espera = VecDeque::new();
+
+        while let Some(encolado) = self.pedidos_en_espera.pop_front() {
+            let productos: Vec<IdProducto> = encolado
+                .pedido
+                .cantidades_por_producto()
+                .into_keys()
+                .collect();
+            if productos.iter().any(|id| productos_esperando.contains(id)) {
+                productos_esperando.extend(productos);
+                siguen_en_espera.push_back(encolado);
+                continue;
+            }
+
+            if let Err(encolado) = self.intentar_desencolar(encolado, ctx) {
+                productos_esperando.extend(productos);
+                siguen_en_espera.push_back(encolado);
+            }
+        }
+        self.pedidos_en_espera = siguen_en_espera;
+    }
+
+    /// Cancela un bloqueo cuyo tiempo de vida se cumplio, devolviendo el stock
+    /// y avisando de la expiracion. Si la cancelacion no puede persistirse, el
+    /// bloqueo sigue y se vuelve a intentar mas tarde
+    fn expirar(&mut self, id: (IdPedido, Puerto), ctx: &mut Context<Self>) {
+        self.vencimientos.remove(&id);
+        let operacion = Operacion::Cancelar {
+            id_pedido: id.0,
+            puerto: id.1,
+        };
+        let pedido = ClavePedido::Ecommerce {
+            id_pedido: id.0,
+            puerto: id.1,
+        };
+        match self.ejecutar_auditado(operacion, TipoOperacion::Expirar, Some(pedido)) {
+            Ok(()) => {}
+            Err(ErrorGuardian::RegistroNoDisponible) => {
+                self.programar_vencimiento(id, REINTENTO_VENCIMIENTO, ctx);
+                return;
+            }
+            Err(_) => return,
+        }
+
+        println!(
+            "El bloqueo del pedido {} del ecommerce en puerto {} expiro",
+            id.0, id.1
+        );
+        if let Some(aviso) = &self.aviso_expiracion {
+            aviso.do_send(PedidoExpirado {
+                id_pedido: id.0,
+                puerto: id.1,
+            });
+        }
+        self.atender_pedidos_en_espera(ctx);
+    }
+
+    /// Ejecuta una operacion, anotandola en el libro de movimientos segun su
+    /// tipo: las operaciones sobre bloqueos se atribuyen al pedido de ecommerce
+    /// que las origino, los descuentos a la venta presencial y el resto a la
+    /// reposicion
+    fn ejecutar(&mut self, operacion: Operacion) -> Result<(), ErrorGuardian> {
+        let (tipo, pedido) = match &operacion {
+            Operacion::Descontar { .. } => (TipoOperacion::Descontar, None),
+            Operacion::Reponer { .. } => (TipoOperacion::Reponer, None),
+            Operacion::DescartarVencidos { .. } => (TipoOperacion::Vencer, None),
+            Operacion::Devolver { .. } => (TipoOperacion::Devolver, None),
+            Operacion::Bloquear {
+                id_pedido, puerto, ..
+            } => (TipoOperacion::Bloquear, Some((*id_pedido, *puerto))),
+            Operacion::Confirmar { id_pedido, puerto } => {
+                (TipoOperacion::Confirmar, Some((*id_pedido, *puerto)))
+            }
+            Operacion::Cancelar { id_pedido, puerto } => {
+                (TipoOperacion::Cancelar, Some((*id_pedido, *puerto)))
+            }
+            Operacion::Reducir {
+                id_pedido, puerto, ..
+            } => (TipoOperacion::Reducir, Some((*id_pedido, *puerto))),
+        };
+        let pedido = pedido.map(|(id_pedido, puerto)| ClavePedido::Ecommerce { id_pedido, puerto });
+        self.ejecutar_auditado(operacion, tipo, pedido)
+    }
+
+    /// Devuelve cuanto de cada producto modificaria la operacion dada
+    fn lineas_afectadas(&self, operacion: &Operacion) -> HashMap<IdProducto, CantidadProducto> {
+        match operacion {
+            Operacion::Descontar {
+                id_producto,
+                cantidad,
+                ..
+            }
+            | Operacion::Reponer {
+                id_producto,
+                cantidad,
+                ..
+            }
+            | Operacion::Devolver {
+                id_producto,
+                cantidad,
+            } => HashMap::from([(*id_producto, *cantidad)]),
+            Operacion::DescartarVencidos { fecha } => self.stock.vencidos(*fecha),
+            Operacion::Reducir {
+                id_producto,
+                cantidad,
+                ..
+            } => HashMap::from([(*id_producto, *cantidad)]),
+            Operacion::Bloquear { pedido, .. } => pedido.cantidades_por_producto(),
+            Operacion::Confirmar { id_pedido, puerto }
+            | Operacion::Cancelar { id_pedido, puerto } => self
+                .pedidos_bloqueados
+                .get(&(*id_pedido, *puerto))
+                .map(Carrito::cantidades_por_producto)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Verifica que una operacion pueda aplicarse sobre el estado actual, sin
+    /// modificarlo
+    fn validar(&self, operacion: &Operacion) -> Result<(), ErrorGuardian> {
+        match operacion {
+            Operacion::Descontar {
+                id_producto,
+                cantidad,
+                fecha,
+            } => self.verificar_stock(
+                &HashMap::from([(*id_producto, *cantidad)]),
+                fecha.unwrap_or(NaiveDate::MIN),
+            ),
+            Operacion::Bloquear {
+                id_pedido,
+                puerto,
+                pedido,
+                fecha,
+            } => {
+                if self.pedidos_bloqueados.contains_key(&(*id_pedido, *puerto)) {
+                    return Err(ErrorGuardian::PedidoYaBloqueado);
                 }
-                Ordering::Equal => {
-                    self.stock.remove(&id);
-                    return Ok(());
+                self.verificar_stock(
+                    &pedido.cantidades_por_producto(),
+                    fecha.unwrap_or(NaiveDate::MIN),
+                )
+            }
+            Operacion::Confirmar { id_pedido, puerto }
+            | Operacion::Cancelar { id_pedido, puerto } => {
+                if !self.pedidos_bloqueados.contains_key(&(*id_pedido, *puerto)) {
+                    return Err(ErrorGuardian::PedidoInexistente);
                 }
-                Ordering::Less => return Err(ErrorGuardian::NoHaySuficienteStock),
+                Ok(())
+            }
+            Operacion::Reducir {
+                id_pedido,
+                puerto,
+                id_producto,
+                cantidad,
+            } => self
+                .pedidos_bloqueados
+                .get(&(*id_pedido, *puerto))
+                .ok_or(ErrorGuardian::PedidoInexistente)?
+                .reducir(*id_producto, *cantidad)
+                .map(|_| ())
+                .ok_or(ErrorGuardian::ReduccionInvalida),
+            Operacion::Reponer { .. }
+            | Operacion::DescartarVencidos { .. }
+            | Operacion::Devolver { .. } => Ok(()),
+        }
+    }
+
+    /// Aplica una operacion y, si fue exitosa, la anota en el libro de movimientos
+    /// con el tipo y pedido dados. La operacion se persiste en el registro antes
+    /// de aplicarse, por lo que si no puede escribirse no se aplica: asi nunca se
+    /// informa el resultado de una operacion que un reinicio perderia. Cada cierta
+    /// cantidad de operaciones compacta el registro en un snapshot.
+    /// # Errors
+    /// * `ErrorGuardian::RegistroNoDisponible` si no se pudo persistir la operacion
+    /// * los de la operacion, si no puede aplicarse sobre el estado actual
+    fn ejecutar_auditado(
+        &mut self,
+        operacion: Operacion,
+        tipo: TipoOperacion,
+        pedido: Option<ClavePedido>,
+    ) -> Result<(), ErrorGuardian> {
+        self.validar(&operacion)?;
+        if let Some(registro) = self.registro.as_mut() {
+            if let Err(e) = registro.agregar(operacion.clone()) {
+                eprintln!("No se pudo persistir una operacion del guardian: {}", e);
+                return Err(ErrorGuardian::RegistroNoDisponible);
+            }
+        }
+        let lineas = self.lineas_afectadas(&operacion);
+        self.aplicar(operacion)?;
+        self.auditoria.registrar(tipo, pedido, lineas);
+
+        let bloqueados = self.bloqueados_como_lista();
+        let lotes_bloqueados = self.lotes_bloqueados_como_lista();
+        if let Some(registro) = self.registro.as_mut() {
+            if registro.debe_compactar() {
+                if let Err(e) = registro.compactar(&self.stock, bloqueados, lotes_bloqueados) {
+                    eprintln!("No se pudo compactar el registro del guardian: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Descuenta todas las lineas de un carrito, o ninguna. Primero verifica
+    /// que haya stock para cada producto, y solo entonces lo descuenta, de
+    /// forma que un carrito que no puede completarse no deja nada bloqueado.
+    /// Devuelve los lotes de los que salio cada producto.
+    /// # Errors
+    /// * `ErrorGuardian::NoHaySuficienteStock` si para algun producto el stock no es suficiente
+    /// * `ErrorGuardian::NoHayStock` si no hay ninguna unidad de algun producto
+    fn descontar_carrito(
+        &mut self,
+        carrito: &Carrito,
+        hoy: NaiveDate,
+    ) -> Result<Vec<(IdProducto, Lote)>, ErrorGuardian> {
+        let cantidades = carrito.cantidades_por_producto();
+        self.verificar_stock(&cantidades, hoy)?;
+
+        let mut lotes = Vec::new();
+        for (id, cantidad) in cantidades {
+            let tomados = self.stock.tomar(id, cantidad, hoy)?;
+            lotes.extend(tomados.into_iter().map(|lote| (id, lote)));
+        }
+        Ok(lotes)
+    }
+
+    /// Verifica que haya en la fecha dada las cantidades pedidas de cada producto
+    /// # Errors
+    /// * `ErrorGuardian::NoHaySuficienteStock` si para algun producto el stock no es suficiente
+    /// * `ErrorGuardian::NoHayStock` si no hay ninguna unidad de algun producto
+    fn verificar_stock(
+        &self,
+        cantidades: &HashMap<IdProducto, CantidadProducto>,
+        hoy: NaiveDate,
+    ) -> Result<(), ErrorGuardian> {
+        for (id, cantidad) in cantidades {
+            match self.stock.disponible(*id, hoy) {
+                0 => return Err(ErrorGuardian::NoHayStock),
+                disp if disp < *cantidad => return Err(ErrorGuardian::NoHaySuficienteStock),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Descarta los lotes que ya estan vencidos a la fecha dada. Las unidades
+    /// bloqueadas por pedidos no se descartan, ya que estan por entregarse.
+    fn descartar_vencidos(&mut self, hoy: NaiveDate) {
+        let vencidos = self.stock.vencidos(hoy);
+        if vencidos.is_empty() {
+            return;
+        }
+        if self
+            .ejecutar(Operacion::DescartarVencidos { fecha: hoy })
+            .is_ok()
+        {
+            for (id, cantidad) in vencidos {
+                println!(
+                    "Se descartaron {} unidades vencidas del producto {}",
+                    cantidad, id
+                );
             }
         }
+    }
+}
 
-        Err(ErrorGuardian::NoHayStock)
+/// Quita la cantidad dada de un producto de los lotes de un pedido bloqueado, y
+/// devuelve los lotes quitados. Se devuelven primero las unidades de los lotes
+/// que vencen mas tarde, para que el pedido conserve las que vencen antes
+fn devolver_de_lotes(
+    lotes: &mut Vec<(IdProducto, Lote)>,
+    id_producto: IdProducto,
+    cantidad: CantidadProducto,
+) -> Vec<Lote> {
+    let mut restante = cantidad;
+    let mut devueltos = Vec::new();
+    for (id, lote) in lotes.iter_mut().rev() {
+        if restante == 0 {
+            break;
+        }
+        if *id != id_producto {
+            continue;
+        }
+        let quitadas = restante.min(lote.cantidad);
+        lote.cantidad -= quitadas;
+        restante -= quitadas;
+        devueltos.push(Lote {
+            vencimiento: lote.vencimiento,
+            cantidad: quitadas,
+        });
     }
+    lotes.retain(|(_, lote)| lote.cantidad > 0);
+    devueltos
+}
+
+/// Fecha actual, contra la que se comparan los vencimientos de los lotes
+fn hoy() -> NaiveDate {
+    chrono::Local::now().date_naive()
 }
 
 impl Actor for Guardian {
     type Context = Context<Self>;
 
+    /// Los bloqueos recuperados del registro no tienen quien los resuelva,
+    /// por lo que se les programa la expiracion por defecto. Ademas descarta
+    /// los lotes vencidos, y programa la revision periodica de vencimientos
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let ids: Vec<_> = self.pedidos_bloqueados.keys().cloned().collect();
+        for id in ids {
+            self.programar_vencimiento(id, TTL_BLOQUEO_POR_DEFECTO, ctx);
+        }
+        self.descartar_vencidos(hoy());
+        ctx.run_interval(INTERVALO_VENCIMIENTOS, |guardian, _ctx| {
+            guardian.descartar_vencidos(hoy())
+        });
+    }
+
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         println!("Frenando la ejecucion del guardian");
         Running::Stop
@@ -73,8 +639,20 @@ impl Handler<Descontar> for Guardian {
     type Result = ();
 
     fn handle(&mut self, msg: Descontar, _ctx: &mut Context<Self>) -> Self::Result {
-        match self.descontar_stock(msg.pedido.get_id(), msg.pedido.get_amount()) {
+        let operacion = Operacion::Descontar {
+            id_producto: msg.pedido.get_id(),
+            cantidad: msg.pedido.get_amount().into(),
+            fecha: Some(hoy()),
+        };
+        let pedido = ClavePedido::Empleado(msg.id);
+        match self.ejecutar_auditado(operacion, TipoOperacion::Descontar, Some(pedido)) {
             Ok(_) => {
+                let vendidas = CantidadProducto::from(msg.pedido.get_amount());
+                let por_devolver = self
+                    .por_devolver
+                    .entry((msg.id, msg.pedido.get_id()))
+                    .or_default();
+                *por_devolver = por_devolver.saturating_add(vendidas);
                 msg.sender.do_send(Respuestas::PedidoConcretado(msg.id));
             }
             Err(ErrorGuardian::NoHaySuficienteStock) => {
@@ -99,49 +677,161 @@ impl Handler<ObtenerStock> for Guardian {
     type Result = CantidadProducto;
 
     fn handle(&mut self, msg: ObtenerStock, _ctx: &mut Context<Self>) -> Self::Result {
-        self.stock.get(&msg.id).cloned().unwrap_or(0)
+        self.stock.disponible(msg.id, hoy())
+    }
+}
+
+/// Estado de un producto dentro del inventario: cuantas unidades estan
+/// disponibles y en que lotes, cuantas estan reservadas por pedidos aun no
+/// resueltos, y que pedidos las reservaron
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct EstadoProducto {
+    pub disponibles: CantidadProducto,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lotes: Vec<Lote>,
+    pub reservadas: CantidadProducto,
+    pub reservas: Vec<(IdPedido, Puerto)>,
+}
+
+/// Foto completa del inventario del local, ordenada por id de producto
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct Inventario {
+    pub productos: BTreeMap<IdProducto, EstadoProducto>,
+}
+
+/// Mensaje que permite obtener el inventario completo del local, separando
+/// las unidades disponibles de las reservadas
+#[derive(Message)]
+#[rtype(result = "Inventario")]
+pub struct ObtenerInventario;
+
+impl Handler<ObtenerInventario> for Guardian {
+    type Result = MessageResult<ObtenerInventario>;
+
+    fn handle(&mut self, _msg: ObtenerInventario, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut inventario = Inventario::default();
+        for id in self.stock.productos() {
+            let producto = inventario.productos.entry(id).or_default();
+            producto.disponibles = self.stock.disponible(id, hoy());
+            producto.lotes = self.stock.lotes(id).to_vec();
+        }
+        for (id_reserva, carrito) in &self.pedidos_bloqueados {
+            for (id, cantidad) in carrito.cantidades_por_producto() {
+                let producto = inventario.productos.entry(id).or_default();
+                producto.reservadas = producto.reservadas.saturating_add(cantidad);
+                producto.reservas.push(*id_reserva);
+            }
+        }
+        inventario
+            .productos
+            .values_mut()
+            .for_each(|producto| producto.reservas.sort());
+        MessageResult(inventario)
     }
 }
 
-/// Mensaje que permite bloquear cierta cantidad de stock.
+/// Mensaje que permite consultar el libro de movimientos del guardian,
+/// opcionalmente filtrando por producto o por pedido
+#[derive(Message, Default, Clone, Copy)]
+#[rtype(result = "Vec<Movimiento>")]
+pub struct ConsultarMovimientos {
+    filtro: FiltroMovimientos,
+}
+
+impl ConsultarMovimientos {
+    /// Crea una consulta por todos los movimientos
+    pub fn todos() -> Self {
+        Self::default()
+    }
+
+    /// Crea una consulta por los movimientos de un producto
+    pub fn del_producto(id_producto: IdProducto) -> Self {
+        Self {
+            filtro: FiltroMovimientos {
+                id_producto: Some(id_producto),
+                pedido: None,
+            },
+        }
+    }
+
+    /// Crea una consulta por los movimientos de un pedido
+    pub fn del_pedido(pedido: ClavePedido) -> Self {
+        Self {
+            filtro: FiltroMovimientos {
+                id_producto: None,
+                pedido: Some(pedido),
+            },
+        }
+    }
+}
+
+impl Handler<ConsultarMovimientos> for Guardian {
+    type Result = MessageResult<ConsultarMovimientos>;
+
+    fn handle(&mut self, msg: ConsultarMovimientos, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.auditoria.consultar(&msg.filtro))
+    }
+}
+
+/// Mensaje que permite bloquear el stock de todas las lineas de un carrito.
+/// Si alguna linea no puede cubrirse, no se bloquea ninguna. Si el bloqueo
+/// no se confirma ni cancela antes de que se cumpla su tiempo de vida, el
+/// guardian lo cancela por su cuenta. Un bloqueo sin tiempo de vida no expira
+/// en el guardian, y quien lo pidio debe encargarse de cancelarlo.
 /// # Errors
 /// * si hay stock de un producto, pero no tanto como se pidio devuelve ErrorGuardian::NoHaySuficienteStock
-/// * si no hay stock del producto devuelve ErrorGuardian::NoHayStock
+/// * si no hay stock de algun producto devuelve ErrorGuardian::NoHayStock
+/// * si el pedido ya estaba bloqueado devuelve ErrorGuardian::PedidoYaBloqueado
 #[derive(Message)]
 #[rtype(result = "Result<(), ErrorGuardian>")]
 pub struct Bloquear {
-    pedido: Pedido,
-    id: (IdPedido, Puerto),
+    pub(super) pedido: Carrito,
+    pub(super) id: (IdPedido, Puerto),
+    pub(super) ttl: Option<Duration>,
 }
 
 impl Bloquear {
-    /// Crea un nuevo mensaje de bloqueo de pedido
-    pub fn new(pedido: Pedido, id_pedido: IdPedido, puerto: Puerto) -> Self {
+    /// Crea un nuevo mensaje de bloqueo de pedido, con el tiempo de vida por defecto
+    pub fn new(pedido: Carrito, id_pedido: IdPedido, puerto: Puerto) -> Self {
         Self {
             pedido,
             id: (id_pedido, puerto),
+            ttl: Some(TTL_BLOQUEO_POR_DEFECTO),
         }
     }
+
+    /// Define cuanto tiempo puede permanecer bloqueado el pedido sin resolverse
+    pub fn con_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
 }
 
 impl Handler<Bloquear> for Guardian {
     type Result = Result<(), ErrorGuardian>;
 
-    fn handle(&mut self, msg: Bloquear, _ctx: &mut Context<Self>) -> Self::Result {
-        self.descontar_stock(msg.pedido.get_id(), msg.pedido.get_amount())?;
-        self.pedidos_bloqueados.insert(msg.id, msg.pedido);
-
+    fn handle(&mut self, msg: Bloquear, ctx: &mut Context<Self>) -> Self::Result {
+        self.ejecutar(Operacion::Bloquear {
+            id_pedido: msg.id.0,
+            puerto: msg.id.1,
+            pedido: msg.pedido,
+            fecha: Some(hoy()),
+        })?;
+        if let Some(ttl) = msg.ttl {
+            self.programar_vencimiento(msg.id, ttl, ctx);
+        }
         Ok(())
     }
 }
 
 /// Mensaje que permite confirmar un pedido que se encontraba bloqueado, mediante su identificador.
+/// Devuelve el carrito confirmado, que tiene menos unidades que el bloqueado si el pedido se redujo.
 /// # Errors
 /// * si no habia un pedido bloqueado con ese identificador devuelve ErrorGuardian::PedidoInexistente
 #[derive(Message)]
-#[rtype(result = "Result<(), ErrorGuardian>")]
+#[rtype(result = "Result<Carrito, ErrorGuardian>")]
 pub struct Confirmar {
-    id: (IdPedido, Puerto),
+    pub(super) id: (IdPedido, Puerto),
 }
 
 impl Confirmar {
@@ -154,25 +844,68 @@ impl Confirmar {
 }
 
 impl Handler<Confirmar> for Guardian {
-    type Result = Result<(), ErrorGuardian>;
+    type Result = Result<Carrito, ErrorGuardian>;
+
+    /// El vencimiento del bloqueo solo se cancela una vez confirmado, para que
+    /// si la confirmacion falla el bloqueo igual expire
+    fn handle(&mut self, msg: Confirmar, ctx: &mut Context<Self>) -> Self::Result {
+        let carrito = self
+            .pedidos_bloqueados
+            .get(&msg.id)
+            .cloned()
+            .ok_or(ErrorGuardian::PedidoInexistente)?;
+        self.ejecutar(Operacion::Confirmar {
+            id_pedido: msg.id.0,
+            puerto: msg.id.1,
+        })?;
+        self.cancelar_vencimiento(&msg.id, ctx);
+        Ok(carrito)
+    }
+}
+
+/// Mensaje que permite saber de que lotes salieron las unidades de un pedido
+/// bloqueado, por ejemplo para enviarlas a otro local con su vencimiento.
+/// # Errors
+/// * si no habia un pedido bloqueado con ese identificador devuelve ErrorGuardian::PedidoInexistente
+#[derive(Message)]
+#[rtype(result = "Result<Vec<(IdProducto, Lote)>, ErrorGuardian>")]
+pub struct ObtenerLotesBloqueados {
+    pub(super) id: (IdPedido, Puerto),
+}
+
+impl ObtenerLotesBloqueados {
+    /// Crea un nuevo mensaje para consultar los lotes de un pedido bloqueado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto) -> Self {
+        Self {
+            id: (id_pedido, puerto),
+        }
+    }
+}
 
-    fn handle(&mut self, msg: Confirmar, _ctx: &mut Context<Self>) -> Self::Result {
-        match self.pedidos_bloqueados.remove(&msg.id) {
-            None => Err(ErrorGuardian::PedidoInexistente),
-            Some(_) => Ok(()),
+impl Handler<ObtenerLotesBloqueados> for Guardian {
+    type Result = Result<Vec<(IdProducto, Lote)>, ErrorGuardian>;
+
+    fn handle(&mut self, msg: ObtenerLotesBloqueados, _ctx: &mut Context<Self>) -> Self::Result {
+        if !self.pedidos_bloqueados.contains_key(&msg.id) {
+            return Err(ErrorGuardian::PedidoInexistente);
         }
+        Ok(self
+            .lotes_bloqueados
+            .get(&msg.id)
+            .cloned()
+            .unwrap_or_default())
     }
 }
 
 /// Mensaje que permite cancelar un pedido que se encontraba bloqueado,
-/// mediante su identificador, volviendo a dejar disponible el stock que
-/// estaba bloqueado.
+/// mediante su identificador, volviendo a dejar disponible el stock de
+/// todas las lineas que estaban bloqueadas.
 /// # Errors
 /// * si no habia un pedido bloqueado con ese identificador devuelve ErrorGuardian::PedidoInexistente
 #[derive(Message)]
 #[rtype(result = "Result<(), ErrorGuardian>")]
 pub struct Cancelar {
-    id: (IdPedido, Puerto),
+    pub(super) id: (IdPedido, Puerto),
 }
 
 impl Cancelar {
@@ -187,23 +920,268 @@ impl Cancelar {
 impl Handler<Cancelar> for Guardian {
     type Result = Result<(), ErrorGuardian>;
 
-    fn handle(&mut self, msg: Cancelar, _ctx: &mut Context<Self>) -> Self::Result {
-        match self.pedidos_bloqueados.remove(&msg.id) {
-            Some(p) => {
-                *self.stock.entry(p.get_id()).or_insert(0) += p.get_amount() as CantidadProducto;
-                Ok(())
-            }
-            None => Err(ErrorGuardian::PedidoInexistente),
+    fn handle(&mut self, msg: Cancelar, ctx: &mut Context<Self>) -> Self::Result {
+        self.ejecutar(Operacion::Cancelar {
+            id_pedido: msg.id.0,
+            puerto: msg.id.1,
+        })?;
+        self.cancelar_vencimiento(&msg.id, ctx);
+        self.atender_pedidos_en_espera(ctx);
+        Ok(())
+    }
+}
+
+/// Mensaje que vuelve a contar el tiempo de vida de un bloqueo desde ahora,
+/// con el ttl dado, por ejemplo cuando su entrega recien empieza tras esperar
+/// su turno. Un bloqueo sin tiempo de vida sigue sin vencer.
+/// # Errors
+/// * si no habia un pedido bloqueado con ese identificador devuelve ErrorGuardian::PedidoInexistente
+#[derive(Message)]
+#[rtype(result = "Result<(), ErrorGuardian>")]
+pub struct Renovar {
+    pub(super) id: (IdPedido, Puerto),
+    pub(super) ttl: Duration,
+}
+
+impl Renovar {
+    /// Crea un nuevo mensaje de renovacion del bloqueo de un pedido
+    pub fn new(id_pedido: IdPedido, puerto: Puerto, ttl: Duration) -> Self {
+        Self {
+            id: (id_pedido, puerto),
+            ttl,
+        }
+    }
+}
+
+impl Handler<Renovar> for Guardian {
+    type Result = Result<(), ErrorGuardian>;
+
+    fn handle(&mut self, msg: Renovar, ctx: &mut Context<Self>) -> Self::Result {
+        if !self.pedidos_bloqueados.contains_key(&msg.id) {
+            return Err(ErrorGuardian::PedidoInexistente);
+        }
+        if self.vencimientos.contains_key(&msg.id) {
+            self.programar_vencimiento(msg.id, msg.ttl, ctx);
+        }
+        Ok(())
+    }
+}
+
+/// Mensaje que permite quitar unidades de un producto de un pedido que se
+/// encontraba bloqueado, a pedido del cliente, volviendo a dejarlas disponibles.
+/// El pedido sigue bloqueado con el resto de sus lineas.
+/// # Errors
+/// * si no habia un pedido bloqueado con ese identificador devuelve ErrorGuardian::PedidoInexistente
+/// * si el pedido no tiene mas unidades del producto que las que se quieren
+///   quitar devuelve ErrorGuardian::ReduccionInvalida
+#[derive(Message)]
+#[rtype(result = "Result<(), ErrorGuardian>")]
+pub struct Reducir {
+    pub(super) id: (IdPedido, Puerto),
+    pub(super) id_producto: IdProducto,
+    pub(super) cantidad: CantidadProducto,
+}
+
+impl Reducir {
+    /// Crea un nuevo mensaje para quitar unidades de un producto de un pedido bloqueado
+    pub fn new(
+        id_pedido: IdPedido,
+        puerto: Puerto,
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+    ) -> Self {
+        Self {
+            id: (id_pedido, puerto),
+            id_producto,
+            cantidad,
+        }
+    }
+}
+
+impl Handler<Reducir> for Guardian {
+    type Result = Result<(), ErrorGuardian>;
+
+    fn handle(&mut self, msg: Reducir, ctx: &mut Context<Self>) -> Self::Result {
+        self.ejecutar(Operacion::Reducir {
+            id_pedido: msg.id.0,
+            puerto: msg.id.1,
+            id_producto: msg.id_producto,
+            cantidad: msg.cantidad,
+        })?;
+        self.atender_pedidos_en_espera(ctx);
+        Ok(())
+    }
+}
+
+/// Mensaje que permite agregar unidades de un producto al stock, por
+/// ejemplo al recibir mercaderia de un proveedor. Las unidades forman un
+/// lote, que por defecto no vence
+/// # Errors
+/// * `ErrorGuardian::RegistroNoDisponible` si no se pudo persistir la reposicion
+#[derive(Message)]
+#[rtype(result = "Result<(), ErrorGuardian>")]
+pub struct Reponer {
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+    pub vencimiento: Option<NaiveDate>,
+}
+
+impl Reponer {
+    /// Crea un nuevo mensaje de reposicion de un producto
+    pub fn new(id_producto: IdProducto, cantidad: CantidadProducto) -> Self {
+        Self {
+            id_producto,
+            cantidad,
+            vencimiento: None,
+        }
+    }
+
+    /// Indica la fecha en que vence el lote repuesto
+    pub fn con_vencimiento(mut self, vencimiento: Option<NaiveDate>) -> Self {
+        self.vencimiento = vencimiento;
+        self
+    }
+}
+
+impl Handler<Reponer> for Guardian {
+    type Result = Result<(), ErrorGuardian>;
+
+    fn handle(&mut self, msg: Reponer, ctx: &mut Context<Self>) -> Self::Result {
+        self.ejecutar(Operacion::Reponer {
+            id_producto: msg.id_producto,
+            cantidad: msg.cantidad,
+            vencimiento: msg.vencimiento,
+        })?;
+        println!(
+            "Se repusieron {} unidades del producto {}",
+            msg.cantidad, msg.id_producto
+        );
+        self.atender_pedidos_en_espera(ctx);
+        Ok(())
+    }
+}
+
+/// Mensaje que devuelve al stock unidades de una venta presencial, identificada
+/// por su numero. Solo pueden devolverse unidades del producto vendido, y no
+/// mas que las que se vendieron menos las ya devueltas, que el guardian lleva
+/// por cada venta. Como no se sabe de que lote salieron, vuelven sin vencimiento.
+/// # Errors
+/// * `ErrorGuardian::DevolucionInvalida` si la venta no vendio el producto, o
+///   no le quedan tantas unidades por devolver
+#[derive(Message)]
+#[rtype(result = "Result<(), ErrorGuardian>")]
+pub struct Devolver {
+    pub venta: usize,
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+}
+
+impl Devolver {
+    /// Crea un nuevo mensaje de devolucion de unidades de una venta
+    pub fn new(venta: usize, id_producto: IdProducto, cantidad: CantidadProducto) -> Self {
+        Self {
+            venta,
+            id_producto,
+            cantidad,
         }
     }
 }
 
+impl Handler<Devolver> for Guardian {
+    type Result = Result<(), ErrorGuardian>;
+
+    fn handle(&mut self, msg: Devolver, ctx: &mut Context<Self>) -> Self::Result {
+        let clave = (msg.venta, msg.id_producto);
+        let devolvibles = self.por_devolver.get(&clave).copied().unwrap_or(0);
+        if msg.cantidad == 0 || msg.cantidad > devolvibles {
+            return Err(ErrorGuardian::DevolucionInvalida);
+        }
+
+        let operacion = Operacion::Devolver {
+            id_producto: msg.id_producto,
+            cantidad: msg.cantidad,
+        };
+        let pedido = ClavePedido::Empleado(msg.venta);
+        self.ejecutar_auditado(operacion, TipoOperacion::Devolver, Some(pedido))?;
+        if msg.cantidad == devolvibles {
+            self.por_devolver.remove(&clave);
+        } else {
+            self.por_devolver.insert(clave, devolvibles - msg.cantidad);
+        }
+        self.atender_pedidos_en_espera(ctx);
+        Ok(())
+    }
+}
+
+/// Mensaje que deja un pedido en la cola de espera del guardian, para cuando
+/// ningun local tiene stock y el ecommerce pidio esperar. Apenas el pedido
+/// puede cubrirse, el guardian lo bloquea y avisa por el canal recibido, a
+/// partir de lo cual se resuelve como cualquier otro pedido bloqueado.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Encolar {
+    pub(super) pedido: Carrito,
+    pub(super) id: (IdPedido, Puerto),
+    pub(super) aviso: oneshot::Sender<()>,
+}
+
+impl Encolar {
+    /// Crea un nuevo mensaje para dejar en espera un pedido
+    pub fn new(
+        pedido: Carrito,
+        id_pedido: IdPedido,
+        puerto: Puerto,
+        aviso: oneshot::Sender<()>,
+    ) -> Self {
+        Self {
+            pedido,
+            id: (id_pedido, puerto),
+            aviso,
+        }
+    }
+}
+
+impl Handler<Encolar> for Guardian {
+    type Result = ();
+
+    fn handle(&mut self, msg: Encolar, ctx: &mut Context<Self>) -> Self::Result {
+        self.pedidos_en_espera.push_back(msg);
+        self.atender_pedidos_en_espera(ctx);
+    }
+}
+
+/// Mensaje que vacia la cola de espera del guardian, por ejemplo al drenar el
+/// local. Quienes encolaron los pedidos se enteran porque el aviso nunca llega.
+/// Responde cuantos pedidos habia en espera
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct VaciarEspera;
+
+impl Handler<VaciarEspera> for Guardian {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: VaciarEspera, _ctx: &mut Context<Self>) -> Self::Result {
+        std::mem::take(&mut self.pedidos_en_espera).len()
+    }
+}
+
+/// Mensaje que envia el guardian cuando un bloqueo expira sin haber sido
+/// confirmado ni cancelado, para que se le avise al ecommerce
+#[derive(Message, Debug, PartialEq)]
+#[rtype(result = "()")]
+pub struct PedidoExpirado {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+}
+
 #[cfg(test)]
 mod tests {
 
     use actix::actors::mocker::Mocker;
 
     use super::*;
+    use crate::local::auditoria::Canal;
+    use crate::pedido::Pedido;
 
     fn crear_guardian() -> Addr<Guardian> {
         let mut stock = HashMap::new();
@@ -215,6 +1193,42 @@ mod tests {
         guardian.start()
     }
 
+    #[actix_rt::test]
+    async fn las_devoluciones_no_dependen_del_libro_de_movimientos() {
+        let empleado: Recipient<Respuestas> =
+            Mocker::<Respuestas>::mock(Box::new(|_msg, _ctx| Box::new(Some(()))))
+                .start()
+                .recipient();
+        let mut guardian = Guardian::new(HashMap::from([(1, 5), (2, 5)]));
+        guardian.auditoria = Auditoria::new().con_max_movimientos(1);
+        let guardian = guardian.start();
+
+        guardian
+            .send(Descontar::new(Pedido::new(1, 3), 7, empleado.clone()))
+            .await
+            .unwrap();
+        guardian
+            .send(Descontar::new(Pedido::new(2, 1), 8, empleado))
+            .await
+            .unwrap();
+
+        assert!(guardian.send(Devolver::new(7, 1, 2)).await.unwrap().is_ok());
+        assert!(matches!(
+            guardian.send(Devolver::new(7, 1, 2)).await.unwrap(),
+            Err(ErrorGuardian::DevolucionInvalida)
+        ));
+        assert!(guardian.send(Devolver::new(7, 1, 1)).await.unwrap().is_ok());
+        assert!(matches!(
+            guardian.send(Devolver::new(7, 1, 1)).await.unwrap(),
+            Err(ErrorGuardian::DevolucionInvalida)
+        ));
+        assert!(matches!(
+            guardian.send(Devolver::new(8, 1, 1)).await.unwrap(),
+            Err(ErrorGuardian::DevolucionInvalida)
+        ));
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+    }
+
     #[actix_rt::test]
     async fn test_escenario_descontar_descuenta_cuando_hay_stock_justo() {
         let (tx, mut rx) = tokio::sync::mpsc::channel(1);
@@ -344,7 +1358,7 @@ mod tests {
         let addr = crear_guardian();
 
         let res = addr
-            .send(Bloquear::new(Pedido::new(1, 1), 1, 1))
+            .send(Bloquear::new(Carrito::from(Pedido::new(1, 1)), 1, 1))
             .await
             .unwrap();
         assert!(res.is_ok());
@@ -356,7 +1370,7 @@ mod tests {
         let addr = crear_guardian();
 
         let res = addr
-            .send(Bloquear::new(Pedido::new(7, 10), 1, 1))
+            .send(Bloquear::new(Carrito::from(Pedido::new(7, 10)), 1, 1))
             .await
             .unwrap();
         assert!(res.is_err());
@@ -367,13 +1381,29 @@ mod tests {
         let addr = crear_guardian();
 
         let res = addr
-            .send(Bloquear::new(Pedido::new(3, 10), 1, 1))
+            .send(Bloquear::new(Carrito::from(Pedido::new(3, 10)), 1, 1))
             .await
             .unwrap();
         assert!(res.is_err());
         assert_eq!(addr.send(ObtenerStock { id: 3 }).await.unwrap(), 5);
     }
 
+    #[actix_rt::test]
+    async fn bloquear_dos_veces_el_mismo_pedido_no_descuenta_de_nuevo() {
+        let addr = crear_guardian();
+
+        addr.send(Bloquear::new(Carrito::from(Pedido::new(1, 2)), 1, 1))
+            .await
+            .unwrap()
+            .unwrap();
+        let res = addr
+            .send(Bloquear::new(Carrito::from(Pedido::new(1, 2)), 1, 1))
+            .await
+            .unwrap();
+        assert!(matches!(res, Err(ErrorGuardian::PedidoYaBloqueado)));
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+    }
+
     #[actix_rt::test]
     async fn confirmar_devuelve_error_si_no_habia_pedido_bloqueado() {
         let addr = crear_guardian();
@@ -395,7 +1425,7 @@ mod tests {
         let addr = crear_guardian();
 
         let res = addr
-            .send(Bloquear::new(Pedido::new(1, 1), 1, 1))
+            .send(Bloquear::new(Carrito::from(Pedido::new(1, 1)), 1, 1))
             .await
             .unwrap();
         assert!(res.is_ok());
@@ -410,7 +1440,7 @@ mod tests {
         let addr = crear_guardian();
 
         let res = addr
-            .send(Bloquear::new(Pedido::new(1, 1), 1, 1))
+            .send(Bloquear::new(Carrito::from(Pedido::new(1, 1)), 1, 1))
             .await
             .unwrap();
         assert!(res.is_ok());
@@ -420,4 +1450,476 @@ mod tests {
 
         assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
     }
+
+    #[actix_rt::test]
+    async fn bloquear_carrito_bloquea_todas_las_lineas_cuando_hay_stock() {
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 5)]).unwrap();
+
+        let res = addr.send(Bloquear::new(carrito, 1, 1)).await.unwrap();
+        assert!(res.is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn bloquear_carrito_no_bloquea_nada_si_alguna_linea_no_tiene_stock() {
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![
+            Pedido::new(1, 2),
+            Pedido::new(2, 3),
+            Pedido::new(3, 6),
+        ])
+        .unwrap();
+
+        let res = addr.send(Bloquear::new(carrito, 1, 1)).await.unwrap();
+        assert!(res.is_err());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 5);
+        assert_eq!(addr.send(ObtenerStock { id: 3 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn bloquear_carrito_considera_lineas_repetidas_del_mismo_producto() {
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![Pedido::new(1, 3), Pedido::new(1, 3)]).unwrap();
+
+        let res = addr.send(Bloquear::new(carrito, 1, 1)).await.unwrap();
+        assert!(res.is_err());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn cancelar_carrito_restaura_stock_de_todas_las_lineas() {
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 5)]).unwrap();
+
+        assert!(addr
+            .send(Bloquear::new(carrito, 1, 1))
+            .await
+            .unwrap()
+            .is_ok());
+        assert!(addr.send(Cancelar::new(1, 1)).await.unwrap().is_ok());
+
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 5);
+    }
+
+    #[test]
+    fn recuperar_reconstruye_stock_y_bloqueos_del_registro() {
+        let dir = std::env::temp_dir().join(format!("guardian-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let stock = HashMap::from([(1, 5), (2, 5)]);
+        let mut guardian = Guardian::new(stock)
+            .con_registro(Registro::abrir(&dir, 0).unwrap())
+            .unwrap();
+
+        let carrito = Carrito::from(Pedido::new(1, 2));
+        let operaciones = [
+            Operacion::Bloquear {
+                id_pedido: 1,
+                puerto: 1,
+                pedido: carrito.clone(),
+                fecha: None,
+            },
+            Operacion::Descontar {
+                id_producto: 2,
+                cantidad: 1,
+                fecha: None,
+            },
+            Operacion::Bloquear {
+                id_pedido: 2,
+                puerto: 1,
+                pedido: carrito.clone(),
+                fecha: None,
+            },
+            Operacion::Cancelar {
+                id_pedido: 2,
+                puerto: 1,
+            },
+        ];
+        for operacion in operaciones {
+            assert!(guardian.ejecutar(operacion).is_ok());
+        }
+        drop(guardian);
+
+        let recuperado = Guardian::recuperar(Registro::abrir(&dir, 0).unwrap()).unwrap();
+        assert_eq!(
+            recuperado.stock.como_tabla(),
+            HashMap::from([(1, 3), (2, 4)])
+        );
+        assert_eq!(recuperado.pedidos_bloqueados.get(&(1, 1)), Some(&carrito));
+        assert!(!recuperado.pedidos_bloqueados.contains_key(&(2, 1)));
+    }
+
+    #[test]
+    fn bloquear_toma_los_lotes_que_vencen_antes_y_cancelar_los_devuelve() {
+        let vence = |dia| NaiveDate::from_ymd_opt(2024, 5, dia).unwrap();
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::con_vencimiento(3, vence(20)));
+        stock.agregar(1, Lote::con_vencimiento(2, vence(10)));
+        let mut guardian = Guardian::new(stock);
+
+        let bloqueo = Operacion::Bloquear {
+            id_pedido: 1,
+            puerto: 1,
+            pedido: Carrito::from(Pedido::new(1, 3)),
+            fecha: None,
+        };
+        assert!(guardian.ejecutar(bloqueo).is_ok());
+        assert_eq!(
+            guardian.stock.lotes(1),
+            &[Lote::con_vencimiento(2, vence(20))]
+        );
+
+        let cancelacion = Operacion::Cancelar {
+            id_pedido: 1,
+            puerto: 1,
+        };
+        assert!(guardian.ejecutar(cancelacion).is_ok());
+        assert_eq!(
+            guardian.stock.lotes(1),
+            &[
+                Lote::con_vencimiento(2, vence(10)),
+                Lote::con_vencimiento(3, vence(20))
+            ]
+        );
+
+        guardian.descartar_vencidos(vence(15));
+        assert_eq!(guardian.stock.disponible(1, vence(15)), 3);
+        let descartes = guardian.auditoria.consultar(&FiltroMovimientos::default());
+        let ultimo = descartes.last().unwrap();
+        assert_eq!(ultimo.operacion, TipoOperacion::Vencer);
+        assert_eq!(ultimo.cantidad, 2);
+    }
+
+    #[test]
+    fn una_operacion_que_no_puede_persistirse_no_se_aplica() {
+        let dir = std::env::temp_dir().join(format!("guardian-lleno-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Toda escritura en /dev/full falla por falta de espacio
+        std::os::unix::fs::symlink("/dev/full", dir.join("guardian0.log")).unwrap();
+        let mut guardian = Guardian::new(HashMap::from([(1, 5)]));
+        guardian.registro = Some(Registro::abrir(&dir, 0).unwrap());
+
+        let descuento = Operacion::Descontar {
+            id_producto: 1,
+            cantidad: 2,
+            fecha: None,
+        };
+        assert!(matches!(
+            guardian.ejecutar(descuento),
+            Err(ErrorGuardian::RegistroNoDisponible)
+        ));
+        assert_eq!(guardian.stock.disponible(1, hoy()), 5);
+        assert!(guardian
+            .auditoria
+            .consultar(&FiltroMovimientos::default())
+            .is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[actix_rt::test]
+    async fn un_bloqueo_que_no_pudo_resolverse_conserva_su_vencimiento() {
+        let dir = std::env::temp_dir().join(format!("guardian-vence-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::os::unix::fs::symlink("/dev/full", dir.join("guardian0.log")).unwrap();
+        let mut guardian = Guardian::new(HashMap::from([(1, 5)]));
+        let mut ctx = Context::new();
+        let carrito = Carrito::from(Pedido::new(1, 2));
+        assert!(guardian
+            .handle(Bloquear::new(carrito, 1, 1), &mut ctx)
+            .is_ok());
+
+        guardian.registro = Some(Registro::abrir(&dir, 0).unwrap());
+        assert!(matches!(
+            guardian.handle(Confirmar::new(1, 1), &mut ctx),
+            Err(ErrorGuardian::RegistroNoDisponible)
+        ));
+        assert!(matches!(
+            guardian.handle(Cancelar::new(1, 1), &mut ctx),
+            Err(ErrorGuardian::RegistroNoDisponible)
+        ));
+        assert!(guardian.pedidos_bloqueados.contains_key(&(1, 1)));
+        assert!(guardian.vencimientos.contains_key(&(1, 1)));
+
+        guardian.registro = None;
+        assert!(guardian.handle(Confirmar::new(1, 1), &mut ctx).is_ok());
+        assert!(!guardian.vencimientos.contains_key(&(1, 1)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reducir_devuelve_primero_las_unidades_de_los_lotes_que_vencen_despues() {
+        let vence = |dia| NaiveDate::from_ymd_opt(2024, 5, dia).unwrap();
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::con_vencimiento(2, vence(10)));
+        stock.agregar(1, Lote::con_vencimiento(3, vence(20)));
+        let mut guardian = Guardian::new(stock);
+        let carrito = Carrito::new(vec![Pedido::new(1, 4)]).unwrap();
+        assert!(guardian
+            .ejecutar(Operacion::Bloquear {
+                id_pedido: 1,
+                puerto: 1,
+                pedido: carrito,
+                fecha: None,
+            })
+            .is_ok());
+
+        let reduccion = Operacion::Reducir {
+            id_pedido: 1,
+            puerto: 1,
+            id_producto: 1,
+            cantidad: 3,
+        };
+        assert!(guardian.ejecutar(reduccion).is_ok());
+        assert_eq!(
+            guardian.stock.lotes(1),
+            &[
+                Lote::con_vencimiento(1, vence(10)),
+                Lote::con_vencimiento(3, vence(20))
+            ]
+        );
+        assert_eq!(
+            guardian.pedidos_bloqueados.get(&(1, 1)),
+            Some(&Carrito::from(Pedido::new(1, 1)))
+        );
+        let movimientos = guardian.auditoria.consultar(&FiltroMovimientos::default());
+        assert_eq!(
+            movimientos.last().unwrap().operacion,
+            TipoOperacion::Reducir
+        );
+    }
+
+    #[actix_rt::test]
+    async fn reducir_rechaza_pedidos_no_bloqueados_y_cantidades_invalidas() {
+        let addr = crear_guardian();
+        assert!(matches!(
+            addr.send(Reducir::new(1, 1, 1, 1)).await.unwrap(),
+            Err(ErrorGuardian::PedidoInexistente)
+        ));
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 5)]).unwrap();
+        addr.send(Bloquear::new(carrito, 1, 1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            addr.send(Reducir::new(1, 1, 1, 2)).await.unwrap(),
+            Err(ErrorGuardian::ReduccionInvalida)
+        ));
+        assert!(addr.send(Reducir::new(1, 1, 2, 4)).await.unwrap().is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 4);
+        let confirmado = addr.send(Confirmar::new(1, 1)).await.unwrap().unwrap();
+        assert_eq!(
+            confirmado.cantidades_por_producto(),
+            HashMap::from([(1, 2), (2, 1)])
+        );
+        assert!(matches!(
+            addr.send(Reducir::new(1, 1, 1, 1)).await.unwrap(),
+            Err(ErrorGuardian::PedidoInexistente)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn bloqueo_expirado_devuelve_el_stock_y_avisa() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let aviso: Recipient<PedidoExpirado> =
+            Mocker::<PedidoExpirado>::mock(Box::new(move |msg, _ctx| {
+                tx.try_send(msg).unwrap();
+                Box::new(Some(()))
+            }))
+            .start()
+            .recipient();
+        let stock = HashMap::from([(1, 5)]);
+        let addr = Guardian::new(stock).con_aviso_expiracion(aviso).start();
+
+        let bloqueo = Bloquear::new(Carrito::from(Pedido::new(1, 2)), 7, 3000)
+            .con_ttl(Duration::from_millis(50));
+        assert!(addr.send(bloqueo).await.unwrap().is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+
+        let expirado = rx.recv().await.unwrap();
+        assert_eq!(
+            expirado.downcast_ref::<PedidoExpirado>().unwrap(),
+            &PedidoExpirado {
+                id_pedido: 7,
+                puerto: 3000
+            }
+        );
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+        assert!(addr.send(Confirmar::new(7, 3000)).await.unwrap().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn bloqueo_confirmado_no_expira() {
+        let addr = crear_guardian();
+
+        let bloqueo = Bloquear::new(Carrito::from(Pedido::new(1, 2)), 1, 1)
+            .con_ttl(Duration::from_millis(50));
+        assert!(addr.send(bloqueo).await.unwrap().is_ok());
+        assert!(addr.send(Confirmar::new(1, 1)).await.unwrap().is_ok());
+
+        actix_rt::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn reponer_agrega_stock_de_un_producto_existente_o_nuevo() {
+        let addr = crear_guardian();
+
+        addr.send(Reponer::new(1, 10)).await.unwrap().unwrap();
+        addr.send(Reponer::new(9, 3)).await.unwrap().unwrap();
+
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 15);
+        assert_eq!(addr.send(ObtenerStock { id: 9 }).await.unwrap(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn reponer_permite_bloquear_un_producto_agotado() {
+        let addr = crear_guardian();
+        let carrito = Carrito::from(Pedido::new(7, 2));
+
+        let res = addr
+            .send(Bloquear::new(carrito.clone(), 1, 1))
+            .await
+            .unwrap();
+        assert!(res.is_err());
+
+        addr.send(Reponer::new(7, 2)).await.unwrap().unwrap();
+        let res = addr.send(Bloquear::new(carrito, 1, 1)).await.unwrap();
+        assert!(res.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn obtener_inventario_separa_unidades_disponibles_y_reservadas() {
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 5)]).unwrap();
+        assert!(addr
+            .send(Bloquear::new(carrito, 4, 3000))
+            .await
+            .unwrap()
+            .is_ok());
+        assert!(addr
+            .send(Bloquear::new(Carrito::from(Pedido::new(1, 1)), 2, 3001))
+            .await
+            .unwrap()
+            .is_ok());
+
+        let inventario = addr.send(ObtenerInventario).await.unwrap();
+
+        assert_eq!(
+            inventario.productos.get(&1),
+            Some(&EstadoProducto {
+                disponibles: 2,
+                lotes: vec![Lote::sin_vencimiento(2)],
+                reservadas: 3,
+                reservas: vec![(2, 3001), (4, 3000)],
+            })
+        );
+        assert_eq!(
+            inventario.productos.get(&2),
+            Some(&EstadoProducto {
+                disponibles: 0,
+                lotes: vec![],
+                reservadas: 5,
+                reservas: vec![(4, 3000)],
+            })
+        );
+        assert_eq!(inventario.productos.get(&3).unwrap().disponibles, 5);
+    }
+
+    #[actix_rt::test]
+    async fn consultar_movimientos_devuelve_las_operaciones_de_cada_canal() {
+        let empleado = Mocker::<Respuestas>::mock(Box::new(|_msg, _ctx| Box::new(Some(()))))
+            .start()
+            .recipient();
+        let addr = crear_guardian();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 1)]).unwrap();
+        addr.send(Bloquear::new(carrito, 1, 3000))
+            .await
+            .unwrap()
+            .unwrap();
+        addr.send(Confirmar::new(1, 3000)).await.unwrap().unwrap();
+        addr.send(Descontar::new(Pedido::new(1, 1), 7, empleado))
+            .await
+            .unwrap();
+        addr.send(Reponer::new(1, 4)).await.unwrap().unwrap();
+
+        let del_producto = addr
+            .send(ConsultarMovimientos::del_producto(1))
+            .await
+            .unwrap();
+        let operaciones: Vec<_> = del_producto
+            .iter()
+            .map(|m| (m.operacion, m.cantidad, m.canal))
+            .collect();
+        assert_eq!(
+            operaciones,
+            vec![
+                (TipoOperacion::Bloquear, 2, Canal::Ecommerce),
+                (TipoOperacion::Confirmar, 2, Canal::Ecommerce),
+                (TipoOperacion::Descontar, 1, Canal::Empleado),
+                (TipoOperacion::Reponer, 4, Canal::Reposicion),
+            ]
+        );
+
+        let del_pedido = addr
+            .send(ConsultarMovimientos::del_pedido(ClavePedido::Empleado(7)))
+            .await
+            .unwrap();
+        assert_eq!(del_pedido.len(), 1);
+        assert_eq!(del_pedido[0].id_producto, 1);
+    }
+
+    #[actix_rt::test]
+    async fn pedido_encolado_se_bloquea_al_reponer_stock() {
+        let addr = crear_guardian();
+        let (tx, rx) = oneshot::channel();
+
+        addr.send(Encolar::new(Carrito::from(Pedido::new(7, 2)), 1, 1, tx))
+            .await
+            .unwrap();
+        addr.send(Reponer::new(7, 3)).await.unwrap().unwrap();
+
+        assert!(rx.await.is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 7 }).await.unwrap(), 1);
+        assert!(addr.send(Confirmar::new(1, 1)).await.unwrap().is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn pedidos_encolados_se_atienden_en_orden_de_llegada() {
+        let addr = crear_guardian();
+        let (tx_primero, mut rx_primero) = oneshot::channel();
+        let (tx_segundo, mut rx_segundo) = oneshot::channel();
+
+        addr.send(Encolar::new(
+            Carrito::from(Pedido::new(7, 3)),
+            1,
+            1,
+            tx_primero,
+        ))
+        .await
+        .unwrap();
+        addr.send(Encolar::new(
+            Carrito::from(Pedido::new(7, 1)),
+            2,
+            1,
+            tx_segundo,
+        ))
+        .await
+        .unwrap();
+
+        // Alcanza para el segundo, pero no puede adelantarse al primero
+        addr.send(Reponer::new(7, 2)).await.unwrap().unwrap();
+        assert!(rx_primero.try_recv().is_err());
+        assert!(rx_segundo.try_recv().is_err());
+
+        addr.send(Reponer::new(7, 2)).await.unwrap().unwrap();
+        assert!(rx_primero.await.is_ok());
+        assert!(rx_segundo.await.is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 7 }).await.unwrap(), 0);
+    }
 }
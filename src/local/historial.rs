@@ -0,0 +1,376 @@
+//! Este modulo define el historial de pedidos de ecommerce que recibio el local.
+//! Como el ecommerce y los demas locales reenvian los pedidos cuando un ack o un
+//! resultado se demora, un mismo pedido puede llegar varias veces; el historial
+//! permite reconocer los repetidos para no volver a reservarlos. Tambien
+//! permite responder las consultas de los ecommerce por el estado de sus pedidos.
+//! Los pedidos ya resueltos por el local, tanto los informados como los
+//! delegados, se recuerdan hasta un maximo, a partir del cual se olvidan los que
+//! se resolvieron hace mas tiempo, para que el historial no crezca sin limite
+//! mientras el local sigue atendiendo.
+
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::Mutex;
+
+use crate::aliases::{IdLocal, IdPedido, Puerto};
+use crate::mensajes::{EstadoConsultado, MensajesServidor};
+
+/// Estado en el que se encuentra un pedido ya recibido por el local
+#[derive(Debug, Clone, PartialEq)]
+pub enum EstadoPedido {
+    /// El local esta resolviendo el pedido
+    EnProceso,
+    /// El local tiene reservado el pedido y lo esta entregando. Hasta que se
+    /// resuelva la entrega, el cliente puede retirarlo o reducirlo
+    Reservado,
+    /// El local delego el pedido al local indicado, que acuso recibirlo
+    Delegado(IdLocal),
+    /// El local le informo al ecommerce el resultado dado
+    Informado(MensajesServidor),
+}
+
+/// Cantidad de pedidos resueltos (informados o delegados) que recuerda el
+/// historial por defecto. Un reenvio de un pedido olvidado se procesaria como
+/// uno nuevo, pero los reenvios dejan de llegar mucho antes de que se resuelvan
+/// tantos pedidos
+pub const MAX_PEDIDOS_RESUELTOS: usize = 10_000;
+
+impl EstadoPedido {
+    /// Indica si el local ya termino con el pedido, ya sea porque informo su
+    /// resultado o porque lo delego. Los demas estados, incluido el pedido en
+    /// espera de stock, corresponden a pedidos en curso que no pueden olvidarse
+    fn resuelto(&self) -> bool {
+        match self {
+            EstadoPedido::Informado(MensajesServidor::EnEspera(_)) => false,
+            EstadoPedido::Informado(_) | EstadoPedido::Delegado(_) => true,
+            EstadoPedido::EnProceso | EstadoPedido::Reservado => false,
+        }
+    }
+}
+
+/// Estado de cada pedido, junto con el orden en que se resolvieron los que ya
+/// estan resueltos, para olvidar primero los mas antiguos
+#[derive(Default)]
+struct Pedidos {
+    estados: HashMap<(Puerto, IdPedido), EstadoPedido>,
+    resueltos: BTreeMap<u64, (Puerto, IdPedido)>,
+    orden_resuelto: HashMap<(Puerto, IdPedido), u64>,
+    siguiente_orden: u64,
+}
+
+impl Pedidos {
+    fn get(&self, clave: &(Puerto, IdPedido)) -> Option<&EstadoPedido> {
+        self.estados.get(clave)
+    }
+
+    /// Cambia el estado de un pedido. Si queda resuelto, pasa a ser el ultimo
+    /// resuelto, y si hay mas resueltos que el maximo se olvida el primero
+    fn fijar(&mut self, clave: (Puerto, IdPedido), estado: EstadoPedido, maximo: usize) {
+        if let Some(orden) = self.orden_resuelto.remove(&clave) {
+            self.resueltos.remove(&orden);
+        }
+        if estado.resuelto() {
+            self.resueltos.insert(self.siguiente_orden, clave);
+            self.orden_resuelto.insert(clave, self.siguiente_orden);
+            self.siguiente_orden += 1;
+        }
+        self.estados.insert(clave, estado);
+
+        while self.resueltos.len() > maximo {
+            if let Some((_, olvidado)) = self.resueltos.pop_first() {
+                self.orden_resuelto.remove(&olvidado);
+                self.estados.remove(&olvidado);
+            }
+        }
+    }
+}
+
+/// Tabla de los pedidos recibidos, identificados por el puerto del ecommerce
+/// que los realizo y su id
+pub struct HistorialPedidos {
+    pedidos: Mutex<Pedidos>,
+    max_resueltos: usize,
+}
+
+impl Default for HistorialPedidos {
+    fn default() -> Self {
+        Self {
+            pedidos: Mutex::new(Pedidos::default()),
+            max_resueltos: MAX_PEDIDOS_RESUELTOS,
+        }
+    }
+}
+
+impl HistorialPedidos {
+    /// Crea un historial vacio
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indica cuantos pedidos resueltos recuerda el historial. Si no se
+    /// indica, es `MAX_PEDIDOS_RESUELTOS`
+    pub fn con_max_resueltos(mut self, max_resueltos: usize) -> Self {
+        self.max_resueltos = max_resueltos;
+        self
+    }
+
+    /// Registra la llegada de un pedido, y devuelve si debe procesarse. Un pedido
+    /// se procesa la primera vez que llega, o si vuelve luego de que este local lo
+    /// delego y recorrio el resto de los locales. En otro caso es un reenvio, y se
+    /// devuelve su estado para poder responderlo sin volver a reservarlo.
+    pub async fn registrar(
+        &self,
+        clave: (Puerto, IdPedido),
+        dio_la_vuelta: bool,
+    ) -> Result<(), EstadoPedido> {
+        let mut pedidos = self.pedidos.lock().await;
+        match pedidos.get(&clave) {
+            None => {}
+            Some(EstadoPedido::Delegado(_)) if dio_la_vuelta => {}
+            Some(estado) => return Err(estado.clone()),
+        }
+        pedidos.fijar(clave, EstadoPedido::EnProceso, self.max_resueltos);
+        Ok(())
+    }
+
+    /// Actualiza el estado de un pedido ya registrado
+    pub async fn actualizar(&self, clave: (Puerto, IdPedido), estado: EstadoPedido) {
+        self.pedidos
+            .lock()
+            .await
+            .fijar(clave, estado, self.max_resueltos);
+    }
+
+    /// Anota que el local al que se delego un pedido acuso recibirlo. Solo se
+    /// anota si el pedido sigue en proceso: un ack que llega tarde, cuando el
+    /// pedido ya se informo o volvio a delegarse, no cambia su estado. Devuelve
+    /// si se anoto
+    pub async fn delegar(&self, clave: (Puerto, IdPedido), local: IdLocal) -> bool {
+        let mut pedidos = self.pedidos.lock().await;
+        if pedidos.get(&clave) != Some(&EstadoPedido::EnProceso) {
+            return false;
+        }
+        pedidos.fijar(clave, EstadoPedido::Delegado(local), self.max_resueltos);
+        true
+    }
+
+    /// Devuelve el estado de un pedido, si es que el local lo recibio
+    pub async fn estado(&self, clave: (Puerto, IdPedido)) -> Option<EstadoPedido> {
+        self.pedidos.lock().await.get(&clave).cloned()
+    }
+
+    /// Marca como cancelado un pedido reservado que el cliente retira. Si el
+    /// pedido ya no esta reservado, por ejemplo porque su entrega se esta
+    /// resolviendo, devuelve su estado
+    pub async fn retirar(&self, clave: (Puerto, IdPedido)) -> Result<(), Option<EstadoPedido>> {
+        let mut pedidos = self.pedidos.lock().await;
+        match pedidos.get(&clave) {
+            Some(EstadoPedido::Reservado) => {
+                let cancelado = MensajesServidor::PedidoCancelado(clave.1);
+                pedidos.fijar(
+                    clave,
+                    EstadoPedido::Informado(cancelado),
+                    self.max_resueltos,
+                );
+                Ok(())
+            }
+            estado => Err(estado.cloned()),
+        }
+    }
+
+    /// Toma un pedido reservado para resolver su entrega, a partir de lo cual
+    /// el cliente ya no puede retirarlo. Devuelve si seguia reservado
+    pub async fn tomar_para_resolver(&self, clave: (Puerto, IdPedido)) -> bool {
+        let mut pedidos = self.pedidos.lock().await;
+        if pedidos.get(&clave) != Some(&EstadoPedido::Reservado) {
+            return false;
+        }
+        pedidos.fijar(clave, EstadoPedido::EnProceso, self.max_resueltos);
+        true
+    }
+
+    /// Devuelve los pedidos que el local tiene reservados
+    pub async fn reservados(&self) -> Vec<(Puerto, IdPedido)> {
+        self.pedidos
+            .lock()
+            .await
+            .estados
+            .iter()
+            .filter(|(_, estado)| **estado == EstadoPedido::Reservado)
+            .map(|(clave, _)| *clave)
+            .collect()
+    }
+
+    /// Devuelve el estado de un pedido como se le informa al ecommerce que lo consulta
+    pub async fn consultar(&self, clave: (Puerto, IdPedido)) -> EstadoConsultado {
+        match self.estado(clave).await {
+            None => EstadoConsultado::Desconocido,
+            Some(EstadoPedido::EnProceso)
+            | Some(EstadoPedido::Reservado)
+            | Some(EstadoPedido::Informado(MensajesServidor::EnEspera(_))) => {
+                EstadoConsultado::Reservado
+            }
+            Some(EstadoPedido::Delegado(local)) => EstadoConsultado::Delegado(local),
+            Some(EstadoPedido::Informado(MensajesServidor::PedidoExitoso(_))) => {
+                EstadoConsultado::Confirmado
+            }
+            Some(EstadoPedido::Informado(_)) => EstadoConsultado::Cancelado,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn un_pedido_repetido_no_se_vuelve_a_procesar() {
+        let historial = HistorialPedidos::new();
+        assert!(historial.registrar((3000, 1), false).await.is_ok());
+        assert_eq!(
+            historial.registrar((3000, 1), false).await,
+            Err(EstadoPedido::EnProceso)
+        );
+
+        let resultado = MensajesServidor::PedidoExitoso(1);
+        historial
+            .actualizar((3000, 1), EstadoPedido::Informado(resultado.clone()))
+            .await;
+        assert_eq!(
+            historial.registrar((3000, 1), true).await,
+            Err(EstadoPedido::Informado(resultado))
+        );
+        assert!(historial.registrar((3001, 1), false).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn un_pedido_delegado_se_procesa_solo_si_dio_la_vuelta() {
+        let historial = HistorialPedidos::new();
+        historial.registrar((3000, 1), false).await.unwrap();
+        historial
+            .actualizar((3000, 1), EstadoPedido::Delegado(2))
+            .await;
+
+        assert_eq!(
+            historial.registrar((3000, 1), false).await,
+            Err(EstadoPedido::Delegado(2))
+        );
+        assert!(historial.registrar((3000, 1), true).await.is_ok());
+        assert_eq!(
+            historial.estado((3000, 1)).await,
+            Some(EstadoPedido::EnProceso)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn la_consulta_de_estado_resume_el_historial() {
+        let historial = HistorialPedidos::new();
+        assert_eq!(
+            historial.consultar((3000, 1)).await,
+            EstadoConsultado::Desconocido
+        );
+        historial.registrar((3000, 1), false).await.unwrap();
+        assert_eq!(
+            historial.consultar((3000, 1)).await,
+            EstadoConsultado::Reservado
+        );
+        historial
+            .actualizar((3000, 1), EstadoPedido::Delegado(4))
+            .await;
+        assert_eq!(
+            historial.consultar((3000, 1)).await,
+            EstadoConsultado::Delegado(4)
+        );
+        historial
+            .actualizar(
+                (3000, 1),
+                EstadoPedido::Informado(MensajesServidor::NoHayStock(1)),
+            )
+            .await;
+        assert_eq!(
+            historial.consultar((3000, 1)).await,
+            EstadoConsultado::Cancelado
+        );
+    }
+
+    #[actix_rt::test]
+    async fn el_cliente_solo_retira_un_pedido_que_sigue_reservado() {
+        let historial = HistorialPedidos::new();
+        historial.registrar((3000, 1), false).await.unwrap();
+        assert_eq!(
+            historial.retirar((3000, 1)).await,
+            Err(Some(EstadoPedido::EnProceso))
+        );
+
+        historial
+            .actualizar((3000, 1), EstadoPedido::Reservado)
+            .await;
+        assert_eq!(historial.reservados().await, vec![(3000, 1)]);
+        assert!(historial.retirar((3000, 1)).await.is_ok());
+        assert!(historial.reservados().await.is_empty());
+        assert!(!historial.tomar_para_resolver((3000, 1)).await);
+        assert_eq!(
+            historial.estado((3000, 1)).await,
+            Some(EstadoPedido::Informado(MensajesServidor::PedidoCancelado(
+                1
+            )))
+        );
+
+        historial
+            .actualizar((3000, 2), EstadoPedido::Reservado)
+            .await;
+        assert!(historial.tomar_para_resolver((3000, 2)).await);
+        assert!(historial.retirar((3000, 2)).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn se_olvidan_primero_los_pedidos_resueltos_hace_mas_tiempo() {
+        let historial = HistorialPedidos::new().con_max_resueltos(2);
+        let informado = |id| EstadoPedido::Informado(MensajesServidor::PedidoExitoso(id));
+        for id in 1..=3 {
+            historial.registrar((3000, id), false).await.unwrap();
+        }
+        historial.actualizar((3000, 1), informado(1)).await;
+        historial.actualizar((3000, 2), informado(2)).await;
+        historial
+            .actualizar((3000, 3), EstadoPedido::Reservado)
+            .await;
+        historial.registrar((3000, 4), false).await.unwrap();
+        assert_eq!(historial.estado((3000, 1)).await, Some(informado(1)));
+
+        historial.actualizar((3000, 3), informado(3)).await;
+        assert_eq!(historial.estado((3000, 1)).await, None);
+        assert_eq!(historial.estado((3000, 2)).await, Some(informado(2)));
+        assert_eq!(historial.estado((3000, 3)).await, Some(informado(3)));
+        assert_eq!(
+            historial.estado((3000, 4)).await,
+            Some(EstadoPedido::EnProceso)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn los_pedidos_delegados_tambien_se_olvidan() {
+        let historial = HistorialPedidos::new().con_max_resueltos(1);
+        for id in 1..=2 {
+            historial.registrar((3000, id), false).await.unwrap();
+            assert!(historial.delegar((3000, id), 2).await);
+        }
+        assert_eq!(historial.estado((3000, 1)).await, None);
+        assert_eq!(
+            historial.estado((3000, 2)).await,
+            Some(EstadoPedido::Delegado(2))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn un_ack_tardio_no_pisa_el_resultado_informado() {
+        let historial = HistorialPedidos::new();
+        let informado = EstadoPedido::Informado(MensajesServidor::PedidoExitoso(1));
+        historial.registrar((3000, 1), false).await.unwrap();
+        historial.actualizar((3000, 1), informado.clone()).await;
+
+        assert!(!historial.delegar((3000, 1), 2).await);
+        assert!(!historial.delegar((3000, 2), 2).await);
+        assert_eq!(historial.estado((3000, 1)).await, Some(informado));
+        assert_eq!(historial.estado((3000, 2)).await, None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Este modulo define como llegan los clientes al local. Las llegadas pueden
+//! ser a intervalos fijos, seguir un proceso de Poisson (intervalos con
+//! distribucion exponencial) o darse en rafagas de varios clientes juntos.
+//! Los intervalos se generan a partir de una semilla, por lo que una misma
+//! configuracion reproduce siempre la misma secuencia de llegadas.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Proceso que siguen las llegadas de los clientes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "tipo", rename_all = "snake_case")]
+pub enum ProcesoLlegadas {
+    /// Llega un cliente cada `intervalo_ms` milisegundos
+    Fijo { intervalo_ms: u64 },
+    /// Los clientes llegan de forma independiente, en promedio uno cada `media_ms` milisegundos
+    Poisson { media_ms: u64 },
+    /// Llegan `clientes` clientes juntos cada `intervalo_ms` milisegundos
+    Rafagas { clientes: usize, intervalo_ms: u64 },
+}
+
+impl Default for ProcesoLlegadas {
+    fn default() -> Self {
+        ProcesoLlegadas::Fijo { intervalo_ms: 500 }
+    }
+}
+
+impl ProcesoLlegadas {
+    /// Devuelve la secuencia infinita de esperas entre un cliente y el siguiente
+    pub fn intervalos(&self, semilla: u64) -> Intervalos {
+        Intervalos {
+            proceso: *self,
+            rng: StdRng::seed_from_u64(semilla),
+            atendidos: 0,
+        }
+    }
+}
+
+/// Muestrea un valor de una distribucion exponencial con la media dada, por
+/// inversa de su funcion de distribucion. Tambien lo usa el repartidor para
+/// las demoras de las entregas
+pub(super) fn muestrear_exponencial(rng: &mut StdRng, media: u64) -> f64 {
+    let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+    -u.ln() * media as f64
+}
+
+/// Iterador de las esperas entre llegadas de un proceso
+pub struct Intervalos {
+    proceso: ProcesoLlegadas,
+    rng: StdRng,
+    atendidos: usize,
+}
+
+impl Iterator for Intervalos {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.atendidos += 1;
+        let espera_ms = match self.proceso {
+            ProcesoLlegadas::Fijo { intervalo_ms } => intervalo_ms as f64,
+            ProcesoLlegadas::Poisson { media_ms } => muestrear_exponencial(&mut self.rng, media_ms),
+            ProcesoLlegadas::Rafagas {
+                clientes,
+                intervalo_ms,
+            } => {
+                if self.atendidos.is_multiple_of(clientes.max(1)) {
+                    intervalo_ms as f64
+                } else {
+                    0.0
+                }
+            }
+        };
+        Some(Duration::from_secs_f64(espera_ms / 1000.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn las_rafagas_agrupan_clientes_sin_espera_entre_ellos() {
+        let proceso = ProcesoLlegadas::Rafagas {
+            clientes: 3,
+            intervalo_ms: 100,
+        };
+        let esperas: Vec<u128> = proceso
+            .intervalos(0)
+            .take(6)
+            .map(|d| d.as_millis())
+            .collect();
+        assert_eq!(esperas, vec![0, 0, 100, 0, 0, 100]);
+    }
+
+    #[test]
+    fn poisson_es_reproducible_con_la_misma_semilla_y_respeta_la_media() {
+        let proceso = ProcesoLlegadas::Poisson { media_ms: 200 };
+        let a: Vec<Duration> = proceso.intervalos(7).take(2000).collect();
+        let b: Vec<Duration> = proceso.intervalos(7).take(2000).collect();
+        let c: Vec<Duration> = proceso.intervalos(8).take(2000).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        let media_ms = a.iter().map(|d| d.as_secs_f64() * 1000.0).sum::<f64>() / 2000.0;
+        assert!((170.0..230.0).contains(&media_ms), "media {}", media_ms);
+    }
+
+    #[test]
+    fn el_proceso_se_lee_de_json() {
+        let proceso: ProcesoLlegadas =
+            serde_json::from_str(r#"{"tipo": "poisson", "media_ms": 300}"#).unwrap();
+        assert_eq!(proceso, ProcesoLlegadas::Poisson { media_ms: 300 });
+    }
+}
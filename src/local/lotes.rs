@@ -0,0 +1,323 @@
+//! Este modulo define el stock del local dividido en lotes. Cada lote tiene
+//! una fecha de vencimiento (o ninguna, si el producto no es perecedero), y
+//! los lotes de un producto se consumen en orden de vencimiento (FEFO), de
+//! forma que primero salen los que vencen antes.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::aliases::{CantidadProducto, IdProducto, TablaStock};
+use crate::errores::ErrorGuardian;
+
+/// Unidades de un producto que vencen en una misma fecha
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Lote {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vencimiento: Option<NaiveDate>,
+    pub cantidad: CantidadProducto,
+}
+
+impl Lote {
+    /// Crea un lote que no vence
+    pub fn sin_vencimiento(cantidad: CantidadProducto) -> Self {
+        Self {
+            vencimiento: None,
+            cantidad,
+        }
+    }
+
+    /// Crea un lote que vence en la fecha dada
+    pub fn con_vencimiento(cantidad: CantidadProducto, vencimiento: NaiveDate) -> Self {
+        Self {
+            vencimiento: Some(vencimiento),
+            cantidad,
+        }
+    }
+
+    /// Devuelve si el lote ya no puede venderse en la fecha dada
+    fn vencido(&self, hoy: NaiveDate) -> bool {
+        self.vencimiento
+            .is_some_and(|vencimiento| vencimiento < hoy)
+    }
+}
+
+/// Stock de un producto tal como puede escribirse en el archivo de stock:
+/// una cantidad suelta, que se toma como un unico lote sin vencimiento, o
+/// una lista de lotes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EntradaStock {
+    Cantidad(CantidadProducto),
+    Lotes(Vec<Lote>),
+}
+
+/// Stock del local, con los lotes de cada producto ordenados por vencimiento.
+/// Los lotes sin vencimiento quedan al final, y nunca hay lotes vacios.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(
+    from = "HashMap<IdProducto, EntradaStock>",
+    into = "HashMap<IdProducto, Vec<Lote>>"
+)]
+pub struct StockLotes {
+    lotes: HashMap<IdProducto, Vec<Lote>>,
+}
+
+impl From<HashMap<IdProducto, EntradaStock>> for StockLotes {
+    fn from(entradas: HashMap<IdProducto, EntradaStock>) -> Self {
+        let mut stock = Self::default();
+        for (id, entrada) in entradas {
+            match entrada {
+                EntradaStock::Cantidad(cantidad) => {
+                    stock.agregar(id, Lote::sin_vencimiento(cantidad))
+                }
+                EntradaStock::Lotes(lotes) => {
+                    lotes.into_iter().for_each(|lote| stock.agregar(id, lote))
+                }
+            }
+        }
+        stock
+    }
+}
+
+impl From<StockLotes> for HashMap<IdProducto, Vec<Lote>> {
+    fn from(stock: StockLotes) -> Self {
+        stock.lotes
+    }
+}
+
+impl From<TablaStock> for StockLotes {
+    fn from(tabla: TablaStock) -> Self {
+        let mut stock = Self::default();
+        for (id, cantidad) in tabla {
+            stock.agregar(id, Lote::sin_vencimiento(cantidad));
+        }
+        stock
+    }
+}
+
+impl StockLotes {
+    /// Devuelve cuantas unidades de un producto pueden venderse en la fecha
+    /// dada, sumando sus lotes que todavia no vencieron
+    pub fn disponible(&self, id: IdProducto, hoy: NaiveDate) -> CantidadProducto {
+        self.lotes(id)
+            .iter()
+            .filter(|lote| !lote.vencido(hoy))
+            .fold(0, |total: CantidadProducto, lote| {
+                total.saturating_add(lote.cantidad)
+            })
+    }
+
+    /// Devuelve los lotes de un producto, del que vence primero al ultimo
+    pub fn lotes(&self, id: IdProducto) -> &[Lote] {
+        self.lotes.get(&id).map_or(&[], |lotes| lotes.as_slice())
+    }
+
+    /// Devuelve los productos de los que hay alguna unidad
+    pub fn productos(&self) -> impl Iterator<Item = IdProducto> + '_ {
+        self.lotes.keys().copied()
+    }
+
+    /// Devuelve el total de unidades de cada producto, sin distinguir lotes
+    /// ni descartar los vencidos
+    pub fn como_tabla(&self) -> TablaStock {
+        self.productos()
+            .map(|id| (id, self.disponible(id, NaiveDate::MIN)))
+            .collect()
+    }
+
+    /// Agrega un lote de un producto. Si ya habia uno con el mismo vencimiento
+    /// se suman, y si no se inserta respetando el orden por vencimiento
+    pub fn agregar(&mut self, id: IdProducto, lote: Lote) {
+        if lote.cantidad == 0 {
+            return;
+        }
+        let lotes = self.lotes.entry(id).or_default();
+        match lotes.iter_mut().find(|l| l.vencimiento == lote.vencimiento) {
+            Some(existente) => {
+                existente.cantidad = existente.cantidad.saturating_add(lote.cantidad)
+            }
+            None => {
+                let posicion = lotes
+                    .iter()
+                    .position(|l| vence_antes(lote.vencimiento, l.vencimiento))
+                    .unwrap_or(lotes.len());
+                lotes.insert(posicion, lote);
+            }
+        }
+    }
+
+    /// Toma unidades de un producto, empezando por los lotes que vencen antes.
+    /// Los lotes ya vencidos a la fecha dada no se tocan, y quedan para que se
+    /// descarten. Devuelve los lotes de los que salieron, para poder devolverlas.
+    /// # Errors
+    /// * `ErrorGuardian::NoHaySuficienteStock` si hay unidades del producto, pero no alcanzan
+    /// * `ErrorGuardian::NoHayStock` si no hay ninguna unidad vigente del producto
+    pub fn tomar(
+        &mut self,
+        id: IdProducto,
+        cantidad: CantidadProducto,
+        hoy: NaiveDate,
+    ) -> Result<Vec<Lote>, ErrorGuardian> {
+        match self.disponible(id, hoy) {
+            0 => return Err(ErrorGuardian::NoHayStock),
+            disponible if disponible < cantidad => return Err(ErrorGuardian::NoHaySuficienteStock),
+            _ => {}
+        }
+
+        let lotes = self.lotes.entry(id).or_default();
+        // Al estar ordenados por vencimiento, los vencidos son los primeros
+        let primero_vigente = lotes.iter().take_while(|lote| lote.vencido(hoy)).count();
+        let mut tomados = Vec::new();
+        let mut faltante = cantidad;
+        while faltante > 0 {
+            let lote = &mut lotes[primero_vigente];
+            let tomado = lote.cantidad.min(faltante);
+            lote.cantidad -= tomado;
+            faltante -= tomado;
+            tomados.push(Lote {
+                vencimiento: lote.vencimiento,
+                cantidad: tomado,
+            });
+            if lote.cantidad == 0 {
+                lotes.remove(primero_vigente);
+            }
+        }
+        if lotes.is_empty() {
+            self.lotes.remove(&id);
+        }
+        Ok(tomados)
+    }
+
+    /// Descarta los lotes vencidos a la fecha dada, devolviendo cuantas
+    /// unidades de cada producto se descartaron
+    pub fn descartar_vencidos(&mut self, hoy: NaiveDate) -> HashMap<IdProducto, CantidadProducto> {
+        let mut descartados = HashMap::new();
+        for (id, lotes) in self.lotes.iter_mut() {
+            lotes.retain(|lote| {
+                if lote.vencido(hoy) {
+                    let total: &mut CantidadProducto = descartados.entry(*id).or_default();
+                    *total = total.saturating_add(lote.cantidad);
+                }
+                !lote.vencido(hoy)
+            });
+        }
+        self.lotes.retain(|_, lotes| !lotes.is_empty());
+        descartados
+    }
+
+    /// Devuelve cuantas unidades de cada producto estan vencidas a la fecha dada
+    pub fn vencidos(&self, hoy: NaiveDate) -> HashMap<IdProducto, CantidadProducto> {
+        let mut vencidos = HashMap::new();
+        for (id, lotes) in &self.lotes {
+            for lote in lotes.iter().filter(|lote| lote.vencido(hoy)) {
+                let total: &mut CantidadProducto = vencidos.entry(*id).or_default();
+                *total = total.saturating_add(lote.cantidad);
+            }
+        }
+        vencidos
+    }
+}
+
+/// Devuelve si un vencimiento es anterior a otro. No vencer es lo ultimo
+fn vence_antes(a: Option<NaiveDate>, b: Option<NaiveDate>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a < b,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fecha(dia: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, dia).unwrap()
+    }
+
+    #[test]
+    fn tomar_consume_primero_los_lotes_que_vencen_antes() {
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::sin_vencimiento(5));
+        stock.agregar(1, Lote::con_vencimiento(3, fecha(20)));
+        stock.agregar(1, Lote::con_vencimiento(2, fecha(10)));
+
+        let tomados = stock.tomar(1, 4, fecha(1)).unwrap();
+        assert_eq!(
+            tomados,
+            vec![
+                Lote::con_vencimiento(2, fecha(10)),
+                Lote::con_vencimiento(2, fecha(20))
+            ]
+        );
+        assert_eq!(
+            stock.lotes(1),
+            &[
+                Lote::con_vencimiento(1, fecha(20)),
+                Lote::sin_vencimiento(5)
+            ]
+        );
+        assert!(matches!(
+            stock.tomar(1, 7, fecha(1)),
+            Err(ErrorGuardian::NoHaySuficienteStock)
+        ));
+        assert!(matches!(
+            stock.tomar(2, 1, fecha(1)),
+            Err(ErrorGuardian::NoHayStock)
+        ));
+    }
+
+    #[test]
+    fn tomar_no_vende_unidades_de_lotes_vencidos() {
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::con_vencimiento(4, fecha(10)));
+        stock.agregar(1, Lote::con_vencimiento(2, fecha(20)));
+        assert_eq!(stock.disponible(1, fecha(11)), 2);
+
+        assert!(matches!(
+            stock.tomar(1, 3, fecha(11)),
+            Err(ErrorGuardian::NoHaySuficienteStock)
+        ));
+        let tomados = stock.tomar(1, 2, fecha(11)).unwrap();
+        assert_eq!(tomados, vec![Lote::con_vencimiento(2, fecha(20))]);
+        assert_eq!(stock.lotes(1), &[Lote::con_vencimiento(4, fecha(10))]);
+        assert!(matches!(
+            stock.tomar(1, 1, fecha(11)),
+            Err(ErrorGuardian::NoHayStock)
+        ));
+    }
+
+    #[test]
+    fn descartar_vencidos_quita_solo_los_lotes_ya_vencidos() {
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::con_vencimiento(2, fecha(10)));
+        stock.agregar(1, Lote::con_vencimiento(3, fecha(20)));
+        stock.agregar(2, Lote::con_vencimiento(4, fecha(5)));
+
+        let descartados = stock.descartar_vencidos(fecha(11));
+        assert_eq!(descartados, HashMap::from([(1, 2), (2, 4)]));
+        assert_eq!(stock.como_tabla(), HashMap::from([(1, 3)]));
+    }
+
+    #[test]
+    fn el_archivo_de_stock_acepta_cantidades_sueltas_y_lotes() {
+        let json = r#"{
+            "1": 5,
+            "2": [{"vencimiento": "2024-05-20", "cantidad": 3}, {"cantidad": 1}]
+        }"#;
+        let stock: StockLotes = serde_json::from_str(json).unwrap();
+
+        assert_eq!(stock.lotes(1), &[Lote::sin_vencimiento(5)]);
+        assert_eq!(
+            stock.lotes(2),
+            &[
+                Lote::con_vencimiento(3, fecha(20)),
+                Lote::sin_vencimiento(1)
+            ]
+        );
+        let releido: StockLotes =
+            serde_json::from_str(&serde_json::to_string(&stock).unwrap()).unwrap();
+        assert_eq!(releido, stock);
+    }
+}
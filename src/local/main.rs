@@ -1,31 +1,129 @@
-//! Ejecuta un local, con el id enviado como argumento del programa
-//! Para inicializarse, lee los archivos "configs/stock{ID}" y "configs/pedidos{ID}"
+//! Ejecuta un local, con el id enviado como argumento del programa, y opcionalmente
+//! la cantidad de particiones en las que se reparte su stock (por defecto, una).
+//! Con la opcion "--consola", las ventas del local no se leen de un archivo sino
+//! que las carga un operador por la consola de punto de venta.
+//! Para inicializarse, lee los archivos "configs/stock{ID}" y "configs/pedidos{ID}",
+//! y si existen, el cronograma de reposicion "configs/reposicion{ID}" y el modelo
+//! de entrega de los pedidos de ecommerce "configs/reparto{ID}".
+//! Si el local ya se habia ejecutado antes, recupera su stock del registro guardado
+//! en "estado/" en lugar de volver a leer "configs/stock{ID}".
+//! El local se une al cluster contactando a las semillas de "configs/membresia.json",
+//! y al terminar anuncia que se va.
+//! Al recibir Ctrl+C, o el pedido de drenaje de un operador, el local se drena antes
+//! de terminar: desvia los pedidos de ecommerce nuevos a otros locales y espera a
+//! que se resuelvan los que tenia en curso.
+//! Al terminar, o al recibir la señal SIGUSR1, guarda en "estado/" el inventario,
+//! los movimientos, el reporte de ventas del local y las retransmisiones que hizo
+//! al delegar pedidos a cada local.
 
 use actix::prelude::*;
 use actix_rt::net::UdpSocket;
 use mensajero::Mensajero;
-use pidgeonhole::aliases::{IdLocal, TablaStock};
+use pidgeonhole::aliases::IdLocal;
 use pidgeonhole::id_a_dir_local;
-use pidgeonhole::local::empleado::{self, TomarPedido};
-use pidgeonhole::local::{guardian::Guardian, stock};
+use pidgeonhole::local::auditoria;
+use pidgeonhole::local::busquedas::Busquedas;
+use pidgeonhole::local::cajas::{self, Cajas, ConfiguracionCajas};
+use pidgeonhole::local::consola::{self, Consola};
+use pidgeonhole::local::eleccion::Eleccion;
+use pidgeonhole::local::empleado::TomarPedido;
+use pidgeonhole::local::guardian::{
+    ConsultarMovimientos, Guardian, ObtenerInventario, TTL_BLOQUEO_POR_DEFECTO,
+};
+use pidgeonhole::local::lotes::StockLotes;
+use pidgeonhole::local::particiones::{self, GuardianParticionado};
+use pidgeonhole::local::registro::{Registro, DIRECTORIO_ESTADO};
+use pidgeonhole::local::reparto::{self, ConfiguracionReparto};
+use pidgeonhole::local::reporte::{ObtenerReporte, Ventas};
+use pidgeonhole::local::reposicion::{self, ProgresoReposicion, Reposicion};
+use pidgeonhole::local::rumores;
+use pidgeonhole::local::stock;
+use pidgeonhole::local::transferencias::Transferencias;
+use pidgeonhole::membresia::{self, Membresia};
 use pidgeonhole::pedido::{self, Pedido};
-use std::env::{self, Args};
-use std::fs::File;
+use pidgeonhole::topologia;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::signal;
 
 use pidgeonhole::errores::{Error, ErrorDuranteParseo, ErrorServidor};
+use pidgeonhole::local::servidor::Delegacion;
 use pidgeonhole::local::{mensajero, servidor};
 
-/// Obtiene una tabla de stock del archivo preparado para el local dado
-fn obtener_stock(id: u16) -> Result<TablaStock, ErrorDuranteParseo> {
+/// Opcion del programa que activa la consola de punto de venta
+const OPCION_CONSOLA: &str = "--consola";
+
+/// Obtiene el stock del archivo preparado para el local dado
+fn obtener_stock(id: u16) -> Result<StockLotes, ErrorDuranteParseo> {
     let archivo_stocks = format!("configs/stock{}.json", id);
     let mut stocks_json = File::open(archivo_stocks)?;
     let stocks = stock::from_reader(&mut stocks_json)?;
     Ok(stocks)
 }
 
+/// Crea los guardianes de cada particion del local. Si una particion tiene un
+/// estado guardado de una ejecucion anterior lo recupera de alli, y si no parte
+/// de su porcion del archivo de stock del local. Falla si el estado guardado es
+/// de otra cantidad de particiones, ya que no podria recuperarse
+fn obtener_guardianes(
+    id: IdLocal,
+    cantidad_particiones: usize,
+) -> Result<Vec<Guardian>, ErrorDuranteParseo> {
+    let directorio = Path::new(DIRECTORIO_ESTADO);
+    if let Some(guardadas) = Registro::particiones_guardadas(directorio, id)? {
+        if guardadas != cantidad_particiones {
+            eprintln!(
+                "El estado guardado del local tiene {} particiones, pero se pidieron {}. Ejecute el local con {} particiones, o borre el directorio {} para descartar el estado",
+                guardadas, cantidad_particiones, guardadas, DIRECTORIO_ESTADO
+            );
+            return Err(ErrorDuranteParseo::ParticionesGuardadasDistintas);
+        }
+    }
+    let mut stock_por_particion: Option<Vec<_>> = None;
+    let mut guardianes = Vec::with_capacity(cantidad_particiones);
+    for particion in 0..cantidad_particiones {
+        let registro = Registro::abrir_particion(directorio, id, particion, cantidad_particiones)?;
+        if registro.hay_estado() {
+            println!(
+                "Recuperando el stock de la particion {} del registro de una ejecucion anterior",
+                particion
+            );
+            guardianes.push(Guardian::recuperar(registro)?);
+            continue;
+        }
+
+        let stock = match stock_por_particion.as_mut() {
+            Some(stock) => stock,
+            None => stock_por_particion.insert(particiones::dividir_stock(
+                obtener_stock(id)?,
+                cantidad_particiones,
+            )),
+        };
+        let stock = std::mem::take(&mut stock[particion]);
+        guardianes.push(Guardian::new(stock).con_registro(registro)?);
+    }
+    Ok(guardianes)
+}
+
+/// Obtiene la cantidad de particiones del guardian, a partir del argumento que
+/// sigue al id del local. Si no se indica, usa una unica particion
+fn obtener_cantidad_particiones(
+    args: &mut impl Iterator<Item = String>,
+) -> Result<usize, ErrorDuranteParseo> {
+    let cantidad = match args.next() {
+        None => return Ok(1),
+        Some(cantidad) => cantidad.parse().unwrap_or(0),
+    };
+    if cantidad == 0 {
+        eprintln!("La cantidad de particiones debe ser un entero positivo");
+        return Err(ErrorDuranteParseo::CantidadParticionesInvalida);
+    }
+    Ok(cantidad)
+}
+
 /// Obtiene una tabla de pedidos del archivo preparado para el local dado
 fn obtener_pedidos(id: u16) -> Result<Vec<Pedido>, ErrorDuranteParseo> {
     let archivo_pedidos = format!("configs/pedidos{}.json", id);
@@ -34,8 +132,67 @@ fn obtener_pedidos(id: u16) -> Result<Vec<Pedido>, ErrorDuranteParseo> {
     Ok(pedidos)
 }
 
+/// Obtiene el cronograma de reposicion del local dado. Como es opcional, si el
+/// archivo no existe devuelve un cronograma vacio
+fn obtener_reposiciones(id: u16) -> Result<Vec<Reposicion>, ErrorDuranteParseo> {
+    let archivo_reposicion = format!("configs/reposicion{}.json", id);
+    let mut reposicion_json = match File::open(archivo_reposicion) {
+        Ok(archivo) => archivo,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let reposiciones = reposicion::from_reader(&mut reposicion_json)?;
+    Ok(reposiciones)
+}
+
+/// Obtiene la configuracion de las cajas del local dado y de la llegada de sus
+/// clientes. Como es opcional, si el archivo no existe usa la configuracion
+/// por defecto
+fn obtener_configuracion_cajas(id: u16) -> Result<ConfiguracionCajas, ErrorDuranteParseo> {
+    let archivo_cajas = format!("configs/cajas{}.json", id);
+    let mut cajas_json = match File::open(archivo_cajas) {
+        Ok(archivo) => archivo,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ConfiguracionCajas::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let configuracion = cajas::from_reader(&mut cajas_json)?;
+    if configuracion.cajas == 0 {
+        eprintln!("La cantidad de cajas debe ser un entero positivo");
+        return Err(ErrorDuranteParseo::CantidadCajasInvalida);
+    }
+    Ok(configuracion)
+}
+
+/// Obtiene el modelo de entrega de los pedidos de ecommerce del local dado.
+/// Como es opcional, si el archivo no existe usa la configuracion por defecto
+fn obtener_configuracion_reparto(id: u16) -> Result<ConfiguracionReparto, ErrorDuranteParseo> {
+    let archivo_reparto = format!("configs/reparto{}.json", id);
+    let mut reparto_json = match File::open(archivo_reparto) {
+        Ok(archivo) => archivo,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ConfiguracionReparto::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let configuracion = reparto::from_reader(&mut reparto_json)?;
+    if configuracion.capacidad == Some(0) {
+        eprintln!("La capacidad del repartidor debe ser un entero positivo");
+        return Err(ErrorDuranteParseo::CapacidadRepartoInvalida);
+    }
+    // La reserva se renueva cuando el pedido sale a repartirse. Si el cliente
+    // pudiera retirarlo despues de que expira, el local no podria confirmarlo
+    if u128::from(configuracion.ventana_retiro_ms) >= TTL_BLOQUEO_POR_DEFECTO.as_millis() {
+        eprintln!(
+            "La ventana de retiro debe ser menor a la reserva de {} ms",
+            TTL_BLOQUEO_POR_DEFECTO.as_millis()
+        );
+        return Err(ErrorDuranteParseo::VentanaRetiroInvalida);
+    }
+    Ok(configuracion)
+}
+
 /// Obtiene el id del local, a partir de los argumentos del programa
-fn obtener_id_local(args: &mut Args) -> Result<IdLocal, ErrorDuranteParseo> {
+fn obtener_id_local(
+    args: &mut impl Iterator<Item = String>,
+) -> Result<IdLocal, ErrorDuranteParseo> {
     let id_str: String = match args.nth(1) {
         Some(id_str) => id_str,
         None => {
@@ -61,6 +218,104 @@ async fn inicializar_socket(id: IdLocal) -> Result<Arc<UdpSocket>, ErrorServidor
     }
 }
 
+/// Escribe el inventario completo del local, separando unidades disponibles
+/// y reservadas, en "estado/inventario{ID}.json"
+async fn volcar_inventario(guardian: &Addr<GuardianParticionado>, id: IdLocal) {
+    let inventario = match guardian.send(ObtenerInventario).await {
+        Ok(inventario) => inventario,
+        Err(_) => {
+            eprintln!("No se pudo obtener el inventario del guardian");
+            return;
+        }
+    };
+    let ruta = Path::new(DIRECTORIO_ESTADO).join(format!("inventario{}.json", id));
+    let resultado = serde_json::to_string_pretty(&inventario)
+        .map_err(io::Error::from)
+        .and_then(|json| fs::write(&ruta, json));
+    match resultado {
+        Ok(_) => println!("Inventario guardado en {}", ruta.display()),
+        Err(e) => eprintln!("No se pudo guardar el inventario: {}", e),
+    }
+}
+
+/// Exporta el libro de movimientos del guardian, un movimiento por linea,
+/// en "estado/movimientos{ID}.jsonl"
+async fn volcar_movimientos(guardian: &Addr<GuardianParticionado>, id: IdLocal) {
+    let movimientos = match guardian.send(ConsultarMovimientos::todos()).await {
+        Ok(movimientos) => movimientos,
+        Err(_) => {
+            eprintln!("No se pudieron obtener los movimientos del guardian");
+            return;
+        }
+    };
+    let ruta = Path::new(DIRECTORIO_ESTADO).join(format!("movimientos{}.jsonl", id));
+    let resultado = File::create(&ruta)
+        .and_then(|archivo| auditoria::exportar(&movimientos, &mut io::BufWriter::new(archivo)));
+    match resultado {
+        Ok(_) => println!("Movimientos guardados en {}", ruta.display()),
+        Err(e) => eprintln!("No se pudieron guardar los movimientos: {}", e),
+    }
+}
+
+/// Muestra el reporte de ventas del local como tabla, y lo guarda en
+/// "estado/reporte{ID}.json" y como tabla en "estado/reporte{ID}.txt"
+async fn volcar_reporte(ventas: &Addr<Ventas>, id: IdLocal) {
+    let reporte = match ventas.send(ObtenerReporte).await {
+        Ok(reporte) => reporte,
+        Err(_) => {
+            eprintln!("No se pudo obtener el reporte de ventas");
+            return;
+        }
+    };
+    println!("Reporte de ventas del local {}\n{}", id, reporte);
+    let ruta_json = Path::new(DIRECTORIO_ESTADO).join(format!("reporte{}.json", id));
+    let ruta_tabla = Path::new(DIRECTORIO_ESTADO).join(format!("reporte{}.txt", id));
+    let resultado = serde_json::to_string_pretty(&reporte)
+        .map_err(io::Error::from)
+        .and_then(|json| fs::write(&ruta_json, json))
+        .and_then(|_| fs::write(&ruta_tabla, reporte.to_string()));
+    match resultado {
+        Ok(_) => println!(
+            "Reporte de ventas guardado en {} y {}",
+            ruta_json.display(),
+            ruta_tabla.display()
+        ),
+        Err(e) => eprintln!("No se pudo guardar el reporte de ventas: {}", e),
+    }
+}
+
+/// Guarda en "estado/retransmisiones{ID}.json" cuantas veces se le retransmitio
+/// un pedido delegado a cada local
+async fn volcar_retransmisiones(delegacion: &Delegacion, id: IdLocal) {
+    let retransmisiones = delegacion.retransmisiones().await;
+    let ruta = Path::new(DIRECTORIO_ESTADO).join(format!("retransmisiones{}.json", id));
+    let resultado = serde_json::to_string_pretty(&retransmisiones)
+        .map_err(io::Error::from)
+        .and_then(|json| fs::write(&ruta, json));
+    match resultado {
+        Ok(_) => println!("Retransmisiones guardadas en {}", ruta.display()),
+        Err(e) => eprintln!("No se pudieron guardar las retransmisiones: {}", e),
+    }
+}
+
+/// Vuelca el inventario, los movimientos, el reporte de ventas y las
+/// retransmisiones cada vez que el proceso recibe la señal SIGUSR1
+async fn volcar_inventario_ante_senial(
+    guardian: Addr<GuardianParticionado>,
+    ventas: Addr<Ventas>,
+    delegacion: Delegacion,
+    id: IdLocal,
+) -> io::Result<()> {
+    let mut senial = signal::unix::signal(signal::unix::SignalKind::user_defined1())?;
+    while senial.recv().await.is_some() {
+        volcar_inventario(&guardian, id).await;
+        volcar_movimientos(&guardian, id).await;
+        volcar_reporte(&ventas, id).await;
+        volcar_retransmisiones(&delegacion, id).await;
+    }
+    Ok(())
+}
+
 async fn handle_exit() -> Result<(), Error> {
     signal::ctrl_c().await.map_err(|_e| Error::ErrorEnCtrlC)?;
     Ok(())
@@ -68,30 +323,104 @@ async fn handle_exit() -> Result<(), Error> {
 
 #[actix_rt::main]
 async fn main() -> Result<(), Error> {
-    let id = obtener_id_local(&mut env::args())?;
-    let stocks = obtener_stock(id)?;
-    let pedidos = obtener_pedidos(id)?;
-    let guardian_addr: Addr<Guardian> = Guardian::new(stocks).start();
+    let modo_consola = env::args().any(|arg| arg == OPCION_CONSOLA);
+    let mut args = env::args().filter(|arg| arg != OPCION_CONSOLA);
+    let id = obtener_id_local(&mut args)?;
+    let cantidad_particiones = obtener_cantidad_particiones(&mut args)?;
+    let guardianes = obtener_guardianes(id, cantidad_particiones)?;
+    let pedidos = if modo_consola {
+        Vec::new()
+    } else {
+        obtener_pedidos(id)?
+    };
+    let reposiciones = obtener_reposiciones(id)?;
+    let progreso_reposicion = ProgresoReposicion::abrir(Path::new(DIRECTORIO_ESTADO), id)?;
+    let configuracion_cajas = obtener_configuracion_cajas(id)?;
+    let configuracion_reparto = obtener_configuracion_reparto(id)?;
+    let topologia = Arc::new(topologia::obtener_topologia()?);
+    let semillas = membresia::obtener_semillas()?;
+    let socket = inicializar_socket(id).await?;
+    let mensajero: Addr<Mensajero> = Mensajero::new(socket.clone()).start();
+    let membresia = Arc::new(Membresia::de_local(id));
+    let guardian_addr: Addr<GuardianParticionado> = GuardianParticionado::new(guardianes)
+        .con_aviso_expiracion(mensajero.clone().recipient())
+        .start();
     let recipient = guardian_addr.clone().recipient();
+    let transferencias =
+        Transferencias::new(id, guardian_addr.clone(), mensajero.clone().recipient()).start();
+    actix_rt::spawn(reposicion::reproducir(
+        reposiciones,
+        progreso_reposicion,
+        guardian_addr.clone().recipient(),
+        transferencias.clone().recipient(),
+    ));
 
-    let handle_clientes = actix_rt::spawn(async move {
-        let empleado_addr = empleado::Empleado::new(recipient).start();
-        for (id, pedido) in pedidos.into_iter().enumerate() {
-            empleado_addr.do_send(TomarPedido::new(pedido.clone(), id));
-            tokio::time::sleep(Duration::from_millis(500)).await;
+    let ventas = Ventas::new().start();
+
+    let server_ecommerce =
+        servidor::ServidorEcommerce::new(guardian_addr.clone(), configuracion_reparto, id, socket)
+            .con_transferencias(transferencias)
+            .con_topologia(topologia)
+            .con_membresia(membresia.clone())
+            .con_eleccion(
+                Eleccion::new(id, mensajero.clone().recipient(), membresia.clone()).start(),
+            )
+            .con_ventas(ventas.clone());
+    let delegacion = server_ecommerce.delegacion();
+    let drenaje = server_ecommerce.drenaje();
+
+    let guardian_senial = guardian_addr.clone();
+    let ventas_senial = ventas.clone();
+    let delegacion_senial = delegacion.clone();
+    actix_rt::spawn(async move {
+        if volcar_inventario_ante_senial(guardian_senial, ventas_senial, delegacion_senial, id)
+            .await
+            .is_err()
+        {
+            eprintln!("No se pudo escuchar la señal para volcar el inventario");
         }
     });
 
-    let socket = inicializar_socket(id).await?;
-    let mensajero: Addr<Mensajero> = Mensajero::new(socket.clone()).start();
-    let mut server_ecommerce = servidor::ServidorEcommerce::new(guardian_addr.clone(), id, socket);
+    let busquedas = Busquedas::new(id, mensajero.clone(), delegacion.clone()).start();
+    let mut server_ecommerce = server_ecommerce.con_busquedas(busquedas.clone());
+    let mut cajas = Cajas::new(
+        recipient,
+        configuracion_cajas.cajas,
+        configuracion_cajas.asignacion,
+    )
+    .con_ventas(ventas.clone().recipient());
+    if configuracion_cajas.buscar_en_otros_locales {
+        cajas = cajas.con_busquedas(busquedas.recipient());
+    }
+    let cajas_addr = cajas.start();
+
+    let mut handle_clientes = if modo_consola {
+        let consola = Consola::new(cajas_addr.recipient(), guardian_addr.clone(), 0);
+        actix_rt::spawn(consola.atender(consola::leer_entrada_estandar()))
+    } else {
+        actix_rt::spawn(async move {
+            let esperas = configuracion_cajas
+                .llegadas
+                .intervalos(configuracion_cajas.semilla_de(id));
+            for ((id, pedido), espera) in pedidos.into_iter().enumerate().zip(esperas) {
+                cajas_addr.do_send(TomarPedido::new(pedido.clone(), id));
+                tokio::time::sleep(espera).await;
+            }
+        })
+    };
 
-    let handle_server =
+    let handle_rumores = actix_rt::spawn(rumores::difundir(
+        membresia.clone(),
+        mensajero.clone(),
+        semillas,
+    ));
+    let mensajero_salida = mensajero.clone();
+    let mut handle_server =
         actix_rt::spawn(async move { server_ecommerce.procesar_pedidos(mensajero).await });
     let ctrlc = actix::spawn(async move { handle_exit().await });
 
     // Retorna cuando alguna de las ramas concurrentes termina su ejecucion
-    tokio::select! {
+    let servidor_terminado = tokio::select! {
         res = ctrlc => {
             let resultado = res?;
             if resultado.is_err(){
@@ -99,17 +428,41 @@ async fn main() -> Result<(), Error> {
             }else{
                 println!("Saliendo del programa debido a un keyboard interrupt (Ctrl+C)");
             }
+            false
         }
-        server = handle_server => {
+        server = &mut handle_server => {
             if server.is_err()  {
                 return Err(Error::ErrorEnJoin);
             }
+            true
+        }
+        _ = &mut handle_clientes, if modo_consola => {
+            println!("Saliendo del programa desde la consola");
+            false
+        }
+    };
+
+    // Antes de salir se drena el local, salvo que el servidor ya haya terminado
+    // por un drenaje pedido por un operador
+    if !servidor_terminado {
+        drenaje.iniciar();
+        if handle_server.await.is_err() {
+            return Err(Error::ErrorEnJoin);
         }
     }
 
-    if handle_clientes.await.is_err() {
+    if modo_consola {
+        // La consola puede seguir esperando al operador, no tiene sentido esperarla
+        handle_clientes.abort();
+    } else if handle_clientes.await.is_err() {
         return Err(Error::ErrorEnJoin);
     }
+    handle_rumores.abort();
+    rumores::anunciar_salida(&membresia, &mensajero_salida).await;
+    volcar_inventario(&guardian_addr, id).await;
+    volcar_movimientos(&guardian_addr, id).await;
+    volcar_reporte(&ventas, id).await;
+    volcar_retransmisiones(&delegacion, id).await;
     println!("Finalizando el sistema de actores");
     actix_rt::System::current().stop();
     Ok(())
@@ -3,8 +3,11 @@
 //! de los locales e ecommerces
 
 use crate::errores::ErrorMensajero;
+use crate::local::guardian::PedidoExpirado;
+use crate::mensajes::MensajesServidor;
+use crate::puerto_a_ip;
 use actix::ActorContext;
-use actix::{fut, Actor, Context, Handler, Message, ResponseFuture, Running};
+use actix::{fut, Actor, AsyncContext, Context, Handler, Message, ResponseFuture, Running};
 use actix_rt::net::UdpSocket;
 use std::{net::SocketAddr, sync::Arc};
 
@@ -38,8 +41,8 @@ impl Actor for Mensajero {
 #[derive(Message)]
 #[rtype(result = "Result<(), ErrorMensajero>")]
 pub struct Enviar {
-    mensaje: Vec<u8>,
-    target: SocketAddr,
+    pub(crate) mensaje: Vec<u8>,
+    pub(crate) target: SocketAddr,
 }
 
 impl Enviar {
@@ -50,6 +53,7 @@ impl Enviar {
 
 impl Handler<Enviar> for Mensajero {
     type Result = ResponseFuture<Result<(), ErrorMensajero>>;
+    #[allow(unused_variables)]
     fn handle(&mut self, msg: Enviar, _ctx: &mut Context<Self>) -> Self::Result {
         // si la tarea nacio antes de la muerte del socket, deberia ejecutarse igual
         let sock = self.socket.clone();
@@ -96,6 +100,20 @@ impl Handler<Reconectar> for Mensajero {
     }
 }
 
+/// Cuando un bloqueo expira en el guardian, el mensajero le avisa al ecommerce
+/// que realizo el pedido que este fue cancelado
+impl Handler<PedidoExpirado> for Mensajero {
+    type Result = ();
+    fn handle(&mut self, msg: PedidoExpirado, ctx: &mut Context<Self>) -> Self::Result {
+        let ecommerce: SocketAddr = match puerto_a_ip(msg.puerto).parse() {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+        let aviso = MensajesServidor::PedidoCancelado(msg.id_pedido).as_bytes();
+        ctx.address().do_send(Enviar::new(aviso, ecommerce));
+    }
+}
+
 /// Mensaje que frena al actor tras no poder recuperarse de una falla
 /// en la conectividad
 #[derive(Message)]
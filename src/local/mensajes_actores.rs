@@ -4,7 +4,7 @@ use crate::pedido::Pedido;
 use actix::prelude::*;
 
 /// Mensajes que envia el guardian al local para notificar el resultado de determinado pedido
-#[derive(Message, PartialEq)]
+#[derive(Message, Debug, Clone, Copy, PartialEq)]
 #[rtype(result = "()")]
 pub enum Respuestas {
     PedidoConcretado(usize),
@@ -65,3 +65,11 @@ impl Descontar {
         Self { pedido, id, sender }
     }
 }
+
+/// Mensaje que envia el empleado de una caja al terminar de atender un pedido,
+/// para que las cajas sepan cuantos clientes le quedan pendientes
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PedidoAtendido {
+    pub caja: usize,
+}
@@ -1,9 +1,27 @@
 //! Este modulo define las estructuras correspondientes a un local, permitiendo su
 //! interaccion con un ecommerce y el manejo de sus pedidos propios
 
+pub mod auditoria;
+pub mod busquedas;
+pub mod cajas;
+pub mod consola;
+pub mod consultas;
+pub mod detector;
+pub mod drenaje;
+pub mod eleccion;
 pub mod empleado;
 pub mod guardian;
+pub mod historial;
+pub mod llegadas;
+pub mod lotes;
 pub mod mensajero;
 pub mod mensajes_actores;
+pub mod particiones;
+pub mod registro;
+pub mod reparto;
+pub mod reporte;
+pub mod reposicion;
+pub mod rumores;
 pub mod servidor;
 pub mod stock;
+pub mod transferencias;
@@ -0,0 +1,1093 @@
+//! Este modulo define el guardian particionado, que reparte el stock del local
+//! entre varios guardianes segun el id de producto. Cada guardian ejecuta en su
+//! propio hilo, y un enrutador expone los mismos mensajes que un guardian unico,
+//! de forma que las operaciones sobre productos distintos no se serializan.
+
+use actix::prelude::*;
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::auditoria::Movimiento;
+use super::guardian::{
+    Bloquear, Cancelar, Confirmar, ConsultarMovimientos, Devolver, Encolar, Guardian, Inventario,
+    ObtenerInventario, ObtenerLotesBloqueados, ObtenerStock, PedidoExpirado, Reducir, Renovar,
+    Reponer, VaciarEspera, REINTENTO_VENCIMIENTO, TTL_BLOQUEO_POR_DEFECTO,
+};
+use super::lotes::{Lote, StockLotes};
+use super::mensajes_actores::Descontar;
+use crate::aliases::{CantidadProducto, IdPedido, IdProducto, IdTransferencia, Puerto};
+use crate::errores::ErrorGuardian;
+use crate::pedido::Carrito;
+
+/// Devuelve la particion a la que pertenece un producto
+pub fn particion_de(id_producto: IdProducto, cantidad_particiones: usize) -> usize {
+    id_producto as usize % cantidad_particiones
+}
+
+/// Reparte el stock entre la cantidad de particiones dada, manteniendo los
+/// lotes de cada producto
+pub fn dividir_stock(stock: impl Into<StockLotes>, cantidad_particiones: usize) -> Vec<StockLotes> {
+    let stock = stock.into();
+    let mut particiones = vec![StockLotes::default(); cantidad_particiones];
+    for id_producto in stock.productos() {
+        let particion = &mut particiones[particion_de(id_producto, cantidad_particiones)];
+        for lote in stock.lotes(id_producto) {
+            particion.agregar(id_producto, lote.clone());
+        }
+    }
+    particiones
+}
+
+/// Estructura de guardian particionado. Cuenta con los guardianes de cada
+/// particion, y recuerda en que particiones quedo bloqueado cada pedido, ya
+/// que un carrito con productos de varias particiones se bloquea en todas
+/// ellas. Los pedidos en espera que abarcan varias particiones se encolan
+/// en el enrutador; los que caen en una sola, en el guardian de esa particion.
+/// Tambien recuerda cuales de los bloqueos son reservas de transferencias, de
+/// cuya expiracion no hay que avisar. Los bloqueos que abarcan varias
+/// particiones vencen en el enrutador y no en cada particion, para que un
+/// carrito nunca quede confirmado en algunas y vencido en otras.
+pub struct GuardianParticionado {
+    por_iniciar: Vec<Guardian>,
+    cantidad_particiones: usize,
+    particiones: Vec<Addr<Guardian>>,
+    arbitros: Vec<Arbiter>,
+    bloqueos: HashMap<(IdPedido, Puerto), Vec<usize>>,
+    vencimientos: HashMap<(IdPedido, Puerto), SpawnHandle>,
+    transferencias: HashSet<(IdPedido, Puerto)>,
+    pedidos_en_espera: VecDeque<Encolar>,
+    aviso_expiracion: Option<Recipient<PedidoExpirado>>,
+}
+
+impl GuardianParticionado {
+    /// Crea un guardian particionado a partir de los guardianes de cada particion.
+    /// El guardian en la posicion `i` solo debe tener productos de la particion `i`.
+    /// # Panics
+    /// Si no se recibe ningun guardian
+    pub fn new(guardianes: Vec<Guardian>) -> Self {
+        assert!(!guardianes.is_empty(), "Se necesita al menos una particion");
+        Self {
+            cantidad_particiones: guardianes.len(),
+            por_iniciar: guardianes,
+            particiones: Vec::new(),
+            arbitros: Vec::new(),
+            bloqueos: HashMap::new(),
+            vencimientos: HashMap::new(),
+            transferencias: HashSet::new(),
+            pedidos_en_espera: VecDeque::new(),
+            aviso_expiracion: None,
+        }
+    }
+
+    /// Indica a quien avisarle cuando un bloqueo expira, para que pueda
+    /// notificarle al ecommerce que su pedido fue cancelado
+    pub fn con_aviso_expiracion(mut self, aviso: Recipient<PedidoExpirado>) -> Self {
+        self.aviso_expiracion = Some(aviso);
+        self
+    }
+
+    /// Devuelve la direccion del guardian de la particion de un producto
+    fn particion(&self, id_producto: IdProducto) -> &Addr<Guardian> {
+        &self.particiones[particion_de(id_producto, self.cantidad_particiones)]
+    }
+
+    /// Separa las lineas de un carrito segun la particion de cada producto
+    fn dividir_carrito(&self, carrito: &Carrito) -> HashMap<usize, Carrito> {
+        carrito.dividir(|pedido| particion_de(pedido.get_id(), self.cantidad_particiones))
+    }
+
+    /// Arma los bloqueos parciales de un carrito, uno por cada particion
+    /// involucrada. Si son varias, las partes no vencen en las particiones
+    fn bloqueos_parciales(
+        &self,
+        carrito: &Carrito,
+        id: (IdPedido, Puerto),
+        ttl: Option<Duration>,
+    ) -> Vec<(usize, Addr<Guardian>, Bloquear)> {
+        let partes = self.dividir_carrito(carrito);
+        let ttl = ttl.filter(|_| partes.len() == 1);
+        partes
+            .into_iter()
+            .map(|(particion, pedido)| {
+                let bloqueo = Bloquear { pedido, id, ttl };
+                (particion, self.particiones[particion].clone(), bloqueo)
+            })
+            .collect()
+    }
+
+    /// Programa el vencimiento de un bloqueo que abarca varias particiones,
+    /// reemplazando el que pudiera haber
+    fn programar_vencimiento(
+        &mut self,
+        id: (IdPedido, Puerto),
+        ttl: Duration,
+        ctx: &mut Context<Self>,
+    ) {
+        let handle = ctx.run_later(ttl, move |guardian, ctx| {
+            guardian.vencimientos.remove(&id);
+            guardian.liberar_bloqueo(id, true, ctx);
+        });
+        if let Some(anterior) = self.vencimientos.insert(id, handle) {
+            ctx.cancel_future(anterior);
+        }
+    }
+
+    /// Cancela el vencimiento de un bloqueo, devolviendo si lo tenia
+    fn cancelar_vencimiento(&mut self, id: &(IdPedido, Puerto), ctx: &mut Context<Self>) -> bool {
+        match self.vencimientos.remove(id) {
+            Some(handle) => ctx.cancel_future(handle),
+            None => false,
+        }
+    }
+
+    /// Cancela en todas sus particiones un bloqueo que abarca varias, por
+    /// ejemplo porque se cumplio su tiempo de vida, en cuyo caso se avisa de la
+    /// expiracion. Si alguna particion no puede cancelarlo, se vuelve a
+    /// intentar mas tarde
+    fn liberar_bloqueo(&mut self, id: (IdPedido, Puerto), avisar: bool, ctx: &mut Context<Self>) {
+        let Some(particiones) = self.particiones_bloqueadas(&id) else {
+            return;
+        };
+        let era_transferencia = self.transferencias.contains(&id);
+        let cancelaciones = enviar_a_cada_particion(particiones, move || Cancelar { id });
+        ctx.spawn(
+            cancelaciones
+                .into_actor(self)
+                .map(move |resultados, guardian, ctx| {
+                    if guardian.resolver_bloqueo(&id, resultados).is_err() {
+                        guardian.rearmar_vencimiento(id, true, ctx);
+                        return;
+                    }
+                    if avisar {
+                        guardian.avisar_expiracion(id, era_transferencia);
+                    }
+                    guardian.atender_pedidos_en_espera(ctx);
+                }),
+        );
+    }
+
+    /// Si un bloqueo que vencia en el enrutador no pudo resolverse en todas sus
+    /// particiones, le vuelve a programar el vencimiento
+    fn rearmar_vencimiento(
+        &mut self,
+        id: (IdPedido, Puerto),
+        vencia: bool,
+        ctx: &mut Context<Self>,
+    ) {
+        if vencia && self.bloqueos.contains_key(&id) {
+            self.programar_vencimiento(id, REINTENTO_VENCIMIENTO, ctx);
+        }
+    }
+
+    /// Avisa que expiro un bloqueo, salvo que sea la reserva de una transferencia
+    fn avisar_expiracion(&self, id: (IdPedido, Puerto), era_transferencia: bool) {
+        if era_transferencia {
+            println!(
+                "Expiro la reserva de la transferencia {} al local en puerto {}",
+                id.0, id.1
+            );
+        } else if let Some(aviso) = &self.aviso_expiracion {
+            aviso.do_send(PedidoExpirado {
+                id_pedido: id.0,
+                puerto: id.1,
+            });
+        }
+    }
+
+    /// Particiones en las que sigue bloqueado un pedido, con sus direcciones
+    fn particiones_bloqueadas(
+        &self,
+        id: &(IdPedido, Puerto),
+    ) -> Option<Vec<(usize, Addr<Guardian>)>> {
+        self.bloqueos.get(id).map(|particiones| {
+            particiones
+                .iter()
+                .map(|particion| (*particion, self.particiones[*particion].clone()))
+                .collect()
+        })
+    }
+
+    /// Deja de recordar el bloqueo de un pedido en las particiones que ya lo
+    /// resolvieron, o que ya no lo tenian. Las que fallaron lo siguen teniendo,
+    /// por lo que se lo sigue recordando en ellas. Devuelve lo que respondio
+    /// cada particion, o el primer error que alguna informe
+    fn resolver_bloqueo<T>(
+        &mut self,
+        id: &(IdPedido, Puerto),
+        resultados: Vec<(usize, Result<T, ErrorGuardian>)>,
+    ) -> Result<Vec<T>, ErrorGuardian> {
+        let resueltas: Vec<usize> = resultados
+            .iter()
+            .filter(|(_, resultado)| {
+                matches!(resultado, Ok(_) | Err(ErrorGuardian::PedidoInexistente))
+            })
+            .map(|(particion, _)| *particion)
+            .collect();
+        if let Some(particiones) = self.bloqueos.get_mut(id) {
+            particiones.retain(|particion| !resueltas.contains(particion));
+            if particiones.is_empty() {
+                self.bloqueos.remove(id);
+                self.transferencias.remove(id);
+            }
+        }
+        resultados
+            .into_iter()
+            .map(|(_, resultado)| resultado)
+            .collect()
+    }
+
+    /// Direcciones de las particiones en las que quedo bloqueado un pedido,
+    /// dejando de recordarlo
+    fn olvidar_bloqueo(&mut self, id: &(IdPedido, Puerto)) -> Option<Vec<Addr<Guardian>>> {
+        self.transferencias.remove(id);
+        self.bloqueos.remove(id).map(|particiones| {
+            particiones
+                .into_iter()
+                .map(|particion| self.particiones[particion].clone())
+                .collect()
+        })
+    }
+
+    /// Recorre la cola de pedidos en espera que abarcan varias particiones en
+    /// orden de llegada, bloqueando los que ahora pueden cubrirse. Mientras lo
+    /// hace no atiende otros mensajes, y un pedido no puede adelantarse a otro
+    /// anterior que espera alguno de sus mismos productos.
+    fn atender_pedidos_en_espera(&mut self, ctx: &mut Context<Self>) {
+        if self.pedidos_en_espera.is_empty() {
+            return;
+        }
+        let en_espera: Vec<_> = std::mem::take(&mut self.pedidos_en_espera)
+            .into_iter()
+            .map(|encolado| {
+                let partes = self.bloqueos_parciales(
+                    &encolado.pedido,
+                    encolado.id,
+                    Some(TTL_BLOQUEO_POR_DEFECTO),
+                );
+                (encolado, partes)
+            })
+            .collect();
+
+        let intentos = async move {
+            let mut productos_esperando = HashSet::new();
+            let mut resultados = Vec::new();
+            for (encolado, partes) in en_espera {
+                let productos: Vec<IdProducto> = encolado
+                    .pedido
+                    .cantidades_por_producto()
+                    .into_keys()
+                    .collect();
+                let particiones: Vec<usize> = partes.iter().map(|(p, _, _)| *p).collect();
+                let bloqueado = !productos.iter().any(|id| productos_esperando.contains(id))
+                    && bloquear_en_particiones(partes).await.is_ok();
+                if !bloqueado {
+                    productos_esperando.extend(productos);
+                }
+                resultados.push((encolado, particiones, bloqueado));
+            }
+            resultados
+        };
+
+        ctx.wait(intentos.into_actor(self).map(|resultados, guardian, ctx| {
+            for (encolado, particiones, bloqueado) in resultados {
+                if !bloqueado {
+                    guardian.pedidos_en_espera.push_back(encolado);
+                    continue;
+                }
+                guardian.bloqueos.insert(encolado.id, particiones);
+                guardian.programar_vencimiento(encolado.id, TTL_BLOQUEO_POR_DEFECTO, ctx);
+                if encolado.aviso.send(()).is_err() {
+                    // Nadie espera ya por este pedido, por lo que se libera el stock
+                    guardian.liberar_bloqueo(encolado.id, false, ctx);
+                }
+            }
+        }));
+    }
+}
+
+/// Bloquea cada parte de un carrito en su particion, de a una y en orden de
+/// particion. Si alguna parte no puede bloquearse, cancela las que si se
+/// bloquearon, de forma que el carrito queda bloqueado por completo o no queda
+/// nada bloqueado. Como todos los carritos recorren las particiones en el mismo
+/// orden, de dos carritos que compiten por el mismo stock gana el que llega
+/// primero a la primera particion en comun, en vez de abortarse ambos.
+async fn bloquear_en_particiones(
+    mut partes: Vec<(usize, Addr<Guardian>, Bloquear)>,
+) -> Result<(), ErrorGuardian> {
+    partes.sort_by_key(|(particion, _, _)| *particion);
+
+    let mut bloqueadas: Vec<(Addr<Guardian>, _)> = Vec::new();
+    for (_, particion, bloqueo) in partes {
+        let id = bloqueo.id;
+        let resultado = particion
+            .send(bloqueo)
+            .await
+            .unwrap_or(Err(ErrorGuardian::ParticionNoDisponible));
+        if let Err(e) = resultado {
+            for (particion, id) in bloqueadas.into_iter().rev() {
+                let _ = particion.send(Cancelar { id }).await;
+            }
+            return Err(e);
+        }
+        bloqueadas.push((particion, id));
+    }
+    Ok(())
+}
+
+/// Envia un mensaje sobre un pedido bloqueado a cada una de las particiones
+/// dadas, devolviendo lo que respondio cada una junto a su numero
+async fn enviar_a_cada_particion<M, T>(
+    particiones: Vec<(usize, Addr<Guardian>)>,
+    mensaje: impl Fn() -> M,
+) -> Vec<(usize, Result<T, ErrorGuardian>)>
+where
+    M: Message<Result = Result<T, ErrorGuardian>> + Send + 'static,
+    T: Send + 'static,
+    Guardian: Handler<M>,
+{
+    let resultados = join_all(
+        particiones
+            .iter()
+            .map(|(_, particion)| particion.send(mensaje())),
+    )
+    .await;
+    particiones
+        .into_iter()
+        .zip(resultados)
+        .map(|((particion, _), resultado)| {
+            let resultado = resultado.unwrap_or(Err(ErrorGuardian::ParticionNoDisponible));
+            (particion, resultado)
+        })
+        .collect()
+}
+
+/// Envia un mensaje sobre un pedido bloqueado a cada una de las particiones
+/// dadas, devolviendo lo que respondio cada una, o el primer error que alguna
+/// informe
+async fn enviar_a_particiones<M, T>(
+    particiones: Vec<Addr<Guardian>>,
+    mensaje: impl Fn() -> M,
+) -> Result<Vec<T>, ErrorGuardian>
+where
+    M: Message<Result = Result<T, ErrorGuardian>> + Send + 'static,
+    T: Send + 'static,
+    Guardian: Handler<M>,
+{
+    let resultados = join_all(
+        particiones
+            .iter()
+            .map(|particion| particion.send(mensaje())),
+    )
+    .await;
+    resultados
+        .into_iter()
+        .map(|resultado| resultado.map_err(|_| ErrorGuardian::ParticionNoDisponible)?)
+        .collect()
+}
+
+impl Actor for GuardianParticionado {
+    type Context = Context<Self>;
+
+    /// Inicia cada guardian en un hilo propio. Los guardianes avisan al enrutador
+    /// cuando un bloqueo expira, y comparten el reloj de su libro de movimientos.
+    /// Los bloqueos que los guardianes recuperaron del registro se recuerdan como
+    /// los demas, para poder confirmarlos, cancelarlos o avisar si expiran
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let aviso: Recipient<PedidoExpirado> = ctx.address().recipient();
+        let inicio = Instant::now();
+        for (particion, guardian) in self.por_iniciar.drain(..).enumerate() {
+            for id in guardian.ids_bloqueados() {
+                self.bloqueos.entry(id).or_default().push(particion);
+            }
+            let guardian = guardian
+                .con_aviso_expiracion(aviso.clone())
+                .con_reloj(inicio);
+            let arbitro = Arbiter::new();
+            let addr = Guardian::start_in_arbiter(&arbitro.handle(), move |_| guardian);
+            self.particiones.push(addr);
+            self.arbitros.push(arbitro);
+        }
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        for arbitro in &self.arbitros {
+            arbitro.stop();
+        }
+    }
+}
+
+impl Handler<Descontar> for GuardianParticionado {
+    type Result = ();
+
+    fn handle(&mut self, msg: Descontar, _ctx: &mut Context<Self>) -> Self::Result {
+        self.particion(msg.pedido.get_id()).do_send(msg);
+    }
+}
+
+impl Handler<ObtenerStock> for GuardianParticionado {
+    type Result = ResponseFuture<CantidadProducto>;
+
+    fn handle(&mut self, msg: ObtenerStock, _ctx: &mut Context<Self>) -> Self::Result {
+        let particion = self.particion(msg.id).clone();
+        Box::pin(async move { particion.send(msg).await.unwrap_or(0) })
+    }
+}
+
+impl Handler<Bloquear> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    fn handle(&mut self, msg: Bloquear, _ctx: &mut Context<Self>) -> Self::Result {
+        let id = msg.id;
+        if self.bloqueos.contains_key(&id) {
+            return Box::pin(fut::ready(Err(ErrorGuardian::PedidoYaBloqueado)));
+        }
+        let partes = self.bloqueos_parciales(&msg.pedido, id, msg.ttl);
+        // Se anota antes de bloquear, para que un reenvio del mismo pedido que
+        // llegue mientras tanto sea rechazado
+        let particiones: Vec<usize> = partes.iter().map(|(p, _, _)| *p).collect();
+        let vence_en_el_enrutador = particiones.len() > 1;
+        self.bloqueos.insert(id, particiones);
+        Box::pin(bloquear_en_particiones(partes).into_actor(self).map(
+            move |resultado, guardian, ctx| {
+                match (&resultado, msg.ttl) {
+                    (Err(_), _) => {
+                        guardian.bloqueos.remove(&id);
+                    }
+                    (Ok(()), Some(ttl)) if vence_en_el_enrutador => {
+                        guardian.programar_vencimiento(id, ttl, ctx);
+                    }
+                    (Ok(()), _) => {}
+                }
+                resultado
+            },
+        ))
+    }
+}
+
+/// Mensaje que reserva unidades de un producto para transferirlas a otro local.
+/// Se bloquean como un pedido, identificado por el id de la transferencia y el
+/// puerto de transferencias del receptor, pero si la reserva expira no se avisa
+/// a nadie, ya que no hay un ecommerce esperando el resultado.
+/// # Errors
+/// Los mismos que `Bloquear`
+#[derive(Message)]
+#[rtype(result = "Result<(), ErrorGuardian>")]
+pub struct ReservarTransferencia {
+    bloqueo: Bloquear,
+}
+
+impl ReservarTransferencia {
+    /// Crea la reserva de las unidades de una transferencia, que expira pasado
+    /// el tiempo de vida dado si no se confirma ni cancela
+    pub fn new(
+        pedido: Carrito,
+        id_transferencia: IdTransferencia,
+        puerto: Puerto,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            bloqueo: Bloquear::new(pedido, id_transferencia, puerto).con_ttl(ttl),
+        }
+    }
+}
+
+impl Handler<ReservarTransferencia> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    fn handle(&mut self, msg: ReservarTransferencia, ctx: &mut Context<Self>) -> Self::Result {
+        let id = msg.bloqueo.id;
+        let ya_bloqueado = self.bloqueos.contains_key(&id);
+        let reserva = <Self as Handler<Bloquear>>::handle(self, msg.bloqueo, ctx);
+        if ya_bloqueado {
+            return reserva;
+        }
+        self.transferencias.insert(id);
+        Box::pin(reserva.map(move |resultado, guardian, _ctx| {
+            if resultado.is_err() {
+                guardian.transferencias.remove(&id);
+            }
+            resultado
+        }))
+    }
+}
+
+impl Handler<Confirmar> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<Carrito, ErrorGuardian>>;
+
+    /// Confirma el pedido en cada particion, y devuelve el carrito confirmado
+    /// como la union de lo que confirmo cada una. El bloqueo se olvida recien
+    /// cuando las particiones lo confirmaron, y mientras tanto no vence
+    fn handle(&mut self, msg: Confirmar, ctx: &mut Context<Self>) -> Self::Result {
+        let id = msg.id;
+        let Some(particiones) = self.particiones_bloqueadas(&id) else {
+            return Box::pin(fut::ready(Err(ErrorGuardian::PedidoInexistente)));
+        };
+        let vencia = self.cancelar_vencimiento(&id, ctx);
+        Box::pin(
+            enviar_a_cada_particion(particiones, move || Confirmar { id })
+                .into_actor(self)
+                .map(move |resultados, guardian, ctx| {
+                    let resultado = guardian.resolver_bloqueo(&id, resultados);
+                    guardian.rearmar_vencimiento(id, vencia, ctx);
+                    let partes = resultado?;
+                    let lineas = partes
+                        .iter()
+                        .flat_map(|parte| parte.get_lineas().iter().cloned())
+                        .collect();
+                    Ok(Carrito::new(lineas)?)
+                }),
+        )
+    }
+}
+
+impl Handler<ObtenerLotesBloqueados> for GuardianParticionado {
+    type Result = ResponseFuture<Result<Vec<(IdProducto, Lote)>, ErrorGuardian>>;
+
+    /// Une los lotes que bloqueo el pedido en cada particion
+    fn handle(&mut self, msg: ObtenerLotesBloqueados, _ctx: &mut Context<Self>) -> Self::Result {
+        let id = msg.id;
+        let Some(particiones) = self.bloqueos.get(&id) else {
+            return Box::pin(async { Err(ErrorGuardian::PedidoInexistente) });
+        };
+        let particiones = particiones
+            .iter()
+            .map(|particion| self.particiones[*particion].clone())
+            .collect();
+        Box::pin(async move {
+            let partes =
+                enviar_a_particiones(particiones, move || ObtenerLotesBloqueados { id }).await?;
+            Ok(partes.into_iter().flatten().collect())
+        })
+    }
+}
+
+impl Handler<Cancelar> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    /// Cancela el pedido en cada particion. El bloqueo se olvida recien cuando
+    /// las particiones lo cancelaron, y mientras tanto no vence
+    fn handle(&mut self, msg: Cancelar, ctx: &mut Context<Self>) -> Self::Result {
+        let id = msg.id;
+        let Some(particiones) = self.particiones_bloqueadas(&id) else {
+            return Box::pin(fut::ready(Err(ErrorGuardian::PedidoInexistente)));
+        };
+        let vencia = self.cancelar_vencimiento(&id, ctx);
+        Box::pin(
+            enviar_a_cada_particion(particiones, move || Cancelar { id })
+                .into_actor(self)
+                .map(move |resultados, guardian, ctx| {
+                    let resultado = guardian.resolver_bloqueo(&id, resultados);
+                    guardian.rearmar_vencimiento(id, vencia, ctx);
+                    guardian.atender_pedidos_en_espera(ctx);
+                    resultado.map(|_| ())
+                }),
+        )
+    }
+}
+
+impl Handler<Renovar> for GuardianParticionado {
+    type Result = ResponseFuture<Result<(), ErrorGuardian>>;
+
+    /// Renueva el vencimiento del bloqueo donde se lleva: en el enrutador si
+    /// abarca varias particiones, o en su unica particion si no
+    fn handle(&mut self, msg: Renovar, ctx: &mut Context<Self>) -> Self::Result {
+        let particion = match self.bloqueos.get(&msg.id).map(Vec::as_slice) {
+            None | Some([]) => return Box::pin(fut::ready(Err(ErrorGuardian::PedidoInexistente))),
+            Some([particion]) => *particion,
+            Some(_) => {
+                if self.vencimientos.contains_key(&msg.id) {
+                    self.programar_vencimiento(msg.id, msg.ttl, ctx);
+                }
+                return Box::pin(fut::ready(Ok(())));
+            }
+        };
+        let guardian = self.particiones[particion].clone();
+        Box::pin(async move {
+            guardian
+                .send(msg)
+                .await
+                .map_err(|_| ErrorGuardian::ParticionNoDisponible)?
+        })
+    }
+}
+
+impl Handler<Reducir> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    fn handle(&mut self, msg: Reducir, _ctx: &mut Context<Self>) -> Self::Result {
+        let particion = particion_de(msg.id_producto, self.cantidad_particiones);
+        match self.bloqueos.get(&msg.id) {
+            None => return Box::pin(fut::ready(Err(ErrorGuardian::PedidoInexistente))),
+            // El producto no es parte del pedido
+            Some(particiones) if !particiones.contains(&particion) => {
+                return Box::pin(fut::ready(Err(ErrorGuardian::ReduccionInvalida)))
+            }
+            Some(_) => {}
+        }
+        let guardian = self.particiones[particion].clone();
+        Box::pin(
+            async move {
+                guardian
+                    .send(msg)
+                    .await
+                    .map_err(|_| ErrorGuardian::ParticionNoDisponible)?
+            }
+            .into_actor(self)
+            .map(|resultado, guardian, ctx| {
+                guardian.atender_pedidos_en_espera(ctx);
+                resultado
+            }),
+        )
+    }
+}
+
+impl Handler<Reponer> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    fn handle(&mut self, msg: Reponer, _ctx: &mut Context<Self>) -> Self::Result {
+        let particion = self.particion(msg.id_producto).clone();
+        Box::pin(
+            async move {
+                particion
+                    .send(msg)
+                    .await
+                    .map_err(|_| ErrorGuardian::ParticionNoDisponible)?
+            }
+            .into_actor(self)
+            .map(|resultado, guardian, ctx| {
+                if resultado.is_ok() {
+                    guardian.atender_pedidos_en_espera(ctx);
+                }
+                resultado
+            }),
+        )
+    }
+}
+
+impl Handler<Devolver> for GuardianParticionado {
+    type Result = ResponseActFuture<Self, Result<(), ErrorGuardian>>;
+
+    fn handle(&mut self, msg: Devolver, _ctx: &mut Context<Self>) -> Self::Result {
+        let particion = self.particion(msg.id_producto).clone();
+        Box::pin(
+            async move {
+                particion
+                    .send(msg)
+                    .await
+                    .map_err(|_| ErrorGuardian::ParticionNoDisponible)?
+            }
+            .into_actor(self)
+            .map(|resultado, guardian, ctx| {
+                if resultado.is_ok() {
+                    guardian.atender_pedidos_en_espera(ctx);
+                }
+                resultado
+            }),
+        )
+    }
+}
+
+impl Handler<Encolar> for GuardianParticionado {
+    type Result = ();
+
+    /// Si el pedido cae en una sola particion, lo encola en el guardian de esa
+    /// particion, y recuerda el bloqueo cuando este avisa que pudo cubrirlo
+    fn handle(&mut self, msg: Encolar, ctx: &mut Context<Self>) -> Self::Result {
+        let particiones = self.dividir_carrito(&msg.pedido);
+        if particiones.len() > 1 {
+            self.pedidos_en_espera.push_back(msg);
+            self.atender_pedidos_en_espera(ctx);
+            return;
+        }
+
+        let Some(particion) = particiones.into_keys().next() else {
+            return;
+        };
+        let id = msg.id;
+        let (aviso_particion, bloqueado) = oneshot::channel();
+        self.particiones[particion].do_send(Encolar {
+            pedido: msg.pedido,
+            id,
+            aviso: aviso_particion,
+        });
+        let aviso = msg.aviso;
+        ctx.spawn(async move { bloqueado.await.is_ok() }.into_actor(self).map(
+            move |bloqueado, guardian, _ctx| {
+                if !bloqueado {
+                    return;
+                }
+                guardian.bloqueos.insert(id, vec![particion]);
+                if aviso.send(()).is_err() {
+                    guardian.bloqueos.remove(&id);
+                    guardian.particiones[particion].do_send(Cancelar { id });
+                }
+            },
+        ));
+    }
+}
+
+impl Handler<PedidoExpirado> for GuardianParticionado {
+    type Result = ();
+
+    /// Cuando expira el bloqueo de un pedido en su particion, avisa de la
+    /// expiracion. Solo los bloqueos de una particion, y los que las particiones
+    /// recuperaron de su registro, vencen en la particion; si abarcaba otras, lo
+    /// cancela en ellas
+    fn handle(&mut self, msg: PedidoExpirado, ctx: &mut Context<Self>) -> Self::Result {
+        let id = (msg.id_pedido, msg.puerto);
+        let era_transferencia = self.transferencias.contains(&id);
+        let Some(particiones) = self.olvidar_bloqueo(&id) else {
+            return;
+        };
+        self.cancelar_vencimiento(&id, ctx);
+        for particion in particiones {
+            particion.do_send(Cancelar { id });
+        }
+        self.avisar_expiracion(id, era_transferencia);
+        self.atender_pedidos_en_espera(ctx);
+    }
+}
+
+impl Handler<VaciarEspera> for GuardianParticionado {
+    type Result = ResponseFuture<usize>;
+
+    /// Vacia la cola del enrutador y las de cada particion
+    fn handle(&mut self, _msg: VaciarEspera, _ctx: &mut Context<Self>) -> Self::Result {
+        let propios = std::mem::take(&mut self.pedidos_en_espera).len();
+        let particiones = self.particiones.clone();
+        Box::pin(async move {
+            join_all(particiones.iter().map(|p| p.send(VaciarEspera)))
+                .await
+                .into_iter()
+                .flatten()
+                .sum::<usize>()
+                + propios
+        })
+    }
+}
+
+impl Handler<ObtenerInventario> for GuardianParticionado {
+    type Result = ResponseFuture<Inventario>;
+
+    fn handle(&mut self, _msg: ObtenerInventario, _ctx: &mut Context<Self>) -> Self::Result {
+        let particiones = self.particiones.clone();
+        Box::pin(async move {
+            let mut inventario = Inventario::default();
+            for parcial in join_all(particiones.iter().map(|p| p.send(ObtenerInventario))).await {
+                match parcial {
+                    Ok(parcial) => inventario.productos.extend(parcial.productos),
+                    Err(_) => eprintln!("No se pudo obtener el inventario de una particion"),
+                }
+            }
+            inventario
+        })
+    }
+}
+
+impl Handler<ConsultarMovimientos> for GuardianParticionado {
+    type Result = ResponseFuture<Vec<Movimiento>>;
+
+    /// Une los movimientos de todas las particiones, ordenados por instante
+    fn handle(&mut self, msg: ConsultarMovimientos, _ctx: &mut Context<Self>) -> Self::Result {
+        let particiones = self.particiones.clone();
+        Box::pin(async move {
+            let mut movimientos: Vec<Movimiento> =
+                join_all(particiones.iter().map(|p| p.send(msg)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .collect();
+            movimientos.sort_by_key(|movimiento| movimiento.instante_us);
+            for (secuencia, movimiento) in movimientos.iter_mut().enumerate() {
+                movimiento.secuencia = secuencia as u64;
+            }
+            movimientos
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::registro::Registro;
+    use crate::pedido::Pedido;
+
+    fn crear_guardian(cantidad_particiones: usize) -> Addr<GuardianParticionado> {
+        let stock = HashMap::from([(1, 5), (2, 5), (3, 5), (4, 5)]);
+        let guardianes = dividir_stock(stock, cantidad_particiones)
+            .into_iter()
+            .map(Guardian::new)
+            .collect();
+        GuardianParticionado::new(guardianes).start()
+    }
+
+    #[test]
+    fn dividir_stock_reparte_los_productos_por_particion() {
+        let particiones = dividir_stock(HashMap::from([(1, 5), (2, 3), (4, 1)]), 2);
+        assert_eq!(particiones[0].como_tabla(), HashMap::from([(2, 3), (4, 1)]));
+        assert_eq!(particiones[1].como_tabla(), HashMap::from([(1, 5)]));
+    }
+
+    #[actix_rt::test]
+    async fn bloquear_carrito_entre_particiones_es_atomico() {
+        let addr = crear_guardian(2);
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 6)]).unwrap();
+
+        assert!(addr
+            .send(Bloquear::new(carrito, 1, 1))
+            .await
+            .unwrap()
+            .is_err());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn confirmar_y_cancelar_alcanzan_a_todas_las_particiones() {
+        let addr = crear_guardian(4);
+        let carrito = Carrito::new(vec![
+            Pedido::new(1, 2),
+            Pedido::new(2, 1),
+            Pedido::new(3, 1),
+        ])
+        .unwrap();
+        addr.send(Bloquear::new(carrito.clone(), 1, 1))
+            .await
+            .unwrap()
+            .unwrap();
+        addr.send(Bloquear::new(carrito, 2, 1))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(addr.send(Confirmar::new(1, 1)).await.unwrap().is_ok());
+        assert!(addr.send(Cancelar::new(2, 1)).await.unwrap().is_ok());
+        assert!(addr.send(Cancelar::new(2, 1)).await.unwrap().is_err());
+
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 4);
+        let inventario = addr.send(ObtenerInventario).await.unwrap();
+        assert_eq!(inventario.productos.len(), 4);
+    }
+
+    #[actix_rt::test]
+    async fn reducir_llega_a_la_particion_del_producto() {
+        let addr = crear_guardian(4);
+        let carrito = Carrito::new(vec![Pedido::new(1, 3), Pedido::new(2, 1)]).unwrap();
+        addr.send(Bloquear::new(carrito, 1, 1))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(addr.send(Reducir::new(1, 1, 1, 2)).await.unwrap().is_ok());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 4);
+        assert!(matches!(
+            addr.send(Reducir::new(1, 1, 3, 1)).await.unwrap(),
+            Err(ErrorGuardian::ReduccionInvalida)
+        ));
+        assert!(matches!(
+            addr.send(Reducir::new(2, 1, 1, 1)).await.unwrap(),
+            Err(ErrorGuardian::PedidoInexistente)
+        ));
+
+        let confirmado = addr.send(Confirmar::new(1, 1)).await.unwrap().unwrap();
+        assert_eq!(
+            confirmado.cantidades_por_producto(),
+            HashMap::from([(1, 1), (2, 1)])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn bloqueo_expirado_en_varias_particiones_avisa_una_vez() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let aviso =
+            actix::actors::mocker::Mocker::<PedidoExpirado>::mock(Box::new(move |msg, _ctx| {
+                tx.try_send(msg).unwrap();
+                Box::new(Some(()))
+            }))
+            .start()
+            .recipient();
+        let stock = dividir_stock(HashMap::from([(1, 5), (2, 5)]), 2);
+        let addr = GuardianParticionado::new(stock.into_iter().map(Guardian::new).collect())
+            .con_aviso_expiracion(aviso)
+            .start();
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 2)]).unwrap();
+        addr.send(Bloquear::new(carrito, 1, 1).con_ttl(Duration::from_millis(50)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(rx.recv().await.is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        assert!(matches!(
+            addr.send(Confirmar::new(1, 1)).await.unwrap(),
+            Err(ErrorGuardian::PedidoInexistente)
+        ));
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn un_carrito_entre_particiones_confirmado_a_tiempo_no_vence_en_ninguna() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let aviso =
+            actix::actors::mocker::Mocker::<PedidoExpirado>::mock(Box::new(move |msg, _ctx| {
+                tx.try_send(msg).unwrap();
+                Box::new(Some(()))
+            }))
+            .start()
+            .recipient();
+        let stock = dividir_stock(HashMap::from([(1, 5), (2, 5)]), 2);
+        let addr = GuardianParticionado::new(stock.into_iter().map(Guardian::new).collect())
+            .con_aviso_expiracion(aviso)
+            .start();
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 2)]).unwrap();
+        addr.send(Bloquear::new(carrito, 1, 1).con_ttl(Duration::from_millis(50)))
+            .await
+            .unwrap()
+            .unwrap();
+        let confirmado = addr.send(Confirmar::new(1, 1)).await.unwrap().unwrap();
+        assert_eq!(
+            confirmado.cantidades_por_producto(),
+            HashMap::from([(1, 2), (2, 2)])
+        );
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn renovar_un_bloqueo_posterga_su_vencimiento() {
+        let addr = crear_guardian(2);
+        let ttl = Duration::from_millis(100);
+        let en_una = Carrito::new(vec![Pedido::new(1, 2)]).unwrap();
+        let entre_dos = Carrito::new(vec![Pedido::new(2, 2), Pedido::new(3, 2)]).unwrap();
+        addr.send(Bloquear::new(en_una, 1, 1).con_ttl(ttl))
+            .await
+            .unwrap()
+            .unwrap();
+        addr.send(Bloquear::new(entre_dos, 2, 1).con_ttl(ttl))
+            .await
+            .unwrap()
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for id in [1, 2] {
+            addr.send(Renovar::new(id, 1, Duration::from_millis(150)))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        assert!(matches!(
+            addr.send(Renovar::new(3, 1, ttl)).await.unwrap(),
+            Err(ErrorGuardian::PedidoInexistente)
+        ));
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        for id in 1..=3 {
+            assert_eq!(addr.send(ObtenerStock { id }).await.unwrap(), 3);
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        for id in 1..=3 {
+            assert_eq!(addr.send(ObtenerStock { id }).await.unwrap(), 5);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn la_reserva_de_una_transferencia_expira_sin_avisar() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let aviso =
+            actix::actors::mocker::Mocker::<PedidoExpirado>::mock(Box::new(move |msg, _ctx| {
+                tx.try_send(msg).unwrap();
+                Box::new(Some(()))
+            }))
+            .start()
+            .recipient();
+        let stock = dividir_stock(HashMap::from([(1, 5)]), 2);
+        let addr = GuardianParticionado::new(stock.into_iter().map(Guardian::new).collect())
+            .con_aviso_expiracion(aviso)
+            .start();
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 2)]).unwrap();
+        let reserva = ReservarTransferencia::new(carrito, 1, 1, Duration::from_millis(50));
+        addr.send(reserva).await.unwrap().unwrap();
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(addr.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn pedido_en_espera_entre_particiones_se_bloquea_al_reponer() {
+        let addr = crear_guardian(2);
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 7)]).unwrap();
+        let (aviso, stock_disponible) = oneshot::channel();
+        addr.send(Encolar::new(carrito, 1, 1, aviso)).await.unwrap();
+
+        addr.send(Reponer::new(2, 2)).await.unwrap().unwrap();
+        stock_disponible.await.unwrap();
+        assert_eq!(addr.send(ObtenerStock { id: 2 }).await.unwrap(), 0);
+        assert!(addr.send(Confirmar::new(1, 1)).await.unwrap().is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn los_bloqueos_recuperados_del_registro_se_pueden_confirmar() {
+        let dir = std::env::temp_dir().join(format!("particiones-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let abrir = |particion| Registro::abrir_particion(&dir, 0, particion, 2).unwrap();
+        let guardianes = dividir_stock(HashMap::from([(1, 5), (2, 5)]), 2)
+            .into_iter()
+            .enumerate()
+            .map(|(particion, stock)| Guardian::new(stock).con_registro(abrir(particion)).unwrap())
+            .collect();
+        let addr = GuardianParticionado::new(guardianes).start();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 1)]).unwrap();
+        assert!(addr
+            .send(Bloquear::new(carrito, 1, 1))
+            .await
+            .unwrap()
+            .is_ok());
+
+        let recuperados = (0..2)
+            .map(|particion| Guardian::recuperar(abrir(particion)).unwrap())
+            .collect();
+        let recuperado = GuardianParticionado::new(recuperados).start();
+        assert!(recuperado.send(Confirmar::new(1, 1)).await.unwrap().is_ok());
+        assert_eq!(recuperado.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[actix_rt::test]
+    async fn vaciar_la_espera_descarta_los_pedidos_de_todas_las_colas() {
+        let addr = crear_guardian(2);
+        let (aviso_varias, varias) = oneshot::channel();
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(2, 7)]).unwrap();
+        addr.send(Encolar::new(carrito, 1, 1, aviso_varias))
+            .await
+            .unwrap();
+        let (aviso_una, una) = oneshot::channel();
+        addr.send(Encolar::new(
+            Carrito::from(Pedido::new(3, 9)),
+            2,
+            1,
+            aviso_una,
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(addr.send(VaciarEspera).await.unwrap(), 2);
+        assert!(varias.await.is_err());
+        assert!(una.await.is_err());
+        addr.send(Reponer::new(3, 9)).await.unwrap().unwrap();
+        assert_eq!(addr.send(ObtenerStock { id: 3 }).await.unwrap(), 14);
+    }
+}
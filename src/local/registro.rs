@@ -0,0 +1,376 @@
+//! Este modulo define el registro de operaciones del guardian, que permite
+//! que el estado del stock sobreviva a un reinicio del local. Cada cambio
+//! sobre el stock se agrega al final de un archivo de log, y cada cierta
+//! cantidad de operaciones el estado completo se compacta en un snapshot.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::lotes::{Lote, StockLotes};
+use crate::aliases::{CantidadProducto, IdLocal, IdPedido, IdProducto, Puerto};
+use crate::errores::ErrorDuranteParseo;
+use crate::pedido::Carrito;
+
+/// Directorio por defecto donde los locales guardan su estado
+pub const DIRECTORIO_ESTADO: &str = "estado";
+
+/// Cantidad de operaciones que se acumulan en el log antes de compactarlo
+const OPERACIONES_POR_SNAPSHOT: u64 = 100;
+
+/// Operaciones que modifican el stock del guardian, tal como se guardan en el log.
+/// Las que toman stock guardan la fecha en que se hicieron, para que al
+/// reproducirlas se salteen los mismos lotes vencidos; las anteriores a este
+/// dato no saltean ninguno
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Operacion {
+    Descontar {
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fecha: Option<NaiveDate>,
+    },
+    Bloquear {
+        id_pedido: IdPedido,
+        puerto: Puerto,
+        pedido: Carrito,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fecha: Option<NaiveDate>,
+    },
+    Confirmar {
+        id_pedido: IdPedido,
+        puerto: Puerto,
+    },
+    Cancelar {
+        id_pedido: IdPedido,
+        puerto: Puerto,
+    },
+    Reducir {
+        id_pedido: IdPedido,
+        puerto: Puerto,
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+    },
+    Reponer {
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vencimiento: Option<NaiveDate>,
+    },
+    DescartarVencidos {
+        fecha: NaiveDate,
+    },
+    Devolver {
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+    },
+}
+
+/// Linea del log. El numero de secuencia permite descartar las operaciones
+/// que ya estaban incluidas en el snapshot
+#[derive(Debug, Serialize, Deserialize)]
+struct Entrada {
+    secuencia: u64,
+    operacion: Operacion,
+}
+
+/// Lotes de los que salio cada pedido bloqueado, tal como se guardan en el snapshot
+pub type LotesBloqueados = Vec<(IdPedido, Puerto, Vec<(IdProducto, Lote)>)>;
+
+/// Estado completo del guardian en un momento dado. Los snapshots anteriores
+/// a los lotes no tienen los lotes de los pedidos bloqueados, y su stock es
+/// una cantidad por producto; ambos formatos pueden leerse.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Snapshot {
+    pub secuencia: u64,
+    pub stock: StockLotes,
+    pub pedidos_bloqueados: Vec<(IdPedido, Puerto, Carrito)>,
+    #[serde(default)]
+    pub lotes_bloqueados: LotesBloqueados,
+}
+
+/// Registro persistente de las operaciones de un guardian
+pub struct Registro {
+    ruta_log: PathBuf,
+    ruta_snapshot: PathBuf,
+    log: File,
+    secuencia: u64,
+    secuencia_snapshot: u64,
+}
+
+impl Registro {
+    /// Abre (o crea) el registro del local dado dentro del directorio pasado
+    pub fn abrir(directorio: &Path, id: IdLocal) -> io::Result<Self> {
+        Self::abrir_con_nombre(directorio, &format!("guardian{}", id))
+    }
+
+    /// Abre (o crea) el registro de una de las particiones del guardian del local
+    /// dado. Con una unica particion usa los mismos archivos que `abrir`, y con
+    /// varias el nombre incluye la cantidad, que se puede consultar con
+    /// `particiones_guardadas` antes de abrirlo.
+    pub fn abrir_particion(
+        directorio: &Path,
+        id: IdLocal,
+        particion: usize,
+        cantidad_particiones: usize,
+    ) -> io::Result<Self> {
+        if cantidad_particiones == 1 {
+            return Self::abrir(directorio, id);
+        }
+        let nombre = format!("guardian{}-{}de{}", id, particion, cantidad_particiones);
+        Self::abrir_con_nombre(directorio, &nombre)
+    }
+
+    /// Devuelve con cuantas particiones se guardo el estado del local dado en el
+    /// directorio pasado, o `None` si no hay ningun estado guardado
+    pub fn particiones_guardadas(directorio: &Path, id: IdLocal) -> io::Result<Option<usize>> {
+        let entradas = match fs::read_dir(directorio) {
+            Ok(entradas) => entradas,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let unica = format!("guardian{}.snapshot.json", id);
+        let prefijo = format!("guardian{}-", id);
+        for entrada in entradas {
+            let nombre = entrada?.file_name();
+            let nombre = nombre.to_string_lossy();
+            if nombre == unica {
+                return Ok(Some(1));
+            }
+            let cantidad = nombre
+                .strip_prefix(&prefijo)
+                .and_then(|resto| resto.strip_suffix(".snapshot.json"))
+                .and_then(|resto| resto.split_once("de"))
+                .and_then(|(_, cantidad)| cantidad.parse().ok());
+            if cantidad.is_some() {
+                return Ok(cantidad);
+            }
+        }
+        Ok(None)
+    }
+
+    fn abrir_con_nombre(directorio: &Path, nombre: &str) -> io::Result<Self> {
+        fs::create_dir_all(directorio)?;
+        let ruta_log = directorio.join(format!("{}.log", nombre));
+        let ruta_snapshot = directorio.join(format!("{}.snapshot.json", nombre));
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&ruta_log)?;
+
+        Ok(Self {
+            ruta_log,
+            ruta_snapshot,
+            log,
+            secuencia: 0,
+            secuencia_snapshot: 0,
+        })
+    }
+
+    /// Devuelve si el registro tiene un estado guardado del cual recuperarse
+    pub fn hay_estado(&self) -> bool {
+        self.ruta_snapshot.exists()
+    }
+
+    /// Lee el ultimo snapshot y las operaciones posteriores a este. Si la ultima
+    /// linea del log quedo escrita a medias (por ejemplo, por una caida durante
+    /// la escritura) se descarta, ya que esa operacion nunca fue informada.
+    /// # Errors
+    /// * `ErrorDuranteParseo::NoSePudoAbrirArchivo` si no existe el snapshot
+    /// * `ErrorDuranteParseo::FormatoArchivoInvalido` si el snapshot o alguna linea
+    ///   intermedia del log estan corruptos
+    pub fn leer(&mut self) -> Result<(Snapshot, Vec<Operacion>), ErrorDuranteParseo> {
+        let snapshot: Snapshot = serde_json::from_reader(File::open(&self.ruta_snapshot)?)?;
+
+        let lineas: Vec<String> = BufReader::new(File::open(&self.ruta_log)?)
+            .lines()
+            .collect::<Result<_, _>>()?;
+        let mut operaciones = Vec::new();
+        let mut secuencia = snapshot.secuencia;
+        for (i, linea) in lineas.iter().enumerate() {
+            let entrada: Entrada = match serde_json::from_str(linea) {
+                Ok(entrada) => entrada,
+                Err(_) if i + 1 == lineas.len() => break,
+                Err(e) => return Err(e.into()),
+            };
+            if entrada.secuencia > snapshot.secuencia {
+                secuencia = entrada.secuencia;
+                operaciones.push(entrada.operacion);
+            }
+        }
+
+        self.secuencia = secuencia;
+        self.secuencia_snapshot = snapshot.secuencia;
+        Ok((snapshot, operaciones))
+    }
+
+    /// Agrega una operacion al final del log, forzando su escritura a disco
+    pub fn agregar(&mut self, operacion: Operacion) -> io::Result<()> {
+        let entrada = Entrada {
+            secuencia: self.secuencia + 1,
+            operacion,
+        };
+        let mut linea = serde_json::to_string(&entrada)?;
+        linea.push('\n');
+        self.log.write_all(linea.as_bytes())?;
+        self.log.sync_data()?;
+        self.secuencia += 1;
+        Ok(())
+    }
+
+    /// Devuelve si ya se acumularon suficientes operaciones como para compactar
+    pub fn debe_compactar(&self) -> bool {
+        self.secuencia - self.secuencia_snapshot >= OPERACIONES_POR_SNAPSHOT
+    }
+
+    /// Guarda el estado dado como snapshot y vacia el log. El snapshot se escribe
+    /// primero en un archivo temporal y luego se renombra, para que una caida a
+    /// mitad de camino no deje un snapshot incompleto.
+    pub fn compactar(
+        &mut self,
+        stock: &StockLotes,
+        pedidos_bloqueados: Vec<(IdPedido, Puerto, Carrito)>,
+        lotes_bloqueados: LotesBloqueados,
+    ) -> io::Result<()> {
+        let snapshot = Snapshot {
+            secuencia: self.secuencia,
+            stock: stock.clone(),
+            pedidos_bloqueados,
+            lotes_bloqueados,
+        };
+        let ruta_temporal = self.ruta_snapshot.with_extension("tmp");
+        let mut archivo = File::create(&ruta_temporal)?;
+        archivo.write_all(serde_json::to_string(&snapshot)?.as_bytes())?;
+        archivo.sync_data()?;
+        fs::rename(&ruta_temporal, &self.ruta_snapshot)?;
+
+        self.log.set_len(0)?;
+        self.secuencia_snapshot = self.secuencia;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pedido::Pedido;
+    use std::collections::HashMap;
+
+    fn directorio_de_prueba(nombre: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("registro-{}-{}", nombre, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn registro_nuevo_no_tiene_estado() {
+        let dir = directorio_de_prueba("nuevo");
+        let registro = Registro::abrir(&dir, 0).unwrap();
+        assert!(!registro.hay_estado());
+    }
+
+    #[test]
+    fn se_detecta_la_cantidad_de_particiones_guardadas() {
+        let dir = directorio_de_prueba("particiones");
+        assert_eq!(Registro::particiones_guardadas(&dir, 1).unwrap(), None);
+
+        let mut registro = Registro::abrir_particion(&dir, 1, 0, 3).unwrap();
+        assert_eq!(Registro::particiones_guardadas(&dir, 1).unwrap(), None);
+        registro
+            .compactar(&StockLotes::default(), vec![], vec![])
+            .unwrap();
+        assert_eq!(Registro::particiones_guardadas(&dir, 1).unwrap(), Some(3));
+        assert_eq!(Registro::particiones_guardadas(&dir, 10).unwrap(), None);
+
+        Registro::abrir(&dir, 2)
+            .unwrap()
+            .compactar(&StockLotes::default(), vec![], vec![])
+            .unwrap();
+        assert_eq!(Registro::particiones_guardadas(&dir, 2).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn leer_devuelve_snapshot_y_operaciones_posteriores() {
+        let dir = directorio_de_prueba("leer");
+        let mut registro = Registro::abrir(&dir, 0).unwrap();
+        let stock = StockLotes::from(HashMap::from([(1, 5)]));
+        registro.compactar(&stock, vec![], vec![]).unwrap();
+
+        let op = Operacion::Bloquear {
+            id_pedido: 1,
+            puerto: 3000,
+            pedido: Carrito::from(Pedido::new(1, 2)),
+            fecha: None,
+        };
+        registro.agregar(op.clone()).unwrap();
+
+        let mut reabierto = Registro::abrir(&dir, 0).unwrap();
+        let (snapshot, operaciones) = reabierto.leer().unwrap();
+        assert_eq!(snapshot.stock, stock);
+        assert_eq!(operaciones, vec![op]);
+    }
+
+    #[test]
+    fn leer_descarta_ultima_linea_incompleta() {
+        let dir = directorio_de_prueba("incompleta");
+        let mut registro = Registro::abrir(&dir, 0).unwrap();
+        registro
+            .compactar(&StockLotes::default(), vec![], vec![])
+            .unwrap();
+        registro
+            .agregar(Operacion::Confirmar {
+                id_pedido: 1,
+                puerto: 1,
+            })
+            .unwrap();
+        registro.log.write_all(b"{\"secuencia\":2,\"oper").unwrap();
+
+        let mut reabierto = Registro::abrir(&dir, 0).unwrap();
+        let (_, operaciones) = reabierto.leer().unwrap();
+        assert_eq!(operaciones.len(), 1);
+    }
+
+    #[test]
+    fn leer_ignora_operaciones_ya_incluidas_en_el_snapshot() {
+        let dir = directorio_de_prueba("compactado");
+        let mut registro = Registro::abrir(&dir, 0).unwrap();
+        registro
+            .compactar(&StockLotes::default(), vec![], vec![])
+            .unwrap();
+        registro
+            .agregar(Operacion::Descontar {
+                id_producto: 1,
+                cantidad: 1,
+                fecha: None,
+            })
+            .unwrap();
+        // Simula una caida entre la escritura del snapshot y el vaciado del log
+        let log_previo = fs::read(&registro.ruta_log).unwrap();
+        registro
+            .compactar(&StockLotes::default(), vec![], vec![])
+            .unwrap();
+        fs::write(&registro.ruta_log, log_previo).unwrap();
+
+        let mut reabierto = Registro::abrir(&dir, 0).unwrap();
+        let (snapshot, operaciones) = reabierto.leer().unwrap();
+        assert_eq!(snapshot.secuencia, 1);
+        assert!(operaciones.is_empty());
+    }
+
+    #[test]
+    fn leer_acepta_snapshots_con_stock_sin_lotes() {
+        let dir = directorio_de_prueba("sin-lotes");
+        let mut registro = Registro::abrir(&dir, 0).unwrap();
+        fs::write(
+            &registro.ruta_snapshot,
+            r#"{"secuencia":0,"stock":{"1":5},"pedidos_bloqueados":[]}"#,
+        )
+        .unwrap();
+
+        let (snapshot, _) = registro.leer().unwrap();
+        assert_eq!(snapshot.stock, StockLotes::from(HashMap::from([(1, 5)])));
+        assert!(snapshot.lotes_bloqueados.is_empty());
+    }
+}
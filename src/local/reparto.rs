@@ -0,0 +1,332 @@
+//! Este modulo simula la entrega de los pedidos de ecommerce que el local
+//! reservo. El repartidor atiende una cantidad limitada de pedidos a la vez, y
+//! cada entrega tarda segun una distribucion configurable. El cliente solo
+//! retira el pedido si la entrega llega dentro de la ventana de retiro, y aun
+//! asi puede no hacerlo con cierta probabilidad. El resultado de cada pedido se
+//! genera a partir de la semilla y del propio pedido, por lo que una misma
+//! configuracion reproduce siempre el mismo resultado para el mismo pedido,
+//! sin importar en que orden lleguen.
+
+use actix::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use super::llegadas::muestrear_exponencial;
+use crate::aliases::{IdLocal, IdPedido, Puerto};
+
+/// Distribucion de la demora de una entrega
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "tipo", rename_all = "snake_case")]
+pub enum Demora {
+    /// Toda entrega tarda `ms` milisegundos
+    Fija { ms: u64 },
+    /// La demora es cualquier valor entre `min_ms` y `max_ms`, con igual probabilidad
+    Uniforme { min_ms: u64, max_ms: u64 },
+    /// La demora tiene distribucion exponencial, con media `media_ms` milisegundos
+    Exponencial { media_ms: u64 },
+}
+
+impl Default for Demora {
+    fn default() -> Self {
+        Demora::Uniforme {
+            min_ms: 500,
+            max_ms: 1500,
+        }
+    }
+}
+
+impl Demora {
+    fn muestrear(&self, rng: &mut StdRng) -> Duration {
+        let demora_ms = match *self {
+            Demora::Fija { ms } => ms as f64,
+            Demora::Uniforme { min_ms, max_ms } if min_ms < max_ms => {
+                rng.gen_range(min_ms..max_ms) as f64
+            }
+            Demora::Uniforme { min_ms, .. } => min_ms as f64,
+            Demora::Exponencial { media_ms } => muestrear_exponencial(rng, media_ms),
+        };
+        Duration::from_secs_f64(demora_ms / 1000.0)
+    }
+}
+
+/// Configuracion del repartidor de un local. Por defecto no tiene limite de
+/// pedidos a la vez, tarda entre 500 y 1500 ms, y el cliente retira todo
+/// pedido que llega en menos de un segundo
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ConfiguracionReparto {
+    /// Cantidad maxima de pedidos en reparto a la vez. Los demas esperan su turno
+    pub capacidad: Option<usize>,
+    /// Demora de cada entrega
+    pub demora: Demora,
+    /// Tiempo que el cliente espera la entrega antes de desistir
+    pub ventana_retiro_ms: u64,
+    /// Probabilidad de que el cliente retire un pedido entregado a tiempo
+    pub probabilidad_retiro: f64,
+    /// Semilla de las entregas. Por defecto es el id del local
+    pub semilla: Option<u64>,
+}
+
+impl Default for ConfiguracionReparto {
+    fn default() -> Self {
+        Self {
+            capacidad: None,
+            demora: Demora::default(),
+            ventana_retiro_ms: 1000,
+            probabilidad_retiro: 1.0,
+            semilla: None,
+        }
+    }
+}
+
+impl ConfiguracionReparto {
+    /// Devuelve la semilla de las entregas para el local dado
+    pub fn semilla_de(&self, id_local: IdLocal) -> u64 {
+        self.semilla.unwrap_or(id_local as u64)
+    }
+
+    /// Devuelve cuanto puede tardar como mucho en saberse como termino la
+    /// entrega de la cantidad de pedidos dada, si se reparten todos a partir
+    /// de ahora: ninguna entrega tarda mas que la ventana de retiro, y si la
+    /// capacidad es limitada los pedidos salen por tandas
+    pub fn plazo_entregas(&self, pedidos: usize) -> Duration {
+        let tandas = match self.capacidad {
+            None => pedidos.min(1),
+            Some(capacidad) => pedidos.div_ceil(capacidad.max(1)),
+        };
+        Duration::from_millis(self.ventana_retiro_ms).saturating_mul(tandas as u32)
+    }
+
+    /// Simula la entrega del pedido dado, y devuelve cuanto tarda en saberse
+    /// el resultado junto con el resultado
+    pub fn simular(
+        &self,
+        semilla: u64,
+        id_pedido: IdPedido,
+        puerto: Puerto,
+    ) -> (Duration, Entrega) {
+        let clave = (u64::from(puerto) << 16) | u64::from(id_pedido);
+        let mut rng = StdRng::seed_from_u64(semilla ^ clave);
+        let demora = self.demora.muestrear(&mut rng);
+        let ventana = Duration::from_millis(self.ventana_retiro_ms);
+        if demora >= ventana {
+            return (ventana, Entrega::FueraDeVentana);
+        }
+        if rng.gen_bool(self.probabilidad_retiro.clamp(0.0, 1.0)) {
+            (demora, Entrega::Retirado)
+        } else {
+            (demora, Entrega::NoRetirado)
+        }
+    }
+}
+
+/// A partir de un reader en formato json, crea la configuracion del repartidor
+pub fn from_reader(reader: &mut dyn Read) -> Result<ConfiguracionReparto, serde_json::Error> {
+    serde_json::from_reader(reader)
+}
+
+/// Resultado de la entrega de un pedido
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entrega {
+    /// El cliente retiro el pedido
+    Retirado,
+    /// El pedido llego a tiempo, pero el cliente no lo retiro
+    NoRetirado,
+    /// El pedido no llego dentro de la ventana de retiro
+    FueraDeVentana,
+}
+
+/// Actor que reparte los pedidos de ecommerce del local, respetando su
+/// capacidad. Los pedidos que no puede atender todavia esperan en orden, y
+/// se avisa cuando cada uno sale a repartirse
+pub struct Repartidor {
+    configuracion: ConfiguracionReparto,
+    semilla: u64,
+    en_reparto: usize,
+    cola: VecDeque<(Repartir, oneshot::Sender<Entrega>)>,
+}
+
+impl Repartidor {
+    /// Crea el repartidor del local dado
+    pub fn new(configuracion: ConfiguracionReparto, id_local: IdLocal) -> Self {
+        Self {
+            configuracion,
+            semilla: configuracion.semilla_de(id_local),
+            en_reparto: 0,
+            cola: VecDeque::new(),
+        }
+    }
+
+    /// Empieza a repartir los pedidos en espera mientras tenga capacidad
+    fn despachar(&mut self, ctx: &mut Context<Self>) {
+        while self
+            .configuracion
+            .capacidad
+            .is_none_or(|capacidad| self.en_reparto < capacidad)
+        {
+            let Some((pedido, aviso)) = self.cola.pop_front() else {
+                return;
+            };
+            self.en_reparto += 1;
+            if let Some(salida) = pedido.aviso_salida {
+                let _ = salida.send(());
+            }
+            let (demora, entrega) =
+                self.configuracion
+                    .simular(self.semilla, pedido.id_pedido, pedido.puerto);
+            ctx.spawn(
+                async move {
+                    tokio::time::sleep(demora).await;
+                    let _ = aviso.send(entrega);
+                }
+                .into_actor(self)
+                .map(|_, repartidor, ctx| {
+                    repartidor.en_reparto -= 1;
+                    repartidor.despachar(ctx);
+                }),
+            );
+        }
+    }
+}
+
+impl Actor for Repartidor {
+    type Context = Context<Self>;
+}
+
+/// Mensaje que pide repartir un pedido de ecommerce reservado, y responde con
+/// el resultado de la entrega. Opcionalmente indica por donde avisar cuando
+/// el pedido sale a repartirse, tras esperar su turno
+#[derive(Message, Debug)]
+#[rtype(result = "Entrega")]
+pub struct Repartir {
+    id_pedido: IdPedido,
+    puerto: Puerto,
+    aviso_salida: Option<oneshot::Sender<()>>,
+}
+
+impl Repartir {
+    /// Crea el pedido de reparto del pedido dado, del ecommerce en el puerto dado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto) -> Self {
+        Self {
+            id_pedido,
+            puerto,
+            aviso_salida: None,
+        }
+    }
+
+    /// Indica por donde avisar cuando el pedido sale a repartirse
+    pub fn con_aviso_salida(mut self, aviso_salida: oneshot::Sender<()>) -> Self {
+        self.aviso_salida = Some(aviso_salida);
+        self
+    }
+}
+
+impl Handler<Repartir> for Repartidor {
+    type Result = ResponseFuture<Entrega>;
+
+    fn handle(&mut self, msg: Repartir, ctx: &mut Context<Self>) -> Self::Result {
+        let (aviso, entrega) = oneshot::channel();
+        self.cola.push_back((msg, aviso));
+        self.despachar(ctx);
+        Box::pin(async move { entrega.await.unwrap_or(Entrega::NoRetirado) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn la_configuracion_por_defecto_entrega_lo_que_llega_antes_de_un_segundo() {
+        let configuracion: ConfiguracionReparto = serde_json::from_str("{}").unwrap();
+        assert_eq!(configuracion, ConfiguracionReparto::default());
+        for id in 0..200 {
+            let (demora, entrega) = configuracion.simular(3, id, 3402);
+            assert!(demora <= Duration::from_millis(1000));
+            assert_eq!(
+                entrega == Entrega::Retirado,
+                demora < Duration::from_millis(1000)
+            );
+        }
+    }
+
+    #[test]
+    fn el_resultado_de_un_pedido_es_reproducible_con_la_misma_semilla() {
+        let configuracion: ConfiguracionReparto = serde_json::from_str(
+            r#"{"demora": {"tipo": "exponencial", "media_ms": 400}, "probabilidad_retiro": 0.7}"#,
+        )
+        .unwrap();
+        let simular = |semilla| -> Vec<(Duration, Entrega)> {
+            (0..100)
+                .map(|id| configuracion.simular(semilla, id, 3402))
+                .collect()
+        };
+        assert_eq!(simular(7), simular(7));
+        assert_ne!(simular(7), simular(8));
+        let resultados = simular(7);
+        assert!(resultados.iter().any(|(_, e)| *e == Entrega::Retirado));
+        assert!(resultados.iter().any(|(_, e)| *e == Entrega::NoRetirado));
+        assert!(resultados
+            .iter()
+            .any(|(_, e)| *e == Entrega::FueraDeVentana));
+    }
+
+    #[test]
+    fn el_plazo_de_las_entregas_cuenta_las_tandas_segun_la_capacidad() {
+        let sin_limite = ConfiguracionReparto::default();
+        assert_eq!(sin_limite.plazo_entregas(0), Duration::ZERO);
+        assert_eq!(sin_limite.plazo_entregas(50), Duration::from_secs(1));
+
+        let de_a_dos = ConfiguracionReparto {
+            capacidad: Some(2),
+            ventana_retiro_ms: 3000,
+            ..ConfiguracionReparto::default()
+        };
+        assert_eq!(de_a_dos.plazo_entregas(5), Duration::from_secs(9));
+    }
+
+    #[actix_rt::test]
+    async fn los_pedidos_que_exceden_la_capacidad_esperan_su_turno() {
+        let configuracion = ConfiguracionReparto {
+            capacidad: Some(1),
+            demora: Demora::Fija { ms: 100 },
+            ..ConfiguracionReparto::default()
+        };
+        let repartidor = Repartidor::new(configuracion, 0).start();
+
+        let inicio = Instant::now();
+        let (primera, segunda) = tokio::join!(
+            repartidor.send(Repartir::new(1, 3402)),
+            repartidor.send(Repartir::new(2, 3402))
+        );
+        assert_eq!(primera.unwrap(), Entrega::Retirado);
+        assert_eq!(segunda.unwrap(), Entrega::Retirado);
+        assert!(inicio.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[actix_rt::test]
+    async fn se_avisa_cuando_el_pedido_sale_a_repartirse() {
+        let configuracion = ConfiguracionReparto {
+            capacidad: Some(1),
+            demora: Demora::Fija { ms: 100 },
+            ..ConfiguracionReparto::default()
+        };
+        let repartidor = Repartidor::new(configuracion, 0).start();
+
+        let inicio = Instant::now();
+        let (aviso_salida, salida) = oneshot::channel();
+        let primera = repartidor.send(Repartir::new(1, 3402));
+        let segunda = repartidor.send(Repartir::new(2, 3402).con_aviso_salida(aviso_salida));
+        let (_, _, salio) = tokio::join!(primera, segunda, async {
+            salida.await.unwrap();
+            inicio.elapsed()
+        });
+        assert!(salio >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,269 @@
+//! Este modulo define el reporte de ventas del local. Los empleados le avisan
+//! el resultado de cada venta presencial, y el servidor el de cada pedido de
+//! ecommerce que resuelve, de forma que al cerrar el dia (o cuando se lo pida)
+//! el local pueda decir cuantas ventas tuvo y cuantas unidades de cada
+//! producto vendio.
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::historial::EstadoPedido;
+use super::mensajes_actores::Respuestas;
+use crate::aliases::IdProducto;
+use crate::mensajes::MensajesServidor;
+use crate::pedido::{Carrito, Pedido};
+
+/// Ventas presenciales del local, segun la respuesta que dio el guardian
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct VentasLocales {
+    pub concretadas: u32,
+    pub stock_insuficiente: u32,
+    pub producto_no_disponible: u32,
+}
+
+/// Pedidos de ecommerce que paso el local, segun como terminaron
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PedidosEcommerce {
+    pub confirmados: u32,
+    pub cancelados: u32,
+    pub delegados_a_otros: u32,
+    pub delegados_por_otros: u32,
+}
+
+/// Reporte de ventas del local. Las unidades vendidas suman las ventas
+/// presenciales concretadas y los pedidos de ecommerce confirmados
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ReporteVentas {
+    pub ventas_locales: VentasLocales,
+    pub pedidos_ecommerce: PedidosEcommerce,
+    pub unidades_vendidas: BTreeMap<IdProducto, u32>,
+}
+
+impl ReporteVentas {
+    /// Anota el resultado de una venta presencial
+    pub fn registrar_venta_local(&mut self, respuesta: &Respuestas, pedido: Option<&Pedido>) {
+        let ventas = &mut self.ventas_locales;
+        match respuesta {
+            Respuestas::PedidoConcretado(_) => {
+                ventas.concretadas += 1;
+                if let Some(pedido) = pedido {
+                    self.sumar_unidades(pedido);
+                }
+            }
+            Respuestas::StockInsuficiente(_) => ventas.stock_insuficiente += 1,
+            Respuestas::ProductoNoDisponible(_) => ventas.producto_no_disponible += 1,
+        }
+    }
+
+    /// Anota un pedido de ecommerce segun el estado en el que lo dejo el local.
+    /// Si el pedido le llego delegado por otro local, ademas se lo cuenta como tal
+    pub fn registrar_pedido_ecommerce(
+        &mut self,
+        estado: Option<&EstadoPedido>,
+        carrito: &Carrito,
+        delegado_por_otro: bool,
+    ) {
+        let pedidos = &mut self.pedidos_ecommerce;
+        if delegado_por_otro {
+            pedidos.delegados_por_otros += 1;
+        }
+        match estado {
+            Some(EstadoPedido::Informado(MensajesServidor::PedidoExitoso(_))) => {
+                pedidos.confirmados += 1;
+                carrito
+                    .get_lineas()
+                    .iter()
+                    .for_each(|linea| self.sumar_unidades(linea));
+            }
+            Some(EstadoPedido::Informado(MensajesServidor::PedidoCancelado(_))) => {
+                pedidos.cancelados += 1
+            }
+            Some(EstadoPedido::Delegado(_)) => pedidos.delegados_a_otros += 1,
+            _ => {}
+        }
+    }
+
+    fn sumar_unidades(&mut self, pedido: &Pedido) {
+        *self.unidades_vendidas.entry(pedido.get_id()).or_default() += pedido.get_amount() as u32;
+    }
+}
+
+/// Muestra el reporte como una tabla para leer en la terminal
+impl fmt::Display for ReporteVentas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let locales = &self.ventas_locales;
+        let ecommerce = &self.pedidos_ecommerce;
+        writeln!(f, "{:<34}{:>8}", "Ventas presenciales", "Cantidad")?;
+        writeln!(f, "{:<34}{:>8}", "  concretadas", locales.concretadas)?;
+        writeln!(
+            f,
+            "{:<34}{:>8}",
+            "  stock insuficiente", locales.stock_insuficiente
+        )?;
+        writeln!(
+            f,
+            "{:<34}{:>8}",
+            "  producto no disponible", locales.producto_no_disponible
+        )?;
+        writeln!(f, "{:<34}{:>8}", "Pedidos de ecommerce", "Cantidad")?;
+        writeln!(f, "{:<34}{:>8}", "  confirmados", ecommerce.confirmados)?;
+        writeln!(f, "{:<34}{:>8}", "  cancelados", ecommerce.cancelados)?;
+        writeln!(
+            f,
+            "{:<34}{:>8}",
+            "  delegados a otros locales", ecommerce.delegados_a_otros
+        )?;
+        writeln!(
+            f,
+            "{:<34}{:>8}",
+            "  delegados por otros locales", ecommerce.delegados_por_otros
+        )?;
+        writeln!(f, "{:<34}{:>8}", "Producto", "Unidades")?;
+        for (producto, unidades) in &self.unidades_vendidas {
+            writeln!(f, "{:<34}{:>8}", format!("  {}", producto), unidades)?;
+        }
+        Ok(())
+    }
+}
+
+/// Actor que acumula el reporte de ventas del local
+#[derive(Default)]
+pub struct Ventas {
+    reporte: ReporteVentas,
+}
+
+impl Ventas {
+    /// Crea un reporte de ventas vacio
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Actor for Ventas {
+    type Context = Context<Self>;
+}
+
+/// Mensaje que envia un empleado con el resultado de una venta presencial
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct VentaLocal {
+    respuesta: Respuestas,
+    pedido: Option<Pedido>,
+}
+
+impl VentaLocal {
+    /// Crea el aviso de una venta, con el pedido si es que el empleado lo conoce
+    pub fn new(respuesta: Respuestas, pedido: Option<Pedido>) -> Self {
+        Self { respuesta, pedido }
+    }
+}
+
+impl Handler<VentaLocal> for Ventas {
+    type Result = ();
+
+    fn handle(&mut self, msg: VentaLocal, _ctx: &mut Context<Self>) -> Self::Result {
+        self.reporte
+            .registrar_venta_local(&msg.respuesta, msg.pedido.as_ref());
+    }
+}
+
+/// Mensaje que envia el servidor al terminar de procesar un pedido de ecommerce
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PedidoEcommerceResuelto {
+    estado: Option<EstadoPedido>,
+    carrito: Carrito,
+    delegado_por_otro: bool,
+}
+
+impl PedidoEcommerceResuelto {
+    /// Crea el aviso de un pedido, con el estado en el que quedo en el historial.
+    /// Si se confirmo, el carrito es el que confirmo el guardian
+    pub fn new(estado: Option<EstadoPedido>, carrito: Carrito, delegado_por_otro: bool) -> Self {
+        Self {
+            estado,
+            carrito,
+            delegado_por_otro,
+        }
+    }
+}
+
+impl Handler<PedidoEcommerceResuelto> for Ventas {
+    type Result = ();
+
+    fn handle(&mut self, msg: PedidoEcommerceResuelto, _ctx: &mut Context<Self>) -> Self::Result {
+        self.reporte.registrar_pedido_ecommerce(
+            msg.estado.as_ref(),
+            &msg.carrito,
+            msg.delegado_por_otro,
+        );
+    }
+}
+
+/// Mensaje para obtener el reporte de ventas acumulado hasta el momento
+#[derive(Message)]
+#[rtype(result = "ReporteVentas")]
+pub struct ObtenerReporte;
+
+impl Handler<ObtenerReporte> for Ventas {
+    type Result = MessageResult<ObtenerReporte>;
+
+    fn handle(&mut self, _msg: ObtenerReporte, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.reporte.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn el_reporte_cuenta_las_ventas_y_las_unidades_vendidas() {
+        let mut reporte = ReporteVentas::default();
+        reporte.registrar_venta_local(&Respuestas::PedidoConcretado(0), Some(&Pedido::new(1, 3)));
+        reporte.registrar_venta_local(&Respuestas::StockInsuficiente(1), Some(&Pedido::new(1, 9)));
+        reporte.registrar_venta_local(&Respuestas::ProductoNoDisponible(2), None);
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(4, 1)]).unwrap();
+        let exitoso = EstadoPedido::Informado(MensajesServidor::PedidoExitoso(5));
+        let cancelado = EstadoPedido::Informado(MensajesServidor::PedidoCancelado(6));
+        reporte.registrar_pedido_ecommerce(Some(&exitoso), &carrito, true);
+        reporte.registrar_pedido_ecommerce(Some(&cancelado), &carrito, false);
+        reporte.registrar_pedido_ecommerce(Some(&EstadoPedido::Delegado(2)), &carrito, false);
+
+        assert_eq!(
+            reporte.ventas_locales,
+            VentasLocales {
+                concretadas: 1,
+                stock_insuficiente: 1,
+                producto_no_disponible: 1,
+            }
+        );
+        assert_eq!(
+            reporte.pedidos_ecommerce,
+            PedidosEcommerce {
+                confirmados: 1,
+                cancelados: 1,
+                delegados_a_otros: 1,
+                delegados_por_otros: 1,
+            }
+        );
+        assert_eq!(reporte.unidades_vendidas, BTreeMap::from([(1, 5), (4, 1)]));
+    }
+
+    #[actix_rt::test]
+    async fn el_actor_devuelve_el_reporte_acumulado() {
+        let ventas = Ventas::new().start();
+        ventas.do_send(VentaLocal::new(
+            Respuestas::PedidoConcretado(0),
+            Some(Pedido::new(2, 4)),
+        ));
+
+        let reporte = ventas.send(ObtenerReporte).await.unwrap();
+        assert_eq!(reporte.ventas_locales.concretadas, 1);
+        assert_eq!(reporte.unidades_vendidas.get(&2), Some(&4));
+        assert!(reporte.to_string().contains("concretadas"));
+    }
+}
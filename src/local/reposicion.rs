@@ -0,0 +1,252 @@
+//! Este modulo define el cronograma de reposicion de un local, que indica
+//! en que momento de la ejecucion llega mercaderia de cada producto. Se lee
+//! de un archivo json con una lista de reposiciones. La mercaderia puede venir
+//! de un proveedor, o pedirse a otro local mediante una transferencia. Cuantas
+//! reposiciones ya se hicieron se guarda en disco, para que un reinicio del
+//! local no vuelva a reponer lo que ya repuso.
+
+use actix::Recipient;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::guardian::Reponer;
+use super::transferencias::{ResultadoTransferencia, SolicitarTransferencia};
+use crate::aliases::{CantidadProducto, IdLocal, IdProducto};
+use crate::errores::ErrorDuranteParseo;
+
+/// Una reposicion indica que, pasados `instante_ms` milisegundos desde el
+/// inicio del local, llegan `cantidad` unidades del producto dado. Si se
+/// indica `desde_local`, en ese instante las unidades se le piden a ese local.
+/// Si se indica `vencimiento`, las unidades forman un lote que vence ese dia
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Reposicion {
+    pub instante_ms: u64,
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desde_local: Option<IdLocal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vencimiento: Option<NaiveDate>,
+}
+
+/// A partir de un reader en formato json, crea un cronograma de reposicion,
+/// ordenado por el instante en que ocurre cada una
+pub fn from_reader(reader: &mut dyn Read) -> Result<Vec<Reposicion>, serde_json::Error> {
+    let mut reposiciones: Vec<Reposicion> = serde_json::from_reader(reader)?;
+    reposiciones.sort_by_key(|r| r.instante_ms);
+    Ok(reposiciones)
+}
+
+/// Cantidad de reposiciones del cronograma que ya se hicieron, guardada en un
+/// archivo para que sobreviva a un reinicio del local. Solo tiene sentido
+/// mientras el cronograma no cambie
+pub struct ProgresoReposicion {
+    ruta: PathBuf,
+    realizadas: usize,
+}
+
+impl ProgresoReposicion {
+    /// Abre el progreso del local dado guardado en el directorio pasado. Si no
+    /// hay ninguno guardado, no se hizo todavia ninguna reposicion
+    /// # Errors
+    /// * `ErrorDuranteParseo::NoSePudoAbrirArchivo` si el archivo existe pero no puede leerse
+    /// * `ErrorDuranteParseo::FormatoArchivoInvalido` si no contiene una cantidad
+    pub fn abrir(directorio: &Path, id: IdLocal) -> Result<Self, ErrorDuranteParseo> {
+        let ruta = directorio.join(format!("reposicion{}.progreso", id));
+        let realizadas = match fs::read_to_string(&ruta) {
+            Ok(contenido) => contenido
+                .trim()
+                .parse()
+                .map_err(|_| ErrorDuranteParseo::FormatoArchivoInvalido)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { ruta, realizadas })
+    }
+
+    /// Devuelve cuantas reposiciones del cronograma ya se hicieron
+    pub fn realizadas(&self) -> usize {
+        self.realizadas
+    }
+
+    /// Anota una reposicion mas como realizada. El archivo se escribe primero
+    /// en uno temporal y luego se renombra, como el snapshot del registro
+    fn avanzar(&mut self) -> io::Result<()> {
+        self.realizadas += 1;
+        if let Some(directorio) = self.ruta.parent() {
+            fs::create_dir_all(directorio)?;
+        }
+        let ruta_temporal = self.ruta.with_extension("tmp");
+        fs::write(&ruta_temporal, self.realizadas.to_string())?;
+        fs::rename(&ruta_temporal, &self.ruta)
+    }
+}
+
+/// Reproduce el cronograma, enviandole al guardian cada reposicion cuando
+/// llega su instante, o pidiendosela al local indicado. El cronograma debe
+/// estar ordenado por instante. Se saltean las reposiciones que ya se hicieron
+/// antes de un reinicio, y las restantes ocurren a su instante contado desde
+/// este inicio. Cada reposicion se anota como realizada una vez que el guardian
+/// la aplico, o que se pidio la transferencia. Si el guardian no pudo aplicarla,
+/// se deja de reproducir el cronograma sin anotarla, para que se vuelva a
+/// intentar al reiniciar el local.
+pub async fn reproducir(
+    reposiciones: Vec<Reposicion>,
+    mut progreso: ProgresoReposicion,
+    guardian: Recipient<Reponer>,
+    transferencias: Recipient<SolicitarTransferencia>,
+) {
+    let inicio = tokio::time::Instant::now();
+    let pendientes = reposiciones.into_iter().skip(progreso.realizadas());
+    for reposicion in pendientes {
+        tokio::time::sleep_until(inicio + Duration::from_millis(reposicion.instante_ms)).await;
+        match reposicion.desde_local {
+            None => {
+                let reponer = Reponer::new(reposicion.id_producto, reposicion.cantidad)
+                    .con_vencimiento(reposicion.vencimiento);
+                if !matches!(guardian.send(reponer).await, Ok(Ok(()))) {
+                    eprintln!("No se pudo reponer el producto {}", reposicion.id_producto);
+                    return;
+                }
+            }
+            Some(donante) => {
+                let solicitud = SolicitarTransferencia::new(
+                    donante,
+                    reposicion.id_producto,
+                    reposicion.cantidad,
+                );
+                let transferencias = transferencias.clone();
+                actix_rt::spawn(async move {
+                    match transferencias.send(solicitud).await {
+                        Ok(ResultadoTransferencia::Aceptada) => {}
+                        Ok(ResultadoTransferencia::Rechazada) => println!(
+                            "El local {} no tiene {} unidades del producto {} para transferir",
+                            donante, reposicion.cantidad, reposicion.id_producto
+                        ),
+                        _ => eprintln!(
+                            "No se pudo pedir la transferencia del producto {} al local {}",
+                            reposicion.id_producto, donante
+                        ),
+                    }
+                });
+            }
+        }
+        if let Err(e) = progreso.avanzar() {
+            eprintln!("No se pudo guardar el progreso de la reposicion: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errores::ErrorGuardian;
+    use actix::actors::mocker::Mocker;
+    use actix::Actor;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn from_reader_ordena_las_reposiciones_por_instante() {
+        let json = r#"[
+            {"instante_ms": 3000, "id_producto": 1, "cantidad": 10},
+            {"instante_ms": 500, "id_producto": 2, "cantidad": 4, "desde_local": 3}
+        ]"#;
+        let reposiciones = from_reader(&mut json.as_bytes()).unwrap();
+
+        assert_eq!(reposiciones.len(), 2);
+        assert_eq!(reposiciones[0].id_producto, 2);
+        assert_eq!(reposiciones[0].desde_local, Some(3));
+        assert_eq!(reposiciones[1].desde_local, None);
+        assert_eq!(reposiciones[1].instante_ms, 3000);
+    }
+
+    #[actix_rt::test]
+    async fn reproducir_saltea_las_reposiciones_ya_realizadas() {
+        let dir = std::env::temp_dir().join(format!("reposicion-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("reposicion0.progreso"), "1").unwrap();
+        let reposiciones = vec![
+            Reposicion {
+                instante_ms: 0,
+                id_producto: 1,
+                cantidad: 10,
+                desde_local: None,
+                vencimiento: None,
+            },
+            Reposicion {
+                instante_ms: 0,
+                id_producto: 2,
+                cantidad: 4,
+                desde_local: None,
+                vencimiento: None,
+            },
+        ];
+
+        let (tx, mut repuestos) = mpsc::unbounded_channel();
+        let guardian = Mocker::<Reponer>::mock(Box::new(move |msg, _ctx| {
+            tx.send(msg.downcast_ref::<Reponer>().unwrap().id_producto)
+                .unwrap();
+            Box::new(Some(Ok::<(), ErrorGuardian>(())))
+        }))
+        .start()
+        .recipient();
+        let transferencias = Mocker::<SolicitarTransferencia>::mock(Box::new(|_msg, _ctx| {
+            Box::new(Some(ResultadoTransferencia::Rechazada))
+        }))
+        .start()
+        .recipient();
+
+        let progreso = ProgresoReposicion::abrir(&dir, 0).unwrap();
+        reproducir(reposiciones, progreso, guardian, transferencias).await;
+        assert_eq!(repuestos.recv().await, Some(2));
+        assert!(repuestos.try_recv().is_err());
+        assert_eq!(ProgresoReposicion::abrir(&dir, 0).unwrap().realizadas(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[actix_rt::test]
+    async fn una_reposicion_que_el_guardian_no_aplico_no_se_anota() {
+        let dir = std::env::temp_dir().join(format!("reposicion-fallida-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let reposicion = |id_producto| Reposicion {
+            instante_ms: 0,
+            id_producto,
+            cantidad: 1,
+            desde_local: None,
+            vencimiento: None,
+        };
+
+        let (tx, mut repuestos) = mpsc::unbounded_channel();
+        let guardian = Mocker::<Reponer>::mock(Box::new(move |msg, _ctx| {
+            tx.send(msg.downcast_ref::<Reponer>().unwrap().id_producto)
+                .unwrap();
+            Box::new(Some(Err::<(), _>(ErrorGuardian::RegistroNoDisponible)))
+        }))
+        .start()
+        .recipient();
+        let transferencias = Mocker::<SolicitarTransferencia>::mock(Box::new(|_msg, _ctx| {
+            Box::new(Some(ResultadoTransferencia::Rechazada))
+        }))
+        .start()
+        .recipient();
+
+        let progreso = ProgresoReposicion::abrir(&dir, 0).unwrap();
+        reproducir(
+            vec![reposicion(1), reposicion(2)],
+            progreso,
+            guardian,
+            transferencias,
+        )
+        .await;
+        assert_eq!(repuestos.recv().await, Some(1));
+        assert!(repuestos.try_recv().is_err());
+        assert_eq!(ProgresoReposicion::abrir(&dir, 0).unwrap().realizadas(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,95 @@
+//! Este modulo difunde la membresia del cluster desde un local. Periodicamente
+//! el local le envia su vista a algunos de los locales activos elegidos al
+//! azar, y mientras no conozca a ningun otro se la envia a las semillas, que es
+//! como se une al cluster. Al terminar, anuncia a todos que se va.
+
+use actix::Addr;
+use rand::seq::SliceRandom;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::mensajero::{Enviar, Mensajero};
+use crate::aliases::IdLocal;
+use crate::id_a_dir_local;
+use crate::membresia::{Membresia, INTERVALO_RUMORES};
+
+/// Cantidad de locales a los que se les envia cada rumor
+const LOCALES_POR_RUMOR: usize = 2;
+
+/// Difunde la vista del local periodicamente, hasta que termine el programa
+pub async fn difundir(
+    membresia: Arc<Membresia>,
+    mensajero: Addr<Mensajero>,
+    semillas: Vec<IdLocal>,
+) {
+    loop {
+        let rumor = membresia.latir().as_bytes();
+        for destino in elegir_destinos(&membresia, &semillas) {
+            // Si el local esta caido el envio falla, y los demas lo daran por caido
+            // al no recibir sus rumores
+            enviar(&mensajero, rumor.clone(), destino).await;
+        }
+        tokio::time::sleep(INTERVALO_RUMORES).await;
+    }
+}
+
+/// Elige a quienes enviarle el proximo rumor: algunos de los otros locales
+/// activos, o las semillas si no conoce a ninguno
+fn elegir_destinos(membresia: &Membresia, semillas: &[IdLocal]) -> Vec<IdLocal> {
+    let propio = membresia.propio();
+    let otros: Vec<IdLocal> = membresia
+        .vivos()
+        .into_iter()
+        .filter(|id| Some(*id) != propio)
+        .collect();
+    if otros.is_empty() {
+        return semillas
+            .iter()
+            .filter(|id| Some(**id) != propio)
+            .copied()
+            .collect();
+    }
+    otros
+        .choose_multiple(&mut rand::thread_rng(), LOCALES_POR_RUMOR)
+        .copied()
+        .collect()
+}
+
+/// Le anuncia a todos los locales activos que el local deja el cluster
+pub async fn anunciar_salida(membresia: &Membresia, mensajero: &Addr<Mensajero>) {
+    let otros = membresia.vivos();
+    let rumor = membresia.salir();
+    let propio = membresia.propio();
+    for destino in otros.into_iter().filter(|id| Some(*id) != propio) {
+        enviar(mensajero, rumor.as_bytes(), destino).await;
+    }
+}
+
+async fn enviar(mensajero: &Addr<Mensajero>, rumor: Vec<u8>, destino: IdLocal) {
+    let Ok(direccion) = id_a_dir_local(destino).parse::<SocketAddr>() else {
+        return;
+    };
+    // Los rumores se repiten, asi que un envio fallido no necesita reintentarse
+    let _ = mensajero.send(Enviar::new(rumor, direccion)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mensajes::{EntradaMembresia, MensajeMembresia};
+
+    #[test]
+    fn sin_conocer_a_nadie_los_rumores_van_a_las_semillas() {
+        let membresia = Membresia::de_local(1);
+        assert_eq!(elegir_destinos(&membresia, &[0, 1]), vec![0]);
+
+        membresia.fusionar(&MensajeMembresia::new(
+            (2..6)
+                .map(|id| EntradaMembresia::new(id, 1, true))
+                .collect(),
+        ));
+        let destinos = elegir_destinos(&membresia, &[0, 1]);
+        assert_eq!(destinos.len(), LOCALES_POR_RUMOR);
+        assert!(destinos.iter().all(|id| (2..6).contains(id)));
+    }
+}
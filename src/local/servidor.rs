@@ -2,63 +2,247 @@
 //! Requiere de la existencia del guardian, ya que hara a este los pedidos.
 
 use crate::aliases::{IdLocal, IdPedido, MonitorAsync, Puerto};
-use crate::local::guardian::{self, Guardian};
+use crate::local::busquedas::{Busquedas, ResultadoRecibido};
+use crate::local::consultas::{self, ConsultasStock};
+use crate::local::detector::{self, DetectorFallas, EstadoLocal};
+use crate::local::drenaje::Drenaje;
+use crate::local::eleccion::{Eleccion, EleccionRecibida, IniciarEleccion};
+use crate::local::guardian::{self, Reducir, VaciarEspera};
+use crate::local::historial::{EstadoPedido, HistorialPedidos};
+use crate::local::particiones::GuardianParticionado;
+use crate::local::reparto::{ConfiguracionReparto, Entrega, Repartidor, Repartir};
+use crate::local::reporte::{PedidoEcommerceResuelto, Ventas};
+use crate::local::transferencias::{
+    AckTransferenciaRecibido, TransferenciaRecibida, Transferencias,
+};
+use crate::membresia::Membresia;
 use crate::mensajes::{
-    AckDelegado, AckEcommerce, MensajeDelegado, MensajeEcommerce, MensajesServidor, TipoMensaje,
+    AckDelegado, AckEcommerce, AckTransferencia, ConsultaEstado, ConsultaStock, MensajeDelegado,
+    MensajeEcommerce, MensajeEleccion, MensajeLatido, MensajeMembresia, MensajeModificacion,
+    MensajeTransferencia, MensajesServidor, RespuestaEstado, RespuestaModificacion, RespuestaStock,
+    ResultadoModificacion, TipoMensaje, MAX_LOCALES_ACK,
 };
+use crate::pedido::{Carrito, Modificacion};
+use crate::topologia::{ModoDelegacion, Topologia};
 use crate::{
-    errores::ErrorServidor, id_a_dir_local, id_a_dir_medico, puerto_a_id, puerto_a_ip,
-    siguiente_id_local, MAX_MENSAJE,
+    errores::{ErrorGuardian, ErrorServidor},
+    id_a_dir_local, id_a_dir_medico, puerto_a_id, puerto_a_ip, MAX_MENSAJE,
 };
-use actix::Addr;
+use actix::{Actor, Addr};
 use actix_rt::net::UdpSocket;
-use actix_rt::time;
-use async_recursion::async_recursion;
 use colored::Colorize;
-use rand::Rng;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Read};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, Notify};
+use tokio::sync::{oneshot, Mutex, Notify};
 use tokio::time::timeout;
 
 use crate::errores::ErrorMensajero;
 use crate::local::mensajero::{Desconectar, Enviar, Matar, Mensajero, Reconectar};
 
+/// Lo necesario para delegar pedidos a otros locales: el monitor en el que el
+/// servidor anota los acks de las delegaciones, la topologia que indica a quien
+/// delegar, la membresia que indica que locales estan activos, el detector de
+/// fallas que saltea a los que se sospechan caidos, y las consultas de stock en
+/// curso. Lo comparten el servidor y las busquedas de productos para los
+/// clientes del local. Ademas cuenta cuantas retransmisiones se le hicieron a
+/// cada local, para diagnosticar problemas de conectividad
+#[derive(Clone)]
+pub struct Delegacion {
+    acks: Arc<MonitorAsync>,
+    topologia: Arc<Topologia>,
+    membresia: Arc<Membresia>,
+    detector: Arc<DetectorFallas>,
+    consultas: Arc<ConsultasStock>,
+    retransmisiones: Arc<Mutex<BTreeMap<IdLocal, u32>>>,
+}
+
+impl Delegacion {
+    /// Crea una delegacion que sigue la topologia dada entre los locales
+    /// activos segun la membresia
+    pub fn new(topologia: Arc<Topologia>, membresia: Arc<Membresia>) -> Self {
+        Self {
+            acks: Arc::new((Mutex::new(HashMap::new()), Notify::new())),
+            topologia,
+            membresia,
+            detector: Arc::new(DetectorFallas::new()),
+            consultas: Arc::new(ConsultasStock::new()),
+            retransmisiones: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Devuelve cuantas retransmisiones de pedidos delegados se le hicieron a
+    /// cada local
+    pub async fn retransmisiones(&self) -> BTreeMap<IdLocal, u32> {
+        self.retransmisiones.lock().await.clone()
+    }
+
+    /// Devuelve los locales a los que puede delegarse. De los locales de los que
+    /// se reciben latidos decide el detector, por lo que se saltean apenas se
+    /// sospecha que cayeron y se vuelven a considerar apenas reviven; de los
+    /// demas decide la membresia
+    fn vivos(&self) -> Vec<IdLocal> {
+        let vivos = self.membresia.vivos();
+        self.membresia
+            .miembros()
+            .into_iter()
+            .filter(|id| match self.detector.estado(*id) {
+                EstadoLocal::Vivo => true,
+                EstadoLocal::Sospechado => false,
+                EstadoLocal::Desconocido => vivos.contains(id),
+            })
+            .collect()
+    }
+}
+
 /// Estructura que procesa los pedidos obtenidos recibidos por diversos ecommerces
 /// mediante un socket, delegando el manejo del stock a un guardian, y los pedidos
-/// que no puede cumplir a un local cercano.
+/// que no puede cumplir a un local cercano. Lleva un historial de los pedidos
+/// recibidos, para responder los reenvios sin volver a reservarlos. Ademas recibe
+/// los mensajes de las transferencias de stock con otros locales, y los resultados
+/// de los pedidos que el local delego para sus propios clientes, y se los pasa a
+/// quien los maneja. Si el local lleva un reporte de ventas, le avisa como
+/// termino cada pedido. Los pedidos reservados se resuelven segun como termina
+/// su entrega, que simula el repartidor del local. Al drenarse el local, deja
+/// de reservar pedidos nuevos y termina cuando se resolvieron los que tenia.
 pub struct ServidorEcommerce {
-    guardian_addr: Addr<Guardian>,
+    guardian_addr: Addr<GuardianParticionado>,
+    repartidor: Addr<Repartidor>,
+    reparto: ConfiguracionReparto,
     socket: Arc<UdpSocket>,
     id_local: IdLocal,
-    acks_delegados: Arc<MonitorAsync>,
+    historial: Arc<HistorialPedidos>,
+    delegacion: Delegacion,
+    transferencias: Option<Addr<Transferencias>>,
+    busquedas: Option<Addr<Busquedas>>,
+    ventas: Option<Addr<Ventas>>,
+    eleccion: Option<Addr<Eleccion>>,
+    drenaje: Arc<Drenaje>,
 }
 
 impl ServidorEcommerce {
-    /// Crea un servidor a partir de un guardian y un id asignado. Inicializa el socket
-    /// por el que va a escuchar los pedidos
-    pub fn new(guardian_addr: Addr<Guardian>, id: IdLocal, socket: Arc<UdpSocket>) -> Self {
+    /// Crea un servidor a partir de un guardian, la configuracion del repartidor
+    /// que entrega los pedidos y un id asignado. Inicializa el socket por el que
+    /// va a escuchar los pedidos
+    pub fn new(
+        guardian_addr: Addr<GuardianParticionado>,
+        reparto: ConfiguracionReparto,
+        id: IdLocal,
+        socket: Arc<UdpSocket>,
+    ) -> Self {
         Self {
             guardian_addr,
+            repartidor: Repartidor::new(reparto, id).start(),
+            reparto,
             socket,
             id_local: id,
-            acks_delegados: Arc::new((Mutex::new(HashSet::new()), Notify::new())),
+            historial: Arc::new(HistorialPedidos::new()),
+            delegacion: Delegacion::new(
+                Arc::new(Topologia::default()),
+                Arc::new(Membresia::de_local(id)),
+            ),
+            transferencias: None,
+            busquedas: None,
+            ventas: None,
+            eleccion: None,
+            drenaje: Arc::new(Drenaje::new()),
         }
     }
 
+    /// Devuelve lo necesario para delegar pedidos, ya que es el servidor quien
+    /// recibe los acks y las respuestas de las consultas de stock
+    pub fn delegacion(&self) -> Delegacion {
+        self.delegacion.clone()
+    }
+
+    /// Devuelve el drenaje del servidor, para poder iniciarlo desde afuera
+    pub fn drenaje(&self) -> Arc<Drenaje> {
+        self.drenaje.clone()
+    }
+
+    /// Indica el orden en que el local delega los pedidos que no puede cumplir,
+    /// y como elige a quien. Si no se indica, los delega siguiendo el anillo de
+    /// locales
+    pub fn con_topologia(mut self, topologia: Arc<Topologia>) -> Self {
+        self.delegacion.topologia = topologia;
+        self
+    }
+
+    /// Indica la vista de la membresia del cluster que se actualiza con los
+    /// rumores recibidos, y entre cuyos locales activos se delegan los pedidos.
+    /// Si no se indica, el servidor lleva su propia vista
+    pub fn con_membresia(mut self, membresia: Arc<Membresia>) -> Self {
+        self.delegacion.membresia = membresia;
+        self
+    }
+
+    /// Indica a quien pasarle los resultados de los pedidos delegados para los
+    /// clientes del local. Si no se indica, esos mensajes se descartan
+    pub fn con_busquedas(mut self, busquedas: Addr<Busquedas>) -> Self {
+        self.busquedas = Some(busquedas);
+        self
+    }
+
+    /// Indica a quien avisarle como termino cada pedido, para el reporte de
+    /// ventas del local
+    pub fn con_ventas(mut self, ventas: Addr<Ventas>) -> Self {
+        self.ventas = Some(ventas);
+        self
+    }
+
+    /// Indica a quien pasarle los mensajes de la eleccion del lider. Si no se
+    /// indica, esos mensajes se descartan
+    pub fn con_eleccion(mut self, eleccion: Addr<Eleccion>) -> Self {
+        self.eleccion = Some(eleccion);
+        self
+    }
+
+    /// Indica a quien pasarle los mensajes de transferencias de stock. Si no se
+    /// indica, esos mensajes se descartan
+    pub fn con_transferencias(mut self, transferencias: Addr<Transferencias>) -> Self {
+        self.transferencias = Some(transferencias);
+        self
+    }
+
     /// Pone al servidor a escuchar por el socket por cualquier mensaje que podria llegar.
     /// Los mensajes validos son:
     /// * ack de una delegacion
     /// * mensaje de delegacion
     /// * mensaje de ecommerce
+    /// * mensaje o ack de una transferencia de stock
+    /// * resultado de un pedido delegado para un cliente del local
+    /// * consulta de stock de otro local, o respuesta a una consulta propia
+    /// * rumor de membresia de otro local, o consulta de la membresia de un ecommerce
+    /// * mensaje de la eleccion del lider, o consulta de quien es el lider
+    /// * consulta de un ecommerce por el estado de uno de sus pedidos
+    /// * cancelacion o reduccion de un pedido pedida por un ecommerce
+    /// * pedido de drenar el local
+    /// * latido de otro local
     /// * matar
+    ///
+    /// Devuelve una vez que termina de drenarse el local
     pub async fn procesar_pedidos(&mut self, mensajero: Addr<Mensajero>) {
+        actix_rt::spawn(drenar(
+            self.guardian_addr.clone(),
+            mensajero.clone(),
+            self.historial.clone(),
+            self.drenaje.clone(),
+            self.reparto,
+        ));
+        actix_rt::spawn(detector::latir(
+            self.id_local,
+            self.delegacion.membresia.clone(),
+            mensajero.clone(),
+        ));
         loop {
             let mut buf: [u8; MAX_MENSAJE as usize] = [0; MAX_MENSAJE as usize];
-            let sender = match self.socket.recv_from(&mut buf).await {
+            let recibido = tokio::select! {
+                recibido = self.socket.recv_from(&mut buf) => recibido,
+                _ = self.drenaje.esperar_fin() => return,
+            };
+            let sender = match recibido {
                 Ok((_, sender)) => sender,
                 Err(_err) => {
                     eprintln!("No pudo leer del socket");
@@ -86,12 +270,63 @@ impl ServidorEcommerce {
                         .await;
                 }
                 TipoMensaje::AckDelegado => {
-                    self.procesar_ack_delegado(&mut cursor).await;
+                    self.procesar_ack_delegado(&mut cursor, sender).await;
+                }
+                TipoMensaje::Transferencia => {
+                    self.procesar_transferencia(&mut cursor, sender);
+                }
+                TipoMensaje::AckTransferencia => {
+                    self.procesar_ack_transferencia(&mut cursor, sender);
+                }
+                TipoMensaje::MensajeServidor => {
+                    self.procesar_resultado_busqueda(&mut cursor, sender);
+                }
+                TipoMensaje::ConsultaStock => {
+                    self.procesar_consulta_stock(&mut cursor, sender, mensajero_addr);
+                }
+                TipoMensaje::RespuestaStock => {
+                    self.procesar_respuesta_stock(&mut cursor, sender).await;
+                }
+                TipoMensaje::Membresia => {
+                    self.procesar_rumor(&mut cursor, sender, mensajero_addr);
+                }
+                TipoMensaje::ConsultaMembresia => {
+                    mensajero_addr.do_send(Enviar::new(
+                        self.delegacion.membresia.rumor().as_bytes(),
+                        sender,
+                    ));
+                }
+                TipoMensaje::ConsultaEstado => {
+                    self.procesar_consulta_estado(&mut cursor, sender, mensajero_addr)
+                        .await;
+                }
+                TipoMensaje::ModificacionPedido => {
+                    self.procesar_modificacion(&mut cursor, mensajero_addr);
+                }
+                TipoMensaje::Eleccion
+                | TipoMensaje::RespuestaEleccion
+                | TipoMensaje::Coordinador
+                | TipoMensaje::ConsultaLider => {
+                    self.procesar_eleccion(tipo_msg, &mut cursor, sender);
+                }
+                TipoMensaje::Latido => match MensajeLatido::from_bytes(&mut cursor) {
+                    Ok(latido) => self.delegacion.detector.latido(latido.id_local),
+                    Err(e) => eprintln!("No se pudo leer un latido: {}", e),
+                },
+                TipoMensaje::Drenar => {
+                    if self.drenaje.iniciar() {
+                        println!("Un operador pidio drenar el local");
+                    }
                 }
                 TipoMensaje::Matar => match self.esperar_a_revivir(&mensajero_addr).await {
                     Ok(sock) => {
                         mensajero_addr.do_send(Reconectar::new(sock));
                         println!("Revivi, ahora a revivir al mensajero");
+                        self.delegacion.detector.reiniciar();
+                        // Mientras estuvo muerto pudo haber cambiado el lider
+                        if let Some(eleccion) = &self.eleccion {
+                            eleccion.do_send(IniciarEleccion);
+                        }
                     }
                     Err(_) => {
                         mensajero_addr.do_send(Matar);
@@ -205,7 +440,10 @@ impl ServidorEcommerce {
 
     /// Envia un mensaje de ack al remitente, y procesa el pedido dado
     /// Debido a que el mensaje se pasa como un vector de bytes, puede ser utilizado
-    /// tanto para mensajes de commerce como para mensajes delegados
+    /// tanto para mensajes de commerce como para mensajes delegados.
+    /// Si el pedido es un reenvio de uno ya recibido, solo se reenvia el ack y,
+    /// si ya se conoce, el resultado que se le informo al ecommerce. Si el local
+    /// se esta drenando, el pedido nuevo se desvia sin reservarlo
     async fn mandar_ack_y_procesar_pedido(
         &self,
         msg: Vec<u8>,
@@ -219,24 +457,119 @@ impl ServidorEcommerce {
             return;
         }
 
+        let clave = (mensaje_delegado.puerto_ecommerce, mensaje_delegado.get_id());
+        let dio_la_vuelta = mensaje_delegado.locales_ack.contains(&self.id_local);
+        if let Err(estado) = self.historial.registrar(clave, dio_la_vuelta).await {
+            println!(
+                "El pedido con id {} de ecommerce en puerto {} es un reenvio ({:?})",
+                clave.1.to_string().blue(),
+                clave.0.to_string().green(),
+                estado
+            );
+            if let EstadoPedido::Informado(resultado) = estado {
+                reenviar_resultado(&mensajero, clave.0, resultado).await;
+            }
+            return;
+        }
+
         let guardian_addr_clone = self.guardian_addr.clone();
-        let ack_delegados_clone = self.acks_delegados.clone();
+        let repartidor = self.repartidor.clone();
+        let historial_clone = self.historial.clone();
+        let delegacion_clone = self.delegacion.clone();
         let id_local_clone = self.id_local;
+        let ventas = self.ventas.clone();
+        let carrito = mensaje_delegado.get_pedido();
+        let delegado_por_otro = !mensaje_delegado.locales_ack.is_empty() && !dio_la_vuelta;
+        let en_curso = self.drenaje.comenzar_pedido();
+        let drenando = self.drenaje.drenando();
         actix_rt::spawn(async move {
-            procesar_pedido(
-                guardian_addr_clone,
-                &mensajero,
-                id_local_clone,
-                mensaje_delegado,
-                ack_delegados_clone,
-            )
-            .await
+            let _en_curso = en_curso;
+            let resultado = if drenando {
+                desviar_pedido(
+                    &mensajero,
+                    id_local_clone,
+                    mensaje_delegado,
+                    &historial_clone,
+                    &delegacion_clone,
+                )
+                .await
+                .map(|()| None)
+            } else {
+                procesar_pedido(
+                    guardian_addr_clone,
+                    &mensajero,
+                    id_local_clone,
+                    mensaje_delegado,
+                    &historial_clone,
+                    &delegacion_clone,
+                    &repartidor,
+                )
+                .await
+            };
+            if let Some(ventas) = ventas {
+                let estado = historial_clone.estado(clave).await;
+                // Si se confirmo, se reporta lo que confirmo el guardian, ya que el
+                // cliente pudo haber reducido el pedido mientras estaba reservado
+                let confirmado = resultado.as_ref().ok().cloned().flatten();
+                ventas.do_send(PedidoEcommerceResuelto::new(
+                    estado,
+                    confirmado.unwrap_or(carrito),
+                    delegado_por_otro,
+                ));
+            }
+            resultado
         });
     }
 
+    /// Pasa una etapa de una transferencia de stock recibida de otro local a
+    /// quien maneja las transferencias
+    fn procesar_transferencia(&self, cursor: &mut dyn Read, sender: SocketAddr) {
+        let Some(transferencias) = &self.transferencias else {
+            return;
+        };
+        match MensajeTransferencia::from_bytes(cursor) {
+            Ok(mensaje) => transferencias.do_send(TransferenciaRecibida {
+                mensaje,
+                origen: puerto_a_id(sender.port()),
+            }),
+            Err(e) => eprintln!("Hubo un error leyendo un mensaje de transferencia: {}", e),
+        }
+    }
+
+    /// Pasa el ack de una etapa de una transferencia de stock recibido de otro
+    /// local a quien maneja las transferencias
+    fn procesar_ack_transferencia(&self, cursor: &mut dyn Read, sender: SocketAddr) {
+        let Some(transferencias) = &self.transferencias else {
+            return;
+        };
+        match AckTransferencia::from_bytes(cursor) {
+            Ok(ack) => transferencias.do_send(AckTransferenciaRecibido {
+                ack,
+                origen: puerto_a_id(sender.port()),
+            }),
+            Err(e) => eprintln!("Hubo un error leyendo un ack de transferencia: {}", e),
+        }
+    }
+
+    /// Pasa el resultado de un pedido que el local delego para uno de sus clientes
+    /// a quien maneja esas busquedas
+    fn procesar_resultado_busqueda(&self, cursor: &mut dyn Read, sender: SocketAddr) {
+        let Some(busquedas) = &self.busquedas else {
+            return;
+        };
+        match MensajesServidor::from_bytes(cursor) {
+            Ok(resultado) => busquedas.do_send(ResultadoRecibido {
+                resultado,
+                origen: puerto_a_id(sender.port()),
+            }),
+            Err(e) => eprintln!("Hubo un error leyendo el resultado de una busqueda: {}", e),
+        }
+    }
+
     /// Procesa la llegada de un ack de otro local, y notifica las tareas espectantes.
-    /// para que puedan continuar su ejecucion
-    async fn procesar_ack_delegado(&self, mut cursor: &mut dyn Read) {
+    /// para que puedan continuar su ejecucion. El pedido queda anotado como
+    /// delegado al local que envio el ack
+    async fn procesar_ack_delegado(&self, mut cursor: &mut dyn Read, sender: SocketAddr) {
         let ack = match AckDelegado::from_bytes(&mut cursor) {
             Ok(msg) => msg,
             Err(e) => {
@@ -247,36 +580,177 @@ impl ServidorEcommerce {
                 return;
             }
         };
-        let mut set = self.acks_delegados.0.lock().await;
-        set.insert((ack.puerto, ack.id_pedido));
-        self.acks_delegados.1.notify_waiters();
+        self.historial
+            .delegar((ack.puerto, ack.id_pedido), puerto_a_id(sender.port()))
+            .await;
+        let clave = (puerto_a_id(sender.port()), ack.puerto, ack.id_pedido);
+        if let Some(recibido) = self.delegacion.acks.0.lock().await.get_mut(&clave) {
+            *recibido = true;
+            self.delegacion.acks.1.notify_waiters();
+        }
+    }
+
+    /// Responde la consulta de stock de otro local sin bloquear la lectura del
+    /// socket, ya que requiere preguntarle al guardian
+    fn procesar_consulta_stock(
+        &self,
+        cursor: &mut dyn Read,
+        sender: SocketAddr,
+        mensajero: Addr<Mensajero>,
+    ) {
+        let consulta = match ConsultaStock::from_bytes(cursor) {
+            Ok(consulta) => consulta,
+            Err(e) => {
+                eprintln!("No se pudo leer la consulta de stock: {}", e);
+                return;
+            }
+        };
+        actix_rt::spawn(consultas::responder_consulta(
+            self.guardian_addr.clone(),
+            mensajero,
+            consulta,
+            sender,
+        ));
+    }
+
+    /// Pasa un mensaje de la eleccion del lider a quien la lleva adelante
+    fn procesar_eleccion(&self, tipo: TipoMensaje, cursor: &mut dyn Read, sender: SocketAddr) {
+        let Some(eleccion) = &self.eleccion else {
+            return;
+        };
+        match MensajeEleccion::from_bytes(tipo, cursor) {
+            Ok(mensaje) => eleccion.do_send(EleccionRecibida {
+                mensaje,
+                origen: sender,
+            }),
+            Err(e) => eprintln!("No se pudo leer el mensaje de eleccion: {}", e),
+        }
+    }
+
+    /// Incorpora a la vista de la membresia el rumor de otro local. Si el rumor
+    /// esta desactualizado le responde con la vista propia, para que se entere
+    /// por ejemplo de que tiene que superar la version con la que se fue
+    fn procesar_rumor(
+        &self,
+        cursor: &mut dyn Read,
+        sender: SocketAddr,
+        mensajero: Addr<Mensajero>,
+    ) {
+        match MensajeMembresia::from_bytes(cursor) {
+            Ok(rumor) => {
+                if self.delegacion.membresia.fusionar(&rumor) {
+                    mensajero.do_send(Enviar::new(
+                        self.delegacion.membresia.rumor().as_bytes(),
+                        sender,
+                    ));
+                }
+            }
+            Err(e) => eprintln!("No se pudo leer el rumor de membresia: {}", e),
+        }
+    }
+
+    /// Le responde a un ecommerce en que estado esta su pedido segun el historial
+    async fn procesar_consulta_estado(
+        &self,
+        cursor: &mut dyn Read,
+        sender: SocketAddr,
+        mensajero: Addr<Mensajero>,
+    ) {
+        let consulta = match ConsultaEstado::from_bytes(cursor) {
+            Ok(consulta) => consulta,
+            Err(e) => {
+                eprintln!("No se pudo leer la consulta de estado: {}", e);
+                return;
+            }
+        };
+        let estado = self
+            .historial
+            .consultar((consulta.puerto, consulta.id_pedido))
+            .await;
+        let respuesta = RespuestaEstado::new(consulta.id_pedido, consulta.puerto, estado);
+        mensajero.do_send(Enviar::new(respuesta.as_bytes(), sender));
+    }
+
+    /// Atiende el pedido de un ecommerce de cancelar o reducir uno de sus pedidos
+    fn procesar_modificacion(&self, cursor: &mut dyn Read, mensajero: Addr<Mensajero>) {
+        let modificacion = match MensajeModificacion::from_bytes(cursor) {
+            Ok(modificacion) => modificacion,
+            Err(e) => {
+                eprintln!("No se pudo leer la modificacion de un pedido: {}", e);
+                return;
+            }
+        };
+        actix_rt::spawn(modificar_pedido(
+            self.guardian_addr.clone(),
+            mensajero,
+            self.historial.clone(),
+            modificacion,
+        ));
+    }
+
+    /// Pasa la respuesta de otro local a la consulta de stock que la espera
+    async fn procesar_respuesta_stock(&self, cursor: &mut dyn Read, sender: SocketAddr) {
+        let respuesta = match RespuestaStock::from_bytes(cursor) {
+            Ok(respuesta) => respuesta,
+            Err(e) => {
+                eprintln!("No se pudo leer la respuesta de stock: {}", e);
+                return;
+            }
+        };
+        self.delegacion
+            .consultas
+            .recibir(puerto_a_id(sender.port()), respuesta)
+            .await;
     }
 }
 
-/// Define si un pedido sera entregado o no, se modela de forma aleatoria
-async fn sera_entregado() -> bool {
-    let mut rng = rand::thread_rng();
-    let y: u64 = rng.gen_range(500..1500);
-    let duracion = Duration::from_millis(y);
-    time::sleep(duracion).await;
-    y < 1000
+/// Vuelve a enviarle al ecommerce el resultado que ya se le habia informado
+/// sobre un pedido que reenvio
+async fn reenviar_resultado(
+    mensajero: &Addr<Mensajero>,
+    puerto_ecommerce: Puerto,
+    resultado: MensajesServidor,
+) {
+    let ecommerce: SocketAddr = match puerto_a_ip(puerto_ecommerce).parse() {
+        Ok(d) => d,
+        Err(_) => {
+            println!("No se pudo procesar la direccion del ecommerce");
+            return;
+        }
+    };
+    if !matches!(
+        mensajero
+            .send(Enviar::new(resultado.as_bytes(), ecommerce))
+            .await,
+        Ok(Ok(_))
+    ) {
+        println!(
+            "No le pude reenviar el resultado al ecommerce {}",
+            puerto_ecommerce.to_string().green()
+        );
+    }
 }
 
-/// Cancela el pedido dado, notificandole al ecommerce del resultado.
+/// Cancela el pedido dado, notificandole al ecommerce del resultado. Si el
+/// bloqueo ya habia expirado, el ecommerce ya fue avisado de la cancelacion,
+/// por lo que solo se anota en el historial.
 async fn cancelar_pedido(
-    guardian_addr: Addr<Guardian>,
+    guardian_addr: Addr<GuardianParticionado>,
     mensajero: &Addr<Mensajero>,
     mensaje: MensajeDelegado,
+    historial: &HistorialPedidos,
 ) -> Result<(), ErrorServidor> {
-    guardian_addr
+    let msg = MensajesServidor::PedidoCancelado(mensaje.get_id());
+    let cancelacion = guardian_addr
         .send(guardian::Cancelar::new(
             mensaje.get_id(),
             mensaje.puerto_ecommerce,
         ))
         .await
-        .map_err(|_e| ErrorServidor::GuardianNoDisponible)??;
+        .map_err(|_e| ErrorServidor::GuardianNoDisponible)?;
+    informar(historial, &mensaje, &msg).await;
+    cancelacion?;
 
-    let msg = MensajesServidor::PedidoCancelado(mensaje.get_id());
     let ecommerce: SocketAddr = puerto_a_ip(mensaje.puerto_ecommerce).parse()?;
 
     if let Err(e) = mensajero
@@ -301,138 +775,435 @@ async fn cancelar_pedido(
     Ok(())
 }
 
-/// Confirma el pedido dado, notificando al ecommerce el resultado. Si no
-/// se puede enviar un mensaje al ecommerce por falta de conectividad, entonces cancela el pedido.
+/// Confirma el pedido dado en el guardian y, solo si lo logra, le notifica al
+/// ecommerce que fue exitoso. Si el bloqueo ya habia expirado, el ecommerce ya
+/// fue avisado de la cancelacion, por lo que solo se anota en el historial. Si
+/// el aviso no le llega al ecommerce, el resultado queda en el historial para
+/// cuando reenvie o consulte el pedido. Devuelve el carrito confirmado por el
+/// guardian, que no incluye lo que el cliente redujo del pedido.
 async fn confirmar_pedido(
-    guardian_addr: Addr<Guardian>,
+    guardian_addr: Addr<GuardianParticionado>,
     mensajero: &Addr<Mensajero>,
     mensaje: MensajeDelegado,
-) -> Result<(), ErrorServidor> {
+    historial: &HistorialPedidos,
+) -> Result<Carrito, ErrorServidor> {
+    let confirmacion = guardian_addr
+        .send(guardian::Confirmar::new(
+            mensaje.get_id(),
+            mensaje.puerto_ecommerce,
+        ))
+        .await
+        .map_err(|_e| ErrorServidor::GuardianNoDisponible)?;
+    let carrito = match confirmacion {
+        Ok(carrito) => carrito,
+        Err(e) => {
+            println!(
+                "No se pudo confirmar el pedido {} del ecommerce {}, su reserva ya no existe",
+                mensaje.get_id().to_string().blue(),
+                mensaje.puerto_ecommerce.to_string().green()
+            );
+            let cancelado = MensajesServidor::PedidoCancelado(mensaje.get_id());
+            informar(historial, &mensaje, &cancelado).await;
+            return Err(e.into());
+        }
+    };
+
     let msg = MensajesServidor::PedidoExitoso(mensaje.get_id());
     let ecommerce: SocketAddr = puerto_a_ip(mensaje.puerto_ecommerce).parse()?;
-    match mensajero
+    informar(historial, &mensaje, &msg).await;
+    if let Err(e) = mensajero
         .send(Enviar::new(msg.as_bytes(), ecommerce))
         .await?
     {
-        Ok(_) => {
-            guardian_addr
-                .send(guardian::Confirmar::new(
-                    mensaje.get_id(),
-                    mensaje.puerto_ecommerce,
-                ))
-                .await
-                .map_err(|_e| ErrorServidor::GuardianNoDisponible)??;
-        }
-        Err(e) => {
-            print!(
-                "No le pude avisar al ecommerce {} sobre la confirmacion de su pedido {} ",
-                mensaje.puerto_ecommerce.to_string().green(),
-                mensaje.get_id().to_string().blue()
-            );
-            match e {
-                ErrorMensajero::DestinoInaccesible => {
-                    println!("porque el ecommerce es inaccesible");
-                }
-                ErrorMensajero::InternetCaido => {
-                    println!("porque se me cayo la conexion");
-                }
+        print!(
+            "No le pude avisar al ecommerce {} sobre la confirmacion de su pedido {} ",
+            mensaje.puerto_ecommerce.to_string().green(),
+            mensaje.get_id().to_string().blue()
+        );
+        match e {
+            ErrorMensajero::DestinoInaccesible => {
+                println!("porque el ecommerce es inaccesible");
+            }
+            ErrorMensajero::InternetCaido => {
+                println!("porque se me cayo la conexion");
             }
-
-            guardian_addr
-                .send(guardian::Cancelar::new(
-                    mensaje.get_id(),
-                    mensaje.puerto_ecommerce,
-                ))
-                .await
-                .map_err(|_e| ErrorServidor::GuardianNoDisponible)??;
         }
     }
 
-    Ok(())
+    Ok(carrito)
 }
 
-/// Define el resultado del pedido, enviando el resultado al ecommerce.
-/// El pedido puede ser entregado, o cancelado
+/// Define el resultado del pedido segun como termina su entrega, enviando el
+/// resultado al ecommerce. Si el cliente retira el pedido, se confirma; si no,
+/// se cancela. Como el pedido puede esperar su turno en el repartidor, el
+/// tiempo de vida de su bloqueo se renueva cuando sale a repartirse. Mientras
+/// se entrega, el pedido figura como reservado, y el cliente puede cancelarlo
+/// por su cuenta, o el local al drenarse, en cuyo caso no se resuelve.
+/// Devuelve el carrito confirmado, si el pedido se confirmo
 async fn resolver_pedido(
-    guardian_addr: Addr<Guardian>,
+    guardian_addr: Addr<GuardianParticionado>,
     mensajero: &Addr<Mensajero>,
     mensaje: MensajeDelegado,
-) -> Result<(), ErrorServidor> {
-    if sera_entregado().await {
+    historial: &HistorialPedidos,
+    repartidor: &Addr<Repartidor>,
+) -> Result<Option<Carrito>, ErrorServidor> {
+    let clave = (mensaje.puerto_ecommerce, mensaje.get_id());
+    historial.actualizar(clave, EstadoPedido::Reservado).await;
+    let (aviso_salida, salida) = oneshot::channel();
+    let reparto = repartidor.send(
+        Repartir::new(mensaje.get_id(), mensaje.puerto_ecommerce).con_aviso_salida(aviso_salida),
+    );
+    let renovacion = async {
+        if salida.await.is_err() {
+            return;
+        }
+        let renovar = guardian::Renovar::new(
+            mensaje.get_id(),
+            mensaje.puerto_ecommerce,
+            guardian::TTL_BLOQUEO_POR_DEFECTO,
+        );
+        if !matches!(guardian_addr.send(renovar).await, Ok(Ok(()))) {
+            eprintln!(
+                "No se pudo renovar el bloqueo del pedido con id {} de ecommerce en puerto {}",
+                mensaje.get_id().to_string().blue(),
+                mensaje.puerto_ecommerce.to_string().green()
+            );
+        }
+    };
+    let (entrega, ()) = tokio::join!(reparto, renovacion);
+    let entrega = entrega.unwrap_or(Entrega::NoRetirado);
+    if !historial.tomar_para_resolver(clave).await {
+        println!(
+            "El pedido con id {} de ecommerce en puerto {} se cancelo antes de resolver su entrega",
+            mensaje.get_id().to_string().blue(),
+            mensaje.puerto_ecommerce.to_string().green()
+        );
+        return Ok(None);
+    }
+    if entrega == Entrega::Retirado {
         println!(
             "El pedido con id {} de ecommerce en puerto {} fue exitoso",
             mensaje.get_id().to_string().blue(),
             mensaje.puerto_ecommerce.to_string().green()
         );
-        confirmar_pedido(guardian_addr, mensajero, mensaje).await
+        confirmar_pedido(guardian_addr, mensajero, mensaje, historial)
+            .await
+            .map(Some)
     } else {
         println!(
-            "El pedido con id {} de ecommerce en puerto {} fue cancelado",
+            "El pedido con id {} de ecommerce en puerto {} fue cancelado ({:?})",
             mensaje.get_id().to_string().blue(),
-            mensaje.puerto_ecommerce.to_string().green()
+            mensaje.puerto_ecommerce.to_string().green(),
+            entrega
         );
-        cancelar_pedido(guardian_addr, mensajero, mensaje).await
+        cancelar_pedido(guardian_addr, mensajero, mensaje, historial)
+            .await
+            .map(|()| None)
+    }
+}
+
+/// Cancela o reduce un pedido a pedido del ecommerce, y le responde el
+/// resultado. Si el local delego el pedido, le reenvia la modificacion al local
+/// que lo recibio, que es quien le responde al ecommerce. Solo pueden
+/// modificarse los pedidos que el local tiene reservados
+async fn modificar_pedido(
+    guardian_addr: Addr<GuardianParticionado>,
+    mensajero: Addr<Mensajero>,
+    historial: Arc<HistorialPedidos>,
+    msg: MensajeModificacion,
+) {
+    let clave = (msg.puerto, msg.id_pedido);
+    let resultado = match (historial.estado(clave).await, msg.modificacion) {
+        (Some(EstadoPedido::Delegado(local)), _) => {
+            if let Ok(destino) = id_a_dir_local(local).parse() {
+                mensajero.do_send(Enviar::new(msg.as_bytes(), destino));
+            }
+            return;
+        }
+        (Some(EstadoPedido::Reservado), Modificacion::Cancelar) => {
+            match historial.retirar(clave).await {
+                Ok(()) if cancelar_reserva(&guardian_addr, &mensajero, clave).await => {
+                    ResultadoModificacion::Aceptada
+                }
+                Ok(()) => ResultadoModificacion::YaCancelado,
+                Err(estado) => resultado_segun_estado(estado),
+            }
+        }
+        (
+            Some(EstadoPedido::Reservado),
+            Modificacion::Reducir {
+                id_producto,
+                cantidad,
+            },
+        ) => {
+            let reduccion = Reducir::new(msg.id_pedido, msg.puerto, id_producto, cantidad);
+            match guardian_addr.send(reduccion).await {
+                Ok(Ok(())) => ResultadoModificacion::Aceptada,
+                Ok(Err(ErrorGuardian::ReduccionInvalida)) => ResultadoModificacion::Invalida,
+                _ => resultado_segun_estado(historial.estado(clave).await),
+            }
+        }
+        (estado, _) => resultado_segun_estado(estado),
+    };
+
+    println!(
+        "Modificacion del pedido con id {} de ecommerce en puerto {}: {:?}",
+        msg.id_pedido.to_string().blue(),
+        msg.puerto.to_string().green(),
+        resultado
+    );
+    let respuesta = RespuestaModificacion::new(msg.id_pedido, resultado);
+    if let Ok(ecommerce) = puerto_a_ip(msg.puerto).parse() {
+        mensajero.do_send(Enviar::new(respuesta.as_bytes(), ecommerce));
+    }
+}
+
+/// Libera la reserva de un pedido retirado del historial, porque lo cancelo el
+/// cliente o porque el local se drena, y le avisa al ecommerce que quedo
+/// cancelado. Devuelve falso si la reserva ya habia expirado, en cuyo caso el
+/// pedido ya estaba cancelado
+async fn cancelar_reserva(
+    guardian_addr: &Addr<GuardianParticionado>,
+    mensajero: &Addr<Mensajero>,
+    (puerto, id_pedido): (Puerto, IdPedido),
+) -> bool {
+    let cancelacion = guardian::Cancelar::new(id_pedido, puerto);
+    if !matches!(guardian_addr.send(cancelacion).await, Ok(Ok(()))) {
+        return false;
+    }
+    if let Ok(ecommerce) = puerto_a_ip(puerto).parse() {
+        let cancelado = MensajesServidor::PedidoCancelado(id_pedido);
+        mensajero.do_send(Enviar::new(cancelado.as_bytes(), ecommerce));
+    }
+    true
+}
+
+/// Tiempo que se espera al drenar el local, ademas de lo que puede tardar el
+/// reparto, para que los pedidos en curso terminen de confirmarse con el
+/// guardian y de avisarse al ecommerce
+const MARGEN_DRENAJE: Duration = Duration::from_secs(2);
+
+/// Espera a que se inicie el drenaje del local, y lo lleva adelante: descarta
+/// los pedidos en espera de stock, avisandoles a sus ecommerce que no hay
+/// stock, y espera a que terminen los pedidos en curso, tanto como puede tardar
+/// su entrega segun la configuracion del reparto. Si no terminan a tiempo,
+/// cancela los que siguen reservados. Al terminar, el servidor deja de escuchar
+async fn drenar(
+    guardian_addr: Addr<GuardianParticionado>,
+    mensajero: Addr<Mensajero>,
+    historial: Arc<HistorialPedidos>,
+    drenaje: Arc<Drenaje>,
+    reparto: ConfiguracionReparto,
+) {
+    drenaje.esperar_inicio().await;
+    println!("Drenando el local: los pedidos nuevos se desvian a otros locales");
+    vaciar_espera(&guardian_addr).await;
+    let plazo = || MARGEN_DRENAJE + reparto.plazo_entregas(drenaje.en_curso());
+    if !drenaje.esperar_pedidos(plazo()).await {
+        println!(
+            "Quedan {} pedidos en curso, se cancelan los reservados",
+            drenaje.en_curso()
+        );
+        // Pudieron haberse encolado pedidos que dieron la vuelta mientras tanto
+        vaciar_espera(&guardian_addr).await;
+        for clave in historial.reservados().await {
+            if historial.retirar(clave).await.is_ok() {
+                cancelar_reserva(&guardian_addr, &mensajero, clave).await;
+            }
+        }
+        if !drenaje.esperar_pedidos(plazo()).await {
+            println!(
+                "Se abandonan {} pedidos que no terminaron a tiempo",
+                drenaje.en_curso()
+            );
+        }
+    }
+    println!("El local termino de drenarse");
+    drenaje.terminar();
+}
+
+/// Descarta los pedidos en espera de stock del guardian
+async fn vaciar_espera(guardian_addr: &Addr<GuardianParticionado>) {
+    match guardian_addr.send(VaciarEspera).await {
+        Ok(0) => {}
+        Ok(descartados) => println!("Se descartaron {} pedidos en espera", descartados),
+        Err(_) => eprintln!("No se pudo vaciar la cola de espera del guardian"),
+    }
+}
+
+/// Devuelve por que no puede modificarse un pedido que no esta reservado, segun su estado
+fn resultado_segun_estado(estado: Option<EstadoPedido>) -> ResultadoModificacion {
+    match estado {
+        Some(EstadoPedido::Informado(MensajesServidor::PedidoExitoso(_))) => {
+            ResultadoModificacion::YaConfirmado
+        }
+        Some(EstadoPedido::Informado(MensajesServidor::PedidoCancelado(_)))
+        | Some(EstadoPedido::Informado(MensajesServidor::NoHayStock(_))) => {
+            ResultadoModificacion::YaCancelado
+        }
+        _ => ResultadoModificacion::NoReservado,
     }
 }
 
 /// Procesa un mensaje delegado, esta funcion tambien se utiliza para procesar mensajes de ecommerce,
-/// encapsulandolos previamente en un mensaje delegado.
+/// encapsulandolos previamente en un mensaje delegado. Devuelve el carrito confirmado, si el
+/// pedido se confirmo en este local.
 async fn procesar_pedido(
-    guardian_addr: Addr<Guardian>,
+    guardian_addr: Addr<GuardianParticionado>,
     mensajero: &Addr<Mensajero>,
     id: IdLocal,
     mensaje: MensajeDelegado,
-    acks: Arc<MonitorAsync>,
-) -> Result<(), ErrorServidor> {
+    historial: &HistorialPedidos,
+    delegacion: &Delegacion,
+    repartidor: &Addr<Repartidor>,
+) -> Result<Option<Carrito>, ErrorServidor> {
     let ecommerce: SocketAddr = puerto_a_ip(mensaje.puerto_ecommerce).parse()?;
     if mensaje.locales_ack.contains(&id) {
         println!(
             "Mensaje delegado repetido, con id {}",
             mensaje.get_id().to_string().blue()
         );
-        let msg = MensajesServidor::NoHayStock(mensaje.get_id());
+        return sin_stock_en_ningun_local(
+            guardian_addr,
+            mensajero,
+            mensaje,
+            ecommerce,
+            historial,
+            repartidor,
+        )
+        .await;
+    }
 
-        if mensajero
-            .send(Enviar::new(msg.as_bytes(), ecommerce))
-            .await
-            .is_err()
-        {
-            eprintln!(
-                "No le pude avisar al ecommerce {} que nadie tiene stock para su pedido {}",
-                mensaje.puerto_ecommerce.to_string().green(),
-                mensaje.get_id()
-            );
+    let result = guardian_addr
+        .send(
+            guardian::Bloquear::new(
+                mensaje.get_pedido(),
+                mensaje.get_id(),
+                mensaje.puerto_ecommerce,
+            )
+            .con_ttl(guardian::TTL_BLOQUEO_POR_DEFECTO),
+        )
+        .await
+        .map_err(|_e| ErrorServidor::GuardianNoDisponible)?;
+
+    match result {
+        Ok(_) => resolver_pedido(guardian_addr, mensajero, mensaje, historial, repartidor).await,
+        // Un reenvio que llego a pesar del historial: el pedido original ya lo resuelve
+        Err(ErrorGuardian::PedidoYaBloqueado) => Ok(None),
+        Err(_e) => match delegar_pedido(delegacion, mensajero, mensaje, id).await {
+            Some(mensaje) => {
+                sin_stock_en_ningun_local(
+                    guardian_addr,
+                    mensajero,
+                    mensaje,
+                    ecommerce,
+                    historial,
+                    repartidor,
+                )
+                .await
+            }
+            None => Ok(None),
+        },
+    }
+}
+
+/// Desvia un pedido que llega mientras el local se drena: sin intentar
+/// reservarlo, se lo delega a otro local. Si no queda ninguno, o si el pedido
+/// ya recorrio los demas locales, se le avisa al ecommerce que no hay stock
+async fn desviar_pedido(
+    mensajero: &Addr<Mensajero>,
+    id: IdLocal,
+    mut mensaje: MensajeDelegado,
+    historial: &HistorialPedidos,
+    delegacion: &Delegacion,
+) -> Result<(), ErrorServidor> {
+    let ecommerce: SocketAddr = puerto_a_ip(mensaje.puerto_ecommerce).parse()?;
+    println!(
+        "El local se esta drenando, desvio el pedido con id {} de ecommerce en puerto {}",
+        mensaje.get_id().to_string().blue(),
+        mensaje.puerto_ecommerce.to_string().green()
+    );
+    if !mensaje.locales_ack.contains(&id) {
+        match delegar_pedido(delegacion, mensajero, mensaje, id).await {
+            Some(sin_delegar) => mensaje = sin_delegar,
+            None => return Ok(()),
         }
-        return Ok(());
+    }
+    notificacion_falta_stock(mensajero, mensaje, ecommerce, historial).await;
+    Ok(())
+}
+
+/// Resuelve un pedido que ningun local pudo cubrir. Si el ecommerce pidio esperar,
+/// lo deja en la cola de espera del guardian y le avisa que quedo en espera; si no,
+/// le notifica que no hay stock. Devuelve el carrito confirmado, si el pedido
+/// en espera llego a confirmarse.
+async fn sin_stock_en_ningun_local(
+    guardian_addr: Addr<GuardianParticionado>,
+    mensajero: &Addr<Mensajero>,
+    mensaje: MensajeDelegado,
+    ecommerce: SocketAddr,
+    historial: &HistorialPedidos,
+    repartidor: &Addr<Repartidor>,
+) -> Result<Option<Carrito>, ErrorServidor> {
+    if !mensaje.quiere_esperar() {
+        notificacion_falta_stock(mensajero, mensaje, ecommerce, historial).await;
+        return Ok(None);
     }
 
-    let result = guardian_addr
-        .send(guardian::Bloquear::new(
+    let (aviso, stock_disponible) = oneshot::channel();
+    guardian_addr
+        .send(guardian::Encolar::new(
             mensaje.get_pedido(),
             mensaje.get_id(),
             mensaje.puerto_ecommerce,
+            aviso,
         ))
         .await
         .map_err(|_e| ErrorServidor::GuardianNoDisponible)?;
 
-    match result {
-        Ok(_) => resolver_pedido(guardian_addr, mensajero, mensaje).await,
-        Err(_e) => {
-            delegar_pedido(acks, mensajero, mensaje, id, ecommerce).await;
-            Ok(())
-        }
+    println!(
+        "El pedido con id {} de ecommerce en puerto {} queda en espera de stock",
+        mensaje.get_id().to_string().blue(),
+        mensaje.puerto_ecommerce.to_string().green()
+    );
+    let msg = MensajesServidor::EnEspera(mensaje.get_id());
+    informar(historial, &mensaje, &msg).await;
+    if mensajero
+        .send(Enviar::new(msg.as_bytes(), ecommerce))
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "No le pude avisar al ecommerce {} que su pedido {} quedo en espera",
+            mensaje.puerto_ecommerce.to_string().green(),
+            mensaje.get_id().to_string().blue()
+        );
+    }
+
+    if stock_disponible.await.is_err() {
+        // El guardian vacio su cola de espera, por ejemplo porque el local se drena
+        notificacion_falta_stock(mensajero, mensaje, ecommerce, historial).await;
+        return Ok(None);
     }
+    println!(
+        "Llego stock para el pedido en espera con id {} de ecommerce en puerto {}",
+        mensaje.get_id().to_string().blue(),
+        mensaje.puerto_ecommerce.to_string().green()
+    );
+    resolver_pedido(guardian_addr, mensajero, mensaje, historial, repartidor).await
 }
 
-/// Delega el pedido al siguiente local disponible, o le envia al ecommerce
-/// en caso de que no haya ninguno disponible.
-async fn delegar_pedido(
-    acks: Arc<MonitorAsync>,
+/// Delega el pedido al vecino preferido que aun no lo haya recibido. Si la
+/// delegacion es por consulta paralela, solo se consideran los vecinos que
+/// respondieron a tiempo que pueden cubrirlo, ordenados segun el criterio. Si no
+/// hay ninguno disponible, o el pedido ya recorrio tantos locales como entran en
+/// un mensaje, devuelve el mensaje para que se resuelva la falta de stock.
+pub(super) async fn delegar_pedido(
+    delegacion: &Delegacion,
     mensajero: &Addr<Mensajero>,
     mut mensaje: MensajeDelegado,
     id_local: IdLocal,
-    ecommerce: SocketAddr,
-) {
+) -> Option<MensajeDelegado> {
     println!(
         "No hay stock para el pedido con id {} en puerto {}",
         mensaje.get_id().to_string().blue(),
@@ -440,25 +1211,55 @@ async fn delegar_pedido(
     );
 
     mensaje.locales_ack.insert(id_local);
-    enviar_a_siguiente_local(acks, mensajero, mensaje, id_local, id_local, ecommerce).await;
+    if mensaje.locales_ack.len() > MAX_LOCALES_ACK {
+        println!(
+            "El pedido con id {} ya recorrio {} locales, no se delega mas",
+            mensaje.get_id().to_string().blue(),
+            MAX_LOCALES_ACK
+        );
+        return Some(mensaje);
+    }
+    let vecinos: Vec<IdLocal> = delegacion
+        .topologia
+        .vecinos(id_local, &delegacion.vivos())
+        .into_iter()
+        .filter(|id| !mensaje.locales_ack.contains(id))
+        .collect();
+    let candidatos = match delegacion.topologia.modo() {
+        ModoDelegacion::Secuencial => vecinos,
+        modo @ ModoDelegacion::ConsultaParalela { criterio, .. } => {
+            let respuestas = delegacion
+                .consultas
+                .consultar(mensajero, &mensaje, &vecinos, modo.plazo())
+                .await;
+            let candidatos = consultas::ordenar_candidatos(criterio, &vecinos, respuestas);
+            println!(
+                "Los locales {:?} pueden cubrir el pedido con id {}",
+                candidatos,
+                mensaje.get_id().to_string().blue()
+            );
+            candidatos
+        }
+    };
+    enviar_a_siguiente_local(delegacion, mensajero, mensaje, candidatos).await
 }
 
-/// Espera a que reciba el ack para determinado mensaje. Esta funcion no devuelve
-/// hasta que haya llegado el ack.
-async fn esperar_mi_ack(acks: Arc<MonitorAsync>, id: (Puerto, IdPedido)) {
+/// Espera a que reciba el ack del local indicado para determinado mensaje,
+/// que debe haberse anotado como esperado. Esta funcion no devuelve hasta que
+/// haya llegado el ack.
+async fn esperar_mi_ack(acks: Arc<MonitorAsync>, id: (IdLocal, Puerto, IdPedido)) {
     loop {
-        let mut lock = acks.0.lock().await;
-        if lock.contains(&id) {
-            lock.remove(&id);
+        let notificado = acks.1.notified();
+        if acks.0.lock().await.get(&id) == Some(&true) {
             println!(
-                "Recibi el ack por el pedido que delegue (puerto {}, id {})",
+                "Recibi el ack del local {} por el pedido que delegue (puerto {}, id {})",
+                id.0,
                 id.1.to_string().green(),
-                id.0.to_string().blue()
+                id.2.to_string().blue()
             );
             return;
         }
-        drop(lock);
-        acks.1.notified().await;
+        notificado.await;
     }
 }
 
@@ -467,8 +1268,10 @@ async fn notificacion_falta_stock(
     mensajero: &Addr<Mensajero>,
     mensaje: MensajeDelegado,
     ecommerce: SocketAddr,
+    historial: &HistorialPedidos,
 ) {
     let msg = MensajesServidor::NoHayStock(mensaje.get_id());
+    informar(historial, &mensaje, &msg).await;
 
     if mensajero
         .send(Enviar::new(msg.as_bytes(), ecommerce))
@@ -489,118 +1292,154 @@ async fn notificacion_falta_stock(
     );
 }
 
-/// Maneja el error del mensajero, imrpimiendo por pantalla
-/// el error y reenviando el mensaje en caso de un error en el destino
-async fn manejar_error_mensajero(
-    e: ErrorMensajero,
-    acks: Arc<MonitorAsync>,
-    mensajero: &Addr<Mensajero>,
-    mensaje: MensajeDelegado,
-    id_propia: IdLocal,
-    ecommerce: SocketAddr,
-    siguiente_local: IdLocal,
+/// Anota en el historial el resultado que se le informa al ecommerce sobre su
+/// pedido, para poder reenviarselo si vuelve a mandarlo. Se anota aunque el
+/// envio falle, ya que es justamente en ese caso cuando el ecommerce reintenta.
+async fn informar(
+    historial: &HistorialPedidos,
+    mensaje: &MensajeDelegado,
+    resultado: &MensajesServidor,
 ) {
+    historial
+        .actualizar(
+            (mensaje.puerto_ecommerce, mensaje.get_id()),
+            EstadoPedido::Informado(resultado.clone()),
+        )
+        .await;
+}
+
+/// Maneja el error del mensajero, imprimiendo por pantalla el error. Devuelve
+/// si tiene sentido probar con el siguiente local, lo cual ocurre ante un
+/// error en el destino.
+fn manejar_error_mensajero(e: ErrorMensajero, siguiente_local: IdLocal) -> bool {
     match e {
         ErrorMensajero::DestinoInaccesible => {
             println!(
                 "La delegacion al local {} fallo. Enviare al siguiente local.",
                 siguiente_local.to_string().blue()
             );
-            enviar_a_siguiente_local(
-                acks,
-                mensajero,
-                mensaje,
-                siguiente_local,
-                id_propia,
-                ecommerce,
-            )
-            .await;
+            true
         }
         ErrorMensajero::InternetCaido => {
-            println!("Se me cayo el internet, no pude delegar el pedido")
+            println!("Se me cayo el internet, no pude delegar el pedido");
+            false
         }
     }
 }
 
-/// Delega el mensaje al siguiente local, y espera a recibir el ack.
-/// Si no recibe el ack, envia al siguiente local de la lista
-/// Si ningun local tiene stock, envia un mensaje al cliente.
-#[async_recursion]
+/// Como termino la delegacion de un pedido a un candidato
+enum ResultadoDelegacion {
+    /// El candidato acuso recibir el pedido
+    Recibido,
+    /// El candidato no respondio o no se le pudo enviar, y se sospecha que cayo
+    NoDisponible,
+    /// No puede seguir delegandose el pedido
+    Abortada,
+}
+
+/// Delega el mensaje a los candidatos en orden, y espera a recibir el ack.
+/// Si no lo recibe, se lo retransmite al mismo candidato esperando cada vez
+/// mas, y si agota las retransmisiones sospecha que el candidato cayo y envia
+/// al siguiente candidato de la lista. Mientras espera a un candidato, anota
+/// que espera su ack para ese pedido, y deja de esperarlo al pasar al siguiente.
+/// Si no queda ningun local al cual delegar, devuelve el mensaje.
 async fn enviar_a_siguiente_local(
-    acks: Arc<MonitorAsync>,
+    delegacion: &Delegacion,
     mensajero: &Addr<Mensajero>,
     mensaje: MensajeDelegado,
-    id_local: IdLocal,
-    id_propia: IdLocal,
-    ecommerce: SocketAddr,
-) {
-    let siguiente_local = siguiente_id_local(id_local);
+    candidatos: Vec<IdLocal>,
+) -> Option<MensajeDelegado> {
+    for siguiente_local in candidatos {
+        println!(
+            "Delegando el pedido del ecommerce (puerto {}, id {}) al local {}",
+            mensaje.puerto_ecommerce.to_string().green(),
+            mensaje.get_id().to_string().blue(),
+            siguiente_local
+        );
 
-    if siguiente_local == id_propia {
-        notificacion_falta_stock(mensajero, mensaje, ecommerce).await;
-        return;
-    }
+        let clave = (siguiente_local, mensaje.puerto_ecommerce, mensaje.get_id());
+        delegacion.acks.0.lock().await.insert(clave, false);
+        let resultado = delegar_a_local(delegacion, mensajero, &mensaje, siguiente_local).await;
+        delegacion.acks.0.lock().await.remove(&clave);
 
-    println!(
-        "Delegando el pedido del ecommerce (puerto {}, id {}) al local {}",
-        mensaje.puerto_ecommerce.to_string().green(),
-        mensaje.get_id().to_string().blue(),
-        siguiente_local
-    );
+        match resultado {
+            ResultadoDelegacion::Recibido | ResultadoDelegacion::Abortada => return None,
+            ResultadoDelegacion::NoDisponible => {
+                delegacion.detector.sospechar(siguiente_local);
+            }
+        }
+    }
+    Some(mensaje)
+}
 
+/// Le envia el mensaje a un candidato, retransmitiendoselo mientras no llegue
+/// su ack y queden reintentos
+async fn delegar_a_local(
+    delegacion: &Delegacion,
+    mensajero: &Addr<Mensajero>,
+    mensaje: &MensajeDelegado,
+    siguiente_local: IdLocal,
+) -> ResultadoDelegacion {
+    let retransmision = delegacion.topologia.retransmision();
     let dir_prox_local: SocketAddr = match id_a_dir_local(siguiente_local).parse() {
         Ok(d) => d,
         Err(_) => {
             println!("No se pudo procesar la direccion del siguiente local");
-            return;
+            return ResultadoDelegacion::Abortada;
         }
     };
 
-    let res = match mensajero
-        .send(Enviar::new(mensaje.as_bytes(), dir_prox_local))
-        .await
-    {
-        Ok(r) => r,
-        Err(_) => {
-            println!("No se pudo comunicar al mensajero, algo raro paso");
-            return;
+    for envio in 0..=retransmision.reintentos {
+        if envio > 0 {
+            println!(
+                "Retransmitiendo el pedido con id {} al local {} (reintento {} de {})",
+                mensaje.get_id().to_string().blue(),
+                siguiente_local,
+                envio,
+                retransmision.reintentos
+            );
+            *delegacion
+                .retransmisiones
+                .lock()
+                .await
+                .entry(siguiente_local)
+                .or_insert(0) += 1;
         }
-    };
 
-    if let Err(e) = res {
-        manejar_error_mensajero(
-            e,
-            acks,
-            mensajero,
-            mensaje,
-            id_propia,
-            ecommerce,
-            siguiente_local,
-        )
-        .await;
-        return;
-    }
+        let res = match mensajero
+            .send(Enviar::new(mensaje.as_bytes(), dir_prox_local))
+            .await
+        {
+            Ok(r) => r,
+            Err(_) => {
+                println!("No se pudo comunicar al mensajero, algo raro paso");
+                return ResultadoDelegacion::Abortada;
+            }
+        };
 
-    let timeout_dur = Duration::from_millis(500);
-    let res = timeout(
-        timeout_dur,
-        esperar_mi_ack(acks.clone(), (mensaje.puerto_ecommerce, mensaje.get_id())),
-    )
-    .await;
+        if let Err(e) = res {
+            if manejar_error_mensajero(e, siguiente_local) {
+                return ResultadoDelegacion::NoDisponible;
+            }
+            return ResultadoDelegacion::Abortada;
+        }
 
-    if res.is_err() {
-        println!(
-            "El local {} no esta disponible, enviando al siguiente",
-            siguiente_local
-        );
-        enviar_a_siguiente_local(
-            acks,
-            mensajero,
-            mensaje,
-            siguiente_local,
-            id_propia,
-            ecommerce,
+        let res = timeout(
+            retransmision.espera(envio),
+            esperar_mi_ack(
+                delegacion.acks.clone(),
+                (siguiente_local, mensaje.puerto_ecommerce, mensaje.get_id()),
+            ),
         )
         .await;
-    };
+
+        if res.is_ok() {
+            return ResultadoDelegacion::Recibido;
+        }
+    }
+    println!(
+        "El local {} no esta disponible, enviando al siguiente",
+        siguiente_local
+    );
+    ResultadoDelegacion::NoDisponible
 }
@@ -1,10 +1,12 @@
 //! Este modulo define los metodos necesarios para el parseo de una tabla de stock
 //! a partir de un archivo fuente
-use crate::aliases::TablaStock;
+use crate::local::lotes::StockLotes;
 use std::io::Read;
 
-/// A partir de un reader en formato json, crea una tabla de stock. Simplemente
-/// encapsula las funcionalidades de la libreria de json.
-pub fn from_reader(reader: &mut dyn Read) -> Result<TablaStock, serde_json::Error> {
+/// A partir de un reader en formato json, crea el stock del local. El archivo
+/// asocia a cada id de producto una cantidad, o una lista de lotes con su
+/// cantidad y fecha de vencimiento. Simplemente encapsula las funcionalidades
+/// de la libreria de json.
+pub fn from_reader(reader: &mut dyn Read) -> Result<StockLotes, serde_json::Error> {
     serde_json::from_reader(reader)
 }
@@ -0,0 +1,704 @@
+//! Este modulo define el protocolo de transferencia de stock entre locales.
+//! Un local le pide a otro unidades de un producto; el donante las reserva en
+//! su guardian y, pasada una demora que simula el traslado, se las envia junto
+//! con los lotes de los que salieron. El receptor las agrega a su stock al
+//! recibirlas, con el mismo vencimiento. Cada etapa se reenvia hasta recibir
+//! su ack, como ocurre con la delegacion de pedidos.
+
+use actix::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use super::guardian::{Cancelar, Confirmar, ObtenerLotesBloqueados, Reponer};
+use super::mensajero::Enviar;
+use super::particiones::{GuardianParticionado, ReservarTransferencia};
+use crate::aliases::{CantidadPedido, CantidadProducto, IdLocal, IdProducto, IdTransferencia};
+use crate::errores::ErrorCarrito;
+use crate::mensajes::{AckTransferencia, EtapaTransferencia, MensajeTransferencia};
+use crate::pedido::{Carrito, Pedido};
+use crate::{id_a_dir_local, id_a_puerto_transferencias};
+
+/// Tiempo que tardan las unidades en llegar del donante al receptor
+pub const DEMORA_TRASLADO: Duration = Duration::from_secs(2);
+
+/// Tiempo que se espera el ack de cada etapa antes de reenviarla
+const TIMEOUT_ACK: Duration = Duration::from_millis(500);
+
+/// Cantidad de veces que se envia cada etapa antes de darla por perdida
+const MAX_INTENTOS: u32 = 3;
+
+/// Tiempo que se recuerda una etapa ya atendida, durante el cual el otro local
+/// todavia puede reenviarla
+const RETENCION: Duration = TIMEOUT_ACK.saturating_mul(MAX_INTENTOS + 1);
+
+/// Resultado de una solicitud de transferencia, desde el punto de vista de
+/// quien la pidio
+#[derive(MessageResponse, Debug, PartialEq, Eq)]
+pub enum ResultadoTransferencia {
+    Aceptada,
+    Rechazada,
+    SinRespuesta,
+}
+
+/// Resultado de enviar una etapa de una transferencia con reintentos
+#[derive(Debug, PartialEq, Eq)]
+enum Entrega {
+    /// El mensaje no pudo enviarse ni una vez
+    NoEnviada,
+    /// El mensaje se envio, pero no llego su ack
+    SinAck,
+    /// Llego el ack, que indica si se acepto la etapa
+    Ack(bool),
+}
+
+/// Actor que lleva adelante las transferencias en las que participa el local,
+/// tanto como receptor como donante. Recuerda que solicitudes ya atendio y que
+/// envios ya recibio, para que un mensaje reenviado no reserve ni agregue
+/// stock dos veces, y los olvida cuando ya no pueden llegar reenvios. Los ids
+/// de sus transferencias parten de la hora de inicio, para no repetir los que
+/// uso antes de reiniciarse mientras los demas locales todavia los recuerdan.
+pub struct Transferencias {
+    id_local: IdLocal,
+    guardian: Addr<GuardianParticionado>,
+    mensajero: Recipient<Enviar>,
+    demora: Duration,
+    siguiente_id: IdTransferencia,
+    acks_pendientes: HashMap<(IdLocal, IdTransferencia, EtapaTransferencia), oneshot::Sender<bool>>,
+    solicitudes_atendidas: HashMap<(IdLocal, IdTransferencia), Option<bool>>,
+    envios_recibidos: HashSet<(IdLocal, IdTransferencia)>,
+}
+
+impl Transferencias {
+    /// Crea el actor de transferencias del local dado, que opera sobre su guardian
+    /// y envia los mensajes a traves del mensajero
+    pub fn new(
+        id_local: IdLocal,
+        guardian: Addr<GuardianParticionado>,
+        mensajero: Recipient<Enviar>,
+    ) -> Self {
+        Self {
+            id_local,
+            guardian,
+            mensajero,
+            demora: DEMORA_TRASLADO,
+            siguiente_id: primer_id_transferencia(),
+            acks_pendientes: HashMap::new(),
+            solicitudes_atendidas: HashMap::new(),
+            envios_recibidos: HashSet::new(),
+        }
+    }
+
+    /// Define cuanto tardan en llegar las unidades que dona este local
+    pub fn con_demora(mut self, demora: Duration) -> Self {
+        self.demora = demora;
+        self
+    }
+
+    /// Envia un mensaje a otro local hasta recibir el ack correspondiente, o hasta
+    /// agotar los intentos. Devuelve lo que indico el ack, si es que llego, o si
+    /// el mensaje llego a enviarse
+    fn enviar_con_reintentos(
+        &mut self,
+        destino: IdLocal,
+        mensaje: MensajeTransferencia,
+    ) -> ResponseActFuture<Self, Entrega> {
+        let clave = (destino, mensaje.id_transferencia, mensaje.etapa);
+        let (aviso, mut ack) = oneshot::channel();
+        self.acks_pendientes.insert(clave, aviso);
+        let mensajero = self.mensajero.clone();
+        let direccion: Option<SocketAddr> = id_a_dir_local(destino).parse().ok();
+
+        let envios = async move {
+            let Some(direccion) = direccion else {
+                return Entrega::NoEnviada;
+            };
+            let mut enviada = false;
+            for _ in 0..MAX_INTENTOS {
+                enviada |= matches!(
+                    mensajero
+                        .send(Enviar::new(mensaje.as_bytes(), direccion))
+                        .await,
+                    Ok(Ok(()))
+                );
+                if let Ok(Ok(aceptada)) = timeout(TIMEOUT_ACK, &mut ack).await {
+                    return Entrega::Ack(aceptada);
+                }
+            }
+            if enviada {
+                Entrega::SinAck
+            } else {
+                Entrega::NoEnviada
+            }
+        };
+        Box::pin(envios.into_actor(self).map(move |respuesta, act, _ctx| {
+            act.acks_pendientes.remove(&clave);
+            respuesta
+        }))
+    }
+
+    /// Envia el ack de una etapa de transferencia al local dado
+    fn responder(
+        &self,
+        destino: IdLocal,
+        etapa: EtapaTransferencia,
+        id_transferencia: IdTransferencia,
+        aceptada: bool,
+    ) {
+        let Ok(direccion) = id_a_dir_local(destino).parse() else {
+            return;
+        };
+        let ack = AckTransferencia::new(etapa, id_transferencia, aceptada);
+        self.mensajero
+            .do_send(Enviar::new(ack.as_bytes(), direccion));
+    }
+
+    /// Atiende la solicitud de otro local reservando las unidades pedidas. Si
+    /// puede hacerlo, las despacha una vez cumplida la demora del traslado
+    fn atender_solicitud(
+        &mut self,
+        origen: IdLocal,
+        solicitud: MensajeTransferencia,
+        ctx: &mut Context<Self>,
+    ) {
+        let id = solicitud.id_transferencia;
+        match self.solicitudes_atendidas.get(&(origen, id)) {
+            Some(Some(aceptada)) => {
+                self.responder(origen, EtapaTransferencia::Solicitud, id, *aceptada);
+                return;
+            }
+            // Todavia se esta reservando, el ack saldra cuando termine
+            Some(None) => return,
+            None => {}
+        }
+        let carrito = match carrito_de(solicitud.id_producto, solicitud.cantidad) {
+            Ok(carrito) => carrito,
+            Err(e) => {
+                eprintln!(
+                    "[Local {}] No se puede reservar la transferencia: {}",
+                    self.id_local, e
+                );
+                self.solicitudes_atendidas.insert((origen, id), Some(false));
+                self.responder(origen, EtapaTransferencia::Solicitud, id, false);
+                return;
+            }
+        };
+        self.solicitudes_atendidas.insert((origen, id), None);
+
+        let ttl = self.demora + TIMEOUT_ACK * (MAX_INTENTOS + 1);
+        let bloqueo =
+            ReservarTransferencia::new(carrito, id, id_a_puerto_transferencias(origen), ttl);
+        let guardian = self.guardian.clone();
+        let reserva = async move { matches!(guardian.send(bloqueo).await, Ok(Ok(_))) };
+
+        ctx.spawn(reserva.into_actor(self).map(move |aceptada, act, ctx| {
+            println!(
+                "El local {} me pidio {} unidades del producto {}: {}",
+                origen,
+                solicitud.cantidad,
+                solicitud.id_producto,
+                if aceptada { "las reservo" } else { "no tengo" }
+            );
+            act.solicitudes_atendidas
+                .insert((origen, id), Some(aceptada));
+            act.responder(origen, EtapaTransferencia::Solicitud, id, aceptada);
+            if aceptada {
+                ctx.run_later(act.demora, move |act, ctx| {
+                    act.despachar(origen, solicitud, ctx)
+                });
+            } else {
+                act.olvidar_solicitud(origen, id, ctx);
+            }
+        }));
+    }
+
+    /// Olvida una solicitud ya resuelta, una vez que no pueden llegar reenvios suyos
+    fn olvidar_solicitud(&self, origen: IdLocal, id: IdTransferencia, ctx: &mut Context<Self>) {
+        ctx.run_later(RETENCION, move |act, _ctx| {
+            act.solicitudes_atendidas.remove(&(origen, id));
+        });
+    }
+
+    /// Envia al receptor las unidades reservadas, con los lotes de los que
+    /// salieron. Una vez enviadas se descuentan del stock aunque no llegue el
+    /// ack, ya que el receptor pudo haberlas agregado y perderse solo su ack:
+    /// devolverlas haria que existan en ambos locales. Solo vuelven a estar
+    /// disponibles si el envio no pudo salir
+    fn despachar(
+        &mut self,
+        destino: IdLocal,
+        solicitud: MensajeTransferencia,
+        ctx: &mut Context<Self>,
+    ) {
+        let id = solicitud.id_transferencia;
+        let puerto = id_a_puerto_transferencias(destino);
+        let consulta = self.guardian.send(ObtenerLotesBloqueados::new(id, puerto));
+        ctx.spawn(
+            consulta
+                .into_actor(self)
+                .then(move |lotes, act, _ctx| {
+                    let lotes = match lotes {
+                        Ok(Ok(lotes)) => lotes.into_iter().map(|(_, lote)| lote).collect(),
+                        _ => Vec::new(),
+                    };
+                    act.enviar_con_reintentos(destino, solicitud.como_envio().con_lotes(lotes))
+                })
+                .map(move |ack, act, ctx| {
+                    match ack {
+                        Entrega::Ack(_) => act.guardian.do_send(Confirmar::new(id, puerto)),
+                        Entrega::SinAck => {
+                            eprintln!(
+                                "El local {} no confirmo la llegada de la transferencia {}",
+                                destino, id
+                            );
+                            act.guardian.do_send(Confirmar::new(id, puerto));
+                        }
+                        Entrega::NoEnviada => act.guardian.do_send(Cancelar::new(id, puerto)),
+                    }
+                    act.olvidar_solicitud(destino, id, ctx);
+                }),
+        );
+    }
+
+    /// Agrega al stock las unidades recibidas de otro local, salvo que ya se
+    /// hubieran recibido antes, y confirma la llegada
+    fn recibir_envio(
+        &mut self,
+        origen: IdLocal,
+        envio: MensajeTransferencia,
+        ctx: &mut Context<Self>,
+    ) {
+        let clave = (origen, envio.id_transferencia);
+        if self.envios_recibidos.insert(clave) {
+            ctx.run_later(RETENCION, move |act, _ctx| {
+                act.envios_recibidos.remove(&clave);
+            });
+            println!(
+                "Llegaron {} unidades del producto {} desde el local {}",
+                envio.cantidad, envio.id_producto, origen
+            );
+            if envio.lotes.is_empty() {
+                self.guardian
+                    .do_send(Reponer::new(envio.id_producto, envio.cantidad));
+            }
+            for lote in envio.lotes {
+                self.guardian.do_send(
+                    Reponer::new(envio.id_producto, lote.cantidad)
+                        .con_vencimiento(lote.vencimiento),
+                );
+            }
+        }
+        self.responder(
+            origen,
+            EtapaTransferencia::Envio,
+            envio.id_transferencia,
+            true,
+        );
+    }
+}
+
+/// Devuelve el primer id de transferencia del local, a partir de los segundos
+/// transcurridos desde la epoca. Se trunca al tamano de un id, por lo que solo
+/// se repetiria uno si el local reinicia tras haber pedido mas de una
+/// transferencia por segundo
+fn primer_id_transferencia() -> IdTransferencia {
+    let segundos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|desde_epoca| desde_epoca.as_secs())
+        .unwrap_or(0);
+    segundos as IdTransferencia
+}
+
+/// Arma un carrito con la cantidad dada de un producto, repartida en tantas
+/// lineas como haga falta
+/// # Errors:
+/// Si la cantidad no entra en las lineas de un carrito
+fn carrito_de(
+    id_producto: IdProducto,
+    cantidad: CantidadProducto,
+) -> Result<Carrito, ErrorCarrito> {
+    let maximo = CantidadProducto::from(CantidadPedido::MAX);
+    let mut lineas = Vec::new();
+    let mut restante = cantidad;
+    while restante > 0 {
+        let parte = restante.min(maximo);
+        lineas.push(Pedido::new(id_producto, parte as CantidadPedido));
+        restante -= parte;
+    }
+    Carrito::new(lineas)
+}
+
+impl Actor for Transferencias {
+    type Context = Context<Self>;
+}
+
+/// Mensaje para pedirle a otro local unidades de un producto
+#[derive(Message)]
+#[rtype(result = "ResultadoTransferencia")]
+pub struct SolicitarTransferencia {
+    pub donante: IdLocal,
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+}
+
+impl SolicitarTransferencia {
+    /// Crea una solicitud de unidades de un producto al local donante
+    pub fn new(donante: IdLocal, id_producto: IdProducto, cantidad: CantidadProducto) -> Self {
+        Self {
+            donante,
+            id_producto,
+            cantidad,
+        }
+    }
+}
+
+impl Handler<SolicitarTransferencia> for Transferencias {
+    type Result = ResponseActFuture<Self, ResultadoTransferencia>;
+
+    fn handle(&mut self, msg: SolicitarTransferencia, _ctx: &mut Context<Self>) -> Self::Result {
+        let id = self.siguiente_id;
+        self.siguiente_id = self.siguiente_id.wrapping_add(1);
+        println!(
+            "[Local {}] Pidiendo {} unidades del producto {} al local {}",
+            self.id_local, msg.cantidad, msg.id_producto, msg.donante
+        );
+        let solicitud = MensajeTransferencia::solicitud(id, msg.id_producto, msg.cantidad);
+        Box::pin(self.enviar_con_reintentos(msg.donante, solicitud).map(
+            |ack, _act, _ctx| match ack {
+                Entrega::Ack(true) => ResultadoTransferencia::Aceptada,
+                Entrega::Ack(false) => ResultadoTransferencia::Rechazada,
+                Entrega::SinAck | Entrega::NoEnviada => ResultadoTransferencia::SinRespuesta,
+            },
+        ))
+    }
+}
+
+/// Mensaje que envia el servidor al recibir una etapa de una transferencia
+/// desde otro local
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TransferenciaRecibida {
+    pub mensaje: MensajeTransferencia,
+    pub origen: IdLocal,
+}
+
+impl Handler<TransferenciaRecibida> for Transferencias {
+    type Result = ();
+
+    fn handle(&mut self, msg: TransferenciaRecibida, ctx: &mut Context<Self>) -> Self::Result {
+        match msg.mensaje.etapa {
+            EtapaTransferencia::Solicitud => self.atender_solicitud(msg.origen, msg.mensaje, ctx),
+            EtapaTransferencia::Envio => self.recibir_envio(msg.origen, msg.mensaje, ctx),
+        }
+    }
+}
+
+/// Mensaje que envia el servidor al recibir el ack de una etapa de una
+/// transferencia desde otro local
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AckTransferenciaRecibido {
+    pub ack: AckTransferencia,
+    pub origen: IdLocal,
+}
+
+impl Handler<AckTransferenciaRecibido> for Transferencias {
+    type Result = ();
+
+    fn handle(&mut self, msg: AckTransferenciaRecibido, _ctx: &mut Context<Self>) -> Self::Result {
+        let clave = (msg.origen, msg.ack.id_transferencia, msg.ack.etapa);
+        if let Some(aviso) = self.acks_pendientes.remove(&clave) {
+            let _ = aviso.send(msg.ack.aceptada);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::guardian::{Bloquear, Guardian, ObtenerInventario, ObtenerStock};
+    use crate::local::lotes::{Lote, StockLotes};
+    use crate::mensajes::TipoMensaje;
+    use actix::actors::mocker::Mocker;
+    use std::io;
+    use tokio::sync::mpsc;
+
+    fn crear_guardian() -> Addr<GuardianParticionado> {
+        let stock = HashMap::from([(1, 5)]);
+        GuardianParticionado::new(vec![Guardian::new(stock)]).start()
+    }
+
+    /// Crea un mensajero falso que entrega por un canal los bytes de cada envio
+    fn crear_mensajero() -> (Recipient<Enviar>, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mensajero = Mocker::<Enviar>::mock(Box::new(move |msg, _ctx| {
+            let enviar = msg.downcast_ref::<Enviar>().unwrap();
+            tx.send(enviar.mensaje.clone()).unwrap();
+            Box::new(Some(Ok::<(), crate::errores::ErrorMensajero>(())))
+        }))
+        .start()
+        .recipient();
+        (mensajero, rx)
+    }
+
+    fn leer_ack(bytes: Vec<u8>) -> AckTransferencia {
+        let mut cursor = io::Cursor::new(bytes);
+        assert!(matches!(
+            TipoMensaje::from_bytes(&mut cursor).unwrap(),
+            TipoMensaje::AckTransferencia
+        ));
+        AckTransferencia::from_bytes(&mut cursor).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn donante_reserva_una_sola_vez_ante_solicitudes_repetidas() {
+        let guardian = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero)
+            .con_demora(Duration::from_secs(60))
+            .start();
+
+        let solicitud = MensajeTransferencia::solicitud(3, 1, 2);
+        for _ in 0..2 {
+            transferencias
+                .send(TransferenciaRecibida {
+                    mensaje: solicitud.clone(),
+                    origen: 1,
+                })
+                .await
+                .unwrap();
+            let ack = leer_ack(enviados.recv().await.unwrap());
+            assert_eq!(
+                ack,
+                AckTransferencia::new(EtapaTransferencia::Solicitud, 3, true)
+            );
+        }
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn la_reserva_no_se_confunde_con_una_busqueda_del_mismo_local() {
+        let guardian = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero)
+            .con_demora(Duration::from_secs(60))
+            .start();
+
+        // Una busqueda del local 1 reservo un pedido con el mismo id
+        let busqueda = Bloquear::new(carrito_de(1, 1).unwrap(), 3, crate::id_a_puerto_local(1));
+        guardian.send(busqueda).await.unwrap().unwrap();
+
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: MensajeTransferencia::solicitud(3, 1, 2),
+                origen: 1,
+            })
+            .await
+            .unwrap();
+        let ack = leer_ack(enviados.recv().await.unwrap());
+        assert!(ack.aceptada);
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn donante_rechaza_si_no_tiene_stock_suficiente() {
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, crear_guardian(), mensajero).start();
+
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: MensajeTransferencia::solicitud(1, 1, 6),
+                origen: 1,
+            })
+            .await
+            .unwrap();
+        let ack = leer_ack(enviados.recv().await.unwrap());
+        assert!(!ack.aceptada);
+    }
+
+    #[actix_rt::test]
+    async fn una_solicitud_rechazada_se_olvida_pasado_el_plazo_de_reenvios() {
+        let guardian = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero)
+            .con_demora(Duration::from_secs(60))
+            .start();
+
+        let solicitud = MensajeTransferencia::solicitud(2, 1, 6);
+        let recibida = || TransferenciaRecibida {
+            mensaje: solicitud.clone(),
+            origen: 1,
+        };
+        transferencias.send(recibida()).await.unwrap();
+        assert!(!leer_ack(enviados.recv().await.unwrap()).aceptada);
+
+        guardian.send(Reponer::new(1, 1)).await.unwrap().unwrap();
+        transferencias.send(recibida()).await.unwrap();
+        assert!(!leer_ack(enviados.recv().await.unwrap()).aceptada);
+
+        // Pasado el plazo, la misma solicitud se atiende de nuevo
+        tokio::time::sleep(RETENCION + Duration::from_millis(100)).await;
+        transferencias.send(recibida()).await.unwrap();
+        assert!(leer_ack(enviados.recv().await.unwrap()).aceptada);
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn un_envio_sin_ack_no_devuelve_las_unidades_al_donante() {
+        let guardian = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero)
+            .con_demora(Duration::from_millis(10))
+            .start();
+
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: MensajeTransferencia::solicitud(3, 1, 2),
+                origen: 1,
+            })
+            .await
+            .unwrap();
+        assert!(leer_ack(enviados.recv().await.unwrap()).aceptada);
+
+        // El receptor nunca responde el envio, pero pudo haberlo recibido
+        tokio::time::sleep(TIMEOUT_ACK * MAX_INTENTOS + Duration::from_millis(200)).await;
+        let puerto = id_a_puerto_transferencias(1);
+        assert!(matches!(
+            guardian.send(Cancelar::new(3, puerto)).await.unwrap(),
+            Err(crate::errores::ErrorGuardian::PedidoInexistente)
+        ));
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn un_envio_que_no_pudo_salir_devuelve_las_unidades_al_donante() {
+        let guardian = crear_guardian();
+        let mensajero = Mocker::<Enviar>::mock(Box::new(|_msg, _ctx| {
+            Box::new(Some(Err::<(), _>(
+                crate::errores::ErrorMensajero::InternetCaido,
+            )))
+        }))
+        .start()
+        .recipient();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero)
+            .con_demora(Duration::from_millis(10))
+            .start();
+
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: MensajeTransferencia::solicitud(3, 1, 2),
+                origen: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 3);
+        tokio::time::sleep(TIMEOUT_ACK * MAX_INTENTOS + Duration::from_millis(200)).await;
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn receptor_agrega_el_envio_una_sola_vez() {
+        let guardian = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, guardian.clone(), mensajero).start();
+
+        let envio = MensajeTransferencia::solicitud(4, 1, 300).como_envio();
+        for _ in 0..2 {
+            transferencias
+                .send(TransferenciaRecibida {
+                    mensaje: envio.clone(),
+                    origen: 2,
+                })
+                .await
+                .unwrap();
+            let ack = leer_ack(enviados.recv().await.unwrap());
+            assert_eq!(ack.etapa, EtapaTransferencia::Envio);
+        }
+        assert_eq!(guardian.send(ObtenerStock { id: 1 }).await.unwrap(), 305);
+    }
+
+    #[actix_rt::test]
+    async fn las_unidades_transferidas_conservan_su_vencimiento() {
+        let vencimiento = chrono::Local::now().date_naive() + chrono::Days::new(30);
+        let mut stock = StockLotes::default();
+        stock.agregar(1, Lote::con_vencimiento(2, vencimiento));
+        stock.agregar(1, Lote::sin_vencimiento(3));
+        let donante = GuardianParticionado::new(vec![Guardian::new(stock)]).start();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, donante, mensajero)
+            .con_demora(Duration::ZERO)
+            .start();
+
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: MensajeTransferencia::solicitud(5, 1, 4),
+                origen: 1,
+            })
+            .await
+            .unwrap();
+        assert!(leer_ack(enviados.recv().await.unwrap()).aceptada);
+        let mut cursor = io::Cursor::new(enviados.recv().await.unwrap());
+        assert!(matches!(
+            TipoMensaje::from_bytes(&mut cursor).unwrap(),
+            TipoMensaje::Transferencia
+        ));
+        let envio = MensajeTransferencia::from_bytes(&mut cursor).unwrap();
+        let lotes = vec![
+            Lote::con_vencimiento(2, vencimiento),
+            Lote::sin_vencimiento(2),
+        ];
+        assert_eq!(envio.lotes, lotes);
+
+        let receptor = crear_guardian();
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(1, receptor.clone(), mensajero).start();
+        transferencias
+            .send(TransferenciaRecibida {
+                mensaje: envio,
+                origen: 0,
+            })
+            .await
+            .unwrap();
+        enviados.recv().await.unwrap();
+        let inventario = receptor.send(ObtenerInventario).await.unwrap();
+        assert_eq!(
+            inventario.productos[&1].lotes,
+            vec![
+                Lote::con_vencimiento(2, vencimiento),
+                Lote::sin_vencimiento(7)
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn solicitud_sin_ack_se_reintenta_y_queda_sin_respuesta() {
+        let (mensajero, mut enviados) = crear_mensajero();
+        let transferencias = Transferencias::new(0, crear_guardian(), mensajero).start();
+
+        let resultado = transferencias
+            .send(SolicitarTransferencia::new(1, 1, 2))
+            .await
+            .unwrap();
+        assert_eq!(resultado, ResultadoTransferencia::SinRespuesta);
+        for _ in 0..MAX_INTENTOS {
+            assert!(enviados.try_recv().is_ok());
+        }
+    }
+
+    #[test]
+    fn carrito_de_reparte_cantidades_grandes_en_varias_lineas() {
+        let carrito = carrito_de(1, 600).unwrap();
+        assert_eq!(carrito.get_lineas().len(), 3);
+        assert_eq!(carrito.cantidades_por_producto().get(&1), Some(&600));
+    }
+
+    #[test]
+    fn carrito_de_rechaza_cantidades_que_no_entran_en_un_carrito() {
+        assert_eq!(
+            carrito_de(1, CantidadProducto::MAX),
+            Err(ErrorCarrito::DemasiadasLineas)
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Este modulo define la vista de la membresia del cluster: que locales estan
+//! activos en este momento. Los locales se enteran de los demas por rumores
+//! que se envian periodicamente entre si, en los que cada uno incrementa su
+//! propia version para mostrar que sigue vivo. Un local del que no llegan
+//! versiones nuevas por un tiempo se considera caido, y uno que se va lo
+//! anuncia con una ultima version inactiva. Los ecommerce no forman parte del
+//! cluster, pero le piden la vista a los locales para saber a quien enviar.
+//! Para unirse al cluster solo hace falta conocer a alguna semilla, que se
+//! leen de "configs/membresia.json".
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::aliases::IdLocal;
+use crate::errores::ErrorDuranteParseo;
+use crate::mensajes::{EntradaMembresia, MensajeMembresia, MAX_ENTRADAS_MEMBRESIA};
+
+/// Archivo del que se leen las semillas, compartido por locales y ecommerces
+pub const ARCHIVO_MEMBRESIA: &str = "configs/membresia.json";
+
+/// Cada cuanto un miembro difunde su vista de la membresia
+pub const INTERVALO_RUMORES: Duration = Duration::from_millis(500);
+
+/// Tiempo sin recibir una version nueva de un local tras el cual se lo
+/// considera caido
+pub const TIEMPO_SIN_NOVEDADES: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy)]
+struct Conocido {
+    version: u32,
+    activo: bool,
+    ultima_novedad: Instant,
+}
+
+/// Vista de la membresia del cluster. Si pertenece a un local, este figura
+/// siempre en ella, y es el unico que incrementa su propia version. Como un
+/// rumor no admite mas de `MAX_ENTRADAS_MEMBRESIA` entradas, recuerda desde
+/// que local empezo el ultimo, para que el siguiente continue con los demas
+#[derive(Debug)]
+pub struct Membresia {
+    propio: Option<IdLocal>,
+    conocidos: Mutex<BTreeMap<IdLocal, Conocido>>,
+    rotacion: AtomicUsize,
+}
+
+impl Membresia {
+    /// Crea una vista vacia, para quien no es miembro del cluster
+    pub fn new() -> Self {
+        Self {
+            propio: None,
+            conocidos: Mutex::new(BTreeMap::new()),
+            rotacion: AtomicUsize::new(0),
+        }
+    }
+
+    /// Crea la vista del local dado, en la que inicialmente solo esta el
+    pub fn de_local(id: IdLocal) -> Self {
+        let propio = Conocido {
+            version: 1,
+            activo: true,
+            ultima_novedad: Instant::now(),
+        };
+        Self {
+            propio: Some(id),
+            conocidos: Mutex::new(BTreeMap::from([(id, propio)])),
+            rotacion: AtomicUsize::new(0),
+        }
+    }
+
+    /// Devuelve el local al que pertenece la vista, si es de un local
+    pub fn propio(&self) -> Option<IdLocal> {
+        self.propio
+    }
+
+    fn conocidos(&self) -> MutexGuard<'_, BTreeMap<IdLocal, Conocido>> {
+        // La vista siempre queda consistente, aunque otro hilo haya fallado
+        self.conocidos.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Incorpora las entradas de un rumor que sean mas nuevas que las conocidas.
+    /// Si el rumor trae una version del propio local igual o mayor a la actual,
+    /// por ejemplo de antes de que se reinicie, la supera para desmentirlo.
+    /// Devuelve si el rumor trae alguna entrada mas vieja que la conocida, en
+    /// cuyo caso conviene responderle a quien lo envio con la vista propia: un
+    /// local que se reinicia vuelve a empezar desde la primera version, y sin
+    /// esa respuesta no se enteraria de que tiene que superar la anterior
+    pub fn fusionar(&self, rumor: &MensajeMembresia) -> bool {
+        let mut conocidos = self.conocidos();
+        let ahora = Instant::now();
+        let mut desactualizado = false;
+        for entrada in &rumor.entradas {
+            if Some(entrada.id) == self.propio {
+                if let Some(propio) = conocidos.get_mut(&entrada.id) {
+                    if entrada.version >= propio.version {
+                        propio.version = entrada.version + 1;
+                    } else {
+                        desactualizado = true;
+                    }
+                }
+                continue;
+            }
+            let anterior = conocidos.get(&entrada.id).copied();
+            if let Some(conocido) = anterior.filter(|c| c.version >= entrada.version) {
+                desactualizado |= conocido.version > entrada.version;
+                continue;
+            }
+            let estaba_activo = anterior.is_some_and(|conocido| {
+                conocido.activo && ahora - conocido.ultima_novedad < TIEMPO_SIN_NOVEDADES
+            });
+            if entrada.activo && !estaba_activo {
+                println!("El local {} se unio al cluster", entrada.id);
+            } else if !entrada.activo && estaba_activo {
+                println!("El local {} dejo el cluster", entrada.id);
+            }
+            conocidos.insert(
+                entrada.id,
+                Conocido {
+                    version: entrada.version,
+                    activo: entrada.activo,
+                    ultima_novedad: ahora,
+                },
+            );
+        }
+        desactualizado
+    }
+
+    /// Incrementa la version del propio local, para mostrar que sigue vivo, y
+    /// devuelve el rumor a difundir
+    pub fn latir(&self) -> MensajeMembresia {
+        self.actualizar_propio(true);
+        self.rumor()
+    }
+
+    /// Marca al propio local como inactivo, y devuelve el rumor con el que
+    /// anuncia que se va
+    pub fn salir(&self) -> MensajeMembresia {
+        self.actualizar_propio(false);
+        self.rumor()
+    }
+
+    fn actualizar_propio(&self, activo: bool) {
+        let Some(id) = self.propio else {
+            return;
+        };
+        if let Some(propio) = self.conocidos().get_mut(&id) {
+            propio.version += 1;
+            propio.activo = activo;
+            propio.ultima_novedad = Instant::now();
+        }
+    }
+
+    /// Devuelve la vista como un rumor. La entrada del propio local va
+    /// primero, para que no se descarte si la vista no entra en un mensaje.
+    /// Si no entra, cada rumor sigue con los locales que quedaron afuera del
+    /// anterior, de forma que todas las entradas terminan difundiendose
+    pub fn rumor(&self) -> MensajeMembresia {
+        let conocidos = self.conocidos();
+        let entrada = |(id, conocido): (&IdLocal, &Conocido)| {
+            EntradaMembresia::new(*id, conocido.version, conocido.activo)
+        };
+        let mut entradas: Vec<EntradaMembresia> = conocidos
+            .iter()
+            .filter(|(id, _)| Some(**id) == self.propio)
+            .map(entrada)
+            .collect();
+        let mut otros: Vec<EntradaMembresia> = conocidos
+            .iter()
+            .filter(|(id, _)| Some(**id) != self.propio)
+            .map(entrada)
+            .collect();
+
+        let lugares = MAX_ENTRADAS_MEMBRESIA - entradas.len();
+        if otros.len() > lugares {
+            let inicio = self.rotacion.fetch_add(lugares, Ordering::Relaxed) % otros.len();
+            otros.rotate_left(inicio);
+        }
+        entradas.extend(otros);
+        MensajeMembresia::new(entradas)
+    }
+
+    /// Devuelve los locales que no anunciaron su salida, aunque no se sepa de
+    /// ellos hace un tiempo, ordenados por id
+    pub fn miembros(&self) -> Vec<IdLocal> {
+        self.conocidos()
+            .iter()
+            .filter(|(_, conocido)| conocido.activo)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Devuelve los locales activos, ordenados por id
+    pub fn vivos(&self) -> Vec<IdLocal> {
+        let ahora = Instant::now();
+        self.conocidos()
+            .iter()
+            .filter(|(id, conocido)| {
+                conocido.activo
+                    && (Some(**id) == self.propio
+                        || ahora - conocido.ultima_novedad < TIEMPO_SIN_NOVEDADES)
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+impl Default for Membresia {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Deserialize)]
+struct ArchivoMembresia {
+    semillas: Vec<IdLocal>,
+}
+
+/// A partir de un reader en formato json, obtiene las semillas del cluster
+pub fn semillas_from_reader(reader: &mut dyn Read) -> Result<Vec<IdLocal>, ErrorDuranteParseo> {
+    let archivo: ArchivoMembresia = serde_json::from_reader(reader)?;
+    if archivo.semillas.is_empty() {
+        eprintln!("Debe haber al menos una semilla");
+        return Err(ErrorDuranteParseo::MembresiaInvalida);
+    }
+    Ok(archivo.semillas)
+}
+
+/// Obtiene los locales a los que se contacta para unirse al cluster o para
+/// conocer su membresia. Como el archivo es opcional, si no existe la unica
+/// semilla es el local 0
+pub fn obtener_semillas() -> Result<Vec<IdLocal>, ErrorDuranteParseo> {
+    let mut membresia_json = match File::open(ARCHIVO_MEMBRESIA) {
+        Ok(archivo) => archivo,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![0]),
+        Err(e) => return Err(e.into()),
+    };
+    semillas_from_reader(&mut membresia_json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn los_rumores_mas_nuevos_reemplazan_a_los_conocidos() {
+        let membresia = Membresia::de_local(1);
+        membresia.fusionar(&MensajeMembresia::new(vec![
+            EntradaMembresia::new(0, 5, true),
+            EntradaMembresia::new(3, 2, true),
+        ]));
+        assert_eq!(membresia.vivos(), vec![0, 1, 3]);
+
+        membresia.fusionar(&MensajeMembresia::new(vec![
+            EntradaMembresia::new(0, 4, false),
+            EntradaMembresia::new(3, 3, false),
+        ]));
+        assert_eq!(membresia.vivos(), vec![0, 1]);
+        assert_eq!(membresia.miembros(), vec![0, 1]);
+    }
+
+    #[test]
+    fn los_rumores_se_turnan_las_entradas_que_no_entran_en_un_mensaje() {
+        let membresia = Membresia::de_local(5);
+        for ids in [0..10, 10..20, 20..30] {
+            membresia.fusionar(&MensajeMembresia::new(
+                ids.filter(|id| *id != 5)
+                    .map(|id| EntradaMembresia::new(id, 1, true))
+                    .collect(),
+            ));
+        }
+
+        let mut difundidos = std::collections::BTreeSet::new();
+        for _ in 0..3 {
+            let rumor = membresia.rumor();
+            assert_eq!(rumor.entradas.len(), MAX_ENTRADAS_MEMBRESIA);
+            assert_eq!(rumor.entradas[0].id, 5);
+            difundidos.extend(rumor.entradas.iter().map(|entrada| entrada.id));
+        }
+        assert_eq!(difundidos, (0..30).collect());
+    }
+
+    #[test]
+    fn un_local_desmiente_los_rumores_viejos_sobre_si_mismo() {
+        let membresia = Membresia::de_local(2);
+        membresia.fusionar(&MensajeMembresia::new(vec![EntradaMembresia::new(
+            2, 9, false,
+        )]));
+        assert_eq!(membresia.vivos(), vec![2]);
+        assert_eq!(
+            membresia.latir().entradas[0],
+            EntradaMembresia::new(2, 11, true)
+        );
+        assert_eq!(
+            membresia.salir().entradas[0],
+            EntradaMembresia::new(2, 12, false)
+        );
+        assert!(membresia.vivos().is_empty());
+    }
+
+    #[test]
+    fn un_local_que_se_fue_vuelve_a_unirse_tras_reiniciarse() {
+        let semilla = Membresia::de_local(0);
+        let local = Membresia::de_local(1);
+        for _ in 0..3 {
+            assert!(!semilla.fusionar(&local.latir()));
+        }
+        assert!(!semilla.fusionar(&local.salir()));
+        assert_eq!(semilla.vivos(), vec![0]);
+
+        // Al reiniciarse vuelve a empezar desde la primera version
+        let reiniciado = Membresia::de_local(1);
+        assert!(semilla.fusionar(&reiniciado.latir()));
+        assert_eq!(semilla.vivos(), vec![0]);
+
+        // Con la respuesta de la semilla supera la version con la que se fue
+        assert!(!reiniciado.fusionar(&semilla.rumor()));
+        assert!(!semilla.fusionar(&reiniciado.latir()));
+        assert_eq!(semilla.vivos(), vec![0, 1]);
+    }
+
+    #[test]
+    fn las_semillas_se_leen_del_archivo() {
+        let json = r#"{"semillas": [2, 0]}"#;
+        assert_eq!(
+            semillas_from_reader(&mut json.as_bytes()).unwrap(),
+            vec![2, 0]
+        );
+        let vacio = r#"{"semillas": []}"#;
+        assert!(semillas_from_reader(&mut vacio.as_bytes()).is_err());
+    }
+}
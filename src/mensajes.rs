@@ -1,13 +1,15 @@
 //! Este modulo define la estructura de los mensajes con los que se comunicaran
 //! los locales entre si, y los ecommerce con los locales
 
+use chrono::{Datelike, NaiveDate};
 use colored::*;
 use std::collections::HashSet;
 use std::fmt;
 use std::io::{self, Read};
 
-use super::aliases::{IdLocal, IdPedido, Puerto};
-use crate::pedido::Pedido;
+use super::aliases::{CantidadProducto, IdLocal, IdPedido, IdProducto, IdTransferencia, Puerto};
+use crate::local::lotes::Lote;
+use crate::pedido::{Carrito, Modificacion, MAX_LINEAS_CARRITO};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
@@ -21,6 +23,22 @@ pub enum TipoMensaje {
     AckEcommerce,
     Matar,
     Revivir,
+    Transferencia,
+    AckTransferencia,
+    ConsultaStock,
+    RespuestaStock,
+    Membresia,
+    ConsultaMembresia,
+    Eleccion,
+    RespuestaEleccion,
+    Coordinador,
+    ConsultaLider,
+    ConsultaEstado,
+    RespuestaEstado,
+    ModificacionPedido,
+    RespuestaModificacion,
+    Drenar,
+    Latido,
 }
 
 impl TipoMensaje {
@@ -41,11 +59,12 @@ impl TipoMensaje {
 
 /// Mensajes que envia el local al ecommerce para avisarle
 /// cual fue el output de su pedido
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MensajesServidor {
     PedidoExitoso(IdPedido),
     PedidoCancelado(IdPedido),
     NoHayStock(IdPedido),
+    EnEspera(IdPedido),
 }
 
 impl MensajesServidor {
@@ -68,6 +87,10 @@ impl MensajesServidor {
                 buf.push(2_u8);
                 buf.extend(id_pedido.to_be_bytes())
             }
+            Self::EnEspera(id_pedido) => {
+                buf.push(3_u8);
+                buf.extend(id_pedido.to_be_bytes())
+            }
         };
         buf
     }
@@ -75,7 +98,7 @@ impl MensajesServidor {
     /// Convierte bytes leidos en un mensaje del tipo MensajeServidor
     /// # Errors:
     /// * si el buffer de lectura pasado tiene menos bytes que los
-    /// necesarios para completar el mensaje
+    ///   necesarios para completar el mensaje
     pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
         let mut msg_type: [u8; 1] = [0; 1];
         buf.read_exact(&mut msg_type)?;
@@ -86,6 +109,7 @@ impl MensajesServidor {
             0 => Ok(Self::PedidoExitoso(id_pedido)),
             1 => Ok(Self::PedidoCancelado(id_pedido)),
             2 => Ok(Self::NoHayStock(id_pedido)),
+            3 => Ok(Self::EnEspera(id_pedido)),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 String::from("Pedido Invalido"),
@@ -96,12 +120,15 @@ impl MensajesServidor {
 
 /// Mensaje inicial que envía el ecommerce a algun local para
 /// realizar un pedido. el id del pedido es el id interno que le asigna
-/// el ecommerce al pedido para reconocerlo, mientras que el id que se
-/// encuentra dentro del pedido es el identificador del producto
+/// el ecommerce al pedido para reconocerlo, mientras que el pedido es
+/// un carrito con una o mas lineas, cada una con su identificador de producto.
+/// Si el ecommerce indica que quiere esperar, cuando ningun local tiene stock
+/// el pedido queda en espera en vez de rechazarse.
 #[derive(Debug, Clone, PartialEq)]
 pub struct MensajeEcommerce {
     pub id_pedido: IdPedido,
-    pub pedido: Pedido,
+    pub esperar: bool,
+    pub pedido: Carrito,
 }
 
 impl fmt::Display for MensajeEcommerce {
@@ -117,9 +144,19 @@ impl fmt::Display for MensajeEcommerce {
 
 impl MensajeEcommerce {
     /// Crea un nuevo pedido a partir de un identificador de pedido
-    /// y un pedido
-    pub fn new(id_pedido: IdPedido, pedido: Pedido) -> Self {
-        Self { id_pedido, pedido }
+    /// y un carrito
+    pub fn new(id_pedido: IdPedido, pedido: Carrito) -> Self {
+        Self {
+            id_pedido,
+            esperar: false,
+            pedido,
+        }
+    }
+
+    /// Indica si el pedido debe quedar en espera cuando ningun local tiene stock
+    pub fn con_espera(mut self, esperar: bool) -> Self {
+        self.esperar = esperar;
+        self
     }
 
     pub fn get_id(&self) -> IdPedido {
@@ -129,13 +166,15 @@ impl MensajeEcommerce {
     /// Convierte bytes leidos en un mensaje del tipo MensajeEcommerce
     /// # Errors:
     /// * si el buffer de lectura pasado tiene menos bytes que los
-    /// necesarios para completar el mensaje
+    ///   necesarios para completar el mensaje
     pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
         let mut id_buf: [u8; 2] = [0; 2];
         buf.read_exact(&mut id_buf)?;
         let id_pedido = <u16>::from_be_bytes(id_buf);
-        let pedido = Pedido::from_bytes(buf)?;
-        Ok(Self::new(id_pedido, pedido))
+        let mut esperar: [u8; 1] = [0; 1];
+        buf.read_exact(&mut esperar)?;
+        let pedido = Carrito::from_bytes(buf)?;
+        Ok(Self::new(id_pedido, pedido).con_espera(esperar[0] != 0))
     }
 
     /// Convierte un MensajeEcommerce en un array de bytes, incluyendo su
@@ -145,6 +184,7 @@ impl MensajeEcommerce {
         buf_message.push(TipoMensaje::MensajeEcommerce as u8);
 
         buf_message.extend_from_slice(&(self.id_pedido).to_be_bytes());
+        buf_message.push(u8::from(self.esperar));
         buf_message.extend(self.pedido.as_bytes());
         buf_message
     }
@@ -167,7 +207,7 @@ impl AckEcommerce {
     /// Convierte bytes leidos en un mensaje del tipo AckEcommerce
     /// # Errors:
     /// * si el buffer de lectura pasado tiene menos bytes que los
-    /// necesarios para completar el mensaje
+    ///   necesarios para completar el mensaje
     pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
         let mut id_buf: [u8; 2] = [0; 2];
         buf.read_exact(&mut id_buf)?;
@@ -220,8 +260,8 @@ impl MensajeDelegado {
         }
     }
 
-    /// Devuelve el pedido al que hace referencia el mensaje
-    pub fn get_pedido(&self) -> Pedido {
+    /// Devuelve el carrito al que hace referencia el mensaje
+    pub fn get_pedido(&self) -> Carrito {
         self.mensaje_ecommerce.pedido.clone()
     }
 
@@ -230,10 +270,15 @@ impl MensajeDelegado {
         self.mensaje_ecommerce.id_pedido
     }
 
+    /// Devuelve si el ecommerce pidio esperar cuando no hay stock en ningun local
+    pub fn quiere_esperar(&self) -> bool {
+        self.mensaje_ecommerce.esperar
+    }
+
     /// Convierte bytes leidos en un mensaje del tipo MensajeDelegado
     /// # Errors:
     /// * si el buffer de lectura pasado tiene menos bytes que los
-    /// necesarios para completar el mensaje
+    ///   necesarios para completar el mensaje
     pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
         let mensaje_ecommerce = MensajeEcommerce::from_bytes(buf)?;
         let mut puerto_buf: [u8; 2] = [0; 2];
@@ -263,11 +308,8 @@ impl MensajeDelegado {
         buf.push(TipoMensaje::MensajeDelegado as u8);
         buf.extend(&self.mensaje_ecommerce.as_bytes()[1..]);
         buf.extend(self.puerto_ecommerce.to_be_bytes());
-        buf.extend(
-            u16::try_from(self.locales_ack.len())
-                .unwrap_or(0)
-                .to_be_bytes(),
-        );
+        // Quien delega no agrega mas de `MAX_LOCALES_ACK` locales
+        buf.extend((self.locales_ack.len() as u16).to_be_bytes());
         self.locales_ack
             .iter()
             .for_each(|id| buf.extend(id.to_be_bytes()));
@@ -275,6 +317,11 @@ impl MensajeDelegado {
     }
 }
 
+/// Cantidad maxima de locales que puede recorrer un pedido delegado: son los
+/// que entran en `locales_ack` junto con el carrito mas largo en un mensaje
+pub const MAX_LOCALES_ACK: usize =
+    (crate::MAX_MENSAJE as usize - 9 - 3 * MAX_LINEAS_CARRITO as usize) / 2;
+
 /// Mensaje que envia un local a otro local para avisarle que
 /// recibio correctamente la delegacion de un pedido
 #[derive(Debug)]
@@ -293,7 +340,7 @@ impl AckDelegado {
     /// Convierte bytes leidos en un mensaje del tipo AckDelegado
     /// # Errors:
     /// * si el buffer de lectura pasado tiene menos bytes que los
-    /// necesarios para completar el mensaje
+    ///   necesarios para completar el mensaje
     pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
         let mut id_buf: [u8; 2] = [0; 2];
         buf.read_exact(&mut id_buf)?;
@@ -315,15 +362,707 @@ impl AckDelegado {
     }
 }
 
+/// Etapas de una transferencia de stock entre locales: el receptor solicita
+/// las unidades, y el donante se las envia una vez que llegan a destino
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum EtapaTransferencia {
+    Solicitud = 0,
+    Envio,
+}
+
+impl EtapaTransferencia {
+    /// Convierte un byte leido en una etapa de transferencia
+    fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut etapa: [u8; 1] = [0; 1];
+        buf.read_exact(&mut etapa)?;
+        EtapaTransferencia::from_u8(etapa[0]).ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No existe la etapa de transferencia {}", etapa[0]),
+        ))
+    }
+}
+
+/// Mensaje que intercambian dos locales para mover stock de uno al otro.
+/// En la etapa de solicitud lo envia el local que necesita las unidades,
+/// y en la de envio el local que las dona. El id de la transferencia lo
+/// asigna el local que la solicita. El envio indica ademas de que lotes
+/// salieron las unidades, para que el receptor conserve sus vencimientos;
+/// si no indica ninguno, las unidades no vencen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MensajeTransferencia {
+    pub etapa: EtapaTransferencia,
+    pub id_transferencia: IdTransferencia,
+    pub id_producto: IdProducto,
+    pub cantidad: CantidadProducto,
+    pub lotes: Vec<Lote>,
+}
+
+impl MensajeTransferencia {
+    /// Crea una solicitud de transferencia de unidades de un producto
+    pub fn solicitud(
+        id_transferencia: IdTransferencia,
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+    ) -> Self {
+        Self {
+            etapa: EtapaTransferencia::Solicitud,
+            id_transferencia,
+            id_producto,
+            cantidad,
+            lotes: Vec::new(),
+        }
+    }
+
+    /// Convierte una solicitud en el envio de las unidades solicitadas
+    pub fn como_envio(mut self) -> Self {
+        self.etapa = EtapaTransferencia::Envio;
+        self
+    }
+
+    /// Indica los lotes de los que salieron las unidades enviadas. Los lotes
+    /// con el mismo vencimiento se unen en uno. Si aun asi no entran en un
+    /// mensaje, las unidades de los lotes que vencen mas tarde se suman al
+    /// ultimo lote que entra, que vence antes que ellos: el receptor las dara
+    /// por vencidas antes de tiempo, pero nunca vendera unidades vencidas
+    pub fn con_lotes(mut self, mut lotes: Vec<Lote>) -> Self {
+        lotes.sort_by_key(|lote| (lote.vencimiento.is_none(), lote.vencimiento));
+        let mut unidos: Vec<Lote> = Vec::with_capacity(lotes.len());
+        for lote in lotes {
+            let completo = unidos.len() == MAX_LOTES_TRANSFERENCIA;
+            match unidos.last_mut() {
+                Some(ultimo) if completo || ultimo.vencimiento == lote.vencimiento => {
+                    ultimo.cantidad += lote.cantidad;
+                }
+                _ => unidos.push(lote),
+            }
+        }
+        self.lotes = unidos;
+        self
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo MensajeTransferencia
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let etapa = EtapaTransferencia::from_bytes(buf)?;
+        let mut campos: [u8; 8] = [0; 8];
+        buf.read_exact(&mut campos)?;
+        let cantidad_lotes = <u16>::from_be_bytes([campos[6], campos[7]]);
+        let lotes = (0..cantidad_lotes)
+            .map(|_| lote_from_bytes(buf))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            etapa,
+            id_transferencia: <u16>::from_be_bytes([campos[0], campos[1]]),
+            id_producto: <u16>::from_be_bytes([campos[2], campos[3]]),
+            cantidad: <u16>::from_be_bytes([campos[4], campos[5]]),
+            lotes,
+        })
+    }
+
+    /// Convierte un MensajeTransferencia en un array de bytes para poder enviarlo
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::Transferencia as u8, self.etapa as u8];
+        buf.extend_from_slice(&self.id_transferencia.to_be_bytes());
+        buf.extend_from_slice(&self.id_producto.to_be_bytes());
+        buf.extend_from_slice(&self.cantidad.to_be_bytes());
+        // `con_lotes` garantiza que los lotes entran en el mensaje
+        buf.extend_from_slice(&(self.lotes.len() as u16).to_be_bytes());
+        for lote in &self.lotes {
+            let dias = lote.vencimiento.map_or(0, |fecha| fecha.num_days_from_ce());
+            buf.extend_from_slice(&dias.to_be_bytes());
+            buf.extend_from_slice(&lote.cantidad.to_be_bytes());
+        }
+        buf
+    }
+}
+
+/// Cantidad maxima de lotes que entran en un mensaje de transferencia
+pub const MAX_LOTES_TRANSFERENCIA: usize = (crate::MAX_MENSAJE as usize - 10) / 6;
+
+/// Lee un lote de un envio de transferencia: su vencimiento, como dias desde
+/// el comienzo de la era o 0 si no vence, y su cantidad
+fn lote_from_bytes(buf: &mut dyn Read) -> io::Result<Lote> {
+    let mut campos: [u8; 6] = [0; 6];
+    buf.read_exact(&mut campos)?;
+    let dias = <i32>::from_be_bytes([campos[0], campos[1], campos[2], campos[3]]);
+    let cantidad = <u16>::from_be_bytes([campos[4], campos[5]]);
+    if dias == 0 {
+        return Ok(Lote::sin_vencimiento(cantidad));
+    }
+    NaiveDate::from_num_days_from_ce_opt(dias)
+        .map(|vencimiento| Lote::con_vencimiento(cantidad, vencimiento))
+        .ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No existe la fecha de vencimiento {}", dias),
+        ))
+}
+
+/// Mensaje que envia un local para avisar que recibio una etapa de una
+/// transferencia. Al responder una solicitud indica ademas si el donante
+/// pudo reservar las unidades
+#[derive(Debug, PartialEq)]
+pub struct AckTransferencia {
+    pub etapa: EtapaTransferencia,
+    pub id_transferencia: IdTransferencia,
+    pub aceptada: bool,
+}
+
+impl AckTransferencia {
+    /// Crea un ack para la etapa dada de una transferencia
+    pub fn new(
+        etapa: EtapaTransferencia,
+        id_transferencia: IdTransferencia,
+        aceptada: bool,
+    ) -> Self {
+        Self {
+            etapa,
+            id_transferencia,
+            aceptada,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo AckTransferencia
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let etapa = EtapaTransferencia::from_bytes(buf)?;
+        let mut id_buf: [u8; 2] = [0; 2];
+        buf.read_exact(&mut id_buf)?;
+        let mut aceptada: [u8; 1] = [0; 1];
+        buf.read_exact(&mut aceptada)?;
+        Ok(Self::new(
+            etapa,
+            <u16>::from_be_bytes(id_buf),
+            aceptada[0] != 0,
+        ))
+    }
+
+    /// Convierte un AckTransferencia en un array de bytes para poder enviarlo
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::AckTransferencia as u8, self.etapa as u8];
+        buf.extend_from_slice(&self.id_transferencia.to_be_bytes());
+        buf.push(u8::from(self.aceptada));
+        buf
+    }
+}
+
+/// Mensaje con el que un local le pregunta a otro si puede cubrir un pedido
+/// de ecommerce, antes de decidir a quien delegarselo. El pedido se identifica
+/// como en la delegacion, por su id y el puerto del ecommerce
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsultaStock {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+    pub pedido: Carrito,
+}
+
+impl ConsultaStock {
+    /// Crea una consulta por el carrito del pedido dado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto, pedido: Carrito) -> Self {
+        Self {
+            id_pedido,
+            puerto,
+            pedido,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo ConsultaStock
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 4] = [0; 4];
+        buf.read_exact(&mut campos)?;
+        let pedido = Carrito::from_bytes(buf)?;
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            <u16>::from_be_bytes([campos[2], campos[3]]),
+            pedido,
+        ))
+    }
+
+    /// Convierte una ConsultaStock en un array de bytes para poder enviarla
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::ConsultaStock as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.extend_from_slice(&self.puerto.to_be_bytes());
+        buf.extend(self.pedido.as_bytes());
+        buf
+    }
+}
+
+/// Respuesta a una consulta de stock. Indica si el local puede cubrir el
+/// pedido completo, y la menor cantidad disponible entre sus productos
+#[derive(Debug, Clone, PartialEq)]
+pub struct RespuestaStock {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+    pub puede_cubrir: bool,
+    pub unidades: CantidadProducto,
+}
+
+impl RespuestaStock {
+    /// Crea la respuesta a la consulta por el pedido dado
+    pub fn new(
+        id_pedido: IdPedido,
+        puerto: Puerto,
+        puede_cubrir: bool,
+        unidades: CantidadProducto,
+    ) -> Self {
+        Self {
+            id_pedido,
+            puerto,
+            puede_cubrir,
+            unidades,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo RespuestaStock
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 7] = [0; 7];
+        buf.read_exact(&mut campos)?;
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            <u16>::from_be_bytes([campos[2], campos[3]]),
+            campos[4] != 0,
+            <u16>::from_be_bytes([campos[5], campos[6]]),
+        ))
+    }
+
+    /// Convierte una RespuestaStock en un array de bytes para poder enviarla
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::RespuestaStock as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.extend_from_slice(&self.puerto.to_be_bytes());
+        buf.push(u8::from(self.puede_cubrir));
+        buf.extend_from_slice(&self.unidades.to_be_bytes());
+        buf
+    }
+}
+
+/// Lo que un proceso sabe de un local del cluster. La version la incrementa
+/// solo el propio local, por lo que ante dos entradas del mismo local vale la
+/// de mayor version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntradaMembresia {
+    pub id: IdLocal,
+    pub version: u32,
+    pub activo: bool,
+}
+
+impl EntradaMembresia {
+    /// Crea la entrada de un local
+    pub fn new(id: IdLocal, version: u32, activo: bool) -> Self {
+        Self {
+            id,
+            version,
+            activo,
+        }
+    }
+}
+
+/// Cantidad maxima de entradas que entran en un mensaje de membresia
+pub const MAX_ENTRADAS_MEMBRESIA: usize = (crate::MAX_MENSAJE as usize - 2) / 7;
+
+/// Rumor con la vista de la membresia del cluster que tiene un local. Los
+/// locales se lo envian entre si periodicamente, y se lo envian a los
+/// ecommerce que la consultan
+#[derive(Debug, Clone, PartialEq)]
+pub struct MensajeMembresia {
+    pub entradas: Vec<EntradaMembresia>,
+}
+
+impl MensajeMembresia {
+    /// Crea un rumor con las entradas dadas. Si no entran en un mensaje, se
+    /// descartan las ultimas
+    pub fn new(mut entradas: Vec<EntradaMembresia>) -> Self {
+        entradas.truncate(MAX_ENTRADAS_MEMBRESIA);
+        Self { entradas }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo MensajeMembresia
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut cantidad: [u8; 1] = [0; 1];
+        buf.read_exact(&mut cantidad)?;
+        let mut entradas = Vec::with_capacity(cantidad[0] as usize);
+        for _ in 0..cantidad[0] {
+            let mut campos: [u8; 7] = [0; 7];
+            buf.read_exact(&mut campos)?;
+            entradas.push(EntradaMembresia::new(
+                <u16>::from_be_bytes([campos[0], campos[1]]),
+                <u32>::from_be_bytes([campos[2], campos[3], campos[4], campos[5]]),
+                campos[6] != 0,
+            ));
+        }
+        Ok(Self::new(entradas))
+    }
+
+    /// Convierte un MensajeMembresia en un array de bytes para poder enviarlo
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::Membresia as u8, self.entradas.len() as u8];
+        for entrada in &self.entradas {
+            buf.extend_from_slice(&entrada.id.to_be_bytes());
+            buf.extend_from_slice(&entrada.version.to_be_bytes());
+            buf.push(u8::from(entrada.activo));
+        }
+        buf
+    }
+}
+
+/// Mensajes de la eleccion del lider entre los locales, segun el algoritmo bully:
+/// un local que inicia una eleccion se la anuncia a los de mayor id, que
+/// le responden para tomarla a su cargo, y el que no recibe respuesta se
+/// proclama coordinador. Ademas, cualquiera puede consultar quien es el lider,
+/// y se le responde con el coordinador conocido
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MensajeEleccion {
+    Eleccion(IdLocal),
+    Respuesta(IdLocal),
+    Coordinador(IdLocal),
+    ConsultaLider,
+}
+
+impl MensajeEleccion {
+    /// Convierte bytes leidos en un mensaje de eleccion, a partir de su tipo
+    /// # Errors:
+    /// * si el tipo no es de la eleccion, o si el buffer de lectura pasado
+    ///   tiene menos bytes que los necesarios para completar el mensaje
+    pub fn from_bytes(tipo: TipoMensaje, buf: &mut dyn Read) -> io::Result<Self> {
+        if let TipoMensaje::ConsultaLider = tipo {
+            return Ok(Self::ConsultaLider);
+        }
+        let mut id: [u8; 2] = [0; 2];
+        buf.read_exact(&mut id)?;
+        let id = <u16>::from_be_bytes(id);
+        match tipo {
+            TipoMensaje::Eleccion => Ok(Self::Eleccion(id)),
+            TipoMensaje::RespuestaEleccion => Ok(Self::Respuesta(id)),
+            TipoMensaje::Coordinador => Ok(Self::Coordinador(id)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                String::from("No es un mensaje de eleccion"),
+            )),
+        }
+    }
+
+    /// Convierte un mensaje de eleccion en un array de bytes para poder
+    /// enviarlo por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let (tipo, id) = match self {
+            Self::Eleccion(id) => (TipoMensaje::Eleccion, Some(id)),
+            Self::Respuesta(id) => (TipoMensaje::RespuestaEleccion, Some(id)),
+            Self::Coordinador(id) => (TipoMensaje::Coordinador, Some(id)),
+            Self::ConsultaLider => (TipoMensaje::ConsultaLider, None),
+        };
+        let mut buf = vec![tipo as u8];
+        if let Some(id) = id {
+            buf.extend_from_slice(&id.to_be_bytes());
+        }
+        buf
+    }
+}
+
+/// Mensaje con el que un ecommerce le pregunta a un local en que estado esta
+/// uno de sus pedidos, identificado por su id y el puerto del ecommerce
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsultaEstado {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+}
+
+impl ConsultaEstado {
+    /// Crea una consulta por el estado del pedido dado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto) -> Self {
+        Self { id_pedido, puerto }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo ConsultaEstado
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 4] = [0; 4];
+        buf.read_exact(&mut campos)?;
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            <u16>::from_be_bytes([campos[2], campos[3]]),
+        ))
+    }
+
+    /// Convierte una ConsultaEstado en un array de bytes para poder enviarla
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::ConsultaEstado as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.extend_from_slice(&self.puerto.to_be_bytes());
+        buf
+    }
+}
+
+/// Estado de un pedido de ecommerce segun el local consultado
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EstadoConsultado {
+    /// El local no recibio el pedido
+    Desconocido,
+    /// El local tiene el pedido en curso: reservado, o en espera de stock
+    Reservado,
+    /// El local delego el pedido al local indicado, que acuso recibirlo
+    Delegado(IdLocal),
+    /// El pedido fue retirado
+    Confirmado,
+    /// El pedido fue cancelado, o no habia stock para cubrirlo
+    Cancelado,
+}
+
+/// Respuesta de un local a la consulta por el estado de un pedido
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RespuestaEstado {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+    pub estado: EstadoConsultado,
+}
+
+impl RespuestaEstado {
+    /// Crea la respuesta a la consulta por el pedido dado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto, estado: EstadoConsultado) -> Self {
+        Self {
+            id_pedido,
+            puerto,
+            estado,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo RespuestaEstado
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje, o el estado es invalido
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 7] = [0; 7];
+        buf.read_exact(&mut campos)?;
+        let estado = match campos[4] {
+            0 => EstadoConsultado::Desconocido,
+            1 => EstadoConsultado::Reservado,
+            2 => EstadoConsultado::Delegado(<u16>::from_be_bytes([campos[5], campos[6]])),
+            3 => EstadoConsultado::Confirmado,
+            4 => EstadoConsultado::Cancelado,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    String::from("Estado de pedido invalido"),
+                ))
+            }
+        };
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            <u16>::from_be_bytes([campos[2], campos[3]]),
+            estado,
+        ))
+    }
+
+    /// Convierte una RespuestaEstado en un array de bytes para poder enviarla
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let (estado, local): (u8, IdLocal) = match self.estado {
+            EstadoConsultado::Desconocido => (0, 0),
+            EstadoConsultado::Reservado => (1, 0),
+            EstadoConsultado::Delegado(local) => (2, local),
+            EstadoConsultado::Confirmado => (3, 0),
+            EstadoConsultado::Cancelado => (4, 0),
+        };
+        let mut buf = vec![TipoMensaje::RespuestaEstado as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.extend_from_slice(&self.puerto.to_be_bytes());
+        buf.push(estado);
+        buf.extend_from_slice(&local.to_be_bytes());
+        buf
+    }
+}
+
+/// Mensaje con el que un ecommerce pide cancelar o reducir uno de sus pedidos
+/// mientras esta reservado. Lleva el puerto del ecommerce, ya que el local que
+/// lo recibe se lo reenvia al local al que delego el pedido
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MensajeModificacion {
+    pub id_pedido: IdPedido,
+    pub puerto: Puerto,
+    pub modificacion: Modificacion,
+}
+
+impl MensajeModificacion {
+    /// Crea el pedido de modificacion del pedido dado
+    pub fn new(id_pedido: IdPedido, puerto: Puerto, modificacion: Modificacion) -> Self {
+        Self {
+            id_pedido,
+            puerto,
+            modificacion,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo MensajeModificacion
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje, o la modificacion es invalida
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 9] = [0; 9];
+        buf.read_exact(&mut campos)?;
+        let modificacion = match campos[4] {
+            0 => Modificacion::Cancelar,
+            1 => Modificacion::Reducir {
+                id_producto: <u16>::from_be_bytes([campos[5], campos[6]]),
+                cantidad: <u16>::from_be_bytes([campos[7], campos[8]]),
+            },
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    String::from("Modificacion invalida"),
+                ))
+            }
+        };
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            <u16>::from_be_bytes([campos[2], campos[3]]),
+            modificacion,
+        ))
+    }
+
+    /// Convierte un MensajeModificacion en un array de bytes para poder
+    /// enviarlo por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let (tipo, id_producto, cantidad): (u8, IdProducto, CantidadProducto) =
+            match self.modificacion {
+                Modificacion::Cancelar => (0, 0, 0),
+                Modificacion::Reducir {
+                    id_producto,
+                    cantidad,
+                } => (1, id_producto, cantidad),
+            };
+        let mut buf = vec![TipoMensaje::ModificacionPedido as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.extend_from_slice(&self.puerto.to_be_bytes());
+        buf.push(tipo);
+        buf.extend_from_slice(&id_producto.to_be_bytes());
+        buf.extend_from_slice(&cantidad.to_be_bytes());
+        buf
+    }
+}
+
+/// Resultado de la modificacion de un pedido pedida por un ecommerce
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
+pub enum ResultadoModificacion {
+    /// El local libero o ajusto la reserva del pedido
+    Aceptada = 0,
+    /// El pedido ya fue confirmado, por lo que no puede modificarse
+    YaConfirmado,
+    /// El pedido ya fue cancelado
+    YaCancelado,
+    /// El local no tiene reservado el pedido, por ejemplo porque espera stock
+    NoReservado,
+    /// La reduccion no es valida para el carrito del pedido
+    Invalida,
+}
+
+/// Respuesta de un local a la modificacion de un pedido
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RespuestaModificacion {
+    pub id_pedido: IdPedido,
+    pub resultado: ResultadoModificacion,
+}
+
+impl RespuestaModificacion {
+    /// Crea la respuesta a la modificacion del pedido dado
+    pub fn new(id_pedido: IdPedido, resultado: ResultadoModificacion) -> Self {
+        Self {
+            id_pedido,
+            resultado,
+        }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo RespuestaModificacion
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje, o el resultado es invalido
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut campos: [u8; 3] = [0; 3];
+        buf.read_exact(&mut campos)?;
+        let resultado = ResultadoModificacion::from_u8(campos[2]).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                String::from("Resultado de modificacion invalido"),
+            )
+        })?;
+        Ok(Self::new(
+            <u16>::from_be_bytes([campos[0], campos[1]]),
+            resultado,
+        ))
+    }
+
+    /// Convierte una RespuestaModificacion en un array de bytes para poder
+    /// enviarla por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::RespuestaModificacion as u8];
+        buf.extend_from_slice(&self.id_pedido.to_be_bytes());
+        buf.push(self.resultado as u8);
+        buf
+    }
+}
+
+/// Latido que un local le envia periodicamente a los demas, para que sepan que
+/// sigue vivo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MensajeLatido {
+    pub id_local: IdLocal,
+}
+
+impl MensajeLatido {
+    /// Crea el latido del local dado
+    pub fn new(id_local: IdLocal) -> Self {
+        Self { id_local }
+    }
+
+    /// Convierte bytes leidos en un mensaje del tipo MensajeLatido
+    /// # Errors:
+    /// * si el buffer de lectura pasado tiene menos bytes que los
+    ///   necesarios para completar el mensaje
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut id: [u8; 2] = [0; 2];
+        buf.read_exact(&mut id)?;
+        Ok(Self::new(<u16>::from_be_bytes(id)))
+    }
+
+    /// Convierte un MensajeLatido en un array de bytes para poder enviarlo
+    /// por un socket
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![TipoMensaje::Latido as u8];
+        buf.extend_from_slice(&self.id_local.to_be_bytes());
+        buf
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
     use super::*;
+    use crate::pedido::Pedido;
     use std::collections::HashSet;
     use std::io;
 
     #[test]
     fn test_constructor_mensaje_ecommerce() {
-        let pedido = Pedido::new(2, 3);
+        let pedido = Carrito::new(vec![Pedido::new(2, 3), Pedido::new(5, 1)]).unwrap();
         let msg = MensajeEcommerce::new(1, pedido).as_bytes();
 
         let mut cursor = io::Cursor::new(msg);
@@ -335,8 +1074,13 @@ mod tests {
                 assert!(msg.is_ok());
                 let msg_ecommerce = msg.unwrap();
                 assert_eq!(msg_ecommerce.id_pedido, 1);
-                assert_eq!(msg_ecommerce.pedido.get_id(), 2);
-                assert_eq!(msg_ecommerce.pedido.get_amount(), 3);
+                assert!(!msg_ecommerce.esperar);
+                let lineas = msg_ecommerce.pedido.get_lineas();
+                assert_eq!(lineas.len(), 2);
+                assert_eq!(lineas[0].get_id(), 2);
+                assert_eq!(lineas[0].get_amount(), 3);
+                assert_eq!(lineas[1].get_id(), 5);
+                assert_eq!(lineas[1].get_amount(), 1);
             }
             _ => assert!(false),
         }
@@ -344,9 +1088,9 @@ mod tests {
 
     #[test]
     fn test_constructor_mensaje_delegado() {
-        let pedido = Pedido::new(2, 3);
+        let pedido = Carrito::from(Pedido::new(2, 3));
 
-        let msg_ecom = MensajeEcommerce::new(1, pedido);
+        let msg_ecom = MensajeEcommerce::new(1, pedido).con_espera(true);
         let mut set_delegados = HashSet::new();
         set_delegados.insert(1);
         set_delegados.insert(2);
@@ -361,6 +1105,7 @@ mod tests {
                 assert!(msg_recv.is_ok());
                 let msg_delegado = msg_recv.unwrap();
                 assert_eq!(msg_delegado.mensaje_ecommerce, msg_ecom);
+                assert!(msg_delegado.quiere_esperar());
                 assert_eq!(msg_delegado.puerto_ecommerce, 3402);
                 assert!(msg_delegado.locales_ack.contains(&1));
                 assert!(msg_delegado.locales_ack.contains(&2));
@@ -458,4 +1203,233 @@ mod tests {
             _ => assert!(false),
         }
     }
+
+    #[test]
+    fn test_constructor_mensaje_servidor_en_espera() {
+        let en_espera = MensajesServidor::EnEspera(31).as_bytes();
+
+        let mut cursor = io::Cursor::new(en_espera);
+        let tipo = TipoMensaje::from_bytes(&mut cursor);
+        assert!(tipo.is_ok());
+        match tipo.unwrap() {
+            TipoMensaje::MensajeServidor => {
+                let msg_recv = MensajesServidor::from_bytes(&mut cursor).unwrap();
+                match msg_recv {
+                    MensajesServidor::EnEspera(id) => assert_eq!(id, 31),
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_constructor_mensaje_transferencia() {
+        let vencimiento = NaiveDate::from_ymd_opt(2024, 5, 20).unwrap();
+        let lotes = vec![
+            Lote::con_vencimiento(200, vencimiento),
+            Lote::sin_vencimiento(100),
+        ];
+        let solicitud = MensajeTransferencia::solicitud(7, 120, 300);
+        let msg = solicitud
+            .clone()
+            .como_envio()
+            .con_lotes(lotes.clone())
+            .as_bytes();
+
+        let mut cursor = io::Cursor::new(msg);
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::Transferencia => {
+                let msg_recv = MensajeTransferencia::from_bytes(&mut cursor).unwrap();
+                assert_eq!(msg_recv.etapa, EtapaTransferencia::Envio);
+                assert_eq!(msg_recv.id_transferencia, 7);
+                assert_eq!(msg_recv.id_producto, 120);
+                assert_eq!(msg_recv.cantidad, 300);
+                assert_eq!(msg_recv.lotes, lotes);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn los_lotes_de_una_transferencia_siempre_entran_en_el_mensaje() {
+        let dia = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut lotes: Vec<Lote> = (1..=20).map(|d| Lote::con_vencimiento(1, dia(d))).collect();
+        lotes.push(Lote::con_vencimiento(2, dia(1)));
+        lotes.push(Lote::sin_vencimiento(3));
+        let envio = MensajeTransferencia::solicitud(7, 120, 25)
+            .como_envio()
+            .con_lotes(lotes);
+
+        assert!(envio.as_bytes().len() <= crate::MAX_MENSAJE as usize);
+        assert_eq!(envio.lotes.len(), MAX_LOTES_TRANSFERENCIA);
+        assert_eq!(envio.lotes[0], Lote::con_vencimiento(3, dia(1)));
+        let ultimo = MAX_LOTES_TRANSFERENCIA - 1;
+        assert_eq!(envio.lotes[ultimo], Lote::con_vencimiento(9, dia(15)));
+        let unidades: CantidadProducto = envio.lotes.iter().map(|lote| lote.cantidad).sum();
+        assert_eq!(unidades, 25);
+    }
+
+    #[test]
+    fn test_constructor_ack_transferencia() {
+        let ack = AckTransferencia::new(EtapaTransferencia::Solicitud, 9, false).as_bytes();
+
+        let mut cursor = io::Cursor::new(ack);
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::AckTransferencia => {
+                let msg_recv = AckTransferencia::from_bytes(&mut cursor).unwrap();
+                assert_eq!(
+                    msg_recv,
+                    AckTransferencia::new(EtapaTransferencia::Solicitud, 9, false)
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_constructor_consulta_y_respuesta_stock() {
+        let consulta = ConsultaStock::new(4, 3402, Carrito::from(Pedido::new(2, 3)));
+        let mut cursor = io::Cursor::new(consulta.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::ConsultaStock => {
+                assert_eq!(ConsultaStock::from_bytes(&mut cursor).unwrap(), consulta);
+            }
+            _ => panic!(),
+        }
+
+        let respuesta = RespuestaStock::new(4, 3402, true, 500);
+        let mut cursor = io::Cursor::new(respuesta.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::RespuestaStock => {
+                assert_eq!(RespuestaStock::from_bytes(&mut cursor).unwrap(), respuesta);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_constructor_mensaje_membresia() {
+        let rumor = MensajeMembresia::new(vec![
+            EntradaMembresia::new(0, 12, true),
+            EntradaMembresia::new(7, 70000, false),
+        ]);
+        let mut cursor = io::Cursor::new(rumor.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::Membresia => {
+                assert_eq!(MensajeMembresia::from_bytes(&mut cursor).unwrap(), rumor);
+            }
+            _ => panic!(),
+        }
+
+        let entradas = (0..30)
+            .map(|id| EntradaMembresia::new(id, 1, true))
+            .collect();
+        let rumor = MensajeMembresia::new(entradas);
+        assert!(rumor.as_bytes().len() <= crate::MAX_MENSAJE as usize);
+    }
+
+    #[test]
+    fn un_delegado_con_todos_los_locales_posibles_entra_en_un_mensaje() {
+        let carrito = Carrito::new(vec![Pedido::new(1, 1); MAX_LINEAS_CARRITO as usize]).unwrap();
+        let locales_ack = (0..MAX_LOCALES_ACK as IdLocal).collect();
+        let delegado = MensajeDelegado::new(MensajeEcommerce::new(1, carrito), 3402, locales_ack);
+        assert_eq!(delegado.as_bytes().len(), crate::MAX_MENSAJE as usize - 1);
+    }
+
+    #[test]
+    fn test_constructor_mensajes_eleccion() {
+        for mensaje in [
+            MensajeEleccion::Eleccion(2),
+            MensajeEleccion::Respuesta(3),
+            MensajeEleccion::Coordinador(300),
+            MensajeEleccion::ConsultaLider,
+        ] {
+            let mut cursor = io::Cursor::new(mensaje.as_bytes());
+            let tipo = TipoMensaje::from_bytes(&mut cursor).unwrap();
+            assert_eq!(
+                MensajeEleccion::from_bytes(tipo, &mut cursor).unwrap(),
+                mensaje
+            );
+        }
+        let mut cursor = io::Cursor::new(AckEcommerce::new(1).as_bytes());
+        let tipo = TipoMensaje::from_bytes(&mut cursor).unwrap();
+        assert!(MensajeEleccion::from_bytes(tipo, &mut cursor).is_err());
+    }
+
+    #[test]
+    fn test_constructor_consulta_y_respuesta_estado() {
+        let consulta = ConsultaEstado::new(9, 3402);
+        let mut cursor = io::Cursor::new(consulta.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::ConsultaEstado => {
+                assert_eq!(ConsultaEstado::from_bytes(&mut cursor).unwrap(), consulta);
+            }
+            _ => panic!(),
+        }
+
+        for estado in [
+            EstadoConsultado::Desconocido,
+            EstadoConsultado::Reservado,
+            EstadoConsultado::Delegado(3),
+            EstadoConsultado::Confirmado,
+            EstadoConsultado::Cancelado,
+        ] {
+            let respuesta = RespuestaEstado::new(9, 3402, estado);
+            let mut cursor = io::Cursor::new(respuesta.as_bytes());
+            match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+                TipoMensaje::RespuestaEstado => {
+                    assert_eq!(RespuestaEstado::from_bytes(&mut cursor).unwrap(), respuesta);
+                }
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_constructor_modificacion_y_respuesta() {
+        for modificacion in [
+            Modificacion::Cancelar,
+            Modificacion::Reducir {
+                id_producto: 300,
+                cantidad: 2,
+            },
+        ] {
+            let mensaje = MensajeModificacion::new(5, 3402, modificacion);
+            let mut cursor = io::Cursor::new(mensaje.as_bytes());
+            match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+                TipoMensaje::ModificacionPedido => {
+                    assert_eq!(
+                        MensajeModificacion::from_bytes(&mut cursor).unwrap(),
+                        mensaje
+                    );
+                }
+                _ => panic!(),
+            }
+        }
+
+        let respuesta = RespuestaModificacion::new(5, ResultadoModificacion::YaConfirmado);
+        let mut cursor = io::Cursor::new(respuesta.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::RespuestaModificacion => {
+                assert_eq!(
+                    RespuestaModificacion::from_bytes(&mut cursor).unwrap(),
+                    respuesta
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_latido_se_serializa_y_deserializa() {
+        let latido = MensajeLatido::new(7);
+        let mut cursor = io::Cursor::new(latido.as_bytes());
+        match TipoMensaje::from_bytes(&mut cursor).unwrap() {
+            TipoMensaje::Latido => {
+                assert_eq!(MensajeLatido::from_bytes(&mut cursor).unwrap(), latido);
+            }
+            _ => panic!(),
+        }
+    }
 }
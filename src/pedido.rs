@@ -1,6 +1,7 @@
 //! Este modulo define la estructura de pedidos, los cuales son enviados entre los distintos procesos del sistema
 
 use std::{
+    collections::HashMap,
     fmt,
     io::{self, Read},
 };
@@ -8,6 +9,13 @@ use std::{
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
 
+use crate::aliases::{CantidadProducto, IdProducto};
+use crate::errores::ErrorCarrito;
+
+/// Cantidad maxima de lineas que puede tener un carrito, para que
+/// el mensaje que lo transporta entre en un datagrama
+pub const MAX_LINEAS_CARRITO: u8 = 16;
+
 /// Un pedido esta definido por un id de producto, y una cantidad de producto a pedir
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Pedido {
@@ -76,3 +84,366 @@ impl fmt::Display for Pedido {
 pub fn from_reader(reader: &mut dyn Read) -> serde_json::Result<Vec<Pedido>> {
     serde_json::from_reader(reader)
 }
+
+/// Un carrito es un pedido compuesto por varias lineas, donde cada linea es un
+/// pedido de un unico producto. Las lineas se resuelven en conjunto: o se
+/// reservan todas en un mismo local, o no se reserva ninguna.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(try_from = "FormatoCarrito", into = "Vec<Pedido>")]
+pub struct Carrito {
+    lineas: Vec<Pedido>,
+}
+
+/// Formatos aceptados para un carrito en los archivos de pedidos: una lista
+/// de lineas, o un pedido suelto, que se interpreta como un carrito de una linea
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FormatoCarrito {
+    Lineas(Vec<Pedido>),
+    Simple(Pedido),
+}
+
+impl TryFrom<FormatoCarrito> for Carrito {
+    type Error = ErrorCarrito;
+
+    fn try_from(formato: FormatoCarrito) -> Result<Self, Self::Error> {
+        match formato {
+            FormatoCarrito::Lineas(lineas) => Self::new(lineas),
+            FormatoCarrito::Simple(pedido) => Ok(Self::from(pedido)),
+        }
+    }
+}
+
+impl From<Carrito> for Vec<Pedido> {
+    fn from(carrito: Carrito) -> Self {
+        carrito.lineas
+    }
+}
+
+impl From<Pedido> for Carrito {
+    fn from(pedido: Pedido) -> Self {
+        Self {
+            lineas: vec![pedido],
+        }
+    }
+}
+
+impl Carrito {
+    /// Crea un nuevo carrito con las lineas dadas
+    /// # Errors:
+    /// Si tiene mas lineas que las que entran en un mensaje
+    pub fn new(lineas: Vec<Pedido>) -> Result<Self, ErrorCarrito> {
+        if lineas.len() > MAX_LINEAS_CARRITO as usize {
+            return Err(ErrorCarrito::DemasiadasLineas);
+        }
+        Ok(Self { lineas })
+    }
+
+    /// Obtiene las lineas del carrito
+    pub fn get_lineas(&self) -> &[Pedido] {
+        &self.lineas
+    }
+
+    /// Devuelve si el carrito no tiene lineas, o alguna de ellas pide cero unidades
+    pub fn esta_vacio(&self) -> bool {
+        self.lineas.is_empty() || self.lineas.iter().any(|l| l.get_amount() == 0)
+    }
+
+    /// Agrupa las lineas del carrito por producto, sumando sus cantidades. Sirve
+    /// para validar el stock cuando un mismo producto aparece en varias lineas
+    pub fn cantidades_por_producto(&self) -> HashMap<IdProducto, CantidadProducto> {
+        let mut cantidades = HashMap::new();
+        for linea in &self.lineas {
+            *cantidades.entry(linea.get_id()).or_insert(0) +=
+                CantidadProducto::from(linea.get_amount());
+        }
+        cantidades
+    }
+
+    /// Devuelve el carrito sin la cantidad dada del producto, que se quita de sus
+    /// ultimas lineas, o nada si la cantidad es cero o no es menor a la pedida de
+    /// ese producto. Para quitar el producto por completo se cancela el pedido
+    pub fn reducir(&self, id_producto: IdProducto, cantidad: CantidadProducto) -> Option<Self> {
+        let pedida = self
+            .cantidades_por_producto()
+            .get(&id_producto)
+            .copied()
+            .unwrap_or(0);
+        if cantidad == 0 || cantidad >= pedida {
+            return None;
+        }
+
+        let mut restante = cantidad;
+        let mut lineas = self.lineas.clone();
+        for linea in lineas.iter_mut().rev() {
+            if restante == 0 {
+                break;
+            }
+            if linea.get_id() != id_producto {
+                continue;
+            }
+            let quitadas = restante.min(CantidadProducto::from(linea.get_amount()));
+            linea.cantidad -= quitadas as u8;
+            restante -= quitadas;
+        }
+        lineas.retain(|linea| linea.get_amount() > 0);
+        Some(Self { lineas })
+    }
+
+    /// Separa las lineas del carrito en varios carritos, segun la clave que
+    /// la funcion dada le asigna a cada una
+    pub fn dividir<K, F>(&self, clave: F) -> HashMap<K, Self>
+    where
+        K: Eq + std::hash::Hash,
+        F: Fn(&Pedido) -> K,
+    {
+        let mut partes: HashMap<K, Self> = HashMap::new();
+        for linea in &self.lineas {
+            partes
+                .entry(clave(linea))
+                .or_insert_with(|| Self { lineas: Vec::new() })
+                .lineas
+                .push(linea.clone());
+        }
+        partes
+    }
+
+    /// Convierte bytes en un carrito valido. Primero se lee la cantidad de
+    /// lineas, y luego cada una de ellas
+    pub fn from_bytes(buf: &mut dyn Read) -> io::Result<Self> {
+        let mut cant_lineas: [u8; 1] = [0; 1];
+        buf.read_exact(&mut cant_lineas)?;
+        if cant_lineas[0] > MAX_LINEAS_CARRITO {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Un carrito no puede tener {} lineas", cant_lineas[0]),
+            ));
+        }
+
+        let mut lineas = Vec::new();
+        for _ in 0..cant_lineas[0] {
+            lineas.push(Pedido::from_bytes(buf)?);
+        }
+        Ok(Self { lineas })
+    }
+
+    /// Convierte un carrito en un vector de bytes, para que pueda ser enviado
+    /// a traves de la red de forma correcta
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_message = Vec::new();
+        // Al crearse, el carrito no supera las lineas que entran en un u8
+        buf_message.push(self.lineas.len() as u8);
+        self.lineas
+            .iter()
+            .for_each(|linea| buf_message.extend(linea.as_bytes()));
+        buf_message
+    }
+}
+
+impl fmt::Display for Carrito {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let [linea] = self.lineas.as_slice() {
+            return write!(f, "{}", linea);
+        }
+        write!(f, "carrito de {} lineas [", self.lineas.len())?;
+        for (i, linea) in self.lineas.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}x{}",
+                linea.get_amount().to_string().blue(),
+                linea.get_id().to_string().blue()
+            )?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Cambio que el cliente puede pedir sobre un pedido de ecommerce mientras
+/// este se encuentra reservado
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "tipo", rename_all = "snake_case")]
+pub enum Modificacion {
+    /// Retira el pedido completo
+    Cancelar,
+    /// Quita unidades de uno de los productos del carrito
+    Reducir {
+        id_producto: IdProducto,
+        cantidad: CantidadProducto,
+    },
+}
+
+/// Modificacion que el cliente pide un tiempo despues de realizar el pedido
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct ModificacionProgramada {
+    pub tras_ms: u64,
+    #[serde(flatten)]
+    pub modificacion: Modificacion,
+}
+
+/// Pedido tal como figura en el archivo de un ecommerce: un carrito, si el
+/// cliente esta dispuesto a esperar a que haya stock en caso de que no haya
+/// en ningun local, y la modificacion que el cliente pide luego, si la hay
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "FormatoPedidoEcommerce")]
+pub struct PedidoEcommerce {
+    pub carrito: Carrito,
+    pub esperar: bool,
+    pub modificacion: Option<ModificacionProgramada>,
+}
+
+/// Formatos aceptados para un pedido de ecommerce: un objeto con las lineas,
+/// el indicador de espera y la modificacion, o directamente un carrito, que
+/// no espera ni se modifica
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FormatoPedidoEcommerce {
+    Completo {
+        lineas: Vec<Pedido>,
+        #[serde(default)]
+        esperar: bool,
+        #[serde(default)]
+        modificar: Option<ModificacionProgramada>,
+    },
+    Carrito(Carrito),
+}
+
+impl TryFrom<FormatoPedidoEcommerce> for PedidoEcommerce {
+    type Error = ErrorCarrito;
+
+    fn try_from(formato: FormatoPedidoEcommerce) -> Result<Self, Self::Error> {
+        Ok(match formato {
+            FormatoPedidoEcommerce::Completo {
+                lineas,
+                esperar,
+                modificar,
+            } => Self {
+                carrito: Carrito::new(lineas)?,
+                esperar,
+                modificacion: modificar,
+            },
+            FormatoPedidoEcommerce::Carrito(carrito) => Self {
+                carrito,
+                esperar: false,
+                modificacion: None,
+            },
+        })
+    }
+}
+
+/// Parsea un lector de bytes (en formato json) en un vector de pedidos de
+/// ecommerce. Cada elemento puede ser un pedido suelto, una lista de pedidos,
+/// o un objeto con `lineas`, `esperar` y `modificar`.
+pub fn pedidos_ecommerce_from_reader(
+    reader: &mut dyn Read,
+) -> serde_json::Result<Vec<PedidoEcommerce>> {
+    serde_json::from_reader(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carrito_acepta_pedidos_sueltos_y_listas() {
+        let json = r#"[
+            {"id_producto": 1, "cantidad": 2},
+            [{"id_producto": 3, "cantidad": 1}, {"id_producto": 4, "cantidad": 5}]
+        ]"#;
+        let carritos: Vec<Carrito> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(carritos.len(), 2);
+        assert_eq!(carritos[0], Carrito::from(Pedido::new(1, 2)));
+        assert_eq!(
+            carritos[1],
+            Carrito::new(vec![Pedido::new(3, 1), Pedido::new(4, 5)]).unwrap()
+        );
+    }
+
+    #[test]
+    fn cantidades_por_producto_suma_lineas_repetidas() {
+        let carrito = Carrito::new(vec![Pedido::new(1, 200), Pedido::new(1, 100)]).unwrap();
+        assert_eq!(carrito.cantidades_por_producto().get(&1), Some(&300));
+    }
+
+    #[test]
+    fn carrito_no_puede_superar_el_maximo_de_lineas() {
+        let lineas = vec![Pedido::new(1, 1); MAX_LINEAS_CARRITO as usize + 1];
+        assert_eq!(Carrito::new(lineas), Err(ErrorCarrito::DemasiadasLineas));
+
+        let json = format!(
+            "[{}]",
+            vec![r#"{"id_producto": 1, "cantidad": 1}"#; 17].join(",")
+        );
+        assert!(serde_json::from_str::<Carrito>(&json).is_err());
+
+        let carrito = Carrito::new(vec![Pedido::new(1, 1); MAX_LINEAS_CARRITO as usize]).unwrap();
+        let mut cursor = io::Cursor::new(carrito.as_bytes());
+        assert_eq!(Carrito::from_bytes(&mut cursor).unwrap(), carrito);
+    }
+
+    #[test]
+    fn carrito_se_serializa_y_deserializa() {
+        let carrito = Carrito::new(vec![Pedido::new(1, 2), Pedido::new(300, 4)]).unwrap();
+        let mut cursor = io::Cursor::new(carrito.as_bytes());
+        assert_eq!(Carrito::from_bytes(&mut cursor).unwrap(), carrito);
+    }
+
+    #[test]
+    fn pedidos_ecommerce_from_reader_acepta_indicador_de_espera() {
+        let json = r#"[
+            {"id_producto": 1, "cantidad": 2},
+            {"lineas": [{"id_producto": 3, "cantidad": 1}], "esperar": true}
+        ]"#;
+        let pedidos = pedidos_ecommerce_from_reader(&mut json.as_bytes()).unwrap();
+
+        assert!(!pedidos[0].esperar);
+        assert_eq!(pedidos[0].carrito, Carrito::from(Pedido::new(1, 2)));
+        assert!(pedidos[1].esperar);
+        assert_eq!(pedidos[1].carrito, Carrito::from(Pedido::new(3, 1)));
+    }
+
+    #[test]
+    fn reducir_quita_unidades_de_las_ultimas_lineas_del_producto() {
+        let carrito = Carrito::new(vec![
+            Pedido::new(1, 2),
+            Pedido::new(2, 4),
+            Pedido::new(1, 3),
+        ])
+        .unwrap();
+        assert_eq!(
+            carrito.reducir(1, 4),
+            Some(Carrito::new(vec![Pedido::new(1, 1), Pedido::new(2, 4)]).unwrap())
+        );
+        assert_eq!(carrito.reducir(1, 5), None);
+        assert_eq!(carrito.reducir(1, 0), None);
+        assert_eq!(carrito.reducir(3, 1), None);
+    }
+
+    #[test]
+    fn pedidos_ecommerce_from_reader_acepta_modificaciones() {
+        let json = r#"[
+            {"lineas": [{"id_producto": 3, "cantidad": 2}], "modificar": {"tras_ms": 800, "tipo": "cancelar"}},
+            {"lineas": [{"id_producto": 3, "cantidad": 2}],
+             "modificar": {"tras_ms": 100, "tipo": "reducir", "id_producto": 3, "cantidad": 1}}
+        ]"#;
+        let pedidos = pedidos_ecommerce_from_reader(&mut json.as_bytes()).unwrap();
+
+        assert_eq!(
+            pedidos[0].modificacion,
+            Some(ModificacionProgramada {
+                tras_ms: 800,
+                modificacion: Modificacion::Cancelar
+            })
+        );
+        assert_eq!(
+            pedidos[1].modificacion.map(|m| m.modificacion),
+            Some(Modificacion::Reducir {
+                id_producto: 3,
+                cantidad: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Este modulo define la topologia de delegacion entre los locales: para cada
+//! local, el orden en que prefiere delegarle pedidos a los demas. Se lee de
+//! "configs/topologia.json", ya sea como una lista ordenada de vecinos por
+//! local o como una matriz de distancias, entre los locales que el archivo
+//! indica como conocidos. Sin archivo, cada local prefiere al siguiente en el
+//! anillo de ids, como si los locales activos formaran un anillo. En todos los
+//! casos solo se consideran los locales activos segun la membresia del cluster.
+//! El archivo tambien indica como se elige a quien delegar: recorriendo los
+//! vecinos de a uno, o consultandole el stock a todos a la vez; y cuantas veces
+//! se retransmite una delegacion antes de dar al vecino por no disponible.
+
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use crate::aliases::IdLocal;
+use crate::errores::ErrorDuranteParseo;
+
+/// Archivo del que se lee la topologia, compartido por locales y ecommerces
+pub const ARCHIVO_TOPOLOGIA: &str = "configs/topologia.json";
+
+/// Criterio para elegir a quien delegar entre los locales que pueden cubrir
+/// un pedido
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Criterio {
+    /// El primero segun el orden de preferencia de la topologia
+    #[default]
+    MasCercano,
+    /// El que tiene mas unidades disponibles de los productos del pedido
+    MasStock,
+}
+
+/// Forma en que un local elige a quien delegarle un pedido que no puede cubrir
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "modo", rename_all = "snake_case")]
+pub enum ModoDelegacion {
+    /// Se delega al primer vecino, y si no lo cubre este lo delega al suyo
+    #[default]
+    Secuencial,
+    /// Se consulta el stock de todos los vecinos a la vez, y se delega
+    /// directamente al mejor de los que respondieron dentro del plazo
+    ConsultaParalela {
+        #[serde(default)]
+        criterio: Criterio,
+        #[serde(default = "plazo_por_defecto")]
+        plazo_ms: u64,
+    },
+}
+
+fn plazo_por_defecto() -> u64 {
+    300
+}
+
+impl ModoDelegacion {
+    /// Devuelve el plazo para recibir las respuestas de una consulta de stock
+    pub fn plazo(&self) -> Duration {
+        match self {
+            ModoDelegacion::Secuencial => Duration::ZERO,
+            ModoDelegacion::ConsultaParalela { plazo_ms, .. } => Duration::from_millis(*plazo_ms),
+        }
+    }
+}
+
+/// Retransmisiones de un pedido delegado a un vecino que no envia el ack. Entre
+/// envios se espera cada vez el doble, hasta un maximo, y a cada espera se le
+/// aplica una variacion al azar para que los locales no retransmitan a la vez
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Retransmision {
+    /// Cantidad de reenvios antes de dar al vecino por no disponible
+    pub reintentos: u32,
+    /// Espera por el ack del primer envio
+    pub espera_inicial_ms: u64,
+    /// Espera maxima por el ack de un envio
+    pub espera_maxima_ms: u64,
+}
+
+impl Default for Retransmision {
+    fn default() -> Self {
+        Self {
+            reintentos: 2,
+            espera_inicial_ms: 250,
+            espera_maxima_ms: 1000,
+        }
+    }
+}
+
+impl Retransmision {
+    /// Devuelve la espera por el ack del envio dado, contando desde 0, sin la
+    /// variacion al azar
+    pub fn espera_base(&self, envio: u32) -> Duration {
+        let espera = self
+            .espera_inicial_ms
+            .saturating_mul(2_u64.saturating_pow(envio))
+            .min(self.espera_maxima_ms);
+        Duration::from_millis(espera)
+    }
+
+    /// Devuelve la espera por el ack del envio dado, contando desde 0. Es un
+    /// valor al azar entre la mitad de la espera base y la espera base
+    pub fn espera(&self, envio: u32) -> Duration {
+        let base = self.espera_base(envio);
+        rand::thread_rng().gen_range(base / 2..=base)
+    }
+}
+
+/// Contenido del archivo de topologia. Los vecinos pueden darse como listas
+/// o como matriz de distancias, pero no de ambas formas a la vez, y en ambos
+/// casos solo pueden referirse a los locales indicados
+#[derive(Deserialize)]
+struct ArchivoTopologia {
+    #[serde(default)]
+    locales: Vec<IdLocal>,
+    #[serde(default)]
+    vecinos: Option<HashMap<IdLocal, Vec<IdLocal>>>,
+    #[serde(default)]
+    distancias: Option<Vec<Vec<u32>>>,
+    #[serde(default)]
+    delegacion: ModoDelegacion,
+    #[serde(default)]
+    retransmision: Retransmision,
+}
+
+/// Orden en que cada local prefiere delegar sus pedidos. Los locales que no
+/// aparecen en la topologia prefieren el orden del anillo de locales activos
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Topologia {
+    vecinos: HashMap<IdLocal, Vec<IdLocal>>,
+    modo: ModoDelegacion,
+    retransmision: Retransmision,
+}
+
+impl Topologia {
+    /// Crea una topologia a partir de los vecinos de cada local
+    /// # Errors:
+    /// Si algun local figura como su propio vecino, repite un vecino, o no es
+    /// uno de los locales conocidos
+    pub fn con_vecinos(
+        locales: &[IdLocal],
+        vecinos: HashMap<IdLocal, Vec<IdLocal>>,
+    ) -> Result<Self, ErrorDuranteParseo> {
+        validar_locales(locales)?;
+        for (id, lista) in &vecinos {
+            let mut vistos = lista.clone();
+            vistos.sort_unstable();
+            vistos.dedup();
+            let desconocido = !locales.contains(id) || lista.iter().any(|v| !locales.contains(v));
+            if desconocido || lista.contains(id) || vistos.len() != lista.len() {
+                eprintln!("Los vecinos del local {} son invalidos", id);
+                return Err(ErrorDuranteParseo::TopologiaInvalida);
+            }
+        }
+        Ok(Self {
+            vecinos,
+            ..Self::default()
+        })
+    }
+
+    /// Crea una topologia en la que cada local prefiere a los mas cercanos segun
+    /// la matriz de distancias, cuyas filas y columnas siguen el orden de los
+    /// locales conocidos. Ante un empate se prefiere el de menor id
+    /// # Errors:
+    /// Si la matriz no es cuadrada con una fila por local conocido
+    pub fn con_distancias(
+        locales: &[IdLocal],
+        distancias: Vec<Vec<u32>>,
+    ) -> Result<Self, ErrorDuranteParseo> {
+        validar_locales(locales)?;
+        let cantidad = locales.len();
+        if distancias.len() != cantidad || distancias.iter().any(|fila| fila.len() != cantidad) {
+            eprintln!("La matriz de distancias debe tener una fila y una columna por local");
+            return Err(ErrorDuranteParseo::TopologiaInvalida);
+        }
+        let vecinos = distancias
+            .iter()
+            .enumerate()
+            .map(|(posicion, fila)| {
+                let mut lista: Vec<usize> =
+                    (0..cantidad).filter(|otro| *otro != posicion).collect();
+                lista.sort_by_key(|otro| (fila[*otro], locales[*otro]));
+                let lista = lista.into_iter().map(|otro| locales[otro]).collect();
+                (locales[posicion], lista)
+            })
+            .collect();
+        Ok(Self {
+            vecinos,
+            ..Self::default()
+        })
+    }
+
+    /// Indica como se elige a quien delegar un pedido
+    pub fn con_modo(mut self, modo: ModoDelegacion) -> Self {
+        self.modo = modo;
+        self
+    }
+
+    /// Devuelve como se elige a quien delegar un pedido
+    pub fn modo(&self) -> ModoDelegacion {
+        self.modo
+    }
+
+    /// Indica como se retransmite un pedido delegado
+    pub fn con_retransmision(mut self, retransmision: Retransmision) -> Self {
+        self.retransmision = retransmision;
+        self
+    }
+
+    /// Devuelve como se retransmite un pedido delegado
+    pub fn retransmision(&self) -> Retransmision {
+        self.retransmision
+    }
+
+    /// Devuelve los vecinos activos del local en orden de preferencia
+    pub fn vecinos(&self, id: IdLocal, vivos: &[IdLocal]) -> Vec<IdLocal> {
+        match self.vecinos.get(&id) {
+            Some(vecinos) => vecinos
+                .iter()
+                .filter(|vecino| vivos.contains(vecino))
+                .copied()
+                .collect(),
+            None => {
+                // Primero los de id mayor y luego, dando la vuelta, los de id menor
+                let mut anillo: Vec<IdLocal> =
+                    vivos.iter().filter(|otro| **otro != id).copied().collect();
+                anillo.sort_unstable_by_key(|otro| (*otro < id, *otro));
+                anillo
+            }
+        }
+    }
+
+    /// Devuelve el local que sigue al actual en el recorrido que empieza en el
+    /// local de origen: primero el origen y luego sus vecinos en orden. Al
+    /// terminar el recorrido vuelve a empezar
+    pub fn siguiente(&self, origen: IdLocal, actual: IdLocal, vivos: &[IdLocal]) -> IdLocal {
+        let mut recorrido = vec![origen];
+        recorrido.extend(self.vecinos(origen, vivos));
+        let posicion = recorrido.iter().position(|id| *id == actual).unwrap_or(0);
+        recorrido[(posicion + 1) % recorrido.len()]
+    }
+}
+
+/// Verifica que se indiquen los locales conocidos, sin repetir
+fn validar_locales(locales: &[IdLocal]) -> Result<(), ErrorDuranteParseo> {
+    let mut vistos = locales.to_vec();
+    vistos.sort_unstable();
+    vistos.dedup();
+    if locales.is_empty() || vistos.len() != locales.len() {
+        eprintln!("La topologia debe indicar los locales conocidos, sin repetir");
+        return Err(ErrorDuranteParseo::TopologiaInvalida);
+    }
+    Ok(())
+}
+
+/// A partir de un reader en formato json, crea la topologia de delegacion
+pub fn from_reader(reader: &mut dyn Read) -> Result<Topologia, ErrorDuranteParseo> {
+    let archivo: ArchivoTopologia = serde_json::from_reader(reader)?;
+    let topologia = match (archivo.vecinos, archivo.distancias) {
+        (Some(vecinos), None) => Topologia::con_vecinos(&archivo.locales, vecinos)?,
+        (None, Some(distancias)) => Topologia::con_distancias(&archivo.locales, distancias)?,
+        (None, None) => Topologia::default(),
+        (Some(_), Some(_)) => {
+            eprintln!("La topologia debe indicar vecinos o distancias, no ambos");
+            return Err(ErrorDuranteParseo::TopologiaInvalida);
+        }
+    };
+    Ok(topologia
+        .con_modo(archivo.delegacion)
+        .con_retransmision(archivo.retransmision))
+}
+
+/// Obtiene la topologia de delegacion. Como es opcional, si el archivo no
+/// existe devuelve la del anillo
+pub fn obtener_topologia() -> Result<Topologia, ErrorDuranteParseo> {
+    let mut topologia_json = match File::open(ARCHIVO_TOPOLOGIA) {
+        Ok(archivo) => archivo,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Topologia::default()),
+        Err(e) => return Err(e.into()),
+    };
+    from_reader(&mut topologia_json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIVOS: [IdLocal; 4] = [0, 1, 2, 3];
+
+    #[test]
+    fn sin_archivo_los_locales_forman_un_anillo() {
+        let topologia = Topologia::default();
+        assert_eq!(topologia.vecinos(2, &VIVOS), vec![3, 0, 1]);
+        assert_eq!(topologia.siguiente(2, 2, &VIVOS), 3);
+        assert_eq!(topologia.siguiente(2, 1, &VIVOS), 2);
+    }
+
+    #[test]
+    fn solo_se_consideran_los_locales_activos() {
+        let topologia = Topologia::default();
+        assert_eq!(topologia.vecinos(2, &[0, 2, 5, 7]), vec![5, 7, 0]);
+        assert_eq!(topologia.siguiente(2, 7, &[0, 2, 5, 7]), 0);
+
+        let json = r#"{"locales": [0, 1, 2, 3], "vecinos": {"0": [3, 1, 2]}}"#;
+        let topologia = from_reader(&mut json.as_bytes()).unwrap();
+        assert_eq!(topologia.vecinos(0, &[0, 1, 2]), vec![1, 2]);
+    }
+
+    #[test]
+    fn la_matriz_de_distancias_ordena_los_vecinos_por_cercania() {
+        let json = r#"{
+            "locales": [0, 1, 2, 3],
+            "distancias": [[0, 7, 2, 7], [7, 0, 1, 3], [2, 1, 0, 9], [7, 3, 9, 0]]
+        }"#;
+        let topologia = from_reader(&mut json.as_bytes()).unwrap();
+        assert_eq!(topologia.vecinos(0, &VIVOS), vec![2, 1, 3]);
+        assert_eq!(topologia.vecinos(3, &VIVOS), vec![1, 0, 2]);
+        assert_eq!(topologia.siguiente(0, 2, &VIVOS), 1);
+        assert_eq!(topologia.siguiente(0, 3, &VIVOS), 0);
+    }
+
+    #[test]
+    fn las_listas_de_vecinos_se_respetan_y_se_validan() {
+        let json = r#"{"locales": [0, 1, 2, 3], "vecinos": {"0": [3, 1], "1": [0]}}"#;
+        let topologia = from_reader(&mut json.as_bytes()).unwrap();
+        assert_eq!(topologia.vecinos(0, &VIVOS), vec![3, 1]);
+        assert_eq!(topologia.vecinos(1, &VIVOS), vec![0]);
+        assert_eq!(topologia.vecinos(2, &VIVOS), vec![3, 0, 1]);
+
+        let invalida = r#"{"locales": [0, 1], "vecinos": {"0": [0, 1]}}"#;
+        assert!(from_reader(&mut invalida.as_bytes()).is_err());
+        let no_cuadrada = r#"{"locales": [0, 1], "distancias": [[0, 1], [1]]}"#;
+        assert!(from_reader(&mut no_cuadrada.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn la_topologia_solo_puede_referirse_a_los_locales_conocidos() {
+        let vecino_desconocido = r#"{"locales": [0, 1], "vecinos": {"0": [1, 7]}}"#;
+        assert!(from_reader(&mut vecino_desconocido.as_bytes()).is_err());
+        let local_desconocido = r#"{"locales": [0, 1], "vecinos": {"7": [0]}}"#;
+        assert!(from_reader(&mut local_desconocido.as_bytes()).is_err());
+        let sin_locales = r#"{"vecinos": {"0": [1]}}"#;
+        assert!(from_reader(&mut sin_locales.as_bytes()).is_err());
+        let filas_de_mas =
+            r#"{"locales": [0, 1], "distancias": [[0, 1, 2], [1, 0, 3], [2, 3, 0]]}"#;
+        assert!(from_reader(&mut filas_de_mas.as_bytes()).is_err());
+
+        let json = r#"{"locales": [4, 9, 2], "distancias": [[0, 5, 1], [5, 0, 3], [1, 3, 0]]}"#;
+        let topologia = from_reader(&mut json.as_bytes()).unwrap();
+        assert_eq!(topologia.vecinos(4, &[2, 4, 9]), vec![2, 9]);
+        assert_eq!(topologia.vecinos(9, &[2, 4, 9]), vec![2, 4]);
+    }
+
+    #[test]
+    fn el_modo_de_delegacion_se_lee_del_archivo() {
+        let json = r#"{"delegacion": {"modo": "consulta_paralela", "criterio": "mas_stock"}}"#;
+        let topologia = from_reader(&mut json.as_bytes()).unwrap();
+        assert_eq!(
+            topologia.modo(),
+            ModoDelegacion::ConsultaParalela {
+                criterio: Criterio::MasStock,
+                plazo_ms: 300
+            }
+        );
+        assert_eq!(topologia.vecinos(0, &VIVOS), vec![1, 2, 3]);
+        assert_eq!(Topologia::default().modo(), ModoDelegacion::Secuencial);
+    }
+
+    #[test]
+    fn la_espera_entre_retransmisiones_se_duplica_hasta_el_maximo() {
+        let json = r#"{"retransmision": {"reintentos": 4, "espera_inicial_ms": 100}}"#;
+        let retransmision = from_reader(&mut json.as_bytes()).unwrap().retransmision();
+        assert_eq!(retransmision.reintentos, 4);
+        let esperas: Vec<u64> = (0..5)
+            .map(|envio| retransmision.espera_base(envio).as_millis() as u64)
+            .collect();
+        assert_eq!(esperas, vec![100, 200, 400, 800, 1000]);
+        for envio in 0..5 {
+            let espera = retransmision.espera(envio);
+            let base = retransmision.espera_base(envio);
+            assert!(base / 2 <= espera && espera <= base);
+        }
+    }
+}